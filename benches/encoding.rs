@@ -0,0 +1,52 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use criterion::{
+    criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion,
+    Throughput,
+};
+use kadcast::bench::EncodingBench;
+
+const SIZES: [usize; 3] = [1024, 64 * 1024, 1024 * 1024];
+
+fn marshal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("marshal");
+    for size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+        let mut bench = EncodingBench::new(size);
+        let message = bench.message();
+        group.bench_function(BenchmarkId::new("bytes", size), |b| {
+            b.iter(|| bench.marshal(&message))
+        });
+        group.bench_function(BenchmarkId::new("marshal_into", size), |b| {
+            b.iter(|| bench.marshal_into(&message))
+        });
+    }
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+        let mut bench = EncodingBench::new(size);
+        group.bench_function(BenchmarkId::new("encode", size), |b| {
+            b.iter_batched(
+                || bench.message(),
+                |message| bench.encode(message),
+                BatchSize::SmallInput,
+            )
+        });
+        let message = bench.message();
+        group.bench_function(BenchmarkId::new("encode_into", size), |b| {
+            b.iter(|| bench.encode_into(&message))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, marshal, encode);
+criterion_main!(benches);
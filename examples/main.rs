@@ -103,7 +103,12 @@ pub async fn main() {
                 "report" => {
                     peer.report().await;
                 }
-                v => peer.broadcast(v.as_bytes(), None).await,
+                v => {
+                    let handle = peer.broadcast(v.as_bytes(), None).await;
+                    if handle.buckets() == 0 {
+                        println!("No peer to broadcast to");
+                    }
+                }
             }
         }
     }
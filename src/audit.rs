@@ -0,0 +1,79 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::broadcast::Receiver;
+use tracing::{error, warn};
+
+use crate::event::{KadcastEvent, SecurityEvent};
+
+/// Append the security events to the audit log, see
+/// [Config::audit_log](crate::config::Config::audit_log).
+///
+/// The file is opened once and written by a dedicated thread, so that a
+/// flood of events never blocks the runtime. It's flushed whenever no event
+/// is pending, and once the [Peer](crate::Peer) is dropped
+pub(crate) fn start(
+    path: &Path,
+    events: Receiver<KadcastEvent>,
+) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    thread::Builder::new()
+        .name("kadcast-audit".to_string())
+        .spawn(move || run(BufWriter::new(file), events))?;
+    Ok(())
+}
+
+fn run(mut writer: BufWriter<File>, mut events: Receiver<KadcastEvent>) {
+    loop {
+        let received = match events.try_recv() {
+            Ok(event) => Ok(event),
+            Err(TryRecvError::Empty) => {
+                flush(&mut writer);
+                events.blocking_recv()
+            }
+            Err(TryRecvError::Lagged(skipped)) => {
+                Err(RecvError::Lagged(skipped))
+            }
+            Err(TryRecvError::Closed) => Err(RecvError::Closed),
+        };
+        match received {
+            Ok(KadcastEvent::Security(event)) => {
+                if let Err(e) = append(&mut writer, &event) {
+                    error!("Unable to write the audit log: {}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Audit log lagging, {} events skipped", skipped);
+            }
+            Err(RecvError::Closed) => {
+                flush(&mut writer);
+                return;
+            }
+        }
+    }
+}
+
+/// Append an event as a JSON line
+fn append(
+    writer: &mut BufWriter<File>,
+    event: &SecurityEvent,
+) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, event)?;
+    writer.write_all(b"\n")
+}
+
+fn flush(writer: &mut BufWriter<File>) {
+    if let Err(e) = writer.flush() {
+        error!("Unable to flush the audit log: {}", e);
+    }
+}
@@ -0,0 +1,79 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+//! Entry points used by the benchmarks of the encoding path.
+//!
+//! This is not part of the public API and can change without notice.
+
+use bytes::{Bytes, BytesMut};
+
+use crate::encoding::message::Message;
+use crate::encoding::payload::{BroadcastPayload, DEFAULT_PRIORITY};
+use crate::peer::PeerNode;
+use crate::transport::encoding::{Configurable, Encoder, TransportEncoder};
+use crate::DEFAULT_TOPIC;
+
+/// Broadcast message to be encoded
+pub struct BenchMessage(Message);
+
+pub struct EncodingBench {
+    encoder: TransportEncoder,
+    root: PeerNode,
+    gossip_frame: Vec<u8>,
+    buffer: BytesMut,
+}
+
+impl EncodingBench {
+    /// Prepare the encoding of a broadcast message of `size` bytes
+    pub fn new(size: usize) -> Self {
+        Self {
+            encoder: TransportEncoder::configure(
+                &TransportEncoder::default_configuration(),
+            ),
+            root: PeerNode::generate("192.168.0.1:666"),
+            gossip_frame: (0..size).map(|i| i as u8).collect(),
+            buffer: BytesMut::new(),
+        }
+    }
+
+    pub fn message(&self) -> BenchMessage {
+        BenchMessage(Message::Broadcast(
+            self.root.as_header(),
+            BroadcastPayload {
+                height: 0,
+                topic: DEFAULT_TOPIC,
+                priority: DEFAULT_PRIORITY,
+                origin: None,
+                message_id: None,
+                batch: false,
+                gossip_frame: self.gossip_frame.clone(),
+            },
+        ))
+    }
+
+    pub fn marshal(&self, message: &BenchMessage) -> Vec<u8> {
+        message.0.bytes()
+    }
+
+    pub fn marshal_into(&mut self, message: &BenchMessage) -> Bytes {
+        message.0.marshal_into(&mut self.buffer).unwrap();
+        self.buffer.split().freeze()
+    }
+
+    pub fn encode(&self, message: BenchMessage) -> Vec<Vec<u8>> {
+        self.encoder
+            .encode(message.0)
+            .iter()
+            .map(|chunk| chunk.bytes())
+            .collect()
+    }
+
+    pub fn encode_into(&mut self, message: &BenchMessage) -> Vec<Bytes> {
+        self.encoder
+            .encode_into(&message.0, &mut self.buffer)
+            .unwrap()
+    }
+}
@@ -0,0 +1,366 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
+
+use crate::channel::Sender;
+use crate::config::{cap_height, Config};
+use crate::encoding::message::{BroadcastPayload, Header, Message};
+use crate::encoding::payload::{
+    MessageId, OriginSignature, Priority, Topic, DEFAULT_PRIORITY,
+    DEFAULT_TOPIC,
+};
+use crate::gossip::RecentMessages;
+use crate::kbucket::{BucketHeight, TableView};
+use crate::peer::{PeerInfo, PeerNode};
+use crate::transport::delivery::BroadcastHandle;
+use crate::transport::MessageBeanOut;
+
+/// Options of a message broadcasted with
+/// [Peer::broadcast_with](crate::Peer::broadcast_with), the default ones
+/// being the ones of [Peer::broadcast](crate::Peer::broadcast)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastOptions {
+    pub(crate) topic: Topic,
+    pub(crate) priority: Priority,
+    pub(crate) beta: Option<usize>,
+    pub(crate) id: Option<MessageId>,
+    pub(crate) height: Option<usize>,
+    pub(crate) heights: Option<Vec<BucketHeight>>,
+}
+
+impl Default for BroadcastOptions {
+    fn default() -> Self {
+        Self {
+            topic: DEFAULT_TOPIC,
+            priority: DEFAULT_PRIORITY,
+            beta: None,
+            id: None,
+            height: None,
+            heights: None,
+        }
+    }
+}
+
+impl BroadcastOptions {
+    /// The [Topic] receivers use to filter the message
+    pub fn with_topic(mut self, topic: Topic) -> Self {
+        self.topic = topic;
+        self
+    }
+
+    /// The [Priority] of the message. Messages with higher priority
+    /// overtake the lower ones in the outgoing queue, and are notified first
+    /// by the receivers
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Override the configured
+    /// [BucketConfig::beta](crate::config::BucketConfig::beta), the amount of
+    /// reliable delegates expected per bucket
+    pub fn with_beta(mut self, beta: usize) -> Self {
+        self.beta = Some(beta);
+        self
+    }
+
+    /// An id assigned by the application. The receivers deduplicate the
+    /// message by its id instead of by its content: messages with the same
+    /// id produced independently by multiple peers (eg: the same vote signed
+    /// by different relays) are delivered only once within the dedup window.
+    /// The id must be unique for messages carrying different information
+    pub fn with_id(mut self, id: MessageId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Override the configured broadcast height. It never exceeds the
+    /// configured max broadcast height
+    pub fn with_height(mut self, height: usize) -> Self {
+        self.height = Some(height);
+        self
+    }
+
+    /// Broadcast only into the buckets at the given heights (eg: the nearest
+    /// ones, for announcements scoped to the neighbourhood of this peer),
+    /// in place of the ones up to the broadcast height. Each delegate relays
+    /// the message in the subtree of its bucket, so the other buckets never
+    /// receive it. The heights above the configured max broadcast height are
+    /// skipped
+    pub fn with_heights(mut self, heights: &[BucketHeight]) -> Self {
+        self.heights = Some(heights.to_vec());
+        self
+    }
+}
+
+/// Originates the broadcasts of the local peer, shared by the [Peer] and by
+/// the task sending the scheduled broadcasts
+///
+/// [Peer]: crate::Peer
+#[derive(Clone)]
+pub(crate) struct Broadcaster {
+    view: TableView<PeerInfo>,
+    header: Header,
+    keypair: Option<Arc<Keypair>>,
+    broadcast_height: Option<usize>,
+    max_broadcast_height: Option<usize>,
+    broadcast_ack: bool,
+    recent: Option<RecentMessages>,
+    outbound_sender: Sender<MessageBeanOut>,
+}
+
+impl Broadcaster {
+    pub(crate) fn new(
+        view: TableView<PeerInfo>,
+        header: Header,
+        outbound_sender: Sender<MessageBeanOut>,
+        recent: Option<RecentMessages>,
+        config: &Config,
+    ) -> Self {
+        let keypair = config.signing_key.or(config.identity_key).map(|key| {
+            let secret =
+                SecretKey::from_bytes(&key).expect("Invalid signing key");
+            let public = PublicKey::from(&secret);
+            Arc::new(Keypair { secret, public })
+        });
+        Self {
+            view,
+            header,
+            keypair,
+            broadcast_height: config.broadcast_height,
+            max_broadcast_height: config.max_broadcast_height,
+            broadcast_ack: config.broadcast_ack.is_some(),
+            recent,
+            outbound_sender,
+        }
+    }
+
+    /// Broadcast a payload to the delegates of the buckets up to `height`,
+    /// with `beta` delegates per bucket if given
+    pub(crate) async fn broadcast(
+        &self,
+        payload: BroadcastPayload,
+        height: Option<usize>,
+        beta: Option<usize>,
+    ) -> BroadcastHandle {
+        let height = cap_height(
+            height.or(self.broadcast_height),
+            self.max_broadcast_height,
+        );
+        let targets = self
+            .view
+            .load()
+            .extract(height, beta)
+            .map(|(h, nodes)| (h, addresses(nodes)))
+            .collect();
+        self.send(payload, targets).await
+    }
+
+    /// Broadcast a payload to the delegates of the buckets at the given
+    /// heights only. The ones above the max broadcast height are skipped
+    pub(crate) async fn broadcast_into(
+        &self,
+        payload: BroadcastPayload,
+        heights: &[BucketHeight],
+        beta: Option<usize>,
+    ) -> BroadcastHandle {
+        let max_height = self.max_broadcast_height.unwrap_or(usize::MAX);
+        let targets = self
+            .view
+            .load()
+            .extract_only(heights, beta)
+            .filter(|(h, _)| *h <= max_height)
+            .map(|(h, nodes)| (h, addresses(nodes)))
+            .collect();
+        self.send(payload, targets).await
+    }
+
+    // Send a payload to the delegates of each bucket, with the height of
+    // the bucket
+    async fn send(
+        &self,
+        mut payload: BroadcastPayload,
+        targets: Vec<(BucketHeight, Vec<SocketAddr>)>,
+    ) -> BroadcastHandle {
+        let (mut handle, tracker) = BroadcastHandle::new();
+        if payload.gossip_frame.is_empty() {
+            error!("Message empty");
+            return handle;
+        }
+        let header = self.originate(&mut payload);
+        for (height, targets) in targets {
+            if targets.is_empty() {
+                continue;
+            }
+            let height = height.try_into().unwrap();
+            let msg = Message::Broadcast(
+                header.with_origin_height(Some(height)),
+                BroadcastPayload {
+                    height,
+                    ..payload.clone() //FIX_ME: avoid clone
+                },
+            );
+            handle.targeted(targets.len());
+            self.outbound_sender
+                .send((msg, targets, Some(tracker.clone())))
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Unable to send from broadcast {}", e)
+                });
+        }
+        handle
+    }
+
+    /// Sign a broadcast originated by this peer, recording it for the pull
+    /// gossip. Returns the header to send it with
+    pub(crate) fn originate(&self, payload: &mut BroadcastPayload) -> Header {
+        payload.origin = self.sign(payload);
+        let header = match self.broadcast_ack {
+            true => self.header.with_ack_request(),
+            false => self.header,
+        };
+        if let Some(recent) = &self.recent {
+            recent.insert(payload);
+        }
+        header
+    }
+
+    /// Compute the originator signature, if a signing key or a key identity
+    /// is configured
+    pub(crate) fn sign(
+        &self,
+        payload: &BroadcastPayload,
+    ) -> Option<OriginSignature> {
+        let origin_id = *self.header.binary_id.as_binary();
+        self.keypair
+            .as_ref()
+            .map(|keypair| OriginSignature::sign(keypair, origin_id, payload))
+    }
+
+    /// Send the scheduled broadcasts once due, until the [Peer] is dropped
+    ///
+    /// [Peer]: crate::Peer
+    pub(crate) async fn run_schedule(
+        self,
+        mut schedule: mpsc::UnboundedReceiver<Scheduled>,
+    ) {
+        // Pending broadcasts by due time, then by scheduling order
+        let mut pending = BTreeMap::new();
+        let mut scheduled_count = 0u64;
+        loop {
+            let next = pending.keys().next().map(|(at, _)| *at);
+            tokio::select! {
+                scheduled = schedule.recv() => match scheduled {
+                    Some(scheduled) => {
+                        pending.insert((scheduled.at, scheduled_count), scheduled);
+                        scheduled_count += 1;
+                    }
+                    None => return,
+                },
+                _ = tokio::time::sleep_until(
+                    next.unwrap_or_else(Instant::now).into()
+                ), if next.is_some() => {
+                    let now = Instant::now();
+                    let due: Vec<_> = pending
+                        .keys()
+                        .take_while(|(at, _)| *at <= now)
+                        .copied()
+                        .collect();
+                    for key in due {
+                        let scheduled = pending.remove(&key).expect("Due");
+                        if scheduled.cancelled.load(Ordering::Relaxed) {
+                            continue;
+                        }
+                        let handle = self
+                            .broadcast(scheduled.payload, scheduled.height, None)
+                            .await;
+                        let _ = scheduled.sent.send(handle);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn addresses(nodes: Vec<&PeerNode>) -> Vec<SocketAddr> {
+    nodes.iter().map(|node| *node.value().address()).collect()
+}
+
+/// Broadcast waiting to be sent by [Broadcaster::run_schedule]
+pub(crate) struct Scheduled {
+    at: Instant,
+    payload: BroadcastPayload,
+    height: Option<usize>,
+    cancelled: Arc<AtomicBool>,
+    sent: oneshot::Sender<BroadcastHandle>,
+}
+
+impl Scheduled {
+    pub(crate) fn new(
+        at: Instant,
+        payload: BroadcastPayload,
+        height: Option<usize>,
+    ) -> (Self, ScheduledBroadcast) {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (sent, sent_rx) = oneshot::channel();
+        let scheduled = Scheduled {
+            at,
+            payload,
+            height,
+            cancelled: cancelled.clone(),
+            sent,
+        };
+        let handle = ScheduledBroadcast {
+            at,
+            cancelled,
+            sent: sent_rx,
+        };
+        (scheduled, handle)
+    }
+}
+
+/// Handle of a broadcast scheduled with
+/// [Peer::broadcast_at](crate::Peer::broadcast_at) or
+/// [Peer::broadcast_after](crate::Peer::broadcast_after)
+pub struct ScheduledBroadcast {
+    at: Instant,
+    cancelled: Arc<AtomicBool>,
+    sent: oneshot::Receiver<BroadcastHandle>,
+}
+
+impl ScheduledBroadcast {
+    /// When the message is broadcasted
+    pub fn at(&self) -> Instant {
+        self.at
+    }
+
+    /// Cancel the broadcast. It has no effect once the message has been
+    /// broadcasted
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Wait for the message to be broadcasted, returning its
+    /// [BroadcastHandle]. `None` if the broadcast has been cancelled or the
+    /// peer has been shut down in the meantime
+    pub async fn sent(self) -> Option<BroadcastHandle> {
+        self.sent.await.ok()
+    }
+}
@@ -0,0 +1,372 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::error::{SendError, TryRecvError, TrySendError};
+use tokio::sync::Notify;
+
+/// What to do with a message sent to a full internal channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// Wait for a free slot, stalling the sending task
+    Block,
+    /// Wait for a free slot up to the given time, then drop the message
+    BlockTimeout(#[serde(with = "humantime_serde")] Duration),
+    /// Drop the oldest message waiting in the channel, making room for the
+    /// new one
+    DropOldest,
+    /// Drop the new message
+    DropNewest,
+}
+
+/// Amount of messages dropped by each internal channel because it was full,
+/// since the peer started. See [Health::channel_overflows]
+///
+/// [Health::channel_overflows]: crate::Health::channel_overflows
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ChannelOverflows {
+    pub(crate) datagrams: u64,
+    pub(crate) inbound: u64,
+    pub(crate) outbound: u64,
+    pub(crate) notifications: u64,
+    pub(crate) progress: u64,
+}
+
+impl ChannelOverflows {
+    /// Datagrams received from the sockets, waiting to be decoded
+    pub fn datagrams(&self) -> u64 {
+        self.datagrams
+    }
+
+    /// Decoded messages, waiting to be handled
+    pub fn inbound(&self) -> u64 {
+        self.inbound
+    }
+
+    /// Messages waiting to be encoded and sent
+    pub fn outbound(&self) -> u64 {
+        self.outbound
+    }
+
+    /// Broadcast messages waiting to be notified to the listeners
+    pub fn notifications(&self) -> u64 {
+        self.notifications
+    }
+
+    /// Decoding progress waiting to be notified to the listeners
+    pub fn progress(&self) -> u64 {
+        self.progress
+    }
+}
+
+/// Overflow counters of the internal channels
+#[derive(Default)]
+pub(crate) struct OverflowCounters {
+    pub(crate) datagrams: Arc<AtomicU64>,
+    pub(crate) inbound: Arc<AtomicU64>,
+    pub(crate) outbound: Arc<AtomicU64>,
+    pub(crate) notifications: Arc<AtomicU64>,
+    pub(crate) progress: Arc<AtomicU64>,
+}
+
+impl OverflowCounters {
+    pub(crate) fn snapshot(&self) -> ChannelOverflows {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        ChannelOverflows {
+            datagrams: load(&self.datagrams),
+            inbound: load(&self.inbound),
+            outbound: load(&self.outbound),
+            notifications: load(&self.notifications),
+            progress: load(&self.progress),
+        }
+    }
+}
+
+struct Shared<T> {
+    policy: OverflowPolicy,
+    size: usize,
+    queue: Mutex<VecDeque<T>>,
+    // Notified once a message is queued or the last sender is gone
+    queued: Notify,
+    // Notified once a message is taken or the receiver is gone
+    freed: Notify,
+    senders: AtomicUsize,
+    closed: AtomicBool,
+    overflows: Arc<AtomicU64>,
+}
+
+// Outcome of a message sent without waiting
+enum Push<T> {
+    Queued,
+    Full(T),
+    Closed(T),
+}
+
+impl<T> Shared<T> {
+    fn overflow(&self) {
+        self.overflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn queue(&self) -> MutexGuard<'_, VecDeque<T>> {
+        self.queue.lock().expect("Channel lock poisoned")
+    }
+
+    // Queue a message if there is room for it. With the `DropOldest` policy
+    // the oldest message makes room for it, so the queue never holds more
+    // than `size` messages
+    fn push(&self, message: T) -> Push<T> {
+        if self.closed.load(Ordering::Acquire) {
+            return Push::Closed(message);
+        }
+        let mut queue = self.queue();
+        if queue.len() >= self.size {
+            if self.policy != OverflowPolicy::DropOldest {
+                return Push::Full(message);
+            }
+            queue.pop_front();
+            self.overflow();
+        }
+        queue.push_back(message);
+        drop(queue);
+        self.queued.notify_one();
+        Push::Queued
+    }
+
+    fn pop(&self) -> Option<T> {
+        let message = self.queue().pop_front()?;
+        self.freed.notify_one();
+        Some(message)
+    }
+}
+
+/// Create a channel holding up to `size` messages, counting the messages
+/// dropped according to the `policy` in `overflows`
+pub(crate) fn channel<T>(
+    size: usize,
+    policy: OverflowPolicy,
+    overflows: Arc<AtomicU64>,
+) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        policy,
+        size,
+        queue: Mutex::new(VecDeque::with_capacity(size)),
+        queued: Notify::new(),
+        freed: Notify::new(),
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+        overflows,
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub(crate) struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.queued.notify_one();
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Send a message according to the overflow policy. Fails only if the
+    /// receiver is gone, the dropped messages are counted instead
+    pub(crate) async fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let shared = &self.shared;
+        let wait = match shared.policy {
+            OverflowPolicy::Block => None,
+            OverflowPolicy::BlockTimeout(timeout) => Some(timeout),
+            OverflowPolicy::DropOldest | OverflowPolicy::DropNewest => {
+                return self.try_send(message).map_err(|e| match e {
+                    TrySendError::Full(message)
+                    | TrySendError::Closed(message) => SendError(message),
+                })
+            }
+        };
+        let queued = async {
+            let mut message = message;
+            loop {
+                // Enabled before trying, so that a slot freed in between
+                // isn't missed
+                let freed = shared.freed.notified();
+                tokio::pin!(freed);
+                freed.as_mut().enable();
+                message = match shared.push(message) {
+                    Push::Queued => return Ok(()),
+                    Push::Closed(message) => return Err(SendError(message)),
+                    Push::Full(message) => message,
+                };
+                freed.await;
+            }
+        };
+        match wait {
+            None => queued.await,
+            Some(timeout) => {
+                match tokio::time::timeout(timeout, queued).await {
+                    Ok(queued) => queued,
+                    Err(_) => {
+                        shared.overflow();
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send a message without waiting. With the blocking policies a full
+    /// channel returns the message back, which is counted as dropped
+    pub(crate) fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        let shared = &self.shared;
+        match shared.push(message) {
+            Push::Queued => Ok(()),
+            Push::Closed(message) => Err(TrySendError::Closed(message)),
+            Push::Full(message) => {
+                shared.overflow();
+                match shared.policy {
+                    OverflowPolicy::DropNewest => Ok(()),
+                    _ => Err(TrySendError::Full(message)),
+                }
+            }
+        }
+    }
+
+    /// Send a message from outside of the runtime, eg: from a blocking task
+    pub(crate) fn blocking_send(&self, message: T) -> Result<(), SendError<T>> {
+        Handle::current().block_on(self.send(message))
+    }
+
+    /// Messages waiting in the channel
+    pub(crate) fn len(&self) -> usize {
+        self.shared.queue().len()
+    }
+}
+
+pub(crate) struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receive the next message, `None` once every sender is gone
+    pub(crate) async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.try_recv() {
+                Ok(message) => return Some(message),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => self.shared.queued.notified().await,
+            }
+        }
+    }
+
+    /// Max amount of messages waiting in the channel
+    pub(crate) fn capacity(&self) -> usize {
+        self.shared.size
+    }
+
+    pub(crate) fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        // The senders are counted first, a message queued by the last one
+        // is still received
+        let disconnected = self.shared.senders.load(Ordering::Acquire) == 0;
+        match self.shared.pop() {
+            Some(message) => Ok(message),
+            None if disconnected => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.freed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{channel, OverflowPolicy};
+
+    #[tokio::test]
+    async fn test_overflow_policies() {
+        let overflows = Arc::new(AtomicU64::new(0));
+        let (tx, mut rx) =
+            channel(2, OverflowPolicy::DropNewest, overflows.clone());
+        for i in 0..3 {
+            tx.send(i).await.unwrap();
+        }
+        assert_eq!(tx.len(), 2);
+        assert_eq!(rx.recv().await, Some(0));
+        assert_eq!(rx.recv().await, Some(1));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(overflows.load(Ordering::Relaxed), 1);
+
+        let (tx, mut rx) =
+            channel(2, OverflowPolicy::DropOldest, overflows.clone());
+        for i in 0..4 {
+            tx.send(i).await.unwrap();
+        }
+        assert_eq!(tx.len(), 2);
+        assert_eq!(rx.recv().await, Some(2));
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(tx.len(), 0);
+        assert_eq!(overflows.load(Ordering::Relaxed), 3);
+
+        let timeout = Duration::from_millis(10);
+        let (tx, mut rx) = channel(
+            1,
+            OverflowPolicy::BlockTimeout(timeout),
+            overflows.clone(),
+        );
+        tx.send(0).await.unwrap();
+        tx.send(1).await.unwrap();
+        assert!(tx.try_send(2).is_err());
+        assert_eq!(overflows.load(Ordering::Relaxed), 5);
+        assert_eq!(rx.recv().await, Some(0));
+        assert!(rx.try_recv().is_err());
+
+        let (tx, mut rx) = channel(1, OverflowPolicy::Block, overflows);
+        tx.send(0).await.unwrap();
+        // A blocked sender is woken up once a slot is freed
+        let blocked = tx.clone();
+        let sent = tokio::spawn(async move { blocked.send(1).await });
+        tokio::time::sleep(timeout).await;
+        assert_eq!(tx.len(), 1);
+        assert_eq!(rx.recv().await, Some(0));
+        sent.await.unwrap().unwrap();
+        assert_eq!(rx.recv().await, Some(1));
+        tx.send(0).await.unwrap();
+        drop(rx);
+        // The receiver is gone, the sender doesn't wait for a slot
+        assert!(tx.send(1).await.is_err());
+    }
+}
@@ -0,0 +1,56 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::encoding::message::MessageUid;
+
+/// Encoder of the broadcast messages sent, configured with
+/// [Config::broadcast_encoder](crate::config::Config::broadcast_encoder) in
+/// place of the RaptorQ one.
+///
+/// It splits the gossip frame of a message into the frames of its chunks.
+/// Every chunk carries the header, the topic, the priority and the
+/// signature of the message, so only the frames are encoded. The same
+/// instance can be shared by several peers. It's run by the task sending
+/// the messages, so it should never block
+pub trait BroadcastEncoder: Send + Sync {
+    fn encode(&self, gossip_frame: &[u8]) -> Vec<Vec<u8>>;
+}
+
+impl fmt::Debug for dyn BroadcastEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BroadcastEncoder")
+    }
+}
+
+/// Decoder of the broadcast messages received, configured with
+/// [Config::broadcast_decoder](crate::config::Config::broadcast_decoder) in
+/// place of the RaptorQ one.
+///
+/// It's handed the frame of every chunk received, and returns the gossip
+/// frame of the message once it can be rebuilt. It's in charge of
+/// discarding the messages already decoded, eg: with a cache shared by
+/// several peers. It's run by the task decoding the messages, so it should
+/// never block
+pub trait BroadcastDecoder: Send + Sync {
+    fn decode(&self, chunk: &[u8], source: IpAddr) -> Option<Vec<u8>>;
+
+    /// The uid of the message a chunk belongs to, which the chunks are
+    /// rate limited by (see
+    /// [Config::rate_limit](crate::config::Config::rate_limit)). The
+    /// chunks without a uid are limited as control messages
+    fn message_uid(&self, _chunk: &[u8]) -> Option<MessageUid> {
+        None
+    }
+}
+
+impl fmt::Debug for dyn BroadcastDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BroadcastDecoder")
+    }
+}
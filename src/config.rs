@@ -4,13 +4,37 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
+pub use crate::channel::OverflowPolicy;
+use crate::kbucket::{
+    BinaryKey, BucketHeight, DelegatePolicy, EvictionPolicy, KeepAlivePolicy,
+    PeerTarget,
+};
+pub use crate::transport::cipher::GossipKey;
 use crate::transport::encoding::Configurable;
 use crate::transport::encoding::TransportDecoder;
 pub use crate::transport::encoding::TransportDecoderConfig;
 use crate::transport::encoding::TransportEncoder;
 pub use crate::transport::encoding::TransportEncoderConfig;
+pub use crate::transport::encoding::TransportEncoderProfile;
+pub use crate::transport::mac::NetworkSecret;
+pub use crate::transport::noise::NoiseKey;
+use crate::{
+    BroadcastDecoder, BroadcastEncoder, MaintenanceStrategy, MessageMiddleware,
+    MessageValidator, RequestHandler, ID_LEN,
+};
+use ed25519_dalek::SecretKey;
 use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
 
 /// Default value while a node is considered alive (no eviction will be
 /// requested)
@@ -22,21 +46,179 @@ pub const BUCKET_DEFAULT_NODE_EVICT_AFTER_MILLIS: u64 = 5000;
 /// Default value after which a bucket is considered idle
 pub const BUCKET_DEFAULT_TTL_SECS: u64 = 60 * 60;
 
+/// Default max random delay before refreshing an idle bucket
+pub const BUCKET_DEFAULT_REFRESH_JITTER_SECS: u64 = 5 * 60;
+
+/// Default max amount of lookups of random keys performed to refill an idle
+/// bucket
+pub const BUCKET_DEFAULT_REFRESH_WALKS: usize = 3;
+
+/// Default amount of failed deliveries removing a peer from the table
+pub const BUCKET_DEFAULT_MAX_DELIVERY_FAILURES: usize = 3;
+
+/// Default amount of reliable delegates per bucket of a broadcast
+pub const BUCKET_DEFAULT_BETA: usize = crate::K_BETA;
+
+/// Default amount of bits each split bucket is divided by, resulting in
+/// 2^bits sub-buckets
+pub const BUCKET_DEFAULT_SPLIT_BITS: u8 = 1;
+
+/// Max amount of bits a split bucket can be divided by
+pub const BUCKET_MAX_SPLIT_BITS: u8 = 4;
+
+/// Default amount of zero bits required to the proof of work of the node
+/// ids, which is also the lowest accepted
+pub const BUCKET_DEFAULT_ID_DIFFICULTY: usize = crate::K_DIFF_MIN_BIT;
+
+/// Max amount of zero bits required to the proof of work of the node ids,
+/// which keeps the search within the range of the nonce
+pub const BUCKET_MAX_ID_DIFFICULTY: usize = 24;
+
 /// Default behaviour for propagation of incoming broadcast messages
 pub const ENABLE_BROADCAST_PROPAGATION: bool = true;
 
+/// Default amount of pings sent to an idle node before removing it
+pub const DEFAULT_KEEP_ALIVE_ATTEMPTS: usize = 3;
+
+/// Default interval between two pings of an idle node
+pub const DEFAULT_KEEP_ALIVE_INTERVAL_SECS: u64 = 5;
+
+/// Default delay before contacting the bootstrapping nodes again, if they
+/// don't reply
+pub const DEFAULT_BOOTSTRAP_MIN_INTERVAL_SECS: u64 = 1;
+
+/// Default max delay between two attempts to contact the bootstrapping nodes
+pub const DEFAULT_BOOTSTRAP_MAX_INTERVAL_SECS: u64 = 60;
+
+/// Default time given to the contacted peers to reply while joining the
+/// network
+pub const DEFAULT_MAINTENANCE_REPLY_GRACE_SECS: u64 = 3;
+
+/// Default min amount of peers close to the local one, below which the
+/// bootstrapping nodes are contacted
+pub const DEFAULT_MAINTENANCE_MIN_PEERS: usize = 3;
+
+/// Default max amount of received messages held back from the relay
+pub const DEFAULT_MAX_HELD_MESSAGES: usize = 64;
+
+/// Default max length of the frames coalescing the messages of a batch
+pub const DEFAULT_MAX_BATCH_LEN: usize = 8 * 1024;
+
 /// Default internal channel size
 pub const DEFAULT_CHANNEL_SIZE: usize = 1000;
 
 pub const DEFAULT_SEND_RETRY_COUNT: u8 = 3;
 pub const DEFAULT_SEND_RETRY_SLEEP_MILLIS: u64 = 5;
 
+/// Default tolerance on the clock difference between peers
+pub const DEFAULT_CLOCK_SKEW_SECS: u64 = 5;
+
+/// Default max time waited by [Peer::shutdown](crate::Peer::shutdown) for
+/// the outgoing messages to be sent
+pub const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 5;
+
+/// Default time waited for the response to a request, before sending it
+/// again
+pub const DEFAULT_REQUEST_TIMEOUT_MILLIS: u64 = 2000;
+
+/// Default amount of times a request is sent again
+pub const DEFAULT_REQUEST_RETRIES: usize = 2;
+
+/// Default time given to the delegates of an acknowledged broadcast to
+/// confirm it, before sending them further repair symbols
+pub const DEFAULT_BROADCAST_ACK_TIMEOUT_MILLIS: u64 = 1000;
+
+/// Default amount of times further repair symbols are sent to the delegates
+/// of an acknowledged broadcast which didn't confirm it
+pub const DEFAULT_BROADCAST_ACK_RETRIES: usize = 3;
+
+/// Default amount of repair symbols per source block sent on each retry of an
+/// acknowledged broadcast
+pub const DEFAULT_BROADCAST_ACK_REPAIR_PACKETS: u32 = 10;
+
+/// Default max amount of control messages a single source address can send
+/// per second
+pub const DEFAULT_RATE_LIMIT_CONTROL_MESSAGES: u32 = 200;
+
+/// Default max amount of distinct broadcast messages a single source address
+/// can introduce per second
+pub const DEFAULT_RATE_LIMIT_BROADCAST_UIDS: u32 = 50;
+
+/// Default length of the window the flood guard counts the datagrams of each
+/// source IP over
+pub const DEFAULT_FLOOD_WINDOW_SECS: u64 = 1;
+
+/// Default max amount of datagrams a single source IP can send per window
+pub const DEFAULT_FLOOD_MAX_DATAGRAMS: u32 = 2000;
+
+/// Default max amount of malformed datagrams a single source IP can send per
+/// window
+pub const DEFAULT_FLOOD_MAX_MALFORMED: u32 = 20;
+
+/// Default max amount of messages with an invalid header a single source IP
+/// can send per window
+pub const DEFAULT_FLOOD_MAX_INVALID_HEADERS: u32 = 20;
+
+/// Default time the datagrams of a source IP crossing a threshold of the
+/// flood guard are ignored for
+pub const DEFAULT_FLOOD_IGNORE_SECS: u64 = 60;
+
+/// Default max size of a `Nodes` reply, as a multiple of the size of the
+/// `FindNodes` request
+pub const DEFAULT_MAX_AMPLIFICATION: usize = 10;
+
+/// Default time a `Pong` proves the bidirectional contact with an address
+pub const DEFAULT_CONTACT_TTL_SECS: u64 = 600;
+
+/// Default interval between two digests of the recent broadcast messages
+/// sent to the neighbours
+pub const DEFAULT_PULL_GOSSIP_INTERVAL_SECS: u64 = 10;
+
+/// Default amount of neighbours a digest of the recent broadcast messages is
+/// sent to
+pub const DEFAULT_PULL_GOSSIP_FANOUT: usize = 3;
+
+/// Default time a broadcast message is kept to be served to the neighbours
+/// which missed it
+pub const DEFAULT_PULL_GOSSIP_WINDOW_SECS: u64 = 120;
+
+/// Default max amount of broadcast messages kept to be served to the
+/// neighbours which missed them
+pub const DEFAULT_PULL_GOSSIP_MAX_MESSAGES: usize = 1024;
+
+/// Default interval between two saves of the peer store
+pub const DEFAULT_PEER_STORE_INTERVAL_SECS: u64 = 60;
+
+/// Default max amount of peers of the bootstrap cache
+pub const DEFAULT_BOOTSTRAP_CACHE_SIZE: usize = 1000;
+
+/// Default sizes the datagrams are padded to
+pub const DEFAULT_PADDING_BUCKETS: [usize; 3] = [256, 1024, 1472];
+
+/// Default max delay between two padded datagrams
+pub const DEFAULT_PADDING_MAX_JITTER_MILLIS: u64 = 5;
+
+/// Default age of a Noise session after which it's renewed with a new
+/// handshake
+pub const DEFAULT_NOISE_REKEY_INTERVAL_SECS: u64 = 120;
+
+/// Default time given to a peer to answer a Noise handshake, before sending
+/// it again
+pub const DEFAULT_NOISE_HANDSHAKE_TIMEOUT_MILLIS: u64 = 1000;
+
+/// Default amount of times an unanswered Noise handshake is sent again
+pub const DEFAULT_NOISE_HANDSHAKE_RETRIES: usize = 3;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
     /// Public `SocketAddress` of the [Peer]. No domain name allowed
     ///
     /// This is the address where other peers can contact you.
     /// This address MUST be accessible from any peer of the network
+    ///
+    /// Port 0 binds an ephemeral port, which is then advertised (see
+    /// [Peer::listen_addr](crate::Peer::listen_addr)). It's allowed only
+    /// if no `listen_address` is set
     pub public_address: String,
 
     /// Optional internal `SocketAddress` to listen incoming connections.
@@ -47,24 +229,79 @@ pub struct Config {
     /// network (Eg: if you are behind a NAT)
     /// If this is not specified, the public address will be used for binding
     /// incoming connection
+    ///
+    /// Port 0 binds an ephemeral port, while the `public_address` is
+    /// advertised as it is
     pub listen_address: Option<String>,
 
+    /// Optional public `SocketAddress` of the other IP family, advertised by
+    /// dual-homed peers alongside the `public_address`. Eg: [2001:db8::1]:9000
+    ///
+    /// Peers unable to reach the `public_address` fail over to this one. If
+    /// the `listen_address` is not specified, this address is bound as well
+    pub public_fallback_address: Option<String>,
+
     /// List of known bootstrapping kadcast nodes.
     ///
     /// It accepts the same representation of `public_address` but with domain
     /// names allowed
     pub bootstrapping_nodes: Vec<String>,
 
+    /// Peers which are never evicted from their bucket to make room for
+    /// other nodes (eg: the bootstrapping nodes or operator chosen anchors).
+    /// They are pinged once idle for half the `node_ttl`, and never removed
+    /// for not answering
+    ///
+    /// It accepts the same representation of `bootstrapping_nodes`
+    #[serde(default)]
+    pub anchor_nodes: Vec<String>,
+
     /// Enable automatic propagation of incoming broadcast messages
     ///
     /// Default value [ENABLE_BROADCAST_PROPAGATION]
     pub auto_propagate: bool,
+
+    /// Run the peer in observer (listen-only) mode, eg: for monitoring or
+    /// indexing: it joins the network and receives the broadcasts, but it
+    /// never relays them (see
+    /// [Peer::set_relay_enabled](crate::Peer::set_relay_enabled)) and the
+    /// other peers never hand it out as a candidate in their `Nodes` replies
+    ///
+    /// Default value `false`
+    #[serde(default)]
+    pub observer: bool,
+
+    /// Max amount of received broadcast messages held back from the relay
+    /// (by the verdict of the listeners, by `auto_propagate` or while the
+    /// relay is paused), which can be relayed later with
+    /// [Peer::repropagate](crate::Peer::repropagate). The oldest ones are
+    /// forgotten first, 0 holds none
+    ///
+    /// Default value [DEFAULT_MAX_HELD_MESSAGES]
+    #[serde(default = "default_max_held_messages")]
+    pub max_held_messages: usize,
+
+    /// Max length of the frames [Peer::broadcast_batch] coalesces the
+    /// messages into. Longer messages are broadcasted in a frame on their
+    /// own
+    ///
+    /// Default value [DEFAULT_MAX_BATCH_LEN]
+    ///
+    /// [Peer::broadcast_batch]: crate::Peer::broadcast_batch
+    #[serde(default = "default_max_batch_len")]
+    pub max_batch_len: usize,
     pub channel_size: usize,
 
+    /// Behaviour of each internal channel once `channel_size` messages are
+    /// waiting in it
+    #[serde(default)]
+    pub channels: ChannelsConfig,
+
     /// Send a `FindNodes` message to every Peer inside `Nodes` message
-    /// received
+    /// received, once it answered the `Ping` it's first sent from the
+    /// address it's been advertised with
     ///
-    /// If disabled, a `Ping` is sent instead of `FindNodes`
+    /// If disabled, only the `Ping` is sent
     /// Default value `true]`
     pub recursive_discovery: bool,
 
@@ -76,6 +313,627 @@ pub struct Config {
 
     /// FEC configuration
     pub fec: FECConfig,
+
+    /// Custom encoder of the broadcasts sent, replacing the RaptorQ one
+    /// configured by `fec` (eg: a mock codec in tests). It can't be set
+    /// along with [Config::broadcast_ack], whose repair symbols are
+    /// RaptorQ ones
+    #[serde(skip)]
+    pub broadcast_encoder: Option<Arc<dyn BroadcastEncoder>>,
+
+    /// Custom decoder of the broadcasts received, replacing the RaptorQ one
+    /// configured by `fec` (eg: one sharing its cache of decoded messages
+    /// with other peers). Broadcasts decoded by a custom decoder are never
+    /// acknowledged, nor report their decoding progress
+    #[serde(skip)]
+    pub broadcast_decoder: Option<Arc<dyn BroadcastDecoder>>,
+
+    /// Pre-shared network key used to encrypt broadcast gossip frames
+    ///
+    /// If set, gossip frames are encrypted with ChaCha20-Poly1305 before
+    /// being FEC encoded, and any frame which can't be authenticated with
+    /// this key is discarded, as well as any frame whose topic has been
+    /// altered. Control messages are always sent in clear.
+    /// Every peer of the network MUST share the same key.
+    #[serde(default)]
+    pub gossip_key: Option<GossipKey>,
+
+    /// Ed25519 secret key used to sign the broadcasted messages
+    ///
+    /// The signature allows the receivers to authenticate the originator of
+    /// a broadcast, which relays can't forge. If not set, the broadcasts are
+    /// signed with the [Config::identity_key], if any, which binds the
+    /// signature to the originator id
+    #[serde(default)]
+    pub signing_key: Option<[u8; 32]>,
+
+    /// Key the id of the node is generated from, instead of deriving it from
+    /// the public address. It keeps the identity of the node stable across
+    /// address changes; a key can be derived from a keypair by passing its
+    /// public key to [derive_key](crate::derive_key)
+    ///
+    /// Default value `None`
+    #[serde(default)]
+    pub node_key: Option<BinaryKey>,
+
+    /// Ed25519 secret key the id of the node is derived from, as the hash
+    /// of its public key. The public key is carried by the headers, and the
+    /// other peers challenge the node to prove it owns the secret key
+    /// before inserting it in their routing table, so the identity can't be
+    /// forged by spoofing or sharing an address. It can't be set along with
+    /// [Config::node_key]
+    ///
+    /// Default value `None`
+    #[serde(default)]
+    pub identity_key: Option<[u8; 32]>,
+
+    /// Never insert in the routing table the peers without a key identity,
+    /// see [Config::identity_key], and discard the signed broadcasts whose
+    /// key is not bound to the originator id
+    ///
+    /// Default value `false`
+    #[serde(default)]
+    pub require_identity: bool,
+
+    /// Discard any incoming broadcast message without a valid originator
+    /// signature
+    ///
+    /// Messages with an invalid signature are always discarded.
+    /// Default value `false`
+    #[serde(default)]
+    pub require_signed_broadcast: bool,
+
+    /// Max age of an incoming control message (`Ping`, `Pong`, `FindNodes`,
+    /// `Nodes`, `Goodbye`, `Request` and `Response`). Older messages are
+    /// considered replays and discarded
+    ///
+    /// The age is computed from the timestamp of the sender, so the clocks
+    /// of the peers must not drift apart more than the `clock_skew`, or
+    /// their messages are discarded. The timestamp is only sent by the peers
+    /// with a replay window, the messages of the others are discarded too.
+    /// The timestamp is authenticated only along with the
+    /// [Config::network_secret], without it an attacker can refresh the
+    /// messages it replays.
+    /// Default value `None`
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub replay_window: Option<Duration>,
+
+    /// Tolerance on the clock difference between peers, applied when
+    /// checking the `replay_window`
+    ///
+    /// Default value [DEFAULT_CLOCK_SKEW_SECS]
+    #[serde(default = "default_clock_skew")]
+    #[serde(with = "humantime_serde")]
+    pub clock_skew: Duration,
+
+    /// Discard the `Pong` and `Nodes` replies which don't echo the nonce of
+    /// a `Ping` or a `FindNodes` request sent recently
+    ///
+    /// Replies echoing an unknown or expired nonce, or answering the same
+    /// `Ping` twice, are always discarded. Older peers don't echo the
+    /// nonces, their replies are accepted unless this is set.
+    /// Default value `false`
+    #[serde(default)]
+    pub require_nonce_echo: bool,
+
+    /// Secret shared by the peers of a permissioned network
+    ///
+    /// If set, control messages (`Ping`, `Pong`, `FindNodes`, `Nodes`,
+    /// `Goodbye`, `Request` and `Response`) are sent with an authentication
+    /// tag derived from this secret, and incoming control messages without
+    /// a valid tag are discarded. This prevents unknown hosts from
+    /// altering the routing table.
+    #[serde(default)]
+    pub network_secret: Option<NetworkSecret>,
+
+    /// Height of the broadcast messages sent without an explicit one
+    ///
+    /// If `None`, messages are sent to every bucket of the routing table
+    #[serde(default)]
+    pub broadcast_height: Option<usize>,
+
+    /// Max height of the outgoing broadcast messages, both originated and
+    /// relayed. Greater heights, even if explicitly requested, are lowered to
+    /// this value
+    ///
+    /// Lower values restrict the propagation to the closest peers in the XOR
+    /// metric (eg: regional rebroadcast)
+    #[serde(default)]
+    pub max_broadcast_height: Option<usize>,
+
+    /// Pad the outgoing datagrams and randomize their timing, in order to
+    /// resist traffic analysis
+    ///
+    /// Every peer of the network must share the same setting, since padded
+    /// datagrams are not understood by peers which don't expect them
+    #[serde(default)]
+    pub padding: Option<PaddingConfig>,
+
+    /// Establish a session with each peer through a Noise_XX handshake
+    /// (X25519, ChaCha20-Poly1305 and BLAKE2s) before exchanging any
+    /// message, giving mutual authentication and encryption of every
+    /// datagram
+    ///
+    /// Datagrams which don't belong to a session are discarded, so every
+    /// peer of the network must share the same setting
+    ///
+    /// As in WireGuard, a handshake is only answered once the initiator
+    /// sent back a cookie proving it receives the datagrams sent to its
+    /// address, which takes a further round trip
+    #[serde(default)]
+    pub noise: Option<NoiseConfig>,
+
+    /// Ask the delegates of the originated broadcasts to acknowledge them
+    /// once decoded, sending further repair symbols to the ones which don't.
+    /// Relayed broadcasts are never acknowledged
+    ///
+    /// Peers not supporting it never acknowledge, they are reported by
+    /// [Delivery::unacknowledged](crate::Delivery::unacknowledged)
+    #[serde(default)]
+    pub broadcast_ack: Option<BroadcastAckConfig>,
+
+    /// Limit the messages every single source IP can send, whatever the
+    /// port, dropping the exceeding ones and lowering the score of the
+    /// sender
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+
+    /// Count the datagrams, the malformed ones and the messages with an
+    /// invalid header received from every source IP, and ignore the sources
+    /// crossing a threshold for a while. Ignored datagrams are discarded as
+    /// soon as they are received, before being decoded
+    #[serde(default)]
+    pub flood_guard: Option<FloodGuardConfig>,
+
+    /// Prevent the `FindNodes` requests from being abused to reflect
+    /// amplified traffic toward a spoofed address. Requests are answered
+    /// only after a bidirectional contact with their source, and with a
+    /// reply bounded relative to the request
+    ///
+    /// A request from an address which didn't answer a `Ping` recently is
+    /// answered with a `Ping`, so the requester is answered once it retries
+    #[serde(default)]
+    pub amplification_guard: Option<AmplificationGuardConfig>,
+
+    /// Periodically send a digest (IHAVE) of the recently seen broadcast
+    /// messages to some neighbours, which request (IWANT) the ones they
+    /// missed because of losses or downtime.
+    ///
+    /// The recovered messages are notified like the broadcasted ones, but
+    /// never propagated. While enabled, a message seen again within the
+    /// window is not notified twice.
+    ///
+    /// Requests are only served to the peers of the routing table or, with
+    /// the `amplification_guard`, to the ones which answered a `Ping`
+    /// recently. Each message is served once per `interval` to the same
+    /// address, and up to 256 KiB per request
+    #[serde(default)]
+    pub pull_gossip: Option<PullGossipConfig>,
+
+    /// File where the known peers are persisted, periodically and when the
+    /// [Peer](crate::Peer) is dropped
+    ///
+    /// At startup the stored peers are contacted before the bootstrapping
+    /// nodes, allowing a restarted node to rejoin the network immediately
+    #[serde(default)]
+    pub peer_store: Option<PathBuf>,
+
+    /// File where a larger set of known good peers is persisted, along with
+    /// the outcome of the previous contacts
+    ///
+    /// Unlike the `peer_store`, the cache is not limited to the peers of the
+    /// routing table. It's sampled at startup when the bootstrapping nodes
+    /// are unreachable, and saved along with the `peer_store`
+    #[serde(default)]
+    pub bootstrap_cache: Option<PathBuf>,
+
+    /// Max amount of peers of the `bootstrap_cache`. The peers with the
+    /// worst record are dropped first
+    ///
+    /// Default value [DEFAULT_BOOTSTRAP_CACHE_SIZE]
+    #[serde(default = "default_bootstrap_cache_size")]
+    pub bootstrap_cache_size: usize,
+
+    /// Interval between two saves of the `peer_store` and the
+    /// `bootstrap_cache`
+    ///
+    /// Default value [DEFAULT_PEER_STORE_INTERVAL_SECS]
+    #[serde(default = "default_peer_store_interval")]
+    #[serde(with = "humantime_serde")]
+    pub peer_store_interval: Duration,
+
+    /// File where the security events (eg: messages from banned sources or
+    /// failing the header verification) are appended, one JSON object per
+    /// line, so that incidents can be reconstructed after the fact
+    ///
+    /// The events are emitted anyway, see
+    /// [KadcastEvent::Security](crate::KadcastEvent::Security)
+    #[serde(default)]
+    pub audit_log: Option<PathBuf>,
+
+    /// Peers allowed to join the routing table, for permissioned overlays
+    ///
+    /// If set, messages from any other peer are discarded, so that only the
+    /// listed peers are inserted in the buckets and relayed to. It can be
+    /// updated at runtime with
+    /// [Peer::set_allowlist](crate::Peer::set_allowlist)
+    #[serde(default)]
+    pub allowlist: Option<Vec<PeerTarget>>,
+
+    /// Peers trusted by the operator, eg: the relays of a backbone
+    ///
+    /// They are exempt from the rate limits and the reputation penalties,
+    /// and are always picked as delegates of their bucket. Only the hosts
+    /// are exempt from the flood guard, which counts the datagrams before
+    /// decoding them. It can be updated at runtime with
+    /// [Peer::set_trusted_peers](crate::Peer::set_trusted_peers)
+    #[serde(default)]
+    pub trusted_peers: Vec<PeerTarget>,
+
+    /// Pings sent to the idle nodes before removing them from the routing
+    /// table
+    #[serde(default)]
+    pub keep_alive: KeepAliveConfig,
+
+    /// Backoff between the attempts to contact the bootstrapping nodes (and
+    /// the peers of the bootstrap cache), repeated until the peer joins the
+    /// network
+    #[serde(default)]
+    pub bootstrap_retry: BootstrapRetryConfig,
+
+    /// Cadence of the maintenance of the routing table (bootstrap, idle
+    /// buckets sweeps and discovery rounds)
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+
+    /// Custom maintenance strategy, replacing `maintenance` (eg: a
+    /// [LightMaintenance](crate::LightMaintenance) for light clients)
+    #[serde(skip)]
+    pub maintenance_strategy: Option<Arc<dyn MaintenanceStrategy>>,
+
+    /// Runtime the tasks of the [Peer](crate::Peer) are spawned on, eg: one
+    /// managed by the application with its own thread pool. `None` (the
+    /// default) spawns them on the runtime the peer is created from
+    #[serde(skip)]
+    pub runtime: Option<Handle>,
+
+    /// Interceptors of the messages received and sent by the peer, run in
+    /// order (see [MessageMiddleware])
+    #[serde(skip)]
+    pub middlewares: Vec<Arc<dyn MessageMiddleware>>,
+
+    /// Custom policy for the idle nodes, replacing `keep_alive` (eg: a
+    /// [NoKeepAlive](crate::NoKeepAlive) in tests). The idle nodes are still
+    /// checked every `keep_alive.interval`
+    #[serde(skip)]
+    pub keep_alive_policy: Option<Arc<dyn KeepAlivePolicy>>,
+
+    /// Send a `Goodbye` message to the peers of the routing table on
+    /// [Peer::shutdown](crate::Peer::shutdown), so that they remove this peer
+    /// straight away
+    ///
+    /// Default value `true`
+    #[serde(default = "default_send_goodbye")]
+    pub send_goodbye: bool,
+
+    /// Max time waited by [Peer::shutdown](crate::Peer::shutdown) for the
+    /// outgoing messages to be sent, before closing the sockets
+    ///
+    /// Default value [DEFAULT_SHUTDOWN_TIMEOUT_SECS]
+    #[serde(default = "default_shutdown_timeout")]
+    #[serde(with = "humantime_serde")]
+    pub shutdown_timeout: Duration,
+
+    /// Handler answering the requests sent by other peers with
+    /// [Peer::request](crate::Peer::request). If `None`, incoming requests
+    /// are discarded
+    ///
+    /// Only the requests of the peers in the routing table are handled, up
+    /// to 64 at the same time
+    #[serde(skip)]
+    pub request_handler: Option<Arc<dyn RequestHandler>>,
+
+    /// Validator of the broadcast messages received, run before they are
+    /// relayed or notified (see [MessageValidator]). If `None`, every
+    /// message is accepted
+    #[serde(skip)]
+    pub validator: Option<Arc<dyn MessageValidator>>,
+
+    /// Time waited for the response to a request, before sending it again
+    ///
+    /// Default value [DEFAULT_REQUEST_TIMEOUT_MILLIS]
+    #[serde(default = "default_request_timeout")]
+    #[serde(with = "humantime_serde")]
+    pub request_timeout: Duration,
+
+    /// Amount of times a request without response is sent again, before
+    /// failing with [RequestError::Timeout](crate::RequestError::Timeout)
+    ///
+    /// Default value [DEFAULT_REQUEST_RETRIES]
+    #[serde(default = "default_request_retries")]
+    pub request_retries: usize,
+}
+
+fn default_request_timeout() -> Duration {
+    Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MILLIS)
+}
+
+fn default_request_retries() -> usize {
+    DEFAULT_REQUEST_RETRIES
+}
+
+fn default_max_held_messages() -> usize {
+    DEFAULT_MAX_HELD_MESSAGES
+}
+
+fn default_max_batch_len() -> usize {
+    DEFAULT_MAX_BATCH_LEN
+}
+
+fn default_send_goodbye() -> bool {
+    true
+}
+
+fn default_shutdown_timeout() -> Duration {
+    Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT_SECS)
+}
+
+fn default_clock_skew() -> Duration {
+    Duration::from_secs(DEFAULT_CLOCK_SKEW_SECS)
+}
+
+fn default_peer_store_interval() -> Duration {
+    Duration::from_secs(DEFAULT_PEER_STORE_INTERVAL_SECS)
+}
+
+fn default_bootstrap_cache_size() -> usize {
+    DEFAULT_BOOTSTRAP_CACHE_SIZE
+}
+
+/// Invalid [Config], returned by [Peer::try_new](crate::Peer::try_new)
+#[derive(Debug)]
+pub enum ConfigError {
+    /// An address which is not a valid `SocketAddr`
+    InvalidAddress(String),
+    /// A fallback address of the same IP family as the public address
+    InvalidFallback(String),
+    /// A bootstrapping or anchor node which is not a `host:port` pair
+    InvalidNode(String),
+    /// The signing key is not a valid Ed25519 secret key
+    InvalidSigningKey,
+    /// A numeric field out of its range
+    OutOfRange(&'static str),
+    /// Unable to bind a listening address
+    Bind(SocketAddr, io::Error),
+    /// A transport setting which doesn't exist
+    UnknownKey(String),
+    /// A transport setting whose value can't be parsed
+    InvalidValue(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidAddress(address) => {
+                write!(f, "Invalid address: {}", address)
+            }
+            ConfigError::InvalidFallback(address) => {
+                write!(f, "Invalid fallback address: {}", address)
+            }
+            ConfigError::InvalidNode(node) => {
+                write!(f, "Invalid node: {}", node)
+            }
+            ConfigError::InvalidSigningKey => write!(f, "Invalid signing key"),
+            ConfigError::OutOfRange(field) => {
+                write!(f, "Value out of range: {}", field)
+            }
+            ConfigError::Bind(address, e) => {
+                write!(f, "Unable to bind {}: {}", address, e)
+            }
+            ConfigError::UnknownKey(key) => {
+                write!(f, "Unknown transport setting: {}", key)
+            }
+            ConfigError::InvalidValue(key, value) => {
+                write!(f, "Invalid value for {}: {}", key, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Parse an address which must be a `SocketAddr`
+fn socket_addr(address: &str) -> Result<SocketAddr, ConfigError> {
+    address
+        .parse()
+        .map_err(|_| ConfigError::InvalidAddress(address.to_string()))
+}
+
+// Check a `host:port` pair, without resolving the host
+fn check_node(node: &str) -> Result<(), ConfigError> {
+    if node.parse::<SocketAddr>().is_ok() {
+        return Ok(());
+    }
+    match node.rsplit_once(':') {
+        Some((host, port))
+            if !host.is_empty() && port.parse::<u16>().is_ok() =>
+        {
+            Ok(())
+        }
+        _ => Err(ConfigError::InvalidNode(node.to_string())),
+    }
+}
+
+// Fail with `field` out of range unless `valid`
+fn check_range(valid: bool, field: &'static str) -> Result<(), ConfigError> {
+    match valid {
+        true => Ok(()),
+        false => Err(ConfigError::OutOfRange(field)),
+    }
+}
+
+fn check_rate_limit(limit: &RateLimitConfig) -> Result<(), ConfigError> {
+    check_range(
+        limit.max_control_messages > 0,
+        "rate_limit.max_control_messages",
+    )?;
+    check_range(
+        limit.max_broadcast_uids > 0,
+        "rate_limit.max_broadcast_uids",
+    )
+}
+
+impl Config {
+    /// Check the addresses, the nodes and the numeric fields, which would
+    /// otherwise make the [Peer](crate::Peer) fail once started
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let public = socket_addr(&self.public_address)?;
+        if let Some(fallback) = &self.public_fallback_address {
+            if socket_addr(fallback)?.is_ipv4() == public.is_ipv4() {
+                return Err(ConfigError::InvalidFallback(fallback.clone()));
+            }
+        }
+        if let Some(listen) = &self.listen_address {
+            socket_addr(listen)?;
+            // The advertised addresses can be bound to an ephemeral port
+            // only if they are the listening ones
+            check_range(public.port() != 0, "public_address")?;
+            if let Some(fallback) = &self.public_fallback_address {
+                check_range(
+                    socket_addr(fallback)?.port() != 0,
+                    "public_fallback_address",
+                )?;
+            }
+        }
+        for node in self.bootstrapping_nodes.iter().chain(&self.anchor_nodes) {
+            check_node(node)?;
+        }
+        if let Some(key) = &self.signing_key {
+            SecretKey::from_bytes(key)
+                .map_err(|_| ConfigError::InvalidSigningKey)?;
+        }
+        check_range(
+            self.identity_key.is_none() || self.node_key.is_none(),
+            "identity_key",
+        )?;
+        check_range(
+            self.broadcast_encoder.is_none() || self.broadcast_ack.is_none(),
+            "broadcast_ack",
+        )?;
+        let max_height = ID_LEN * 8;
+        check_range(self.channel_size > 0, "channel_size")?;
+        check_range(self.max_batch_len > 0, "max_batch_len")?;
+        check_range(self.bucket.capacity > 0, "bucket.capacity")?;
+        check_range(self.bucket.beta > 0, "bucket.beta")?;
+        check_range(
+            (BUCKET_DEFAULT_ID_DIFFICULTY..=BUCKET_MAX_ID_DIFFICULTY)
+                .contains(&self.bucket.id_difficulty),
+            "bucket.id_difficulty",
+        )?;
+        check_range(
+            self.broadcast_height.is_none_or(|h| h <= max_height),
+            "broadcast_height",
+        )?;
+        check_range(
+            self.max_broadcast_height.is_none_or(|h| h <= max_height),
+            "max_broadcast_height",
+        )?;
+        check_range(
+            !self.keep_alive.interval.is_zero(),
+            "keep_alive.interval",
+        )?;
+        check_range(
+            !self.bootstrap_retry.min_interval.is_zero()
+                && self.bootstrap_retry.min_interval
+                    <= self.bootstrap_retry.max_interval,
+            "bootstrap_retry",
+        )?;
+        check_range(
+            self.maintenance.min_peers > 0
+                && self.maintenance.min_peers <= self.bucket.capacity,
+            "maintenance.min_peers",
+        )?;
+        check_range(
+            self.maintenance.sweep_interval != Some(Duration::ZERO),
+            "maintenance.sweep_interval",
+        )?;
+        check_range(
+            self.maintenance.discovery_interval != Some(Duration::ZERO),
+            "maintenance.discovery_interval",
+        )?;
+        check_range(
+            !self.peer_store_interval.is_zero(),
+            "peer_store_interval",
+        )?;
+        if let Some(padding) = &self.padding {
+            check_range(
+                !padding.buckets.is_empty()
+                    && padding.buckets.iter().all(|size| *size > 0),
+                "padding.buckets",
+            )?;
+        }
+        if let Some(noise) = &self.noise {
+            check_range(
+                !noise.rekey_interval.is_zero(),
+                "noise.rekey_interval",
+            )?;
+            check_range(
+                !noise.handshake_timeout.is_zero(),
+                "noise.handshake_timeout",
+            )?;
+        }
+        if let Some(limit) = &self.rate_limit {
+            check_rate_limit(limit)?;
+        }
+        if let Some(guard) = &self.flood_guard {
+            check_range(!guard.window.is_zero(), "flood_guard.window")?;
+            check_range(guard.max_datagrams > 0, "flood_guard.max_datagrams")?;
+        }
+        if let Some(guard) = &self.amplification_guard {
+            check_range(
+                guard.max_amplification > 0,
+                "amplification_guard.max_amplification",
+            )?;
+            check_range(
+                !guard.contact_ttl.is_zero(),
+                "amplification_guard.contact_ttl",
+            )?;
+        }
+        if let Some(gossip) = &self.pull_gossip {
+            check_range(!gossip.interval.is_zero(), "pull_gossip.interval")?;
+            check_range(gossip.fanout > 0, "pull_gossip.fanout")?;
+            check_range(gossip.max_messages > 0, "pull_gossip.max_messages")?;
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Spawn a task of the peer on the configured runtime
+    pub(crate) fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        match &self.runtime {
+            Some(runtime) => runtime.spawn(task),
+            None => tokio::spawn(task),
+        }
+    }
+}
+
+/// Apply the `max` limit to a broadcast height, where `None` stands for the
+/// whole routing table
+pub(crate) fn cap_height(
+    height: Option<usize>,
+    max: Option<usize>,
+) -> Option<usize> {
+    match (height, max) {
+        (Some(height), Some(max)) => Some(height.min(max)),
+        (height, None) => height,
+        (None, max) => max,
+    }
 }
 
 impl Default for Config {
@@ -83,18 +941,65 @@ impl Default for Config {
         Self {
             public_address: "127.0.0.1:9000".to_string(),
             listen_address: None,
+            public_fallback_address: None,
             bootstrapping_nodes: vec![],
+            anchor_nodes: vec![],
             auto_propagate: ENABLE_BROADCAST_PROPAGATION,
+            observer: false,
+            max_held_messages: default_max_held_messages(),
+            max_batch_len: default_max_batch_len(),
             channel_size: DEFAULT_CHANNEL_SIZE,
+            channels: ChannelsConfig::default(),
             recursive_discovery: true,
             network: NetworkConfig::default(),
             bucket: BucketConfig::default(),
             fec: FECConfig::default(),
+            broadcast_encoder: None,
+            broadcast_decoder: None,
+            gossip_key: None,
+            signing_key: None,
+            node_key: None,
+            identity_key: None,
+            require_identity: false,
+            require_signed_broadcast: false,
+            replay_window: None,
+            clock_skew: default_clock_skew(),
+            require_nonce_echo: false,
+            network_secret: None,
+            broadcast_height: None,
+            max_broadcast_height: None,
+            padding: None,
+            noise: None,
+            broadcast_ack: None,
+            rate_limit: None,
+            flood_guard: None,
+            amplification_guard: None,
+            pull_gossip: None,
+            peer_store: None,
+            bootstrap_cache: None,
+            bootstrap_cache_size: default_bootstrap_cache_size(),
+            peer_store_interval: default_peer_store_interval(),
+            audit_log: None,
+            allowlist: None,
+            trusted_peers: vec![],
+            keep_alive: KeepAliveConfig::default(),
+            bootstrap_retry: BootstrapRetryConfig::default(),
+            maintenance: MaintenanceConfig::default(),
+            maintenance_strategy: None,
+            runtime: None,
+            middlewares: vec![],
+            keep_alive_policy: None,
+            send_goodbye: default_send_goodbye(),
+            shutdown_timeout: default_shutdown_timeout(),
+            request_handler: None,
+            validator: None,
+            request_timeout: default_request_timeout(),
+            request_retries: default_request_retries(),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BucketConfig {
     /// Sets the maximum duration for a node to be considered alive (no
     /// eviction will be requested).
@@ -111,48 +1016,743 @@ pub struct BucketConfig {
 
     /// Set duration after which a bucket is considered idle
     ///
+    /// An idle bucket is refreshed with a lookup of a random key in its
+    /// range.
     /// Default value [BUCKET_DEFAULT_TTL_SECS]
     #[serde(with = "humantime_serde")]
     pub bucket_ttl: Duration,
-}
 
-impl Default for BucketConfig {
-    fn default() -> Self {
-        Self {
-            node_evict_after: Duration::from_millis(
-                BUCKET_DEFAULT_NODE_EVICT_AFTER_MILLIS,
-            ),
-            node_ttl: Duration::from_millis(BUCKET_DEFAULT_NODE_TTL_MILLIS),
-            bucket_ttl: Duration::from_secs(BUCKET_DEFAULT_TTL_SECS),
-        }
-    }
-}
+    /// Max random delay added to `bucket_ttl` before refreshing a bucket,
+    /// which prevents the peers of the network from refreshing at the same
+    /// time
+    ///
+    /// Default value [BUCKET_DEFAULT_REFRESH_JITTER_SECS]
+    #[serde(default = "default_refresh_jitter")]
+    #[serde(with = "humantime_serde")]
+    pub refresh_jitter: Duration,
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
-pub struct FECConfig {
-    pub encoder: TransportEncoderConfig,
-    pub decoder: TransportDecoderConfig,
-}
+    /// Max amount of lookups of random keys in the range of an idle bucket.
+    /// The random walk stops as soon as the bucket is full, or a lookup
+    /// doesn't find any peer
+    ///
+    /// Default value [BUCKET_DEFAULT_REFRESH_WALKS]
+    #[serde(default = "default_refresh_walks")]
+    pub refresh_walks: usize,
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
-pub struct NetworkConfig {
-    pub udp_recv_buffer_size: Option<usize>,
-    #[serde(default)]
-    #[serde(with = "humantime_serde")]
-    pub udp_send_backoff_timeout: Option<Duration>,
-    #[serde(with = "humantime_serde")]
-    pub udp_send_retry_interval: Duration,
-    pub udp_send_retry_count: u8,
-}
+    /// Amount of consecutive strikes removing a node from its bucket. A node
+    /// gets a strike whenever the messages to it can't be sent, even after
+    /// the retries, and any message received from it resets them. Dead
+    /// nodes this way stop being delegates way before the `node_ttl`
+    ///
+    /// Default value [BUCKET_DEFAULT_MAX_DELIVERY_FAILURES], `0` disables it
+    #[serde(default = "default_max_delivery_failures")]
+    pub max_delivery_failures: usize,
 
-impl Default for FECConfig {
-    fn default() -> Self {
-        Self {
-            encoder: TransportEncoder::default_configuration(),
+    /// Max amount of nodes of a bucket (the `K` parameter of Kademlia), which
+    /// is also the amount of nodes returned to a `FindNodes` request
+    ///
+    /// Default value is the `KADCAST_K` environment variable at compile time,
+    /// or 20 if it's not set
+    #[serde(default = "default_bucket_capacity")]
+    pub capacity: usize,
+
+    /// Policy choosing which node of a full bucket is replaced by new nodes.
+    /// If not set [crate::LruEviction] is used
+    #[serde(skip)]
+    pub eviction_policy: Option<Arc<dyn EvictionPolicy>>,
+
+    /// Policy choosing the delegates of each bucket a broadcast message is
+    /// sent to. If not set [crate::AdaptiveDelegates] is used
+    #[serde(skip)]
+    pub delegate_policy: Option<Arc<dyn DelegatePolicy>>,
+
+    /// Amount of reliable delegates expected per bucket when broadcasting
+    /// (the `beta` parameter of Kadcast), which trades redundancy for
+    /// bandwidth. It's honored by the default delegate policy and can be
+    /// overridden per message with
+    /// [BroadcastOptions::with_beta](crate::BroadcastOptions::with_beta)
+    ///
+    /// Default value [BUCKET_DEFAULT_BETA]
+    #[serde(default = "default_beta")]
+    pub beta: usize,
+
+    /// Favour the delegates with a low round trip time, which reduces the
+    /// propagation time of the broadcast messages. It's honored by
+    /// [crate::AdaptiveDelegates]
+    ///
+    /// Default value is `false`
+    #[serde(default)]
+    pub latency_aware_delegates: bool,
+
+    /// Amount of highest buckets which are split into sub-buckets, each one
+    /// holding up to `capacity` nodes. This gives a finer resolution of the
+    /// crowded ranges of the table, and more candidates to the delegate
+    /// policy
+    ///
+    /// Default value is `0` (no bucket is split)
+    #[serde(default)]
+    pub split_buckets: usize,
+
+    /// Split buckets are divided into `2^split_bits` sub-buckets, up to
+    /// [BUCKET_MAX_SPLIT_BITS]
+    ///
+    /// Default value [BUCKET_DEFAULT_SPLIT_BITS]
+    #[serde(default = "default_split_bits")]
+    pub split_bits: u8,
+
+    /// Max amount of nodes of a bucket from the same subnet (/24 for IPv4,
+    /// /64 for IPv6). New nodes beyond the limit are discarded, which makes
+    /// harder to eclipse the peer from a single hosting provider
+    ///
+    /// Default value is `None` (no limit)
+    #[serde(default)]
+    pub max_subnet_nodes_per_bucket: Option<usize>,
+
+    /// Max amount of nodes of the whole table from the same subnet (/24 for
+    /// IPv4, /64 for IPv6)
+    ///
+    /// Default value is `None` (no limit)
+    #[serde(default)]
+    pub max_subnet_nodes_per_table: Option<usize>,
+
+    /// Max amount of bytes used by the routing table, counting the nodes of
+    /// the buckets, the pending nodes and the metadata of the peers. Beyond
+    /// the limit the pending nodes are shed first, then the nodes with the
+    /// lowest score
+    ///
+    /// Default value is `None` (no limit)
+    #[serde(default)]
+    pub max_memory: Option<usize>,
+
+    /// Amount of zero bits required to the proof of work of the node ids,
+    /// from [BUCKET_DEFAULT_ID_DIFFICULTY] up to [BUCKET_MAX_ID_DIFFICULTY].
+    /// Each additional bit doubles the work needed to create an id, which
+    /// makes generating many Sybil identities costly. The difficulty is
+    /// advertised in the headers and it's a network parameter: the nodes
+    /// with an id below it, or advertising a different one, are never
+    /// inserted in the routing table
+    ///
+    /// Default value [BUCKET_DEFAULT_ID_DIFFICULTY]
+    #[serde(default = "default_id_difficulty")]
+    pub id_difficulty: usize,
+}
+
+impl BucketConfig {
+    /// The max amount of nodes of the bucket at `height`, according to the
+    /// bucket splitting
+    pub fn capacity_at(&self, height: BucketHeight) -> usize {
+        let max_height = crate::K_ID_LEN_BYTES * 8 - 1;
+        match height + self.split_buckets > max_height {
+            true => self.capacity << self.split_bits.min(BUCKET_MAX_SPLIT_BITS),
+            false => self.capacity,
+        }
+    }
+}
+
+fn default_split_bits() -> u8 {
+    BUCKET_DEFAULT_SPLIT_BITS
+}
+
+fn default_id_difficulty() -> usize {
+    BUCKET_DEFAULT_ID_DIFFICULTY
+}
+
+fn default_beta() -> usize {
+    BUCKET_DEFAULT_BETA
+}
+
+fn default_bucket_capacity() -> usize {
+    crate::K_K
+}
+
+fn default_refresh_jitter() -> Duration {
+    Duration::from_secs(BUCKET_DEFAULT_REFRESH_JITTER_SECS)
+}
+
+fn default_max_delivery_failures() -> usize {
+    BUCKET_DEFAULT_MAX_DELIVERY_FAILURES
+}
+
+fn default_refresh_walks() -> usize {
+    BUCKET_DEFAULT_REFRESH_WALKS
+}
+
+impl Default for BucketConfig {
+    fn default() -> Self {
+        Self {
+            node_evict_after: Duration::from_millis(
+                BUCKET_DEFAULT_NODE_EVICT_AFTER_MILLIS,
+            ),
+            node_ttl: Duration::from_millis(BUCKET_DEFAULT_NODE_TTL_MILLIS),
+            bucket_ttl: Duration::from_secs(BUCKET_DEFAULT_TTL_SECS),
+            refresh_jitter: default_refresh_jitter(),
+            refresh_walks: default_refresh_walks(),
+            max_delivery_failures: default_max_delivery_failures(),
+            capacity: default_bucket_capacity(),
+            beta: default_beta(),
+            eviction_policy: None,
+            delegate_policy: None,
+            latency_aware_delegates: false,
+            split_buckets: 0,
+            split_bits: default_split_bits(),
+            max_subnet_nodes_per_bucket: None,
+            max_subnet_nodes_per_table: None,
+            max_memory: None,
+            id_difficulty: default_id_difficulty(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct KeepAliveConfig {
+    /// Amount of pings sent to an idle node before removing it. If 0 the
+    /// idle nodes are removed without pinging them
+    ///
+    /// Default value [DEFAULT_KEEP_ALIVE_ATTEMPTS]
+    pub attempts: usize,
+
+    /// Time between two pings of an idle node, which is also the time
+    /// given to the last ping to be replied
+    ///
+    /// Default value [DEFAULT_KEEP_ALIVE_INTERVAL_SECS]
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for KeepAliveConfig {
+    fn default() -> Self {
+        Self {
+            attempts: DEFAULT_KEEP_ALIVE_ATTEMPTS,
+            interval: Duration::from_secs(DEFAULT_KEEP_ALIVE_INTERVAL_SECS),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct BootstrapRetryConfig {
+    /// Delay between the first two attempts, doubled after each attempt
+    ///
+    /// Default value [DEFAULT_BOOTSTRAP_MIN_INTERVAL_SECS]
+    #[serde(with = "humantime_serde")]
+    pub min_interval: Duration,
+
+    /// Max delay between two attempts
+    ///
+    /// Default value [DEFAULT_BOOTSTRAP_MAX_INTERVAL_SECS]
+    #[serde(with = "humantime_serde")]
+    pub max_interval: Duration,
+}
+
+impl Default for BootstrapRetryConfig {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::from_secs(
+                DEFAULT_BOOTSTRAP_MIN_INTERVAL_SECS,
+            ),
+            max_interval: Duration::from_secs(
+                DEFAULT_BOOTSTRAP_MAX_INTERVAL_SECS,
+            ),
+        }
+    }
+}
+
+impl BootstrapRetryConfig {
+    /// Delay after the given attempt, starting from 1
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.min_interval
+            .checked_mul(factor)
+            .map_or(self.max_interval, |delay| delay.min(self.max_interval))
+    }
+}
+
+/// [OverflowPolicy] of each internal channel. The overflows are counted in
+/// [Health::channel_overflows](crate::Health::channel_overflows)
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct ChannelsConfig {
+    /// Datagrams received from the sockets, waiting to be decoded
+    ///
+    /// Default value [OverflowPolicy::Block]
+    #[serde(default = "default_overflow_policy")]
+    pub datagrams: OverflowPolicy,
+
+    /// Decoded messages, waiting to be handled
+    ///
+    /// Default value [OverflowPolicy::Block]
+    #[serde(default = "default_overflow_policy")]
+    pub inbound: OverflowPolicy,
+
+    /// Messages waiting to be encoded and sent
+    ///
+    /// Default value [OverflowPolicy::Block]
+    #[serde(default = "default_overflow_policy")]
+    pub outbound: OverflowPolicy,
+
+    /// Broadcast messages waiting to be notified to the listeners
+    ///
+    /// Default value [OverflowPolicy::Block]
+    #[serde(default = "default_overflow_policy")]
+    pub notifications: OverflowPolicy,
+
+    /// Decoding progress waiting to be notified to the listeners, never
+    /// waiting for a free slot
+    ///
+    /// Default value [OverflowPolicy::Block]
+    #[serde(default = "default_overflow_policy")]
+    pub progress: OverflowPolicy,
+}
+
+impl Default for ChannelsConfig {
+    fn default() -> Self {
+        Self {
+            datagrams: default_overflow_policy(),
+            inbound: default_overflow_policy(),
+            outbound: default_overflow_policy(),
+            notifications: default_overflow_policy(),
+            progress: default_overflow_policy(),
+        }
+    }
+}
+
+fn default_overflow_policy() -> OverflowPolicy {
+    OverflowPolicy::Block
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct MaintenanceConfig {
+    /// Time given to the stored peers, the bootstrapping nodes and the
+    /// peers of the bootstrap cache to reply, before falling back to the
+    /// next ones
+    ///
+    /// Default value [DEFAULT_MAINTENANCE_REPLY_GRACE_SECS]
+    #[serde(with = "humantime_serde")]
+    pub reply_grace: Duration,
+
+    /// Min amount of peers close to the local one, below which the
+    /// bootstrapping nodes are contacted. Can't exceed the bucket capacity
+    ///
+    /// Default value [DEFAULT_MAINTENANCE_MIN_PEERS]
+    pub min_peers: usize,
+
+    /// Max time between two sweeps of the idle buckets, which otherwise
+    /// happen when the next bucket becomes idle. Each sweep also checks if
+    /// the bootstrapping nodes have to be contacted again
+    ///
+    /// Default value `None`
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub sweep_interval: Option<Duration>,
+
+    /// Interval of the lookups of the local id, discovering the new peers
+    /// close to the local one. If `None` the lookup only happens once
+    /// joined the network
+    ///
+    /// Default value `None`
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub discovery_interval: Option<Duration>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            reply_grace: Duration::from_secs(
+                DEFAULT_MAINTENANCE_REPLY_GRACE_SECS,
+            ),
+            min_peers: DEFAULT_MAINTENANCE_MIN_PEERS,
+            sweep_interval: None,
+            discovery_interval: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PaddingConfig {
+    /// Sizes the datagrams are padded to. Each datagram is padded to the
+    /// smallest size which fits it, bigger datagrams are padded to a multiple
+    /// of the biggest size
+    ///
+    /// Default value [DEFAULT_PADDING_BUCKETS]
+    pub buckets: Vec<usize>,
+
+    /// Max random delay after sending a datagram
+    ///
+    /// Default value [DEFAULT_PADDING_MAX_JITTER_MILLIS]
+    #[serde(with = "humantime_serde")]
+    pub max_jitter: Duration,
+}
+
+impl Default for PaddingConfig {
+    fn default() -> Self {
+        Self {
+            buckets: DEFAULT_PADDING_BUCKETS.to_vec(),
+            max_jitter: Duration::from_millis(
+                DEFAULT_PADDING_MAX_JITTER_MILLIS,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoiseConfig {
+    /// X25519 secret key authenticating the peer in the handshakes. A
+    /// random one is generated if `None`, see
+    /// [Peer::noise_key](crate::Peer::noise_key)
+    #[serde(default)]
+    pub static_key: Option<NoiseKey>,
+
+    /// Public keys of the peers a session can be established with. `None`
+    /// accepts any peer
+    #[serde(default)]
+    pub allowed_keys: Option<Vec<NoiseKey>>,
+
+    /// Age of a session after which it's renewed with a new handshake
+    ///
+    /// Default value [DEFAULT_NOISE_REKEY_INTERVAL_SECS]
+    #[serde(with = "humantime_serde")]
+    pub rekey_interval: Duration,
+
+    /// Time given to a peer to answer a handshake, before sending it again
+    ///
+    /// Default value [DEFAULT_NOISE_HANDSHAKE_TIMEOUT_MILLIS]
+    #[serde(with = "humantime_serde")]
+    pub handshake_timeout: Duration,
+
+    /// Amount of times an unanswered handshake is sent again, before
+    /// dropping the datagrams waiting for it
+    ///
+    /// Default value [DEFAULT_NOISE_HANDSHAKE_RETRIES]
+    pub handshake_retries: usize,
+}
+
+impl Default for NoiseConfig {
+    fn default() -> Self {
+        Self {
+            static_key: None,
+            allowed_keys: None,
+            rekey_interval: Duration::from_secs(
+                DEFAULT_NOISE_REKEY_INTERVAL_SECS,
+            ),
+            handshake_timeout: Duration::from_millis(
+                DEFAULT_NOISE_HANDSHAKE_TIMEOUT_MILLIS,
+            ),
+            handshake_retries: DEFAULT_NOISE_HANDSHAKE_RETRIES,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct BroadcastAckConfig {
+    /// Time given to the delegates to acknowledge a broadcast, counted from
+    /// when it's queued for sending
+    ///
+    /// Default value [DEFAULT_BROADCAST_ACK_TIMEOUT_MILLIS]
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+
+    /// Amount of times further repair symbols are sent to the delegates
+    /// which didn't acknowledge
+    ///
+    /// Default value [DEFAULT_BROADCAST_ACK_RETRIES]
+    pub retries: usize,
+
+    /// Repair symbols per source block sent on each retry
+    ///
+    /// Default value [DEFAULT_BROADCAST_ACK_REPAIR_PACKETS]
+    pub repair_packets: u32,
+}
+
+impl Default for BroadcastAckConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(
+                DEFAULT_BROADCAST_ACK_TIMEOUT_MILLIS,
+            ),
+            retries: DEFAULT_BROADCAST_ACK_RETRIES,
+            repair_packets: DEFAULT_BROADCAST_ACK_REPAIR_PACKETS,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Max amount of control messages (every message but the broadcast
+    /// chunks) received from a source IP per second
+    ///
+    /// Default value [DEFAULT_RATE_LIMIT_CONTROL_MESSAGES]
+    pub max_control_messages: u32,
+
+    /// Max amount of distinct broadcast messages a source IP can
+    /// introduce per second. The chunks of a message already introduced are
+    /// not limited
+    ///
+    /// Default value [DEFAULT_RATE_LIMIT_BROADCAST_UIDS]
+    pub max_broadcast_uids: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_control_messages: DEFAULT_RATE_LIMIT_CONTROL_MESSAGES,
+            max_broadcast_uids: DEFAULT_RATE_LIMIT_BROADCAST_UIDS,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct FloodGuardConfig {
+    /// Window the datagrams of each source IP are counted over
+    ///
+    /// Default value [DEFAULT_FLOOD_WINDOW_SECS]
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+
+    /// Max amount of datagrams a source IP can send per window
+    ///
+    /// Default value [DEFAULT_FLOOD_MAX_DATAGRAMS]
+    pub max_datagrams: u32,
+
+    /// Max amount of datagrams which can't be decoded a source IP can send
+    /// per window
+    ///
+    /// Default value [DEFAULT_FLOOD_MAX_MALFORMED]
+    pub max_malformed: u32,
+
+    /// Max amount of messages a source IP can send per window with a header
+    /// failing the verification (an id not matching the address, or an
+    /// invalid authentication tag)
+    ///
+    /// Default value [DEFAULT_FLOOD_MAX_INVALID_HEADERS]
+    pub max_invalid_headers: u32,
+
+    /// Time the datagrams of a source crossing a threshold are ignored for
+    ///
+    /// Default value [DEFAULT_FLOOD_IGNORE_SECS]
+    #[serde(with = "humantime_serde")]
+    pub ignore_duration: Duration,
+}
+
+impl Default for FloodGuardConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(DEFAULT_FLOOD_WINDOW_SECS),
+            max_datagrams: DEFAULT_FLOOD_MAX_DATAGRAMS,
+            max_malformed: DEFAULT_FLOOD_MAX_MALFORMED,
+            max_invalid_headers: DEFAULT_FLOOD_MAX_INVALID_HEADERS,
+            ignore_duration: Duration::from_secs(DEFAULT_FLOOD_IGNORE_SECS),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct AmplificationGuardConfig {
+    /// Max size of a `Nodes` reply (of all its pages), as a multiple of the
+    /// size of the `FindNodes` request. The farthest peers are left out
+    /// beyond it
+    ///
+    /// Default value [DEFAULT_MAX_AMPLIFICATION]
+    pub max_amplification: usize,
+
+    /// Time a `Pong` received from an address proves the bidirectional
+    /// contact with it. Only the `Pong` echoing the nonce of a `Ping` count
+    ///
+    /// Default value [DEFAULT_CONTACT_TTL_SECS]
+    #[serde(with = "humantime_serde")]
+    pub contact_ttl: Duration,
+}
+
+impl Default for AmplificationGuardConfig {
+    fn default() -> Self {
+        Self {
+            max_amplification: DEFAULT_MAX_AMPLIFICATION,
+            contact_ttl: Duration::from_secs(DEFAULT_CONTACT_TTL_SECS),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct PullGossipConfig {
+    /// Interval between two digests
+    ///
+    /// Default value [DEFAULT_PULL_GOSSIP_INTERVAL_SECS]
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+
+    /// Amount of random neighbours each digest is sent to
+    ///
+    /// Default value [DEFAULT_PULL_GOSSIP_FANOUT]
+    pub fanout: usize,
+
+    /// Time a message is kept, to be advertised and served
+    ///
+    /// Default value [DEFAULT_PULL_GOSSIP_WINDOW_SECS]
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+
+    /// Max amount of messages kept, the oldest ones are forgotten first
+    ///
+    /// Default value [DEFAULT_PULL_GOSSIP_MAX_MESSAGES]
+    pub max_messages: usize,
+}
+
+impl Default for PullGossipConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(DEFAULT_PULL_GOSSIP_INTERVAL_SECS),
+            fanout: DEFAULT_PULL_GOSSIP_FANOUT,
+            window: Duration::from_secs(DEFAULT_PULL_GOSSIP_WINDOW_SECS),
+            max_messages: DEFAULT_PULL_GOSSIP_MAX_MESSAGES,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FECConfig {
+    pub encoder: TransportEncoderConfig,
+    pub decoder: TransportDecoderConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub udp_recv_buffer_size: Option<usize>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub udp_send_backoff_timeout: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    pub udp_send_retry_interval: Duration,
+    pub udp_send_retry_count: u8,
+}
+
+impl Default for FECConfig {
+    fn default() -> Self {
+        Self {
+            encoder: TransportEncoder::default_configuration(),
             decoder: TransportDecoder::default_configuration(),
         }
     }
 }
+impl FECConfig {
+    /// Build the transport configuration from the legacy flat map of
+    /// settings (eg: `"fec_redundancy" => "0.15"`, `"cache_ttl" => "1m"`),
+    /// starting from the defaults.
+    ///
+    /// Unlike the map, an unknown or misspelled key is an error
+    pub fn from_map(
+        map: &HashMap<String, String>,
+    ) -> Result<Self, ConfigError> {
+        let mut parsed = Self::default();
+        let mut conf = serde_json::to_value(&parsed)
+            .expect("Transport config is serializable");
+        for (key, value) in map {
+            let side = ["encoder", "decoder"]
+                .iter()
+                .find(|side| conf[**side].get(key).is_some())
+                .ok_or_else(|| ConfigError::UnknownKey(key.clone()))?;
+            // Numbers and booleans are JSON values, durations are strings
+            conf[*side][key] = serde_json::from_str(value)
+                .unwrap_or_else(|_| Value::String(value.clone()));
+            parsed = serde_json::from_value(conf.clone()).map_err(|_| {
+                ConfigError::InvalidValue(key.clone(), value.clone())
+            })?;
+        }
+        Ok(parsed)
+    }
+}
+
+/// Runtime parameters changed with
+/// [Peer::reconfigure](crate::Peer::reconfigure). The fields left to `None`
+/// keep their current value.
+///
+/// The sizes of the internal channels can't be changed once the peer is
+/// created, since their buffers are allocated along with them
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PartialConfig {
+    /// See [BucketConfig::node_ttl]
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub node_ttl: Option<Duration>,
+
+    /// See [BucketConfig::node_evict_after]
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub node_evict_after: Option<Duration>,
+
+    /// See [BucketConfig::bucket_ttl]
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub bucket_ttl: Option<Duration>,
+
+    /// FEC parameters (eg: the redundancy) of the broadcasts encoded from
+    /// now on
+    #[serde(default)]
+    pub fec_encoder: Option<TransportEncoderConfig>,
+
+    /// Max number of messages being decoded which a single source IP can
+    /// start
+    #[serde(default)]
+    pub max_objects_per_source: Option<usize>,
+
+    /// Max number of symbols which can be buffered from a single source IP
+    #[serde(default)]
+    pub max_symbols_per_source: Option<usize>,
+
+    /// Per-source limits of the incoming messages, enabling them if they
+    /// were not. See [Config::rate_limit]
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl PartialConfig {
+    /// Check the numeric fields, as [Config::validate] does
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_range(
+            self.max_objects_per_source.is_none_or(|max| max > 0),
+            "max_objects_per_source",
+        )?;
+        check_range(
+            self.max_symbols_per_source.is_none_or(|max| max > 0),
+            "max_symbols_per_source",
+        )?;
+        if let Some(limit) = &self.rate_limit {
+            check_rate_limit(limit)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn updates_table(&self) -> bool {
+        self.node_ttl.is_some()
+            || self.node_evict_after.is_some()
+            || self.bucket_ttl.is_some()
+    }
+
+    pub(crate) fn apply_bucket(&self, bucket: &mut BucketConfig) {
+        if let Some(node_ttl) = self.node_ttl {
+            bucket.node_ttl = node_ttl;
+        }
+        if let Some(node_evict_after) = self.node_evict_after {
+            bucket.node_evict_after = node_evict_after;
+        }
+        if let Some(bucket_ttl) = self.bucket_ttl {
+            bucket.bucket_ttl = bucket_ttl;
+        }
+    }
+
+    pub(crate) fn apply_fec(&self, fec: &mut FECConfig) {
+        if let Some(encoder) = &self.fec_encoder {
+            fec.encoder = encoder.clone();
+        }
+        if let Some(max) = self.max_objects_per_source {
+            fec.decoder.max_objects_per_source = max;
+        }
+        if let Some(max) = self.max_symbols_per_source {
+            fec.decoder.max_symbols_per_source = max;
+        }
+    }
+}
+
 impl Default for NetworkConfig {
     fn default() -> Self {
         Self {
@@ -165,3 +1765,122 @@ impl Default for NetworkConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::{BootstrapRetryConfig, Config, ConfigError, FECConfig};
+
+    #[test]
+    fn test_validate() {
+        assert!(Config::default().validate().is_ok());
+
+        let mut conf = Config {
+            bootstrapping_nodes: vec![
+                "127.0.0.1:9000".to_string(),
+                "[::1]:9000".to_string(),
+                "bootstrap.example.com:9000".to_string(),
+            ],
+            public_fallback_address: Some("[::1]:9000".to_string()),
+            ..Default::default()
+        };
+        assert!(conf.validate().is_ok());
+
+        conf.public_fallback_address = Some("127.0.0.2:9000".to_string());
+        assert!(matches!(
+            conf.validate(),
+            Err(ConfigError::InvalidFallback(_))
+        ));
+        conf.public_fallback_address = None;
+
+        conf.bootstrapping_nodes
+            .push("bootstrap.example.com".to_string());
+        assert!(matches!(conf.validate(), Err(ConfigError::InvalidNode(_))));
+        conf.bootstrapping_nodes.pop();
+
+        conf.public_address = "localhost:9000".to_string();
+        assert!(matches!(
+            conf.validate(),
+            Err(ConfigError::InvalidAddress(_))
+        ));
+        conf.public_address = "127.0.0.1:9000".to_string();
+
+        // Ephemeral ports are advertised only if bound
+        conf.public_address = "127.0.0.1:0".to_string();
+        assert!(conf.validate().is_ok());
+        conf.listen_address = Some("0.0.0.0:0".to_string());
+        assert!(matches!(
+            conf.validate(),
+            Err(ConfigError::OutOfRange("public_address"))
+        ));
+        conf.public_address = "127.0.0.1:9000".to_string();
+        assert!(conf.validate().is_ok());
+        conf.listen_address = None;
+
+        conf.channel_size = 0;
+        assert!(matches!(
+            conf.validate(),
+            Err(ConfigError::OutOfRange("channel_size"))
+        ));
+    }
+
+    #[test]
+    fn test_bootstrap_backoff() {
+        let retry = BootstrapRetryConfig {
+            min_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(10),
+        };
+        let delays: Vec<_> = (1..=6)
+            .map(|attempt| retry.backoff(attempt).as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+        assert_eq!(retry.backoff(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_fec_from_map() {
+        let map = |entries: &[(&str, &str)]| -> HashMap<String, String> {
+            entries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let conf = FECConfig::from_map(&map(&[
+            ("fec_redundancy", "0.5"),
+            ("cache_ttl", "1m"),
+            ("request_transmission_info", "true"),
+        ]))
+        .expect("Valid settings");
+        assert_eq!(conf.decoder.cache_ttl, Duration::from_secs(60));
+        assert!(conf.decoder.request_transmission_info);
+        let encoder = serde_json::to_value(&conf.encoder).unwrap();
+        assert_eq!(encoder["fec_redundancy"], 0.5);
+
+        assert!(matches!(
+            FECConfig::from_map(&map(&[("fec_redundnacy", "0.5")])),
+            Err(ConfigError::UnknownKey(key)) if key == "fec_redundnacy"
+        ));
+        assert!(matches!(
+            FECConfig::from_map(&map(&[("mtu", "large")])),
+            Err(ConfigError::InvalidValue(key, _)) if key == "mtu"
+        ));
+        assert!(matches!(
+            FECConfig::from_map(&map(&[("mtu", "70000")])),
+            Err(ConfigError::InvalidValue(key, _)) if key == "mtu"
+        ));
+        assert!(matches!(
+            FECConfig::from_map(&map(&[("cache_ttl", "{\"secs\": 1}")])),
+            Err(ConfigError::InvalidValue(key, _)) if key == "cache_ttl"
+        ));
+
+        // Misspelled keys of a configuration file are rejected as well
+        let file = "[encoder]\nmin_repair_packets_per_block = 5\nmtu = 1300\n\
+                    fec_redundnacy = 0.15\n[decoder]\ncache_ttl = \"1m\"\n\
+                    cache_prune_every = \"5m\"\n";
+        assert!(toml::from_str::<FECConfig>(file).is_err());
+        let file = file.replace("redundnacy", "redundancy");
+        assert!(toml::from_str::<FECConfig>(&file).is_ok());
+    }
+}
@@ -6,6 +6,9 @@
 
 use std::io::{self, Read, Write};
 
+pub(crate) use error::DecodeError;
+
+mod error;
 mod header;
 pub mod message;
 pub(crate) mod payload;
@@ -20,13 +23,21 @@ pub trait Marshallable {
 #[cfg(test)]
 mod tests {
     use std::io::{BufReader, BufWriter, Cursor, Read, Seek};
+    use std::time::Duration;
+
+    use ed25519_dalek::{Keypair, PublicKey, SecretKey};
 
     use crate::{
         encoding::{
-            message::Message,
-            payload::{BroadcastPayload, NodePayload},
+            message::{Message, MAX_RAW_MESSAGE_LEN, MIN_RAW_MESSAGE_TYPE},
+            payload::{
+                BroadcastPayload, NodePayload, OriginSignature, PeerEncodedInfo,
+            },
+            DecodeError,
         },
+        kbucket::derive_key,
         peer::PeerNode,
+        K_DIFF_MIN_BIT, K_ID_LEN_BYTES, K_NONCE_LEN,
     };
 
     use super::Marshallable;
@@ -45,6 +56,70 @@ mod tests {
         assert_eq!(1, 1);
     }
 
+    #[test]
+    fn test_encode_goodbye() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        test_kadkast_marshal(Message::Goodbye(peer.as_header()));
+    }
+
+    #[test]
+    fn test_encode_request() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        let header = peer.as_header();
+        test_kadkast_marshal(Message::Request(header, 42, vec![1, 2, 3]));
+        test_kadkast_marshal(Message::Response(header, u64::MAX, vec![]));
+    }
+
+    #[test]
+    fn test_encode_ack() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        let header = peer.as_header().with_ack_request();
+        assert!(header.requests_ack());
+        assert!(!peer.as_header().requests_ack());
+        test_kadkast_marshal(Message::Ack(header, [7; 32]));
+    }
+
+    #[test]
+    fn test_encode_origin_height() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        assert_eq!(peer.as_header().origin_height(), None);
+        let header = peer.as_header().with_ack_request();
+        let header = header.with_origin_height(Some(0));
+        assert_eq!(header.origin_height(), Some(0));
+        assert!(header.requests_ack());
+        let header = header.with_origin_height(Some(127));
+        assert_eq!(header.origin_height(), Some(127));
+        test_kadkast_marshal(Message::Ping(header));
+        assert_eq!(header.with_origin_height(None).origin_height(), None);
+    }
+
+    #[test]
+    fn test_encode_observer() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        assert!(!peer.as_header().is_observer());
+        let header = peer.as_header().with_observer().with_ack_request();
+        assert!(header.is_observer());
+        assert!(header.requests_ack());
+        test_kadkast_marshal(Message::Ping(header));
+        let node =
+            PeerNode::from_header(&header, "192.168.0.1".parse().unwrap());
+        assert!(node.value().is_observer());
+        assert!(node.as_header().is_observer());
+    }
+
+    #[test]
+    fn test_encode_digest() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        let header = peer.as_header();
+        test_kadkast_marshal(Message::IHave(header, vec![[1; 32], [2; 32]]));
+        test_kadkast_marshal(Message::IWant(header, vec![]));
+
+        let too_many = Message::IWant(header, vec![[3; 32]; 33]);
+        let mut bytes = vec![];
+        too_many.marshal_binary(&mut bytes).unwrap();
+        assert!(Message::unmarshal_binary(&mut &bytes[..]).is_err());
+    }
+
     #[test]
     fn test_encode_find_nodes() {
         let peer = PeerNode::generate("192.168.0.1:666");
@@ -69,6 +144,260 @@ mod tests {
         assert_eq!(1, 1);
     }
 
+    #[test]
+    fn test_encode_fallback_address() {
+        let fallback = "[2001:db8::1]:777".parse().unwrap();
+        let peer =
+            PeerNode::generate("192.168.0.1:666").with_fallback(fallback);
+        let info = peer.as_peer_info();
+        assert_eq!(info.fallback_address(), Some(fallback));
+        let payload = NodePayload {
+            peers: vec![
+                info,
+                PeerNode::generate("192.168.1.1:666").as_peer_info(),
+            ],
+        };
+        test_kadkast_marshal(Message::Nodes(peer.as_header(), payload));
+        test_kadkast_marshal(Message::Ping(peer.as_header()));
+
+        // The fallback address must be of the other IP family
+        let mut same_family = peer.as_peer_info();
+        same_family.fallback = Some((
+            "192.168.0.2".parse::<std::net::IpAddr>().unwrap().into(),
+            777,
+        ));
+        let mut bytes = vec![];
+        same_family.marshal_binary(&mut bytes).unwrap();
+        let e = PeerEncodedInfo::unmarshal_binary(&mut &bytes[..]).unwrap_err();
+        assert_eq!(e.to_string(), DecodeError::InvalidFallback.to_string());
+    }
+
+    #[test]
+    fn test_encode_custom_id() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        let address = "192.168.1.1:666".parse().unwrap();
+        let fallback = "[2001:db8::1]:777".parse().unwrap();
+        let custom = PeerEncodedInfo::with_id(address, [7; K_ID_LEN_BYTES]);
+        let payload = NodePayload {
+            peers: vec![custom.clone(), custom.with_fallback(fallback)],
+        };
+        test_kadkast_marshal(Message::Nodes(peer.as_header(), payload));
+        test_kadkast_marshal(Message::Ping(peer.as_header().with_custom_id()));
+
+        // Unknown flags are rejected
+        let mut bytes = vec![];
+        PeerEncodedInfo::from_address(address)
+            .marshal_binary(&mut bytes)
+            .unwrap();
+        *bytes.last_mut().unwrap() = 0x04;
+        let e = PeerEncodedInfo::unmarshal_binary(&mut &bytes[..]).unwrap_err();
+        assert_eq!(e.to_string(), DecodeError::InvalidFallback.to_string());
+    }
+
+    #[test]
+    fn test_encode_identity() {
+        let config = crate::config::Config {
+            public_address: "192.168.0.1:666".to_string(),
+            identity_key: Some([1; 32]),
+            ..Default::default()
+        };
+        let peer = PeerNode::root(&config);
+        let fallback = "[2001:db8::1]:777".parse().unwrap();
+        let dual = peer.clone().with_fallback(fallback);
+        assert!(peer.as_header().identity().is_some());
+        test_kadkast_marshal(Message::Ping(peer.as_header()));
+        test_kadkast_marshal(Message::Ping(dual.as_header()));
+        test_kadkast_marshal(Message::Challenge(peer.as_header(), [7; 32]));
+        test_kadkast_marshal(Message::ChallengeResponse(
+            peer.as_header(),
+            [7; 32],
+            [9; 64],
+        ));
+    }
+
+    #[test]
+    fn test_encode_id_difficulty() {
+        let mut config = crate::config::Config {
+            public_address: "192.168.0.1:666".to_string(),
+            ..Default::default()
+        };
+        config.bucket.id_difficulty = 10;
+        let header = PeerNode::root(&config).as_header();
+        assert_eq!(header.id_difficulty(), 10);
+        test_kadkast_marshal(Message::Ping(header));
+        // The default difficulty isn't carried
+        let header = PeerNode::generate("192.168.0.1:666").as_header();
+        assert_eq!(header.id_difficulty(), K_DIFF_MIN_BIT);
+        assert_eq!(
+            Message::Ping(header).bytes().len(),
+            1 + K_ID_LEN_BYTES + K_NONCE_LEN + 4
+        );
+    }
+
+    #[test]
+    fn test_encode_request_nonce() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        let request = crate::Header {
+            request_nonce: Some(u64::MAX - 1),
+            ..peer.as_header()
+        };
+        test_kadkast_marshal(Message::Ping(request));
+        let reply = peer.as_header().echoing(&request);
+        assert_eq!(reply.request_nonce, Some(u64::MAX - 1));
+        test_kadkast_marshal(Message::Pong(reply));
+    }
+
+    #[test]
+    fn test_message_max_len() {
+        let config = crate::config::Config {
+            public_address: "[2001:db8::1]:666".to_string(),
+            identity_key: Some([1; 32]),
+            ..Default::default()
+        };
+        let fallback = "192.168.0.1:777".parse().unwrap();
+        let peer = PeerNode::root(&config).with_fallback(fallback);
+        let header = crate::Header {
+            id_difficulty: K_DIFF_MIN_BIT as u8 + 1,
+            request_nonce: Some(1),
+            ..peer.as_header()
+        };
+        // The largest messages fit their max length
+        let response = Message::ChallengeResponse(header, [7; 32], [9; 64]);
+        assert!(Message::check_len(&response.bytes(), 0).is_ok());
+        let peers = (0..100)
+            .map(|i| {
+                let address = format!("[2001:db8::{}]:666", i + 2);
+                PeerNode::generate(&address[..])
+                    .with_fallback(fallback)
+                    .as_peer_info()
+            })
+            .collect();
+        for page in NodePayload::paginate(peers) {
+            let nodes = Message::Nodes(header, page);
+            assert!(Message::check_len(&nodes.bytes(), 0).is_ok());
+        }
+
+        // Outliers are rejected before being unmarshalled
+        let mut ping = Message::Ping(header).bytes();
+        ping.extend_from_slice(&[0; 200]);
+        let e = Message::check_len(&ping, 0).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            DecodeError::MessageTooLong(0, ping.len()).to_string()
+        );
+        // Unless the data following the message accounts for them
+        assert!(Message::check_len(&ping, 200).is_ok());
+        // The unknown messages are not bounded
+        ping[0] = 42;
+        assert!(Message::check_len(&ping, 0).is_ok());
+    }
+
+    #[test]
+    fn test_encode_transmission_info() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        test_kadkast_marshal(Message::TransmissionInfoRequest(
+            peer.as_header(),
+            [3; 32],
+        ));
+        test_kadkast_marshal(Message::TransmissionInfoResponse(
+            peer.as_header(),
+            [3; 32],
+            [4; 12],
+        ));
+    }
+
+    #[test]
+    fn test_decode_unknown_message() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        let mut bytes = vec![];
+        Message::Ping(peer.as_header())
+            .marshal_binary(&mut bytes)
+            .unwrap();
+        bytes[0] = 42;
+        bytes.extend_from_slice(&[1, 2, 3, 4, 5]);
+        let mut reader = &bytes[..];
+        let message = Message::unmarshal_binary(&mut reader).unwrap();
+        assert_eq!(message, Message::Unknown(peer.as_header(), 42));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_encode_raw_message() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        test_kadkast_marshal(Message::Raw(
+            peer.as_header(),
+            MIN_RAW_MESSAGE_TYPE,
+            vec![1, 2, 3],
+        ));
+        test_kadkast_marshal(Message::Raw(peer.as_header(), u8::MAX, vec![]));
+
+        let mut bytes = vec![];
+        Message::Raw(peer.as_header(), MIN_RAW_MESSAGE_TYPE, vec![])
+            .marshal_binary(&mut bytes)
+            .unwrap();
+        let len = bytes.len();
+        bytes[len - 4..]
+            .copy_from_slice(&(MAX_RAW_MESSAGE_LEN as u32 + 1).to_le_bytes());
+        assert!(Message::unmarshal_binary(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_paginate_nodes() {
+        let peers = || {
+            (0..100)
+                .map(|i| {
+                    PeerNode::generate(&format!("192.168.1.{}:666", i))
+                        .as_peer_info()
+                })
+                .collect::<Vec<_>>()
+        };
+        let pages = NodePayload::paginate(peers());
+        assert!(pages.len() > 1);
+        for page in &pages {
+            let mut bytes = vec![];
+            page.marshal_binary(&mut bytes).unwrap();
+            assert!(bytes.len() <= 1024 + 2);
+        }
+        let paginated: Vec<_> =
+            pages.into_iter().flat_map(|page| page.peers).collect();
+        assert_eq!(paginated, peers());
+
+        let pages = NodePayload::paginate(vec![]);
+        assert_eq!(pages, vec![NodePayload { peers: vec![] }]);
+
+        // The bounded pages leave out the last peers
+        let peer_len = 4 + 2 + K_ID_LEN_BYTES + 1;
+        let pages = NodePayload::paginate_bounded(peers(), 10 * peer_len, 0);
+        assert_eq!(pages, vec![NodePayload::new(peers()[..10].to_vec())]);
+        let pages =
+            NodePayload::paginate_bounded(peers(), 10 * peer_len, peer_len);
+        assert_eq!(pages, vec![NodePayload::new(peers()[..9].to_vec())]);
+        let unbounded = NodePayload::paginate_bounded(peers(), usize::MAX, 50);
+        assert_eq!(unbounded, NodePayload::paginate(peers()));
+        let pages = NodePayload::paginate_bounded(peers(), 10, 50);
+        assert_eq!(pages, vec![NodePayload { peers: vec![] }]);
+    }
+
+    #[test]
+    fn test_proto_builders() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        let payload = BroadcastPayload::new(2, vec![1, 2, 3])
+            .with_topic(4)
+            .with_priority(5);
+        assert_eq!(
+            (payload.height(), payload.topic(), payload.priority()),
+            (2, 4, 5)
+        );
+        test_kadkast_marshal(Message::Broadcast(peer.as_header(), payload));
+
+        let address = "192.168.1.1:666".parse().unwrap();
+        let info = PeerEncodedInfo::from_address(address);
+        assert_eq!(info, PeerNode::generate("192.168.1.1:666").as_peer_info());
+        assert_eq!(info.to_socket_address(), address);
+        let payload = NodePayload::new(vec![info]);
+        test_kadkast_marshal(Message::Nodes(peer.as_header(), payload));
+    }
+
     #[test]
     fn test_encode_empty_nodes() {
         let peer = PeerNode::generate("192.168.0.1:666");
@@ -83,6 +412,11 @@ mod tests {
             peer.as_header(),
             BroadcastPayload {
                 height: 10,
+                topic: 1,
+                priority: 0,
+                origin: None,
+                message_id: None,
+                batch: false,
                 gossip_frame: vec![3, 5, 6, 7],
             },
         );
@@ -90,6 +424,182 @@ mod tests {
         assert_eq!(1, 1);
     }
 
+    #[test]
+    fn test_encode_batch() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        let messages = [vec![1; 10], vec![], vec![2; 20], vec![3; 100]];
+        let frames = BroadcastPayload::batch_frames(&messages, 50);
+        assert_eq!(frames.len(), 2);
+
+        let payloads: Vec<_> = frames
+            .into_iter()
+            .map(|frame| BroadcastPayload {
+                batch: true,
+                ..BroadcastPayload::new(1, frame)
+            })
+            .collect();
+        assert!(payloads[0].is_batch());
+        assert_eq!(
+            payloads[0].messages(),
+            Some(vec![&messages[0][..], &messages[2][..]])
+        );
+        // Longer messages get a frame on their own
+        assert_eq!(payloads[1].messages(), Some(vec![&messages[3][..]]));
+        test_kadkast_marshal(Message::Broadcast(
+            peer.as_header(),
+            payloads[0].clone(),
+        ));
+
+        let plain = BroadcastPayload::new(1, vec![4; 10]);
+        assert_eq!(plain.messages(), Some(vec![&[4; 10][..]]));
+
+        let mut truncated = payloads[0].clone();
+        truncated.gossip_frame.pop();
+        assert_eq!(truncated.messages(), None);
+        truncated.gossip_frame.truncate(2);
+        assert_eq!(truncated.messages(), None);
+    }
+
+    #[test]
+    fn test_encode_signed_broadcast() {
+        let peer = PeerNode::generate("192.168.0.1:666");
+        let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        let keypair = Keypair { secret, public };
+        let mut payload =
+            BroadcastPayload::new(10, vec![3, 5, 6, 7]).with_topic(1);
+        let origin_id = *peer.id().as_binary();
+        payload.origin =
+            Some(OriginSignature::sign(&keypair, origin_id, &payload));
+        assert!(payload.verify_origin());
+        assert!(!payload.origin.unwrap().binds_identity());
+
+        // Relays can rewrite the height only
+        let mut relayed = payload.clone();
+        relayed.height = 3;
+        assert!(relayed.verify_origin());
+        let mut tampered = payload.clone();
+        tampered.gossip_frame[0] = 4;
+        assert!(!tampered.verify_origin());
+        let tampered = payload.clone().with_priority(9);
+        assert!(!tampered.verify_origin());
+        let tampered = payload.clone().with_message_id([1; 32]);
+        assert!(!tampered.verify_origin());
+        let mut tampered = payload.clone();
+        tampered.batch = true;
+        assert!(!tampered.verify_origin());
+        let mut tampered = payload.clone();
+        tampered.origin.as_mut().unwrap().origin_id[0] ^= 1;
+        assert!(!tampered.verify_origin());
+
+        let mut bound = payload.clone();
+        bound.origin = Some(OriginSignature::sign(
+            &keypair,
+            derive_key(&keypair.public.to_bytes()),
+            &bound,
+        ));
+        assert!(bound.verify_origin());
+        assert!(bound.origin.unwrap().binds_identity());
+        test_kadkast_marshal(Message::Broadcast(
+            peer.as_header(),
+            payload.clone(),
+        ));
+        let with_id = payload.with_message_id([9; 32]);
+        assert_eq!(with_id.message_id(), Some(&[9; 32]));
+        test_kadkast_marshal(Message::Broadcast(peer.as_header(), with_id));
+        let unsigned =
+            BroadcastPayload::new(1, vec![1]).with_message_id([9; 32]);
+        test_kadkast_marshal(Message::Broadcast(peer.as_header(), unsigned));
+    }
+
+    #[test]
+    fn test_header_freshness() {
+        let window = Duration::from_secs(30);
+        let skew = Duration::from_secs(5);
+        let now = 1_000_000;
+        let mut header = PeerNode::generate("192.168.0.1:666").as_header();
+        header.timestamp = Some(now - 10);
+        assert!(header.is_fresh(now, window, skew));
+        header.timestamp = Some(now - 35);
+        assert!(header.is_fresh(now, window, skew));
+        header.timestamp = Some(now - 36);
+        assert!(!header.is_fresh(now, window, skew));
+        header.timestamp = Some(now + 5);
+        assert!(header.is_fresh(now, window, skew));
+        header.timestamp = Some(now + 6);
+        assert!(!header.is_fresh(now, window, skew));
+        test_kadkast_marshal(Message::Ping(header));
+        // The timestamp is only sent by the peers checking it
+        header.timestamp = None;
+        assert!(!header.is_fresh(now, window, skew));
+        test_kadkast_marshal(Message::Ping(header));
+    }
+
+    #[test]
+    fn test_decode_bounds() {
+        let decode_error = |bytes: &[u8]| {
+            let e = Message::unmarshal_binary(&mut &bytes[..]).unwrap_err();
+            e.get_ref()
+                .and_then(|e| e.downcast_ref::<DecodeError>())
+                .map(|e| e.to_string())
+        };
+        let peer = PeerNode::generate("192.168.0.1:666");
+        let mut ping = vec![];
+        Message::Ping(peer.as_header())
+            .marshal_binary(&mut ping)
+            .unwrap();
+
+        let mut nodes = ping.clone();
+        nodes[0] = 3;
+        nodes.extend_from_slice(&[0xFF, 0xFF]);
+        assert_eq!(
+            decode_error(&nodes),
+            Some(DecodeError::TooManyPeers(0xFFFF).to_string())
+        );
+
+        let mut broadcast = ping.clone();
+        broadcast[0] = 10;
+        broadcast.extend_from_slice(&[0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(
+            decode_error(&broadcast),
+            Some(DecodeError::GossipFrameTooLong(0xFFFF_FFFF).to_string())
+        );
+
+        let mut request = ping.clone();
+        request[0] = 7;
+        request.extend_from_slice(&[0; 8]);
+        request.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(
+            decode_error(&request),
+            Some(DecodeError::RequestTooLong(0xFFFF_FFFF).to_string())
+        );
+
+        let mut zero_port = ping.clone();
+        // Message type, id and nonce precede the port
+        let port = 1 + K_ID_LEN_BYTES + K_NONCE_LEN;
+        zero_port[port..port + 2].copy_from_slice(&[0, 0]);
+        assert_eq!(
+            decode_error(&zero_port),
+            Some(DecodeError::InvalidPort.to_string())
+        );
+
+        let mut wrong_id = PeerNode::generate("192.168.1.1:666").as_peer_info();
+        wrong_id.port = 667;
+        let mut nodes = vec![];
+        Message::Nodes(
+            peer.as_header(),
+            NodePayload {
+                peers: vec![wrong_id],
+            },
+        )
+        .marshal_binary(&mut nodes)
+        .unwrap();
+        assert_eq!(
+            decode_error(&nodes),
+            Some(DecodeError::InvalidPeerId.to_string())
+        );
+    }
+
     fn test_kadkast_marshal(messge: Message) {
         println!("orig: {:?}", messge);
         let mut c = Cursor::new(Vec::new());
@@ -0,0 +1,65 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+/// Malformed or out of bounds field found while unmarshalling a message.
+///
+/// It's carried by the [io::Error] returned by
+/// [Marshallable::unmarshal_binary](super::Marshallable::unmarshal_binary)
+#[derive(Debug, PartialEq)]
+pub(crate) enum DecodeError {
+    InvalidNonce,
+    InvalidPort,
+    InvalidPeerId,
+    InvalidFallback,
+    TooManyPeers(usize),
+    GossipFrameTooLong(usize),
+    RequestTooLong(usize),
+    RawMessageTooLong(usize),
+    TooManyUids(usize),
+    MessageTooLong(u8, usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidNonce => write!(f, "Invalid Nonce"),
+            DecodeError::InvalidPort => write!(f, "Invalid port"),
+            DecodeError::InvalidPeerId => write!(f, "Invalid peer id"),
+            DecodeError::InvalidFallback => {
+                write!(f, "Invalid fallback address")
+            }
+            DecodeError::TooManyPeers(len) => {
+                write!(f, "Too many peers: {}", len)
+            }
+            DecodeError::GossipFrameTooLong(len) => {
+                write!(f, "Gossip frame too long: {}", len)
+            }
+            DecodeError::RequestTooLong(len) => {
+                write!(f, "Request too long: {}", len)
+            }
+            DecodeError::RawMessageTooLong(len) => {
+                write!(f, "Raw message too long: {}", len)
+            }
+            DecodeError::TooManyUids(len) => {
+                write!(f, "Too many message uids: {}", len)
+            }
+            DecodeError::MessageTooLong(message_type, len) => {
+                write!(f, "Message {} too long: {}", message_type, len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for io::Error {
+    fn from(e: DecodeError) -> Self {
+        io::Error::new(ErrorKind::InvalidData, e)
+    }
+}
@@ -5,15 +5,194 @@
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
 use std::io::{self, Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::{kbucket::BinaryID, K_ID_LEN_BYTES, K_NONCE_LEN};
+use crate::{kbucket::BinaryID, K_DIFF_MIN_BIT, K_ID_LEN_BYTES, K_NONCE_LEN};
+
+use super::payload::{is_valid_fallback, IpInfo};
+use super::{DecodeError, Marshallable};
+
+// Flag of the first reserved byte, set when the header carries the
+// addresses of a dual-homed sender
+const DUAL_ADDRESS_FLAG: u8 = 0x01;
+
+// Flag of the first reserved byte, set on the chunks of a broadcast whose
+// receivers are asked to acknowledge it once decoded
+const ACK_REQUEST_FLAG: u8 = 0x02;
+
+// Flag of the first reserved byte, set by the peers in observer mode, which
+// are never handed out as candidates in the `Nodes` replies
+const OBSERVER_FLAG: u8 = 0x04;
+
+// Flag of the first reserved byte, set by the peers whose id is supplied by
+// the application instead of being derived from their address
+const CUSTOM_ID_FLAG: u8 = 0x08;
+
+// Flag of the first reserved byte, set when the header carries the Ed25519
+// public key the id of the sender is derived from
+const IDENTITY_FLAG: u8 = 0x10;
+
+// Flag of the first reserved byte, set when the header carries the id
+// difficulty required by the sender, if not the default one
+const ID_DIFFICULTY_FLAG: u8 = 0x20;
+
+// Flag of the first reserved byte, set when the header carries the nonce of
+// a `Ping` or a `FindNodes` request, or the one echoed by its reply
+const REQUEST_NONCE_FLAG: u8 = 0x40;
+
+// Flag of the first reserved byte, set when the header carries the time
+// it's been sent at, see [Header::is_fresh]
+const TIMESTAMP_FLAG: u8 = 0x80;
+
+// The second reserved byte carries the height a broadcast has been
+// originated with, plus one. Zero stands for unknown (eg: older peers)
+const ORIGIN_HEIGHT_BYTE: usize = 1;
+
+/// Max encoded length of a header: id, nonce, port and reserved bytes,
+/// followed by the optional timestamp, addresses of a dual-homed sender,
+/// identity, id difficulty and request nonce
+pub(crate) const MAX_HEADER_LEN: usize =
+    K_ID_LEN_BYTES + K_NONCE_LEN + 2 + 2 + 8 + (17 + 17 + 2) + 32 + 1 + 8;
 
-use super::Marshallable;
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Header {
     pub(crate) binary_id: BinaryID,
     pub(crate) sender_port: u16,
     pub(crate) reserved: [u8; 2],
+    /// Sender time (seconds since UNIX epoch), set just before sending by
+    /// the peers with a
+    /// [Config::replay_window](crate::config::Config::replay_window)
+    pub(crate) timestamp: Option<u64>,
+    /// Addresses of a dual-homed sender, whose messages may come from either
+    /// of them
+    pub(crate) dual: Option<DualAddress>,
+    /// Ed25519 public key of the sender, whose hash is its id
+    pub(crate) identity: Option<IdentityKey>,
+    /// Zero bits of the proof of work required to the node ids by the
+    /// sender
+    pub(crate) id_difficulty: u8,
+    /// Random nonce of a request, echoed by its replies
+    pub(crate) request_nonce: Option<u64>,
+}
+
+/// Ed25519 public key a node id is derived from, see
+/// [Config::identity_key](crate::config::Config::identity_key)
+pub type IdentityKey = [u8; 32];
+
+/// Addresses of a dual-homed node.
+///
+/// The node id is bound to the primary ip (and the sender port), the
+/// fallback address belongs to the other IP family
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct DualAddress {
+    pub(crate) primary: IpAddr,
+    pub(crate) fallback: SocketAddr,
+}
+
+impl Header {
+    pub(crate) fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    }
+
+    /// Identifier of the sender, along with the nonce proving its work
+    pub fn binary_id(&self) -> &BinaryID {
+        &self.binary_id
+    }
+
+    /// Port the sender is reachable at
+    pub fn sender_port(&self) -> u16 {
+        self.sender_port
+    }
+
+    /// Ask the receivers of a broadcast to acknowledge it once decoded
+    pub(crate) fn with_ack_request(mut self) -> Self {
+        self.reserved[0] |= ACK_REQUEST_FLAG;
+        self
+    }
+
+    pub(crate) fn requests_ack(&self) -> bool {
+        self.reserved[0] & ACK_REQUEST_FLAG != 0
+    }
+
+    /// Flag the sender as a peer in observer mode
+    pub(crate) fn with_observer(mut self) -> Self {
+        self.reserved[0] |= OBSERVER_FLAG;
+        self
+    }
+
+    /// Whether the sender is a peer in observer mode, see
+    /// [Config::observer](crate::config::Config::observer)
+    pub fn is_observer(&self) -> bool {
+        self.reserved[0] & OBSERVER_FLAG != 0
+    }
+
+    pub(crate) fn with_custom_id(mut self) -> Self {
+        self.reserved[0] |= CUSTOM_ID_FLAG;
+        self
+    }
+
+    /// Whether the id of the sender is not bound to its address, see
+    /// [Config::node_key](crate::config::Config::node_key)
+    pub fn has_custom_id(&self) -> bool {
+        self.reserved[0] & CUSTOM_ID_FLAG != 0
+    }
+
+    /// Ed25519 public key the id of the sender is derived from. The sender
+    /// proves to own it by answering a challenge before being inserted in
+    /// the routing table
+    pub fn identity(&self) -> Option<&IdentityKey> {
+        self.identity.as_ref()
+    }
+
+    /// Amount of zero bits required by the sender to the proof of work of
+    /// the node ids, see
+    /// [BucketConfig::id_difficulty](crate::config::BucketConfig::id_difficulty)
+    pub fn id_difficulty(&self) -> usize {
+        self.id_difficulty as usize
+    }
+
+    /// Echo the nonce of the request being answered
+    pub(crate) fn echoing(mut self, request: &Header) -> Self {
+        self.request_nonce = request.request_nonce;
+        self
+    }
+
+    /// Record the height a broadcast has been originated with, carried
+    /// along by the relays
+    pub(crate) fn with_origin_height(mut self, height: Option<u8>) -> Self {
+        self.reserved[ORIGIN_HEIGHT_BYTE] =
+            height.map_or(0, |height| height.saturating_add(1));
+        self
+    }
+
+    pub(crate) fn origin_height(&self) -> Option<u8> {
+        self.reserved[ORIGIN_HEIGHT_BYTE].checked_sub(1)
+    }
+
+    /// Check if the header has been sent within the `window`. Headers without
+    /// a timestamp can't tell, so they are never fresh.
+    ///
+    /// Timestamps in the future are accepted up to the `skew` tolerance, which
+    /// is also added to the `window`
+    pub(crate) fn is_fresh(
+        &self,
+        now: u64,
+        window: Duration,
+        skew: Duration,
+    ) -> bool {
+        let timestamp = match self.timestamp {
+            Some(timestamp) => timestamp,
+            None => return false,
+        };
+        let skew = skew.as_secs();
+        let max_age = window.as_secs().saturating_add(skew);
+        timestamp <= now.saturating_add(skew)
+            && now.saturating_sub(timestamp) <= max_age
+    }
 }
 
 impl Marshallable for Header {
@@ -24,7 +203,41 @@ impl Marshallable for Header {
         writer.write_all(self.binary_id.as_binary())?;
         writer.write_all(self.binary_id.nonce())?;
         writer.write_all(&self.sender_port.to_le_bytes())?;
-        writer.write_all(&self.reserved)?;
+        let mut reserved = self.reserved;
+        if self.dual.is_some() {
+            reserved[0] |= DUAL_ADDRESS_FLAG;
+        }
+        if self.identity.is_some() {
+            reserved[0] |= IDENTITY_FLAG;
+        }
+        let custom_difficulty = self.id_difficulty() != K_DIFF_MIN_BIT;
+        if custom_difficulty {
+            reserved[0] |= ID_DIFFICULTY_FLAG;
+        }
+        if self.request_nonce.is_some() {
+            reserved[0] |= REQUEST_NONCE_FLAG;
+        }
+        if self.timestamp.is_some() {
+            reserved[0] |= TIMESTAMP_FLAG;
+        }
+        writer.write_all(&reserved)?;
+        if let Some(timestamp) = self.timestamp {
+            writer.write_all(&timestamp.to_le_bytes())?;
+        }
+        if let Some(dual) = &self.dual {
+            IpInfo::from(dual.primary).marshal_binary(writer)?;
+            IpInfo::from(dual.fallback.ip()).marshal_binary(writer)?;
+            writer.write_all(&dual.fallback.port().to_le_bytes())?;
+        }
+        if let Some(identity) = &self.identity {
+            writer.write_all(identity)?;
+        }
+        if custom_difficulty {
+            writer.write_all(&[self.id_difficulty])?;
+        }
+        if let Some(nonce) = self.request_nonce {
+            writer.write_all(&nonce.to_le_bytes())?;
+        }
         Ok(())
     }
 
@@ -38,18 +251,80 @@ impl Marshallable for Header {
         reader.read_exact(&mut nonce)?;
         let binary_id = BinaryID::from_nonce(id, nonce);
         if !binary_id.verify_nonce() {
-            return Err(Error::new(ErrorKind::Other, "Invalid Nonce"));
+            return Err(DecodeError::InvalidNonce.into());
         }
 
         let mut port_buffer = [0; 2];
         reader.read_exact(&mut port_buffer)?;
         let port = u16::from_le_bytes(port_buffer);
+        if port == 0 {
+            return Err(DecodeError::InvalidPort.into());
+        }
         let mut reserved = [0; 2];
         reader.read_exact(&mut reserved)?;
+        let timestamp = match reserved[0] & TIMESTAMP_FLAG {
+            0 => None,
+            _ => {
+                reserved[0] &= !TIMESTAMP_FLAG;
+                let mut timestamp = [0; 8];
+                reader.read_exact(&mut timestamp)?;
+                Some(u64::from_le_bytes(timestamp))
+            }
+        };
+        let dual = match reserved[0] & DUAL_ADDRESS_FLAG {
+            0 => None,
+            _ => {
+                reserved[0] &= !DUAL_ADDRESS_FLAG;
+                let primary = IpInfo::unmarshal_binary(reader)?.to_ip();
+                let fallback_ip = IpInfo::unmarshal_binary(reader)?.to_ip();
+                let mut fallback_port = [0; 2];
+                reader.read_exact(&mut fallback_port)?;
+                let fallback = SocketAddr::new(
+                    fallback_ip,
+                    u16::from_le_bytes(fallback_port),
+                );
+                if !is_valid_fallback(&primary, &fallback) {
+                    return Err(DecodeError::InvalidFallback.into());
+                }
+                Some(DualAddress { primary, fallback })
+            }
+        };
+        let identity = match reserved[0] & IDENTITY_FLAG {
+            0 => None,
+            _ => {
+                reserved[0] &= !IDENTITY_FLAG;
+                let mut identity = [0; 32];
+                reader.read_exact(&mut identity)?;
+                Some(identity)
+            }
+        };
+        let id_difficulty = match reserved[0] & ID_DIFFICULTY_FLAG {
+            0 => K_DIFF_MIN_BIT as u8,
+            _ => {
+                reserved[0] &= !ID_DIFFICULTY_FLAG;
+                let mut difficulty = [0; 1];
+                reader.read_exact(&mut difficulty)?;
+                difficulty[0]
+            }
+        };
+        let request_nonce = match reserved[0] & REQUEST_NONCE_FLAG {
+            0 => None,
+            _ => {
+                reserved[0] &= !REQUEST_NONCE_FLAG;
+                let mut nonce = [0; 8];
+                reader.read_exact(&mut nonce)?;
+                Some(u64::from_le_bytes(nonce))
+            }
+        };
         Ok(Header {
             binary_id,
             sender_port: port,
             reserved,
+            timestamp,
+            dual,
+            identity,
+            id_difficulty,
+            request_nonce,
         })
     }
 }
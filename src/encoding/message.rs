@@ -4,11 +4,20 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use std::io::{self, Error, ErrorKind, Read, Write};
+use std::io::{self, Read, Write};
 
+use bytes::{BufMut, BytesMut};
+
+use super::error::DecodeError;
+use super::header::MAX_HEADER_LEN;
+use super::payload::MAX_NODES_PAYLOAD_SIZE;
 use crate::kbucket::BinaryKey;
+use crate::rpc::{RequestId, MAX_REQUEST_LEN};
+use crate::K_ID_LEN_BYTES;
 
-pub(crate) use super::payload::{BroadcastPayload, NodePayload};
+pub(crate) use super::header::DualAddress;
+pub use super::header::IdentityKey;
+pub use super::payload::{BroadcastPayload, NodePayload};
 pub use super::{header::Header, Marshallable};
 
 // PingMsg wire Ping message id.
@@ -23,16 +32,110 @@ const ID_MSG_FIND_NODES: u8 = 2;
 // NodesMsg wire Nodes message id.
 const ID_MSG_NODES: u8 = 3;
 
+// TransmissionInfoRequestMsg wire TransmissionInfoRequest message id.
+const ID_MSG_TRANSMISSION_INFO_REQUEST: u8 = 4;
+
+// TransmissionInfoResponseMsg wire TransmissionInfoResponse message id.
+const ID_MSG_TRANSMISSION_INFO_RESPONSE: u8 = 5;
+
+// GoodbyeMsg wire Goodbye message id.
+const ID_MSG_GOODBYE: u8 = 6;
+
+// RequestMsg wire Request message id.
+const ID_MSG_REQUEST: u8 = 7;
+
+// ResponseMsg wire Response message id.
+const ID_MSG_RESPONSE: u8 = 8;
+
+// AckMsg wire Ack message id.
+const ID_MSG_ACK: u8 = 9;
+
 // BroadcastMsg Message propagation type.
 const ID_MSG_BROADCAST: u8 = 10;
 
+// IHaveMsg wire IHave message id.
+const ID_MSG_IHAVE: u8 = 11;
+
+// IWantMsg wire IWant message id.
+const ID_MSG_IWANT: u8 = 12;
+
+// ChallengeMsg wire Challenge message id.
+const ID_MSG_CHALLENGE: u8 = 13;
+
+// ChallengeResponseMsg wire ChallengeResponse message id.
+const ID_MSG_CHALLENGE_RESPONSE: u8 = 14;
+
+/// Lowest type id reserved to the messages of protocol extensions, which are
+/// carried by [Message::Raw]. Every id from this one up to `u8::MAX` can be
+/// used
+pub const MIN_RAW_MESSAGE_TYPE: u8 = 0x80;
+
+/// Max length of the body of a [Message::Raw], which must fit a single
+/// datagram
+pub const MAX_RAW_MESSAGE_LEN: usize = 60_000;
+
+/// Max number of message uids carried by an `IHave` or an `IWant` message,
+/// keeping the datagram below the minimum IPv6 MTU
+pub(crate) const MAX_DIGEST_UIDS: usize = 32;
+
+/// Max length of a broadcast chunk, whose size depends on the mtu of the
+/// encoder: the largest UDP payload
+const MAX_BROADCAST_LEN: usize = 65_507;
+
+/// Identifier of a broadcast message, shared by all its chunks
+pub type MessageUid = [u8; 32];
+
+/// Serialized RaptorQ object transmission information
+pub type TransmissionInfo = [u8; 12];
+
+/// Random token a peer with a key identity has to sign
+pub type ChallengeToken = [u8; 32];
+
+/// Ed25519 signature answering a [ChallengeToken]
+pub type IdentitySignature = [u8; 64];
+
+/// Message exchanged by the peers
 #[derive(Debug, PartialEq)]
-pub(crate) enum Message {
+#[non_exhaustive]
+pub enum Message {
+    /// Liveness check, answered with a `Pong`
     Ping(Header),
     Pong(Header),
+    /// Lookup of the peers closest to a key, answered with `Nodes`
     FindNodes(Header, BinaryKey),
     Nodes(Header, NodePayload), //should we pass node[] as ref?
     Broadcast(Header, BroadcastPayload),
+    /// Request of the transmission info of a broadcast message
+    TransmissionInfoRequest(Header, MessageUid),
+    TransmissionInfoResponse(Header, MessageUid, TransmissionInfo),
+    /// Sent by a peer shutting down, which is removed from the routing table
+    /// of the receivers
+    Goodbye(Header),
+    /// Application request sent with [Peer::request](crate::Peer::request),
+    /// answered with a `Response` carrying the same id
+    Request(Header, RequestId, Vec<u8>),
+    Response(Header, RequestId, Vec<u8>),
+    /// Sent by the delegates of a broadcast requesting it, once decoded
+    Ack(Header, MessageUid),
+    /// Digest of the recently seen broadcast messages, answered with an
+    /// `IWant` listing the missing ones, see
+    /// [Config::pull_gossip](crate::config::Config::pull_gossip)
+    IHave(Header, Vec<MessageUid>),
+    IWant(Header, Vec<MessageUid>),
+    /// Sent to a peer with a key identity before inserting it in the routing
+    /// table, answered with a `ChallengeResponse` signing the token with the
+    /// identity key
+    Challenge(Header, ChallengeToken),
+    ChallengeResponse(Header, ChallengeToken, IdentitySignature),
+    /// Message with a type id unknown to this version, whose body has been
+    /// skipped
+    Unknown(Header, u8),
+    /// Message of a protocol extension, with a type id not lower than
+    /// [MIN_RAW_MESSAGE_TYPE] and an opaque body. It's sent with
+    /// [Peer::send_message](crate::Peer::send_message) and it's handed to the
+    /// [MessageMiddleware](crate::MessageMiddleware) of the receiver, which
+    /// otherwise discards it
+    Raw(Header, u8, Vec<u8>),
 }
 
 impl Message {
@@ -40,19 +143,70 @@ impl Message {
         match self {
             Message::Ping(_) => ID_MSG_PING,
             Message::Pong(_) => ID_MSG_PONG,
+            Message::Goodbye(_) => ID_MSG_GOODBYE,
+            Message::Request(..) => ID_MSG_REQUEST,
+            Message::Response(..) => ID_MSG_RESPONSE,
+            Message::Ack(..) => ID_MSG_ACK,
+            Message::IHave(..) => ID_MSG_IHAVE,
+            Message::IWant(..) => ID_MSG_IWANT,
+            Message::Challenge(..) => ID_MSG_CHALLENGE,
+            Message::ChallengeResponse(..) => ID_MSG_CHALLENGE_RESPONSE,
             Message::FindNodes(_, _) => ID_MSG_FIND_NODES,
             Message::Nodes(_, _) => ID_MSG_NODES,
             Message::Broadcast(_, _) => ID_MSG_BROADCAST,
+            Message::TransmissionInfoRequest(_, _) => {
+                ID_MSG_TRANSMISSION_INFO_REQUEST
+            }
+            Message::TransmissionInfoResponse(_, _, _) => {
+                ID_MSG_TRANSMISSION_INFO_RESPONSE
+            }
+            Message::Unknown(_, message_type)
+            | Message::Raw(_, message_type, _) => *message_type,
+        }
+    }
+
+    /// Header of the sender of the message
+    pub fn header(&self) -> &Header {
+        match self {
+            Message::Ping(header) => header,
+            Message::Pong(header) => header,
+            Message::Goodbye(header) => header,
+            Message::Request(header, ..) => header,
+            Message::Response(header, ..) => header,
+            Message::Ack(header, _) => header,
+            Message::IHave(header, _) => header,
+            Message::IWant(header, _) => header,
+            Message::Challenge(header, _) => header,
+            Message::ChallengeResponse(header, ..) => header,
+            Message::FindNodes(header, _) => header,
+            Message::Nodes(header, _) => header,
+            Message::Broadcast(header, _) => header,
+            Message::TransmissionInfoRequest(header, _) => header,
+            Message::TransmissionInfoResponse(header, _, _) => header,
+            Message::Unknown(header, _) => header,
+            Message::Raw(header, ..) => header,
         }
     }
 
-    pub(crate) fn header(&self) -> &Header {
+    pub(crate) fn header_mut(&mut self) -> &mut Header {
         match self {
             Message::Ping(header) => header,
             Message::Pong(header) => header,
+            Message::Goodbye(header) => header,
+            Message::Request(header, ..) => header,
+            Message::Response(header, ..) => header,
+            Message::Ack(header, _) => header,
+            Message::IHave(header, _) => header,
+            Message::IWant(header, _) => header,
+            Message::Challenge(header, _) => header,
+            Message::ChallengeResponse(header, ..) => header,
             Message::FindNodes(header, _) => header,
             Message::Nodes(header, _) => header,
             Message::Broadcast(header, _) => header,
+            Message::TransmissionInfoRequest(header, _) => header,
+            Message::TransmissionInfoResponse(header, _, _) => header,
+            Message::Unknown(header, _) => header,
+            Message::Raw(header, ..) => header,
         }
     }
 
@@ -61,15 +215,108 @@ impl Message {
         self.marshal_binary(&mut bytes).unwrap();
         bytes
     }
+
+    /// Serialize the message at the end of `buf`, reusing its capacity
+    pub(crate) fn marshal_into(&self, buf: &mut BytesMut) -> io::Result<()> {
+        self.marshal_binary(&mut buf.writer())
+    }
+
+    /// Serialize a broadcast message using the concatenation of `frame` as
+    /// gossip frame, in place of the one contained in `payload`.
+    ///
+    /// This avoids to build the gossip frame of every chunk of a message
+    pub(crate) fn marshal_broadcast<W: Write>(
+        header: &Header,
+        payload: &BroadcastPayload,
+        frame: &[&[u8]],
+        writer: &mut W,
+    ) -> io::Result<()> {
+        writer.write_all(&[ID_MSG_BROADCAST])?;
+        header.marshal_binary(writer)?;
+        payload.marshal_with_frame(frame, writer)?;
+        writer.flush()
+    }
+
+    /// Max encoded length of a message of the given type. The control
+    /// messages never come close to the MTU, the ones of an unknown type are
+    /// not bounded
+    pub(crate) fn max_encoded_len(message_type: u8) -> Option<usize> {
+        let body = match message_type {
+            ID_MSG_PING | ID_MSG_PONG | ID_MSG_GOODBYE => 0,
+            ID_MSG_FIND_NODES => K_ID_LEN_BYTES,
+            ID_MSG_NODES => 2 + MAX_NODES_PAYLOAD_SIZE,
+            ID_MSG_BROADCAST => return Some(MAX_BROADCAST_LEN),
+            ID_MSG_ACK
+            | ID_MSG_CHALLENGE
+            | ID_MSG_TRANSMISSION_INFO_REQUEST => 32,
+            ID_MSG_TRANSMISSION_INFO_RESPONSE => 32 + 12,
+            ID_MSG_CHALLENGE_RESPONSE => 32 + 64,
+            ID_MSG_IHAVE | ID_MSG_IWANT => 1 + 32 * MAX_DIGEST_UIDS,
+            ID_MSG_REQUEST | ID_MSG_RESPONSE => 8 + 4 + MAX_REQUEST_LEN,
+            raw if raw >= MIN_RAW_MESSAGE_TYPE => 4 + MAX_RAW_MESSAGE_LEN,
+            _ => return None,
+        };
+        Some(1 + MAX_HEADER_LEN + body)
+    }
+
+    /// Reject a datagram longer than the max length of the message it
+    /// carries before unmarshalling it, bounding the cost of the outliers.
+    /// `trailer` is the length of the data following the message, such as
+    /// its authentication tag
+    pub(crate) fn check_len(datagram: &[u8], trailer: usize) -> io::Result<()> {
+        let message_type = match datagram.first() {
+            Some(message_type) => *message_type,
+            None => return Ok(()),
+        };
+        match Message::max_encoded_len(message_type) {
+            Some(max) if datagram.len() > max + trailer => {
+                Err(DecodeError::MessageTooLong(message_type, datagram.len())
+                    .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Read the id and the body of a request or a response
+    fn unmarshal_request<R: Read>(
+        reader: &mut R,
+    ) -> io::Result<(RequestId, Vec<u8>)> {
+        let mut id = [0; 8];
+        reader.read_exact(&mut id)?;
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_REQUEST_LEN {
+            return Err(DecodeError::RequestTooLong(len).into());
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body)?;
+        Ok((RequestId::from_le_bytes(id), body))
+    }
+
+    // Read the uids of an `IHave` or an `IWant` message
+    fn unmarshal_uids<R: Read>(reader: &mut R) -> io::Result<Vec<MessageUid>> {
+        let mut len = [0; 1];
+        reader.read_exact(&mut len)?;
+        let len = len[0] as usize;
+        if len > MAX_DIGEST_UIDS {
+            return Err(DecodeError::TooManyUids(len).into());
+        }
+        let mut uids = vec![[0; 32]; len];
+        for uid in uids.iter_mut() {
+            reader.read_exact(uid)?;
+        }
+        Ok(uids)
+    }
 }
 
 impl Marshallable for Message {
     fn marshal_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         writer.write_all(&[self.type_byte()])?;
         match self {
-            Message::Ping(header) | Message::Pong(header) => {
-                header.marshal_binary(writer)?
-            }
+            Message::Ping(header)
+            | Message::Pong(header)
+            | Message::Goodbye(header) => header.marshal_binary(writer)?,
             Message::FindNodes(header, target) => {
                 header.marshal_binary(writer)?;
                 target.marshal_binary(writer)?;
@@ -82,6 +329,42 @@ impl Marshallable for Message {
                 header.marshal_binary(writer)?;
                 broadcast_payload.marshal_binary(writer)?;
             }
+            Message::TransmissionInfoRequest(header, uid)
+            | Message::Ack(header, uid)
+            | Message::Challenge(header, uid) => {
+                header.marshal_binary(writer)?;
+                writer.write_all(uid)?;
+            }
+            Message::IHave(header, uids) | Message::IWant(header, uids) => {
+                header.marshal_binary(writer)?;
+                writer.write_all(&[uids.len() as u8])?;
+                for uid in uids {
+                    writer.write_all(uid)?;
+                }
+            }
+            Message::TransmissionInfoResponse(header, uid, info) => {
+                header.marshal_binary(writer)?;
+                writer.write_all(uid)?;
+                writer.write_all(info)?;
+            }
+            Message::ChallengeResponse(header, token, signature) => {
+                header.marshal_binary(writer)?;
+                writer.write_all(token)?;
+                writer.write_all(signature)?;
+            }
+            Message::Request(header, id, body)
+            | Message::Response(header, id, body) => {
+                header.marshal_binary(writer)?;
+                writer.write_all(&id.to_le_bytes())?;
+                writer.write_all(&(body.len() as u32).to_le_bytes())?;
+                writer.write_all(body)?;
+            }
+            Message::Unknown(header, _) => header.marshal_binary(writer)?,
+            Message::Raw(header, _, body) => {
+                header.marshal_binary(writer)?;
+                writer.write_all(&(body.len() as u32).to_le_bytes())?;
+                writer.write_all(body)?;
+            }
         };
         writer.flush()?;
         Ok(())
@@ -94,6 +377,40 @@ impl Marshallable for Message {
         match message_type[0] {
             ID_MSG_PING => Ok(Message::Ping(header)),
             ID_MSG_PONG => Ok(Message::Pong(header)),
+            ID_MSG_GOODBYE => Ok(Message::Goodbye(header)),
+            ID_MSG_REQUEST => {
+                let (id, body) = Message::unmarshal_request(reader)?;
+                Ok(Message::Request(header, id, body))
+            }
+            ID_MSG_RESPONSE => {
+                let (id, body) = Message::unmarshal_request(reader)?;
+                Ok(Message::Response(header, id, body))
+            }
+            ID_MSG_ACK => {
+                let mut uid = [0; 32];
+                reader.read_exact(&mut uid)?;
+                Ok(Message::Ack(header, uid))
+            }
+            ID_MSG_IHAVE => {
+                let uids = Message::unmarshal_uids(reader)?;
+                Ok(Message::IHave(header, uids))
+            }
+            ID_MSG_IWANT => {
+                let uids = Message::unmarshal_uids(reader)?;
+                Ok(Message::IWant(header, uids))
+            }
+            ID_MSG_CHALLENGE => {
+                let mut token = [0; 32];
+                reader.read_exact(&mut token)?;
+                Ok(Message::Challenge(header, token))
+            }
+            ID_MSG_CHALLENGE_RESPONSE => {
+                let mut token = [0; 32];
+                reader.read_exact(&mut token)?;
+                let mut signature = [0; 64];
+                reader.read_exact(&mut signature)?;
+                Ok(Message::ChallengeResponse(header, token, signature))
+            }
             ID_MSG_FIND_NODES => {
                 let target = BinaryKey::unmarshal_binary(reader)?;
                 Ok(Message::FindNodes(header, target))
@@ -106,10 +423,35 @@ impl Marshallable for Message {
                 let payload = BroadcastPayload::unmarshal_binary(reader)?;
                 Ok(Message::Broadcast(header, payload))
             }
-            unknown => Err(Error::new(
-                ErrorKind::Other,
-                format!("Invalid message type: '{}'", unknown),
-            )),
+            ID_MSG_TRANSMISSION_INFO_REQUEST => {
+                let mut uid = [0; 32];
+                reader.read_exact(&mut uid)?;
+                Ok(Message::TransmissionInfoRequest(header, uid))
+            }
+            ID_MSG_TRANSMISSION_INFO_RESPONSE => {
+                let mut uid = [0; 32];
+                reader.read_exact(&mut uid)?;
+                let mut info = [0; 12];
+                reader.read_exact(&mut info)?;
+                Ok(Message::TransmissionInfoResponse(header, uid, info))
+            }
+            raw if raw >= MIN_RAW_MESSAGE_TYPE => {
+                let mut len = [0; 4];
+                reader.read_exact(&mut len)?;
+                let len = u32::from_le_bytes(len) as usize;
+                if len > MAX_RAW_MESSAGE_LEN {
+                    return Err(DecodeError::RawMessageTooLong(len).into());
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body)?;
+                Ok(Message::Raw(header, raw, body))
+            }
+            // Every message is carried by its own datagram, so the body of
+            // an unknown message spans the rest of the reader
+            unknown => {
+                io::copy(reader, &mut io::sink())?;
+                Ok(Message::Unknown(header, unknown))
+            }
         }
     }
 }
@@ -6,7 +6,12 @@
 
 pub(super) mod broadcast;
 pub(super) mod nodes;
-pub(crate) use crate::encoding::payload::broadcast::BroadcastPayload;
-pub(crate) use crate::encoding::payload::nodes::NodePayload;
+pub use crate::encoding::payload::broadcast::BroadcastPayload;
+pub use crate::encoding::payload::nodes::NodePayload;
+pub(crate) use broadcast::OriginSignature;
+pub use broadcast::{
+    MessageId, Priority, Topic, DEFAULT_PRIORITY, DEFAULT_TOPIC,
+};
 pub use nodes::IpInfo;
-pub(crate) use nodes::PeerEncodedInfo;
+pub use nodes::PeerEncodedInfo;
+pub(crate) use nodes::{is_valid_fallback, MAX_NODES_PAYLOAD_SIZE};
@@ -4,33 +4,358 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
+use std::convert::{TryFrom, TryInto};
 use std::io::{self, Read, Write};
 
-use crate::encoding::Marshallable;
-#[derive(Debug, PartialEq)]
-pub(crate) struct BroadcastPayload {
+use blake2::{Blake2s, Digest};
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+
+use crate::encoding::{DecodeError, Marshallable};
+use crate::kbucket::{derive_key, BinaryKey};
+use crate::K_ID_LEN_BYTES;
+
+/// Identifier of the logical stream a broadcast message belongs to
+pub type Topic = u8;
+
+/// Topic assigned to messages broadcasted without an explicit one
+pub const DEFAULT_TOPIC: Topic = 0;
+
+/// Priority of a broadcast message. Higher values are sent and notified
+/// first
+pub type Priority = u8;
+
+/// Priority assigned to messages broadcasted without an explicit one
+pub const DEFAULT_PRIORITY: Priority = 0;
+
+// Max length of the gossip frame of a received chunk, which can't exceed the
+// max UDP datagram size
+const MAX_GOSSIP_FRAME_LEN: usize = 65_507;
+
+// Flags of the byte preceding the optional fields of the payload
+const SIGNED_FLAG: u8 = 0x01;
+const MESSAGE_ID_FLAG: u8 = 0x02;
+const BATCH_FLAG: u8 = 0x04;
+
+// Length of the optional fields, which every chunk of a message repeats
+const ORIGIN_LEN: usize = 32 + 64 + K_ID_LEN_BYTES;
+const MESSAGE_ID_LEN: usize = 32;
+
+// Length of the prefix of each message coalesced in a batch frame
+const BATCH_ITEM_LEN_PREFIX: usize = 4;
+
+/// Identifier of a broadcast message assigned by the application, see
+/// [BroadcastOptions::with_id](crate::BroadcastOptions::with_id)
+pub type MessageId = [u8; 32];
+
+/// Payload of a broadcast message
+#[derive(Debug, PartialEq, Clone)]
+pub struct BroadcastPayload {
     pub(crate) height: u8,
+    pub(crate) topic: Topic,
+    pub(crate) priority: Priority,
+    pub(crate) origin: Option<OriginSignature>,
+    pub(crate) message_id: Option<MessageId>,
+    pub(crate) batch: bool,
     pub(crate) gossip_frame: Vec<u8>,
 }
 
-impl Marshallable for BroadcastPayload {
-    fn marshal_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+/// Ed25519 signature of the message originator.
+///
+/// The signature covers the originator id and every part of a broadcast that
+/// relays are not allowed to modify: the topic, the priority, the message id,
+/// the batch flag and the gossip frame. The height and the header are
+/// rewritten at each hop, the origin height included since the originator
+/// picks it per bucket, so they are not covered.
+///
+/// The public key is bound to the originator id if it signs with its key
+/// identity, see [Config::identity_key](crate::config::Config::identity_key)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct OriginSignature {
+    pub(crate) public_key: [u8; 32],
+    pub(crate) signature: [u8; 64],
+    pub(crate) origin_id: BinaryKey,
+}
+
+impl OriginSignature {
+    fn digest(
+        public_key: &[u8; 32],
+        origin_id: &BinaryKey,
+        payload: &BroadcastPayload,
+    ) -> [u8; 32] {
+        let mut hasher = Blake2s::new();
+        hasher.update(public_key);
+        hasher.update(origin_id);
+        hasher.update([payload.topic, payload.priority, payload.batch as u8]);
+        match &payload.message_id {
+            Some(message_id) => {
+                hasher.update([1]);
+                hasher.update(message_id);
+            }
+            None => hasher.update([0]),
+        }
+        hasher.update(&payload.gossip_frame);
+        hasher.finalize().into()
+    }
+
+    /// Sign a message with the originator keypair
+    pub(crate) fn sign(
+        keypair: &Keypair,
+        origin_id: BinaryKey,
+        payload: &BroadcastPayload,
+    ) -> Self {
+        let public_key = keypair.public.to_bytes();
+        let digest = Self::digest(&public_key, &origin_id, payload);
+        OriginSignature {
+            public_key,
+            signature: keypair.sign(&digest).to_bytes(),
+            origin_id,
+        }
+    }
+
+    fn verify(&self, payload: &BroadcastPayload) -> bool {
+        let public_key = match PublicKey::from_bytes(&self.public_key) {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        };
+        let digest = Self::digest(&self.public_key, &self.origin_id, payload);
+        Signature::try_from(&self.signature[..])
+            .and_then(|signature| public_key.verify(&digest, &signature))
+            .is_ok()
+    }
+
+    /// Whether the originator id is the one derived from the public key,
+    /// which only the owner of the key identity can sign for
+    pub(crate) fn binds_identity(&self) -> bool {
+        self.origin_id == derive_key(&self.public_key)
+    }
+}
+
+impl BroadcastPayload {
+    /// Create an unsigned payload with the default topic and priority
+    pub fn new(height: u8, gossip_frame: Vec<u8>) -> Self {
+        BroadcastPayload {
+            height,
+            topic: DEFAULT_TOPIC,
+            priority: DEFAULT_PRIORITY,
+            origin: None,
+            message_id: None,
+            batch: false,
+            gossip_frame,
+        }
+    }
+
+    pub fn with_topic(mut self, topic: Topic) -> Self {
+        self.topic = topic;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Deduplicate the message by the given id instead of by its content
+    pub fn with_message_id(mut self, message_id: MessageId) -> Self {
+        self.message_id = Some(message_id);
+        self
+    }
+
+    pub fn height(&self) -> u8 {
+        self.height
+    }
+
+    pub fn topic(&self) -> Topic {
+        self.topic
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// The id assigned by the application which originated the message, if
+    /// any
+    pub fn message_id(&self) -> Option<&MessageId> {
+        self.message_id.as_ref()
+    }
+
+    pub fn gossip_frame(&self) -> &[u8] {
+        &self.gossip_frame
+    }
+
+    /// Mutable access to the gossip frame, eg: for a
+    /// [MessageMiddleware](crate::MessageMiddleware) stamping the messages.
+    ///
+    /// Modifying the frame of a signed payload invalidates its signature
+    pub fn gossip_frame_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.gossip_frame
+    }
+
+    /// Whether the gossip frame coalesces several messages, see
+    /// [Peer::broadcast_batch](crate::Peer::broadcast_batch)
+    pub fn is_batch(&self) -> bool {
+        self.batch
+    }
+
+    /// The messages carried by the payload: the ones coalesced in a batch,
+    /// or the gossip frame itself.
+    ///
+    /// Returns `None` if the batch is malformed
+    pub(crate) fn messages(&self) -> Option<Vec<&[u8]>> {
+        if !self.batch {
+            return Some(vec![&self.gossip_frame]);
+        }
+        let mut messages = vec![];
+        let mut frame = &self.gossip_frame[..];
+        while !frame.is_empty() {
+            if frame.len() < BATCH_ITEM_LEN_PREFIX {
+                return None;
+            }
+            let (len, rest) = frame.split_at(BATCH_ITEM_LEN_PREFIX);
+            let len = u32::from_le_bytes(len.try_into().ok()?) as usize;
+            if len == 0 || len > rest.len() {
+                return None;
+            }
+            let (message, rest) = rest.split_at(len);
+            messages.push(message);
+            frame = rest;
+        }
+        Some(messages)
+    }
+
+    /// Coalesce `messages` into batch frames of up to `max_len` bytes, each
+    /// message prefixed by its length. Longer messages get a frame on their
+    /// own, empty ones are skipped
+    pub(crate) fn batch_frames<M: AsRef<[u8]>>(
+        messages: impl IntoIterator<Item = M>,
+        max_len: usize,
+    ) -> Vec<Vec<u8>> {
+        let mut frames = vec![];
+        let mut frame = vec![];
+        for message in messages {
+            let message = message.as_ref();
+            if message.is_empty() {
+                continue;
+            }
+            let len = BATCH_ITEM_LEN_PREFIX + message.len();
+            if !frame.is_empty() && frame.len() + len > max_len {
+                frames.push(std::mem::take(&mut frame));
+            }
+            frame.extend_from_slice(&(message.len() as u32).to_le_bytes());
+            frame.extend_from_slice(message);
+        }
+        if !frame.is_empty() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    /// Check the originator signature.
+    ///
+    /// Returns `false` if the payload is not signed
+    pub(crate) fn verify_origin(&self) -> bool {
+        self.origin.is_some_and(|origin| origin.verify(self))
+    }
+}
+
+impl BroadcastPayload {
+    // Serialize the payload using the concatenation of `frame` as gossip
+    // frame
+    /// Length of the optional fields of the payload, which are repeated in
+    /// every chunk of the message
+    pub(crate) fn chunk_metadata_len(&self) -> usize {
+        let origin = self.origin.map_or(0, |_| ORIGIN_LEN);
+        let message_id = self.message_id.map_or(0, |_| MESSAGE_ID_LEN);
+        origin + message_id
+    }
+
+    pub(crate) fn marshal_with_frame<W: Write>(
+        &self,
+        frame: &[&[u8]],
+        writer: &mut W,
+    ) -> io::Result<()> {
         writer.write_all(&[self.height])?;
-        let len = self.gossip_frame.len() as u32;
+        writer.write_all(&[self.topic])?;
+        writer.write_all(&[self.priority])?;
+        let mut flags = 0;
+        if self.origin.is_some() {
+            flags |= SIGNED_FLAG;
+        }
+        if self.message_id.is_some() {
+            flags |= MESSAGE_ID_FLAG;
+        }
+        if self.batch {
+            flags |= BATCH_FLAG;
+        }
+        writer.write_all(&[flags])?;
+        if let Some(origin) = &self.origin {
+            writer.write_all(&origin.public_key)?;
+            writer.write_all(&origin.signature)?;
+            writer.write_all(&origin.origin_id)?;
+        }
+        if let Some(message_id) = &self.message_id {
+            writer.write_all(message_id)?;
+        }
+        let len = frame.iter().map(|part| part.len()).sum::<usize>() as u32;
         writer.write_all(&len.to_le_bytes())?;
-        writer.write_all(&self.gossip_frame)?;
+        for part in frame {
+            writer.write_all(part)?;
+        }
         Ok(())
     }
+}
+
+impl Marshallable for BroadcastPayload {
+    fn marshal_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.marshal_with_frame(&[&self.gossip_frame], writer)
+    }
     fn unmarshal_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
         let mut height_buf = [0; 1];
         reader.read_exact(&mut height_buf)?;
+        let mut topic_buf = [0; 1];
+        reader.read_exact(&mut topic_buf)?;
+        let mut priority_buf = [0; 1];
+        reader.read_exact(&mut priority_buf)?;
+        let mut flags = [0; 1];
+        reader.read_exact(&mut flags)?;
+        let origin = match flags[0] & SIGNED_FLAG {
+            0 => None,
+            _ => {
+                let mut public_key = [0; 32];
+                reader.read_exact(&mut public_key)?;
+                let mut signature = [0; 64];
+                reader.read_exact(&mut signature)?;
+                let mut origin_id = [0; K_ID_LEN_BYTES];
+                reader.read_exact(&mut origin_id)?;
+                Some(OriginSignature {
+                    public_key,
+                    signature,
+                    origin_id,
+                })
+            }
+        };
+        let message_id = match flags[0] & MESSAGE_ID_FLAG {
+            0 => None,
+            _ => {
+                let mut message_id = [0; MESSAGE_ID_LEN];
+                reader.read_exact(&mut message_id)?;
+                Some(message_id)
+            }
+        };
         let mut gossip_length_buf = [0; 4];
         reader.read_exact(&mut gossip_length_buf)?;
-        let gossip_length = u32::from_le_bytes(gossip_length_buf);
-        let mut gossip_frame = vec![0; gossip_length as usize];
+        let gossip_length = u32::from_le_bytes(gossip_length_buf) as usize;
+        if gossip_length > MAX_GOSSIP_FRAME_LEN {
+            return Err(DecodeError::GossipFrameTooLong(gossip_length).into());
+        }
+        let mut gossip_frame = vec![0; gossip_length];
         reader.read_exact(&mut gossip_frame)?;
         Ok(BroadcastPayload {
             height: height_buf[0],
+            topic: topic_buf[0],
+            priority: priority_buf[0],
+            origin,
+            message_id,
+            batch: flags[0] & BATCH_FLAG != 0,
             gossip_frame,
         })
     }
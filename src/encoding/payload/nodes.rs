@@ -6,46 +6,83 @@
 
 use std::convert::TryInto;
 use std::io::{self, Read, Write};
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6,
+};
 
-use crate::{encoding::Marshallable, kbucket::BinaryKey, K_ID_LEN_BYTES};
+use crate::{
+    encoding::{DecodeError, Marshallable},
+    kbucket::BinaryKey,
+    peer::PeerNode,
+    K_ID_LEN_BYTES,
+};
+
+// Max size of the peers of a single `Nodes` message. It keeps the whole
+// datagram below the minimum IPv6 MTU, avoiding IP fragmentation
+pub(crate) const MAX_NODES_PAYLOAD_SIZE: usize = 1024;
+
+// Max number of peers accepted from a single `Nodes` message. It's the
+// number of IPv4 peers without fallback fitting `MAX_NODES_PAYLOAD_SIZE`
+const MAX_PEERS_PER_MESSAGE: usize =
+    MAX_NODES_PAYLOAD_SIZE / (4 + 2 + K_ID_LEN_BYTES + 1);
+
+// Flags of the byte following the id of a peer
+const FALLBACK_FLAG: u8 = 0x01;
+const CUSTOM_ID_FLAG: u8 = 0x02;
+
+/// Payload of a `Nodes` message
 #[derive(Debug, PartialEq)]
-pub(crate) struct NodePayload {
+pub struct NodePayload {
     pub(crate) peers: Vec<PeerEncodedInfo>,
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) struct PeerEncodedInfo {
+/// Address and id of a peer.
+///
+/// Dual-homed peers advertise a fallback address of the other IP family
+/// alongside the primary one, which their id is bound to unless it's supplied
+/// by their application
+#[derive(Debug, PartialEq, Clone)]
+pub struct PeerEncodedInfo {
     pub(crate) ip: IpInfo,
     pub(crate) port: u16,
     pub(crate) id: BinaryKey,
+    pub(crate) fallback: Option<(IpInfo, u16)>,
+    pub(crate) custom_id: bool,
 }
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum IpInfo {
     IPv4([u8; 4]),
     IPv6([u8; 16]),
 }
 
-impl PeerEncodedInfo {
-    pub(crate) fn to_socket_address(&self) -> SocketAddr {
-        match self.ip {
-            IpInfo::IPv4(bytes) => SocketAddr::V4(SocketAddrV4::new(
-                Ipv4Addr::from(bytes),
-                self.port,
-            )),
-            IpInfo::IPv6(bytes) => SocketAddr::V6(SocketAddrV6::new(
-                Ipv6Addr::from(bytes),
-                self.port,
-                0,
-                0,
-            )),
+impl From<IpAddr> for IpInfo {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => IpInfo::IPv4(ip.octets()),
+            IpAddr::V6(ip) => IpInfo::IPv6(ip.octets()),
         }
     }
 }
 
-impl Marshallable for PeerEncodedInfo {
+impl IpInfo {
+    pub(crate) fn to_ip(&self) -> IpAddr {
+        match self {
+            IpInfo::IPv4(bytes) => IpAddr::V4(Ipv4Addr::from(*bytes)),
+            IpInfo::IPv6(bytes) => IpAddr::V6(Ipv6Addr::from(*bytes)),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        match self {
+            IpInfo::IPv4(_) => 4,
+            IpInfo::IPv6(_) => 17,
+        }
+    }
+}
+
+impl Marshallable for IpInfo {
     fn marshal_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        match &self.ip {
+        match self {
             IpInfo::IPv6(bytes) => {
                 writer.write_all(&[0u8])?;
                 writer.write_all(bytes)?;
@@ -54,8 +91,6 @@ impl Marshallable for PeerEncodedInfo {
                 writer.write_all(bytes)?;
             }
         }
-        writer.write_all(&self.port.to_le_bytes())?;
-        writer.write_all(&self.id)?;
         Ok(())
     }
 
@@ -79,13 +114,209 @@ impl Marshallable for PeerEncodedInfo {
                 IpInfo::IPv6(ipv6_bytes)
             }
         };
+        Ok(ip)
+    }
+}
 
+impl NodePayload {
+    /// Create a payload with the given peers.
+    ///
+    /// Receivers reject payloads which don't fit a single datagram
+    pub fn new(peers: Vec<PeerEncodedInfo>) -> Self {
+        NodePayload { peers }
+    }
+
+    pub fn peers(&self) -> &[PeerEncodedInfo] {
+        &self.peers
+    }
+
+    /// Split a list of peers into payloads small enough to be sent with a
+    /// single datagram each.
+    ///
+    /// An empty list results in a single empty payload
+    pub(crate) fn paginate(peers: Vec<PeerEncodedInfo>) -> Vec<NodePayload> {
+        let mut pages = vec![];
+        let mut page = vec![];
+        let mut page_size = 0;
+        for peer in peers {
+            let size = peer.encoded_len();
+            if !page.is_empty() && page_size + size > MAX_NODES_PAYLOAD_SIZE {
+                pages.push(NodePayload { peers: page });
+                page = vec![];
+                page_size = 0;
+            }
+            page_size += size;
+            page.push(peer);
+        }
+        if !page.is_empty() || pages.is_empty() {
+            pages.push(NodePayload { peers: page });
+        }
+        pages
+    }
+
+    /// Like [paginate](Self::paginate), leaving out the last peers which
+    /// don't fit `max_len` bytes, counting `page_overhead` bytes for each
+    /// page
+    pub(crate) fn paginate_bounded(
+        peers: Vec<PeerEncodedInfo>,
+        max_len: usize,
+        page_overhead: usize,
+    ) -> Vec<NodePayload> {
+        let mut kept = vec![];
+        let mut len = 0;
+        let mut page_size = 0;
+        for peer in peers {
+            let size = peer.encoded_len();
+            let mut added = size;
+            if kept.is_empty() || page_size + size > MAX_NODES_PAYLOAD_SIZE {
+                added += page_overhead;
+                page_size = 0;
+            }
+            if len + added > max_len {
+                break;
+            }
+            len += added;
+            page_size += size;
+            kept.push(peer);
+        }
+        Self::paginate(kept)
+    }
+}
+
+impl PeerEncodedInfo {
+    /// Create the info of the peer listening on `address`
+    pub fn from_address(address: SocketAddr) -> Self {
+        PeerEncodedInfo {
+            ip: address.ip().into(),
+            port: address.port(),
+            id: PeerNode::compute_id(&address.ip(), address.port()),
+            fallback: None,
+            custom_id: false,
+        }
+    }
+
+    /// Create the info of the peer listening on `address`, whose id is
+    /// supplied by its application
+    pub fn with_id(address: SocketAddr, id: BinaryKey) -> Self {
+        PeerEncodedInfo {
+            id,
+            custom_id: true,
+            ..PeerEncodedInfo::from_address(address)
+        }
+    }
+
+    /// Advertise a fallback address, which must be of the other IP family
+    pub fn with_fallback(mut self, fallback: SocketAddr) -> Self {
+        self.fallback = Some((fallback.ip().into(), fallback.port()));
+        self
+    }
+
+    fn encoded_len(&self) -> usize {
+        let fallback_len = match &self.fallback {
+            Some((ip, _)) => ip.encoded_len() + 2,
+            None => 0,
+        };
+        self.ip.encoded_len() + 2 + K_ID_LEN_BYTES + 1 + fallback_len
+    }
+
+    pub fn to_socket_address(&self) -> SocketAddr {
+        to_socket_address(&self.ip, self.port)
+    }
+
+    /// The fallback address of a dual-homed peer, if any
+    pub fn fallback_address(&self) -> Option<SocketAddr> {
+        self.fallback
+            .as_ref()
+            .map(|(ip, port)| to_socket_address(ip, *port))
+    }
+}
+
+fn to_socket_address(ip: &IpInfo, port: u16) -> SocketAddr {
+    match ip {
+        IpInfo::IPv4(bytes) => {
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(*bytes), port))
+        }
+        IpInfo::IPv6(bytes) => SocketAddr::V6(SocketAddrV6::new(
+            Ipv6Addr::from(*bytes),
+            port,
+            0,
+            0,
+        )),
+    }
+}
+
+/// Check that a fallback address is usable alongside the primary one
+pub(crate) fn is_valid_fallback(
+    primary: &IpAddr,
+    fallback: &SocketAddr,
+) -> bool {
+    fallback.port() != 0 && primary.is_ipv4() != fallback.is_ipv4()
+}
+
+impl Marshallable for PeerEncodedInfo {
+    fn marshal_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.ip.marshal_binary(writer)?;
+        writer.write_all(&self.port.to_le_bytes())?;
+        writer.write_all(&self.id)?;
+        let mut flags = 0;
+        if self.fallback.is_some() {
+            flags |= FALLBACK_FLAG;
+        }
+        if self.custom_id {
+            flags |= CUSTOM_ID_FLAG;
+        }
+        writer.write_all(&[flags])?;
+        if let Some((ip, port)) = &self.fallback {
+            ip.marshal_binary(writer)?;
+            writer.write_all(&port.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn unmarshal_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let ip = IpInfo::unmarshal_binary(reader)?;
         let mut port = [0; 2];
         reader.read_exact(&mut port)?;
         let port = u16::from_le_bytes(port);
+        if port == 0 {
+            return Err(DecodeError::InvalidPort.into());
+        }
         let mut id = [0; K_ID_LEN_BYTES];
         reader.read_exact(&mut id)?;
-        Ok(PeerEncodedInfo { ip, port, id })
+        let mut flags = [0; 1];
+        reader.read_exact(&mut flags)?;
+        let flags = flags[0];
+        if flags & !(FALLBACK_FLAG | CUSTOM_ID_FLAG) != 0 {
+            return Err(DecodeError::InvalidFallback.into());
+        }
+        let fallback = match flags & FALLBACK_FLAG {
+            0 => None,
+            _ => {
+                let fallback_ip = IpInfo::unmarshal_binary(reader)?;
+                let mut fallback_port = [0; 2];
+                reader.read_exact(&mut fallback_port)?;
+                Some((fallback_ip, u16::from_le_bytes(fallback_port)))
+            }
+        };
+        let peer = PeerEncodedInfo {
+            ip,
+            port,
+            id,
+            fallback,
+            custom_id: flags & CUSTOM_ID_FLAG != 0,
+        };
+        // The id of a peer is bound to its primary address, unless it's
+        // supplied by its application
+        let address = peer.to_socket_address();
+        if !peer.custom_id && PeerNode::compute_id(&address.ip(), port) != id {
+            return Err(DecodeError::InvalidPeerId.into());
+        }
+        if let Some(fallback) = peer.fallback_address() {
+            if !is_valid_fallback(&address.ip(), &fallback) {
+                return Err(DecodeError::InvalidFallback.into());
+            }
+        }
+        Ok(peer)
     }
 }
 impl Marshallable for NodePayload {
@@ -101,7 +332,11 @@ impl Marshallable for NodePayload {
         let mut peers: Vec<PeerEncodedInfo> = vec![];
         let mut len = [0; 2];
         reader.read_exact(&mut len)?;
-        for _ in 0..u16::from_le_bytes(len) {
+        let len = u16::from_le_bytes(len) as usize;
+        if len > MAX_PEERS_PER_MESSAGE {
+            return Err(DecodeError::TooManyPeers(len).into());
+        }
+        for _ in 0..len {
             peers.push(PeerEncodedInfo::unmarshal_binary(reader)?)
         }
         Ok(NodePayload { peers })
@@ -0,0 +1,327 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_derive::Serialize;
+use tokio::sync::broadcast;
+
+use crate::kbucket::{BucketHeight, TableEvent};
+use crate::peer::PeerInfo;
+
+pub(crate) type EventSender = broadcast::Sender<KadcastEvent>;
+
+/// Events emitted by a [Peer](crate::Peer).
+///
+/// See [Peer::events](crate::Peer::events)
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum KadcastEvent {
+    /// A source exceeded the limits of in-flight broadcast messages or
+    /// buffered symbols. Its chunks are discarded until its pending messages
+    /// are decoded or expire
+    SourceThrottled(IpAddr),
+
+    /// A message with an unknown type id (eg: introduced by a newer version
+    /// of the protocol) has been received from the given address and skipped
+    UnknownMessage(u8, SocketAddr),
+
+    /// A peer has been added to the routing table
+    PeerAdded(SocketAddr),
+
+    /// A peer has been removed from the routing table, either because it
+    /// didn't reply to an eviction check or because it's been idle for too
+    /// long
+    PeerEvicted(SocketAddr),
+
+    /// A message has been received from a peer of the routing table
+    PeerRefreshed(SocketAddr),
+
+    /// No message has been received from the peers of the bucket at the
+    /// given height for a while. Its peers are asked for new nodes
+    BucketIdle(BucketHeight),
+
+    /// Progress of the join to the network, at startup or whenever the
+    /// routing table runs short of peers
+    Bootstrap(BootstrapProgress),
+
+    /// A message received from the given address has been discarded
+    MessageDropped(SocketAddr, DropReason),
+
+    /// A datagram received from the given address can't be decoded (eg:
+    /// malformed or with an invalid padding)
+    DecodeFailed(SocketAddr),
+
+    /// A message can't be sent to the given address, even after the retries
+    SendFailed(SocketAddr, ErrorKind),
+
+    /// A source crossed a threshold of the flood guard, its datagrams are
+    /// ignored for a while, see
+    /// [Config::flood_guard](crate::config::Config::flood_guard)
+    SourceIgnored(IpAddr, Flood),
+
+    /// Runtime parameters have been changed with
+    /// [Peer::reconfigure](crate::Peer::reconfigure)
+    Reconfigured,
+
+    /// A security relevant event, emitted right after the event it
+    /// classifies (eg: a message dropped because of an invalid header). See
+    /// [Config::audit_log](crate::config::Config::audit_log)
+    Security(SecurityEvent),
+}
+
+/// Security relevant event, see [KadcastEvent::Security]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SecurityEvent {
+    /// When it occurred (seconds since UNIX epoch)
+    pub timestamp: u64,
+    /// Host of the offending source
+    pub source: IpAddr,
+    pub kind: SecurityKind,
+}
+
+/// Kind of a [SecurityEvent]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub enum SecurityKind {
+    /// A message whose header failed the verification (id, authentication
+    /// tag, originator signature or Noise session)
+    InvalidHeader(DropReason),
+    /// A message from a banned source
+    BannedSource,
+    /// A message from a source not in the allowlist
+    NotAllowed,
+    /// A message longer than the max length of its type
+    OversizedPayload,
+    /// A message sent outside of the replay window, or a reply to no pending
+    /// request
+    Replay(DropReason),
+    /// A source crossed a threshold of the flood guard
+    Flood(Flood),
+    /// A broadcast message rejected by the
+    /// [MessageValidator](crate::MessageValidator)
+    Rejected,
+}
+
+impl SecurityEvent {
+    /// Classify an event, if security relevant
+    fn classify(event: &KadcastEvent) -> Option<Self> {
+        let (source, kind) = match *event {
+            KadcastEvent::MessageDropped(source, reason) => {
+                let kind = match reason {
+                    DropReason::Banned => SecurityKind::BannedSource,
+                    DropReason::NotAllowed => SecurityKind::NotAllowed,
+                    DropReason::Unauthenticated
+                    | DropReason::InvalidSignature
+                    | DropReason::InvalidId
+                    | DropReason::NoSession => {
+                        SecurityKind::InvalidHeader(reason)
+                    }
+                    DropReason::Stale | DropReason::UnexpectedReply => {
+                        SecurityKind::Replay(reason)
+                    }
+                    DropReason::Oversized => SecurityKind::OversizedPayload,
+                    DropReason::Rejected => SecurityKind::Rejected,
+                    DropReason::RateLimited
+                    | DropReason::NoContact
+                    | DropReason::UnknownSender
+                    | DropReason::Overloaded => return None,
+                };
+                (source.ip(), kind)
+            }
+            KadcastEvent::SourceIgnored(source, flood) => {
+                (source, SecurityKind::Flood(flood))
+            }
+            _ => return None,
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Some(SecurityEvent {
+            timestamp,
+            source,
+            kind,
+        })
+    }
+}
+
+/// Threshold of the flood guard crossed by a source, see
+/// [KadcastEvent::SourceIgnored]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Flood {
+    /// Too many datagrams
+    Datagrams,
+    /// Too many datagrams which can't be decoded
+    Malformed,
+    /// Too many messages with a header failing the verification
+    InvalidHeaders,
+}
+
+/// Step of the join to the network, see [KadcastEvent::Bootstrap]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BootstrapProgress {
+    /// The given amount of peers known before the last shutdown has been
+    /// contacted
+    StoredPeers(usize),
+
+    /// The given amount of bootstrapping nodes has been contacted
+    Bootstrappers(usize),
+
+    /// The given amount of peers of the bootstrap cache has been contacted,
+    /// since the bootstrapping nodes didn't reply
+    CachedPeers(usize),
+
+    /// The bootstrapping nodes (and the peers of the bootstrap cache)
+    /// didn't reply, they are contacted again. The attempt number starts
+    /// from 2
+    Retrying(u32),
+
+    /// The lookup of the closest peers completed, finding the given amount
+    /// of peers
+    Joined(usize),
+}
+
+/// Reason a received message has been discarded, see
+/// [KadcastEvent::MessageDropped]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub enum DropReason {
+    /// The sender is banned
+    Banned,
+
+    /// The sender is not in the allowlist
+    NotAllowed,
+
+    /// A control message whose authentication tag is not valid
+    Unauthenticated,
+
+    /// A control message sent outside of the replay window
+    Stale,
+
+    /// A broadcast message whose originator signature is missing or not
+    /// valid
+    InvalidSignature,
+
+    /// The id of the sender doesn't match its address
+    InvalidId,
+
+    /// The sender exceeded its rate limit, see
+    /// [Config::rate_limit](crate::config::Config::rate_limit)
+    RateLimited,
+
+    /// A datagram not belonging to any Noise session, or not authentic, see
+    /// [Config::noise](crate::config::Config::noise)
+    NoSession,
+
+    /// A `Pong` or a `Nodes` reply not echoing the nonce of a pending
+    /// request, eg: a replayed one, see
+    /// [Config::require_nonce_echo](crate::config::Config::require_nonce_echo)
+    UnexpectedReply,
+
+    /// A broadcast message rejected by the
+    /// [MessageValidator](crate::MessageValidator)
+    Rejected,
+
+    /// A message longer than the max length of its type
+    Oversized,
+
+    /// A `FindNodes`, or an `IWant` from outside the routing table, from an
+    /// address which didn't answer a `Ping` recently, see
+    /// [Config::amplification_guard](crate::config::Config::amplification_guard)
+    NoContact,
+
+    /// A `Request` from a peer which is not in the routing table
+    UnknownSender,
+
+    /// A `Request` received while every request handler is busy
+    Overloaded,
+}
+
+impl From<TableEvent<PeerInfo>> for KadcastEvent {
+    fn from(event: TableEvent<PeerInfo>) -> Self {
+        match event {
+            TableEvent::Added(peer) => KadcastEvent::PeerAdded(*peer.address()),
+            TableEvent::Evicted(peer) => {
+                KadcastEvent::PeerEvicted(*peer.address())
+            }
+            TableEvent::Refreshed(peer) => {
+                KadcastEvent::PeerRefreshed(*peer.address())
+            }
+        }
+    }
+}
+
+pub(crate) fn emit(sender: &EventSender, event: KadcastEvent) {
+    let security = SecurityEvent::classify(&event);
+    // Sending fails only if there are no subscribers
+    let _ = sender.send(event);
+    if let Some(security) = security {
+        let _ = sender.send(KadcastEvent::Security(security));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_security_events() {
+        let source: SocketAddr = "192.168.0.1:666".parse().unwrap();
+        let classified = |event| {
+            SecurityEvent::classify(&event).map(|security| {
+                assert_eq!(security.source, source.ip());
+                security.kind
+            })
+        };
+        assert_eq!(
+            classified(KadcastEvent::MessageDropped(
+                source,
+                DropReason::Unauthenticated
+            )),
+            Some(SecurityKind::InvalidHeader(DropReason::Unauthenticated))
+        );
+        assert_eq!(
+            classified(KadcastEvent::MessageDropped(source, DropReason::Stale)),
+            Some(SecurityKind::Replay(DropReason::Stale))
+        );
+        assert_eq!(
+            classified(KadcastEvent::SourceIgnored(
+                source.ip(),
+                Flood::Malformed
+            )),
+            Some(SecurityKind::Flood(Flood::Malformed))
+        );
+        assert_eq!(
+            classified(KadcastEvent::MessageDropped(
+                source,
+                DropReason::RateLimited
+            )),
+            None
+        );
+        assert_eq!(classified(KadcastEvent::DecodeFailed(source)), None);
+
+        let (sender, mut events) = broadcast::channel(10);
+        emit(
+            &sender,
+            KadcastEvent::MessageDropped(source, DropReason::Oversized),
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            KadcastEvent::MessageDropped(source, DropReason::Oversized)
+        );
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            KadcastEvent::Security(SecurityEvent {
+                kind: SecurityKind::OversizedPayload,
+                ..
+            })
+        ));
+    }
+}
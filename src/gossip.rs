@@ -0,0 +1,277 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use blake2::{Blake2s, Digest};
+
+use crate::config::PullGossipConfig;
+use crate::encoding::message::{BroadcastPayload, MessageUid, MAX_DIGEST_UIDS};
+
+/// Max length of the gossip frames served in answer to a single `IWant`,
+/// the request being much smaller than the messages
+pub(crate) const MAX_SERVED_LEN: usize = 256 * 1024;
+
+/// Identifier of a broadcast message in the pull gossip. It only covers the
+/// parts of a broadcast that relays are not allowed to modify, so every copy
+/// of a message shares it regardless of the height and of the encryption
+pub(crate) fn gossip_uid(payload: &BroadcastPayload) -> MessageUid {
+    let mut hasher = Blake2s::new();
+    hasher.update([payload.topic]);
+    hasher.update(&payload.gossip_frame);
+    hasher
+        .finalize()
+        .as_slice()
+        .try_into()
+        .expect("Wrong length")
+}
+
+#[derive(Default)]
+struct Recent {
+    messages: HashMap<MessageUid, (BroadcastPayload, Instant)>,
+    // Uids of the `messages`, from the oldest
+    order: VecDeque<MessageUid>,
+    // Missing messages already requested to a neighbour
+    requested: HashMap<MessageUid, Instant>,
+    // Messages already served to a neighbour, and the same entries in the
+    // order they were served
+    served: HashMap<(SocketAddr, MessageUid), Instant>,
+    served_order: VecDeque<((SocketAddr, MessageUid), Instant)>,
+}
+
+/// Broadcast messages recently seen, advertised to the neighbours with
+/// `IHave` digests and served to the ones which request them with `IWant`
+#[derive(Clone)]
+pub(crate) struct RecentMessages {
+    conf: PullGossipConfig,
+    recent: Arc<Mutex<Recent>>,
+}
+
+impl RecentMessages {
+    pub(crate) fn new(conf: PullGossipConfig) -> Self {
+        Self {
+            conf,
+            recent: Arc::default(),
+        }
+    }
+
+    pub(crate) fn conf(&self) -> &PullGossipConfig {
+        &self.conf
+    }
+
+    /// Record a message, returning `false` if it has already been seen
+    /// within the window
+    pub(crate) fn insert(&self, payload: &BroadcastPayload) -> bool {
+        let uid = gossip_uid(payload);
+        let now = Instant::now();
+        let mut recent = self.lock();
+        self.prune(&mut recent, now);
+        if recent.messages.contains_key(&uid) {
+            return false;
+        }
+        recent.requested.remove(&uid);
+        // Served copies are never propagated
+        let mut payload = payload.clone();
+        payload.height = 0;
+        recent.messages.insert(uid, (payload, now));
+        recent.order.push_back(uid);
+        while recent.order.len() > self.conf.max_messages {
+            if let Some(oldest) = recent.order.pop_front() {
+                recent.messages.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Uids of the most recent messages, to be advertised
+    pub(crate) fn digest(&self) -> Vec<MessageUid> {
+        let mut recent = self.lock();
+        self.prune(&mut recent, Instant::now());
+        recent
+            .order
+            .iter()
+            .rev()
+            .take(MAX_DIGEST_UIDS)
+            .copied()
+            .collect()
+    }
+
+    /// Advertised messages which have not been seen, nor requested in the
+    /// last interval
+    pub(crate) fn missing(&self, uids: &[MessageUid]) -> Vec<MessageUid> {
+        let now = Instant::now();
+        let mut recent = self.lock();
+        let Recent {
+            messages,
+            requested,
+            ..
+        } = &mut *recent;
+        let mut missing = vec![];
+        for uid in uids {
+            let requested_at = requested.get(uid);
+            if messages.contains_key(uid)
+                || requested_at.is_some_and(|at| {
+                    now.duration_since(*at) < self.conf.interval
+                })
+            {
+                continue;
+            }
+            requested.insert(*uid, now);
+            missing.push(*uid);
+        }
+        missing
+    }
+
+    /// Requested messages which are known and have not been served to `to`
+    /// in the last interval, up to [MAX_SERVED_LEN] bytes
+    pub(crate) fn serve(
+        &self,
+        to: SocketAddr,
+        uids: &[MessageUid],
+    ) -> Vec<BroadcastPayload> {
+        let now = Instant::now();
+        let mut recent = self.lock();
+        let Recent {
+            messages,
+            served,
+            served_order,
+            ..
+        } = &mut *recent;
+        while let Some(&(key, at)) = served_order.front() {
+            if now.duration_since(at) < self.conf.interval {
+                break;
+            }
+            served_order.pop_front();
+            if served.get(&key) == Some(&at) {
+                served.remove(&key);
+            }
+        }
+        let mut len = 0;
+        let mut payloads = vec![];
+        for uid in uids {
+            let payload = match messages.get(uid) {
+                Some((payload, _)) => payload,
+                None => continue,
+            };
+            if served.contains_key(&(to, *uid))
+                || len + payload.gossip_frame.len() > MAX_SERVED_LEN
+            {
+                continue;
+            }
+            len += payload.gossip_frame.len();
+            served.insert((to, *uid), now);
+            served_order.push_back(((to, *uid), now));
+            payloads.push(payload.clone());
+        }
+        payloads
+    }
+
+    // Forget the messages out of the window
+    fn prune(&self, recent: &mut Recent, now: Instant) {
+        let window = self.conf.window;
+        while let Some(oldest) = recent.order.front() {
+            match recent.messages.get(oldest) {
+                Some((_, seen)) if now.duration_since(*seen) < window => break,
+                _ => {
+                    let oldest = *oldest;
+                    recent.order.pop_front();
+                    recent.messages.remove(&oldest);
+                }
+            }
+        }
+        recent
+            .requested
+            .retain(|_, at| now.duration_since(*at) < window);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Recent> {
+        self.recent.lock().expect("Recent messages lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{gossip_uid, RecentMessages, MAX_SERVED_LEN};
+    use crate::config::PullGossipConfig;
+    use crate::encoding::message::BroadcastPayload;
+
+    fn payload(height: u8, frame: u8) -> BroadcastPayload {
+        BroadcastPayload {
+            height,
+            topic: 0,
+            priority: 0,
+            origin: None,
+            message_id: None,
+            batch: false,
+            gossip_frame: vec![frame; 100],
+        }
+    }
+
+    #[test]
+    fn test_recent_messages() {
+        let recent = RecentMessages::new(PullGossipConfig {
+            max_messages: 2,
+            ..Default::default()
+        });
+        // Copies at different heights are the same message
+        assert!(recent.insert(&payload(3, 1)));
+        assert!(!recent.insert(&payload(1, 1)));
+        assert!(recent.insert(&payload(3, 2)));
+        assert!(recent.insert(&payload(3, 3)));
+
+        // The oldest message has been forgotten
+        let uids: Vec<_> =
+            (1..=3).map(|i| gossip_uid(&payload(0, i))).collect();
+        assert_eq!(recent.digest(), vec![uids[2], uids[1]]);
+        assert_eq!(recent.missing(&uids), vec![uids[0]]);
+        // Already requested
+        assert!(recent.missing(&uids).is_empty());
+
+        let to = "192.168.0.1:666".parse().unwrap();
+        let served = recent.serve(to, &uids);
+        assert_eq!(served, vec![payload(0, 2), payload(0, 3)]);
+        // Served once per interval to the same address
+        assert!(recent.serve(to, &uids).is_empty());
+        let other = "192.168.0.2:666".parse().unwrap();
+        assert_eq!(recent.serve(other, &uids[1..2]), vec![payload(0, 2)]);
+    }
+
+    #[test]
+    fn test_served_len() {
+        let recent = RecentMessages::new(PullGossipConfig::default());
+        let big = |frame| BroadcastPayload {
+            gossip_frame: vec![frame; MAX_SERVED_LEN / 2],
+            ..payload(0, frame)
+        };
+        let uids: Vec<_> = (1..=3)
+            .map(|i| {
+                recent.insert(&big(i));
+                gossip_uid(&big(i))
+            })
+            .collect();
+        let to = "192.168.0.1:666".parse().unwrap();
+        assert_eq!(recent.serve(to, &uids), vec![big(1), big(2)]);
+        // The messages left out are served to the next request
+        assert_eq!(recent.serve(to, &uids), vec![big(3)]);
+    }
+
+    #[test]
+    fn test_recent_messages_window() {
+        let recent = RecentMessages::new(PullGossipConfig {
+            window: Duration::ZERO,
+            ..Default::default()
+        });
+        assert!(recent.insert(&payload(1, 1)));
+        assert!(recent.digest().is_empty());
+        assert!(recent.insert(&payload(1, 1)));
+    }
+}
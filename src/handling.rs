@@ -4,26 +4,49 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryInto;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Semaphore;
+use tokio::task::{self, JoinHandle};
 use tracing::*;
 
-use crate::config::Config;
+use crate::channel::{Receiver, Sender};
+use crate::config::{cap_height, AmplificationGuardConfig, Config};
 use crate::encoding::message::{
-    BroadcastPayload, Header, Message, NodePayload,
+    BroadcastPayload, Header, Message, MessageUid, NodePayload,
 };
-use crate::kbucket::{BinaryKey, NodeInsertError, Tree};
+use crate::encoding::payload::{Priority, Topic};
+use crate::encoding::Marshallable;
+use crate::event::{self, DropReason, EventSender, KadcastEvent};
+use crate::gossip::{gossip_uid, RecentMessages};
+use crate::identity::Challenges;
+use crate::kbucket::{BinaryKey, NodeInsertError, PeerTarget, TableView, Tree};
+use crate::lookup::NodesReplySender;
 use crate::peer::{PeerInfo, PeerNode};
+use crate::rpc::{PendingRequests, MAX_CONCURRENT_REQUESTS, MAX_REQUEST_LEN};
 use crate::transport::{MessageBeanIn, MessageBeanOut};
-use crate::{RwLock, K_K};
+use crate::RwLock;
 
 /// Message metadata for incoming message notifications
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MessageInfo {
     pub(crate) src: SocketAddr,
     pub(crate) height: u8,
+    pub(crate) topic: Topic,
+    pub(crate) priority: Priority,
+    pub(crate) origin: Option<[u8; 32]>,
+    pub(crate) origin_id: Option<BinaryKey>,
+    pub(crate) uid: MessageUid,
+    pub(crate) sender_id: BinaryKey,
+    pub(crate) origin_height: Option<u8>,
+    pub(crate) received_at: SystemTime,
+    pub(crate) chunks: usize,
+    pub(crate) decode_duration: Duration,
 }
 
 impl MessageInfo {
@@ -35,6 +58,415 @@ impl MessageInfo {
     pub fn height(&self) -> u8 {
         self.height
     }
+    /// Returns the topic the message has been broadcasted on
+    pub fn topic(&self) -> Topic {
+        self.topic
+    }
+    /// Returns the priority of the message
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+    /// Returns the Ed25519 public key of the message originator, if the
+    /// message has been signed
+    pub fn origin(&self) -> Option<[u8; 32]> {
+        self.origin
+    }
+    /// Returns the id of the message originator, if the message has been
+    /// signed. It's bound to the [origin](MessageInfo::origin) key only if
+    /// the originator has signed with its key identity
+    pub fn origin_id(&self) -> Option<BinaryKey> {
+        self.origin_id
+    }
+    /// Returns the identifier of the message, shared by every copy of it
+    /// regardless of the relays. The same payload broadcasted twice on the
+    /// same topic has the same uid
+    pub fn uid(&self) -> [u8; 32] {
+        self.uid
+    }
+    /// Returns the id of the peer the message has been received from
+    pub fn sender_id(&self) -> BinaryKey {
+        self.sender_id
+    }
+    /// Returns the height the message has been broadcasted with by its
+    /// originator, if known. It's unknown if a relay didn't carry it along
+    /// (eg: a message sent with [Peer::send_message](crate::Peer::send_message))
+    pub fn origin_height(&self) -> Option<u8> {
+        self.origin_height
+    }
+    /// Returns when the message has been decoded
+    pub fn received_at(&self) -> SystemTime {
+        self.received_at
+    }
+    /// Returns the amount of chunks accepted until the message has been
+    /// decoded
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+    /// Returns the time from the first chunk received to the decoding
+    pub fn decode_duration(&self) -> Duration {
+        self.decode_duration
+    }
+}
+
+/// Verdict of the listener on the propagation of a received broadcast
+/// message, see [NetworkListen::on_relay](crate::NetworkListen::on_relay)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    /// Relay the message to the buckets below the height it's been received
+    /// with
+    Propagate,
+    /// Don't relay the message, eg: because it's invalid
+    Drop,
+    /// Relay the message to the buckets below the given height, which can't
+    /// exceed the one the message would be relayed to
+    PropagateWithHeight(u8),
+}
+
+/// Received broadcast message to relay again with
+/// [Peer::repropagate](crate::Peer::repropagate), given by uid (see
+/// [MessageInfo::uid]) or by content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageRef {
+    Uid([u8; 32]),
+    Bytes(Vec<u8>),
+}
+
+impl From<[u8; 32]> for MessageRef {
+    fn from(uid: [u8; 32]) -> Self {
+        MessageRef::Uid(uid)
+    }
+}
+
+impl From<Vec<u8>> for MessageRef {
+    fn from(bytes: Vec<u8>) -> Self {
+        MessageRef::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for MessageRef {
+    fn from(bytes: &[u8]) -> Self {
+        MessageRef::Bytes(bytes.to_vec())
+    }
+}
+
+// Received message which has not been relayed
+struct Held {
+    uid: MessageUid,
+    payload: BroadcastPayload,
+    origin_height: Option<u8>,
+}
+
+/// Relay of the received broadcast messages, according to the verdict of the
+/// listener
+#[derive(Clone)]
+pub(crate) struct Relayer {
+    view: TableView<PeerInfo>,
+    header: Header,
+    auto_propagate: bool,
+    // Cleared by `Peer::set_relay_enabled` to pause the relay, shared by
+    // the clones
+    enabled: Arc<AtomicBool>,
+    max_height: Option<usize>,
+    outbound_sender: Sender<MessageBeanOut>,
+    // Messages not relayed, the oldest first, which can be repropagated
+    held: Arc<Mutex<VecDeque<Held>>>,
+    max_held: usize,
+}
+
+impl Relayer {
+    pub(crate) fn new(
+        view: TableView<PeerInfo>,
+        header: Header,
+        outbound_sender: Sender<MessageBeanOut>,
+        enabled: bool,
+        config: &Config,
+    ) -> Self {
+        Self {
+            view,
+            header,
+            auto_propagate: config.auto_propagate,
+            enabled: Arc::new(AtomicBool::new(enabled)),
+            max_height: config.max_broadcast_height,
+            outbound_sender,
+            held: Arc::default(),
+            max_held: config.max_held_messages,
+        }
+    }
+
+    /// Whether the received messages are relayed at all
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Relay a received message. Without a verdict, the message is relayed
+    /// if [Config::auto_propagate] is enabled. Nothing is relayed while the
+    /// relay is paused.
+    ///
+    /// The messages not relayed are held, to be repropagated later
+    pub(crate) async fn relay(
+        &self,
+        payload: &BroadcastPayload,
+        origin_height: Option<u8>,
+        verdict: Option<Propagation>,
+    ) {
+        let height = match self.is_enabled() {
+            true => relay_height(payload.height, verdict, self.auto_propagate),
+            false => None,
+        };
+        match height {
+            Some(height) => self.send(payload, origin_height, height).await,
+            None => self.hold(payload, origin_height),
+        }
+    }
+
+    /// Relay a held message to the buckets below `from_height`, which can't
+    /// exceed the height the message has been received with.
+    ///
+    /// Returns `false` if the message isn't held or can't be relayed
+    pub(crate) async fn repropagate(
+        &self,
+        message: &MessageRef,
+        from_height: u8,
+    ) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let held = {
+            let mut held = self.held.lock().expect("Held messages poisoned");
+            let position = held.iter().position(|h| match message {
+                MessageRef::Uid(uid) => &h.uid == uid,
+                MessageRef::Bytes(bytes) => &h.payload.gossip_frame == bytes,
+            });
+            match position {
+                Some(i) if held[i].payload.height.min(from_height) > 0 => {
+                    held.remove(i)
+                }
+                _ => None,
+            }
+        };
+        match held {
+            Some(held) => {
+                let height = held.payload.height.min(from_height) - 1;
+                self.send(&held.payload, held.origin_height, height).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn hold(&self, payload: &BroadcastPayload, origin_height: Option<u8>) {
+        if payload.height == 0 || self.max_held == 0 {
+            return;
+        }
+        let mut held = self.held.lock().expect("Held messages poisoned");
+        held.push_back(Held {
+            uid: gossip_uid(payload),
+            payload: payload.clone(),
+            origin_height,
+        });
+        while held.len() > self.max_held {
+            held.pop_front();
+        }
+    }
+
+    // Relay a message to the buckets up to `height`
+    async fn send(
+        &self,
+        payload: &BroadcastPayload,
+        origin_height: Option<u8>,
+        height: u8,
+    ) {
+        let height = cap_height(Some(height.into()), self.max_height);
+        debug!("Extracting for height {:?}", height);
+        let header = self.header.with_origin_height(origin_height);
+        let messages: Vec<MessageBeanOut> = self
+            .view
+            .load()
+            .extract(height, None)
+            .map(|(height, nodes)| {
+                let msg = Message::Broadcast(
+                    header,
+                    BroadcastPayload {
+                        height: height.try_into().unwrap(),
+                        topic: payload.topic,
+                        priority: payload.priority,
+                        origin: payload.origin,
+                        message_id: payload.message_id,
+                        batch: payload.batch,
+                        gossip_frame: payload.gossip_frame.clone(), //FIX_ME: avoid clone
+                    },
+                );
+                let targets: Vec<SocketAddr> =
+                    nodes.iter().map(|node| *node.value().address()).collect();
+                (msg, targets, None)
+            })
+            .collect();
+
+        for tosend in messages {
+            self.outbound_sender
+                .send(tosend)
+                .await
+                .unwrap_or_else(|op| {
+                    error!("Unable to send broadcast {:?}", op)
+                });
+        }
+    }
+}
+
+// Height a message received with `height` is relayed with, if any
+fn relay_height(
+    height: u8,
+    verdict: Option<Propagation>,
+    auto_propagate: bool,
+) -> Option<u8> {
+    let below = height.checked_sub(1)?;
+    match verdict {
+        Some(Propagation::Propagate) => Some(below),
+        Some(Propagation::PropagateWithHeight(height)) => {
+            Some(height.min(below))
+        }
+        None if auto_propagate => Some(below),
+        None | Some(Propagation::Drop) => None,
+    }
+}
+
+/// Dispatch of the replies to the running lookups and requests, and of the
+/// recent broadcast messages requested by the neighbours
+#[derive(Clone)]
+pub(crate) struct Replies {
+    pub(crate) nodes: NodesReplySender,
+    pub(crate) requests: PendingRequests,
+    pub(crate) recent: Option<RecentMessages>,
+}
+
+/// Time a peer learned from a `Nodes` reply is given to answer the `Ping`
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Max number of peers learned from the `Nodes` replies waiting for their
+/// `Pong`, the further ones are not contacted
+const MAX_PROBES: usize = 1024;
+
+/// Peers learned from the `Nodes` replies, which are pinged before being
+/// contacted any further. Only the ones answering from the claimed address
+/// are inserted in the routing table and queried, so that unreachable or
+/// victim addresses never fill the tables nor receive lookups
+#[derive(Default)]
+struct Probes {
+    pending: HashMap<SocketAddr, Instant>,
+}
+
+impl Probes {
+    /// Record a ping to `address`. `false` if one is already pending for
+    /// it, or if too many are
+    fn probe(&mut self, address: SocketAddr, now: Instant) -> bool {
+        if self.pending.len() >= MAX_PROBES {
+            self.pending
+                .retain(|_, sent| now.duration_since(*sent) < PROBE_TIMEOUT);
+        }
+        if self.pending.contains_key(&address)
+            || self.pending.len() >= MAX_PROBES
+        {
+            return false;
+        }
+        self.pending.insert(address, now);
+        true
+    }
+
+    /// Resolve the ping to `address` with its `Pong`. `false` if no ping is
+    /// pending for it
+    fn answered(&mut self, address: &SocketAddr, now: Instant) -> bool {
+        match self.pending.remove(address) {
+            Some(sent) => now.duration_since(sent) < PROBE_TIMEOUT,
+            None => false,
+        }
+    }
+}
+
+/// Max number of addresses whose contact is tracked, the least recent ones
+/// are forgotten beyond it
+const MAX_CONTACTS: usize = 4096;
+
+/// Addresses which answered a `Ping` recently, the only ones whose
+/// `FindNodes` are answered, see [Config::amplification_guard]. The nonce
+/// echoed by their `Pong`, checked by the transport to be the one of a
+/// `Ping` sent to that very address, proves that they are reachable at the
+/// address, thus that their requests are not spoofed
+struct AmplificationGuard {
+    conf: AmplificationGuardConfig,
+    contacts: HashMap<SocketAddr, Instant>,
+    // Contacts in the order they were recorded, including the outdated
+    // entries of the refreshed ones, so that the least recent is found
+    // without scanning them all
+    order: VecDeque<(SocketAddr, Instant)>,
+}
+
+impl AmplificationGuard {
+    fn new(conf: AmplificationGuardConfig) -> Self {
+        Self {
+            conf,
+            contacts: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Record the `Pong` received from `address`, if it echoes the nonce of
+    /// a `Ping` (the `Pong` without one are accepted by the transport unless
+    /// the echo is required)
+    fn pong(&mut self, address: SocketAddr, header: &Header, now: Instant) {
+        if header.request_nonce.is_none()
+            || self.contacts.insert(address, now) == Some(now)
+        {
+            return;
+        }
+        self.order.push_back((address, now));
+        // Forget the expired contacts and the least recent ones beyond the
+        // max, skipping the outdated entries
+        while let Some(&(oldest, seen)) = self.order.front() {
+            let current = self.contacts.get(&oldest) == Some(&seen);
+            if current
+                && now.duration_since(seen) < self.conf.contact_ttl
+                && self.contacts.len() <= MAX_CONTACTS
+            {
+                break;
+            }
+            self.order.pop_front();
+            if current {
+                self.contacts.remove(&oldest);
+            }
+        }
+        // The outdated entries are dropped once they outnumber the contacts
+        if self.order.len() > 2 * MAX_CONTACTS {
+            let contacts = &self.contacts;
+            self.order
+                .retain(|(address, seen)| contacts.get(address) == Some(seen));
+        }
+    }
+
+    /// Check if `address` answered a `Ping` recently
+    fn contacted(&self, address: &SocketAddr, now: Instant) -> bool {
+        matches!(
+            self.contacts.get(address),
+            Some(seen) if now.duration_since(*seen) < self.conf.contact_ttl
+        )
+    }
+
+    /// Max length of the reply to a request of `request_len` bytes
+    fn max_reply_len(&self, request_len: usize) -> usize {
+        request_len.saturating_mul(self.conf.max_amplification)
+    }
+}
+
+// Length of an encoded message
+fn encoded_len(message: &Message) -> usize {
+    let mut bytes = vec![];
+    message
+        .marshal_binary(&mut bytes)
+        .map(|_| bytes.len())
+        .unwrap_or_default()
 }
 
 pub(crate) struct MessageHandler;
@@ -44,99 +476,483 @@ impl MessageHandler {
         ktable: RwLock<Tree<PeerInfo>>,
         mut inbound_receiver: Receiver<MessageBeanIn>,
         outbound_sender: Sender<MessageBeanOut>,
-        listener_sender: Sender<(Vec<u8>, MessageInfo)>,
+        listener_sender: Sender<(BroadcastPayload, MessageInfo)>,
+        events: EventSender,
+        replies: Replies,
         config: &Config,
-    ) {
-        let nodes_reply_fn = match config.recursive_discovery {
-            true => |header: Header, target: BinaryKey| {
-                Message::FindNodes(header, target)
-            },
-            false => |header: Header, _: BinaryKey| Message::Ping(header),
-        };
-        let auto_propagate = config.auto_propagate;
-        tokio::spawn(async move {
+    ) -> JoinHandle<()> {
+        let recursive_discovery = config.recursive_discovery;
+        let mut probes = Probes::default();
+        let mut amplification =
+            config.amplification_guard.map(AmplificationGuard::new);
+        let bucket_capacity = config.bucket.capacity;
+        let request_handler = config.request_handler.clone();
+        let request_slots = Arc::new(Semaphore::new(MAX_CONCURRENT_REQUESTS));
+        let require_identity = config.require_identity;
+        let mut challenges = Challenges::new(config);
+        config.spawn(async move {
             debug!("MessageHandler started");
-            let my_header = { ktable.read().await.root().as_header() };
-            while let Some((message, mut remote_node_addr)) =
+            let my_header = ktable.read().await.root().as_header();
+            while let Some((message, remote_address, reception)) =
                 inbound_receiver.recv().await
             {
                 debug!("Handler received message");
                 trace!("Handler received message {:?}", message);
-                remote_node_addr.set_port(message.header().sender_port);
-
-                let remote_node = PeerNode::from_socket(
-                    remote_node_addr,
-                    message.header().binary_id,
+                // Replies go to the address the message came from, which is
+                // the fallback one if the sender is dual-homed and its
+                // primary address is not reachable
+                let remote_node_addr = PeerNode::reply_address(
+                    message.header(),
+                    remote_address.ip(),
+                );
+                let remote_node = PeerNode::from_header(
+                    message.header(),
+                    remote_address.ip(),
                 );
+                let remote_primary_addr = *remote_node.value().address();
 
-                match ktable.write().await.insert(remote_node) {
-                    Err(e) => match e {
-                        NodeInsertError::Full(n) => {
-                            debug!(
-                                "Unable to insert node - FULL {}",
-                                n.value().address()
-                            )
-                        }
-                        NodeInsertError::Invalid(n) => {
-                            error!(
-                                "Unable to insert node - INVALID {}",
-                                n.value().address()
-                            );
-                            continue;
-                        }
-                    },
-                    Ok(result) => {
-                        debug!("Written node in ktable: {:?}", &result);
-                        if let Some(pending) = result.pending_eviction() {
-                            outbound_sender
-                                .send((
-                                    Message::Ping(my_header),
-                                    vec![*pending.value().address()],
-                                ))
-                                .await
-                                .unwrap_or_else(|op| {
-                                    error!("Unable to send PING to pending node {:?}", op)
-                                });
+                let mut table = ktable.write().await;
+                table.apply_score_reports();
+                table.apply_delivery_failures();
+                // Leaving peers are removed instead of refreshed
+                if let Message::Goodbye(_) = message {
+                    let id = *remote_node.id().as_binary();
+                    table.remove_nodes(|node| node.id().as_binary() == &id);
+                    for event in table.drain_events() {
+                        event::emit(&events, event.into());
+                    }
+                    continue;
+                }
+                // Measured before the insertion, which resets the pings
+                if let Message::Pong(header) = &message {
+                    let now = Instant::now();
+                    if let Some(guard) = &mut amplification {
+                        guard.pong(remote_node_addr, header, now);
+                    }
+                    if table
+                        .node_mut(header.binary_id.as_binary())
+                        .is_some_and(|node| node.record_pong(now))
+                    {
+                        table.last_round_trip = Some(now);
+                    }
+                }
+                if let Some(fallback) = remote_node.value().fallback_address() {
+                    table
+                        .filter()
+                        .fallbacks
+                        .record(remote_primary_addr, *fallback);
+                }
+                // Peers with a key identity are inserted once they prove to
+                // own it, which the ones in the table have already done
+                let id = *remote_node.id().as_binary();
+                let my_id = *my_header.binary_id.as_binary();
+                let verified = match (&message, remote_node.value().identity())
+                {
+                    (_, None) => !require_identity,
+                    (_, Some(_)) if table.has_peer(&id).is_some() => true,
+                    (
+                        Message::ChallengeResponse(header, token, signature),
+                        Some(_),
+                    ) => challenges.verify(
+                        &my_id,
+                        header,
+                        token,
+                        signature,
+                        Instant::now(),
+                    ),
+                    (_, Some(_)) => false,
+                };
+                // Pull requests and application requests are served to the
+                // table members, which are known before this message inserts
+                // the sender
+                let member = matches!(
+                    message,
+                    Message::IWant(..) | Message::Request(..)
+                )
+                    && table.node_mut(&id).is_some_and(|node| {
+                        node.value().address() == &remote_primary_addr
+                    });
+                // Peers advertising another id difficulty belong to another
+                // network, and they would never insert this one
+                let id_difficulty = remote_node.value().id_difficulty();
+                if id_difficulty != my_header.id_difficulty() {
+                    debug!(
+                        "Not inserting node {} - id difficulty {}",
+                        remote_primary_addr, id_difficulty
+                    );
+                } else if !verified {
+                    if table.bans().is_banned(&PeerTarget::Id(id)) {
+                        debug!(
+                            "Discarding message from banned node {}",
+                            remote_primary_addr
+                        );
+                        continue;
+                    }
+                    let token = match remote_node.value().identity() {
+                        Some(_) => challenges.challenge(&id, Instant::now()),
+                        None => None,
+                    };
+                    if let Some(token) = token {
+                        outbound_sender
+                            .send((
+                                Message::Challenge(my_header, token),
+                                vec![remote_node_addr],
+                                None,
+                            ))
+                            .await
+                            .unwrap_or_else(|op| {
+                                error!("Unable to send Challenge {:?}", op)
+                            });
+                    }
+                } else {
+                    match table.insert(remote_node) {
+                        Err(e) => match e {
+                            NodeInsertError::Full(n) => {
+                                debug!(
+                                    "Unable to insert node - FULL {}",
+                                    n.value().address()
+                                )
+                            }
+                            NodeInsertError::SubnetLimit(n) => {
+                                debug!(
+                                    "Unable to insert node - SUBNET LIMIT {}",
+                                    n.value().address()
+                                )
+                            }
+                            NodeInsertError::Invalid(n) => {
+                                error!(
+                                    "Unable to insert node - INVALID {}",
+                                    n.value().address()
+                                );
+                                continue;
+                            }
+                            NodeInsertError::Banned(n) => {
+                                debug!(
+                                    "Discarding message from banned node {}",
+                                    n.value().address()
+                                );
+                                continue;
+                            }
+                        },
+                        Ok(result) => {
+                            debug!("Written node in ktable: {:?}", &result);
+                            if let Some(pending) = result.pending_eviction() {
+                                outbound_sender
+                                    .send((
+                                        Message::Ping(my_header),
+                                        vec![*pending.value().address()],
+                                        None,
+                                    ))
+                                    .await
+                                    .unwrap_or_else(|op| {
+                                        error!("Unable to send PING to pending node {:?}", op)
+                                    });
+                            }
                         }
                     }
                 }
+                table.shed_memory();
+                for event in table.drain_events() {
+                    event::emit(&events, event.into());
+                }
+                drop(table);
                 match message {
-                    Message::Ping(_) => {
+                    Message::Ping(header) => {
                         outbound_sender
                             .send((
-                                Message::Pong(my_header),
+                                Message::Pong(my_header.echoing(&header)),
                                 vec![remote_node_addr],
+                                None,
                             ))
                             .await
                             .unwrap_or_else(|op| {
                                 error!("Unable to send Pong {:?}", op)
                             });
                     }
-                    Message::Pong(_) => {}
-                    Message::FindNodes(_, target) => {
+                    // The peers learned from a `Nodes` reply are queried
+                    // once they answered from the claimed address
+                    Message::Pong(_)
+                        if recursive_discovery
+                            && probes.answered(
+                                &remote_primary_addr,
+                                Instant::now(),
+                            ) =>
+                    {
                         outbound_sender
                             .send((
-                                Message::Nodes(
-                                    my_header,
-                                    NodePayload {
-                                        peers: ktable
-                                            .read()
-                                            .await
-                                            .closest_peers::<K_K>(&target)
-                                            .map(|p| p.as_peer_info())
-                                            .collect(),
-                                    },
+                                Message::FindNodes(my_header, id),
+                                vec![remote_node_addr],
+                                None,
+                            ))
+                            .await
+                            .unwrap_or_else(|op| {
+                                error!("Unable to send FindNodes {:?}", op)
+                            });
+                    }
+                    Message::Pong(_) | Message::Goodbye(_) => {}
+                    Message::Challenge(header, token) => {
+                        let signature = match challenges
+                            .answer(header.binary_id.as_binary(), &token)
+                        {
+                            Some(signature) => signature,
+                            None => continue,
+                        };
+                        outbound_sender
+                            .send((
+                                Message::ChallengeResponse(
+                                    my_header, token, signature,
+                                ),
+                                vec![remote_node_addr],
+                                None,
+                            ))
+                            .await
+                            .unwrap_or_else(|op| {
+                                error!("Unable to send ChallengeResponse {:?}", op)
+                            });
+                    }
+                    // Verified before the insertion
+                    Message::ChallengeResponse(..) => {}
+                    Message::Request(_, id, request) => {
+                        let handler = match &request_handler {
+                            Some(handler) => handler.clone(),
+                            None => {
+                                debug!(
+                                    "Discarding request {} - no handler",
+                                    id
+                                );
+                                continue;
+                            }
+                        };
+                        if !member {
+                            debug!(
+                                "Discarding request {} from unknown {}",
+                                id, remote_node_addr
+                            );
+                            event::emit(
+                                &events,
+                                KadcastEvent::MessageDropped(
+                                    remote_address,
+                                    DropReason::UnknownSender,
                                 ),
+                            );
+                            continue;
+                        }
+                        let slot = match request_slots.clone().try_acquire_owned()
+                        {
+                            Ok(slot) => slot,
+                            Err(_) => {
+                                warn!("Discarding request {} - busy", id);
+                                event::emit(
+                                    &events,
+                                    KadcastEvent::MessageDropped(
+                                        remote_address,
+                                        DropReason::Overloaded,
+                                    ),
+                                );
+                                continue;
+                            }
+                        };
+                        let outbound_sender = outbound_sender.clone();
+                        // The application handler may block
+                        task::spawn_blocking(move || {
+                            let _slot = slot;
+                            let response = match handler
+                                .on_request(request, remote_node_addr)
+                            {
+                                Some(response)
+                                    if response.len() > MAX_REQUEST_LEN =>
+                                {
+                                    error!(
+                                        "Response too long: {}",
+                                        response.len()
+                                    );
+                                    return;
+                                }
+                                Some(response) => response,
+                                None => return,
+                            };
+                            outbound_sender
+                                .blocking_send((
+                                    Message::Response(my_header, id, response),
+                                    vec![remote_node_addr],
+                                    None,
+                                ))
+                                .unwrap_or_else(|op| {
+                                    error!("Unable to send Response {:?}", op)
+                                });
+                        });
+                    }
+                    Message::Response(_, id, response) => {
+                        if !replies.requests.resolve(
+                            id,
+                            &[remote_primary_addr, remote_address],
+                            response,
+                        ) {
+                            debug!("Discarding unexpected response {}", id);
+                        }
+                    }
+                    // Handled by the transport layer
+                    Message::TransmissionInfoRequest(..)
+                    | Message::TransmissionInfoResponse(..)
+                    | Message::Ack(..)
+                    | Message::Unknown(..)
+                    | Message::Raw(..) => {}
+                    Message::IHave(_, uids) => {
+                        let recent = match &replies.recent {
+                            Some(recent) => recent,
+                            None => continue,
+                        };
+                        let missing = recent.missing(&uids);
+                        if missing.is_empty() {
+                            continue;
+                        }
+                        debug!(
+                            "Requesting {} missed messages to {}",
+                            missing.len(),
+                            remote_node_addr
+                        );
+                        outbound_sender
+                            .send((
+                                Message::IWant(my_header, missing),
                                 vec![remote_node_addr],
+                                None,
                             ))
                             .await
                             .unwrap_or_else(|op| {
-                                error!("Unable to send Nodes {:?}", op)
+                                error!("Unable to send IWant {:?}", op)
                             });
                     }
+                    Message::IWant(_, uids) => {
+                        let recent = match &replies.recent {
+                            Some(recent) => recent,
+                            None => continue,
+                        };
+                        // The messages are much bigger than the request, so
+                        // they are never sent to an unverified address
+                        let contacted = amplification.as_ref().is_some_and(
+                            |guard| {
+                                guard.contacted(&remote_node_addr, Instant::now())
+                            },
+                        );
+                        if !member && !contacted {
+                            debug!(
+                                "Not serving IWant from {}",
+                                remote_node_addr
+                            );
+                            event::emit(
+                                &events,
+                                KadcastEvent::MessageDropped(
+                                    remote_address,
+                                    DropReason::NoContact,
+                                ),
+                            );
+                            continue;
+                        }
+                        for payload in recent.serve(remote_node_addr, &uids) {
+                            outbound_sender
+                                .send((
+                                    Message::Broadcast(my_header, payload),
+                                    vec![remote_node_addr],
+                                    None,
+                                ))
+                                .await
+                                .unwrap_or_else(|op| {
+                                    error!(
+                                        "Unable to send missed message {:?}",
+                                        op
+                                    )
+                                });
+                        }
+                    }
+                    Message::FindNodes(header, target) => {
+                        let now = Instant::now();
+                        let reply_header = my_header.echoing(&header);
+                        // Ping back the requesters not contacted yet, the
+                        // `Ping` being smaller than the request
+                        let contacted = amplification.as_ref().map(|guard| {
+                            guard.contacted(&remote_node_addr, now)
+                        });
+                        if contacted == Some(false) {
+                            debug!(
+                                "Not answering FindNodes from {}",
+                                remote_node_addr
+                            );
+                            event::emit(
+                                &events,
+                                KadcastEvent::MessageDropped(
+                                    remote_address,
+                                    DropReason::NoContact,
+                                ),
+                            );
+                            outbound_sender
+                                .send((
+                                    Message::Ping(my_header),
+                                    vec![remote_node_addr],
+                                    None,
+                                ))
+                                .await
+                                .unwrap_or_else(|op| {
+                                    error!("Unable to send Ping {:?}", op)
+                                });
+                            continue;
+                        }
+                        // Observers are never handed out as candidates
+                        let peers = ktable
+                            .read()
+                            .await
+                            .closest_peers(&target, usize::MAX)
+                            .filter(|p| !p.value().is_observer())
+                            .take(bucket_capacity)
+                            .map(|p| p.as_peer_info())
+                            .collect();
+                        // Each page is a self-contained `Nodes` message
+                        let pages = match &amplification {
+                            Some(guard) => {
+                                let request = Message::FindNodes(header, target);
+                                let empty = Message::Nodes(
+                                    reply_header,
+                                    NodePayload { peers: vec![] },
+                                );
+                                NodePayload::paginate_bounded(
+                                    peers,
+                                    guard.max_reply_len(encoded_len(&request)),
+                                    encoded_len(&empty),
+                                )
+                            }
+                            None => NodePayload::paginate(peers),
+                        };
+                        for page in pages {
+                            outbound_sender
+                                .send((
+                                    Message::Nodes(reply_header, page),
+                                    vec![remote_node_addr],
+                                    None,
+                                ))
+                                .await
+                                .unwrap_or_else(|op| {
+                                    error!("Unable to send Nodes {:?}", op)
+                                });
+                        }
+                    }
                     Message::Nodes(_, nodes) => {
+                        // Feed the running lookups, if any
+                        if replies.nodes.receiver_count() > 0 {
+                            let _ = replies.nodes.send((
+                                remote_primary_addr,
+                                nodes.peers.clone(),
+                            ));
+                        }
                         if !nodes.peers.is_empty() {
                             let reader = ktable.read().await;
+                            for peer in &nodes.peers {
+                                if let Some(fallback) = peer.fallback_address()
+                                {
+                                    reader.filter().fallbacks.record(
+                                        peer.to_socket_address(),
+                                        fallback,
+                                    );
+                                }
+                            }
                             let messages = nodes
                                 .peers
                                 .iter()
@@ -144,6 +960,12 @@ impl MessageHandler {
                                 .filter(|&n| {
                                     &n.id != my_header.binary_id.as_binary()
                                 })
+                                .filter(|&n| {
+                                    reader.filter().accepts(
+                                        &n.to_socket_address().ip(),
+                                        &n.id,
+                                    )
+                                })
                                 .filter(|&n| {
                                     let h = my_header
                                         .binary_id
@@ -158,23 +980,38 @@ impl MessageHandler {
                                         }
                                     }
                                 })
+                                .filter(|&n| {
+                                    probes.probe(
+                                        n.to_socket_address(),
+                                        Instant::now(),
+                                    )
+                                })
                                 .map(|n| {
                                     (
-                                        nodes_reply_fn(my_header, n.id),
+                                        Message::Ping(my_header),
                                         vec![n.to_socket_address()],
+                                        None,
                                     )
                                 })
-                                .collect::<Vec<(Message, Vec<SocketAddr>)>>();
+                                .collect::<Vec<MessageBeanOut>>();
                             for tosend in messages {
                                 outbound_sender.send(tosend).await.unwrap_or_else(
                                     |op| {
-                                        error!( "Unable to send FindNodes after reply {:?}", op)
+                                        error!( "Unable to send Ping after reply {:?}", op)
                                     },
                                 );
                             }
                         }
                     }
-                    Message::Broadcast(_, payload) => {
+                    Message::Broadcast(header, payload) => {
+                        // Copies recovered from several neighbours are
+                        // notified once
+                        if let Some(recent) = &replies.recent {
+                            if !recent.insert(&payload) {
+                                debug!("Skipping already seen message");
+                                continue;
+                            }
+                        }
                         debug!(
                             "Received payload with height {:?} and len {}",
                             payload.height,
@@ -182,57 +1019,211 @@ impl MessageHandler {
                         );
 
                         // Aggregate message + metadata for lib client
-                        let msg = payload.gossip_frame.clone();
                         let md = MessageInfo {
                             src: remote_node_addr,
                             height: payload.height,
+                            topic: payload.topic,
+                            priority: payload.priority,
+                            origin: payload
+                                .origin
+                                .map(|origin| origin.public_key),
+                            origin_id: payload
+                                .origin
+                                .map(|origin| origin.origin_id),
+                            uid: gossip_uid(&payload),
+                            sender_id: *header.binary_id.as_binary(),
+                            origin_height: header.origin_height(),
+                            received_at: reception
+                                .map_or_else(SystemTime::now, |r| r.decoded_at),
+                            chunks: reception.map_or(1, |r| r.chunks),
+                            decode_duration: reception
+                                .map(|r| r.duration)
+                                .unwrap_or_default(),
                         };
 
-                        // Notify lib client
-                        listener_sender.send((msg, md)).await.unwrap_or_else(
-                            |op| error!("Unable to notify client {:?}", op),
-                        );
-                        if auto_propagate && payload.height > 0 {
-                            debug!(
-                                "Extracting for height {:?}",
-                                payload.height - 1
-                            );
-                            let table_read = ktable.read().await;
-
-                            let messages: Vec<(Message, Vec<SocketAddr>)> = table_read
-                                .extract(Some((payload.height - 1).into()))
-                                .map(|(height, nodes)| {
-                                    let msg = Message::Broadcast(
-                                        my_header,
-                                        BroadcastPayload {
-                                            height: height.try_into().unwrap(),
-                                            gossip_frame: payload
-                                                .gossip_frame
-                                                .clone(), //FIX_ME: avoid clone
-                                        },
-                                    );
-                                    let targets: Vec<SocketAddr> = nodes
-                                        .map(|node| *node.value().address())
-                                        .collect();
-                                    (msg, targets)
-                                }).collect();
-                            drop(table_read);
-
-                            for tosend in messages {
-                                outbound_sender
-                                    .send(tosend)
-                                    .await
-                                    .unwrap_or_else(|op| {
-                                        error!(
-                                            "Unable to send broadcast {:?}",
-                                            op
-                                        )
-                                    });
-                            }
-                        }
+                        // Notify lib client, which judges the propagation
+                        listener_sender
+                            .send((payload, md))
+                            .await
+                            .unwrap_or_else(|op| {
+                                error!("Unable to notify client {:?}", op)
+                            });
                     }
                 }
             }
-        });
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use super::{
+        relay_height, AmplificationGuard, MessageRef, Probes, Propagation,
+        Relayer, MAX_CONTACTS, MAX_PROBES, PROBE_TIMEOUT,
+    };
+    use crate::channel::{self, Receiver};
+    use crate::config::{
+        AmplificationGuardConfig, BucketConfig, Config, OverflowPolicy,
+    };
+    use crate::encoding::message::{BroadcastPayload, Header, Message};
+    use crate::gossip::gossip_uid;
+    use crate::kbucket::Tree;
+    use crate::peer::PeerNode;
+    use crate::transport::MessageBeanOut;
+    use crate::{DEFAULT_PRIORITY, DEFAULT_TOPIC};
+
+    fn relayer(enabled: bool) -> (Relayer, Receiver<MessageBeanOut>) {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let header = root.as_header();
+        let mut tree = Tree::new(root, BucketConfig::default());
+        for i in 2..6 {
+            let _ = tree.insert(PeerNode::generate(
+                &format!("192.168.0.{}:666", i)[..],
+            ));
+        }
+        let (tx, rx) =
+            channel::channel(100, OverflowPolicy::Block, Arc::default());
+        let conf = Config::default();
+        (
+            Relayer::new(tree.view().clone(), header, tx, enabled, &conf),
+            rx,
+        )
+    }
+
+    fn payload(frame: u8) -> BroadcastPayload {
+        BroadcastPayload {
+            height: 128,
+            topic: DEFAULT_TOPIC,
+            priority: DEFAULT_PRIORITY,
+            origin: None,
+            message_id: None,
+            batch: false,
+            gossip_frame: vec![frame; 3],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pause_relay() {
+        let (relayer, mut rx) = relayer(false);
+        relayer.relay(&payload(1), None, None).await;
+        assert!(rx.try_recv().is_err());
+
+        relayer.set_enabled(true);
+        relayer.relay(&payload(1), None, None).await;
+        assert!(rx.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_repropagate() {
+        let (relayer, mut rx) = relayer(true);
+        let dropped = Some(Propagation::Drop);
+        relayer.relay(&payload(1), None, dropped).await;
+        relayer.relay(&payload(2), None, dropped).await;
+        assert!(rx.try_recv().is_err());
+
+        let uid = MessageRef::Uid(gossip_uid(&payload(1)));
+        assert!(!relayer.repropagate(&uid, 0).await);
+        assert!(relayer.repropagate(&uid, 200).await);
+        match rx.try_recv() {
+            Ok((Message::Broadcast(_, relayed), _, _)) => {
+                assert!(relayed.height < 128);
+                assert_eq!(relayed.gossip_frame, payload(1).gossip_frame);
+            }
+            _ => panic!("Message not relayed"),
+        }
+        // Relayed only once
+        assert!(!relayer.repropagate(&uid, 200).await);
+
+        let bytes = MessageRef::from(&payload(2).gossip_frame[..]);
+        relayer.set_enabled(false);
+        assert!(!relayer.repropagate(&bytes, 200).await);
+        relayer.set_enabled(true);
+        assert!(relayer.repropagate(&bytes, 200).await);
+    }
+
+    #[test]
+    fn test_probes() {
+        let mut probes = Probes::default();
+        let now = Instant::now();
+        let address = "192.168.0.2:666".parse().unwrap();
+        assert!(probes.probe(address, now));
+        // A single ping is pending per address
+        assert!(!probes.probe(address, now));
+        assert!(probes.answered(&address, now));
+        assert!(!probes.answered(&address, now));
+
+        // Late answers are not accepted
+        assert!(probes.probe(address, now));
+        assert!(!probes.answered(&address, now + PROBE_TIMEOUT));
+
+        // The expired pings make room for the new ones
+        for port in 0..MAX_PROBES {
+            let address = format!("192.168.1.1:{}", port + 1).parse().unwrap();
+            assert!(probes.probe(address, now));
+        }
+        assert!(!probes.probe(address, now));
+        assert!(probes.probe(address, now + PROBE_TIMEOUT));
+    }
+
+    #[test]
+    fn test_amplification_guard() {
+        let conf = AmplificationGuardConfig {
+            max_amplification: 3,
+            contact_ttl: Duration::from_secs(10),
+        };
+        let mut guard = AmplificationGuard::new(conf);
+        let now = Instant::now();
+        let address = "192.168.0.2:666".parse().unwrap();
+        let header = PeerNode::generate("192.168.0.2:666").as_header();
+        assert!(!guard.contacted(&address, now));
+
+        // Only the `Pong` echoing a nonce count
+        guard.pong(address, &header, now);
+        assert!(!guard.contacted(&address, now));
+        let echoing = Header {
+            request_nonce: Some(1),
+            ..header
+        };
+        guard.pong(address, &echoing, now);
+        assert!(guard.contacted(&address, now));
+        assert!(!guard.contacted(&address, now + conf.contact_ttl));
+
+        // The least recent contacts make room for the new ones
+        for port in 0..MAX_CONTACTS {
+            let other = format!("192.168.1.1:{}", port + 1).parse().unwrap();
+            guard.pong(other, &echoing, now + Duration::from_secs(1));
+        }
+        assert!(!guard.contacted(&address, now));
+        assert_eq!(guard.contacts.len(), MAX_CONTACTS);
+
+        // The refreshed contacts are kept, their outdated entries dropped
+        let other = "192.168.1.1:1".parse().unwrap();
+        let last = MAX_CONTACTS as u64 + 3;
+        for secs in 2..=last {
+            guard.pong(other, &echoing, now + Duration::from_secs(secs));
+        }
+        assert!(guard.contacted(&other, now + Duration::from_secs(last)));
+        assert!(guard.order.len() <= 2 * MAX_CONTACTS);
+        assert_eq!(guard.max_reply_len(100), 300);
+    }
+
+    #[test]
+    fn test_relay_height() {
+        assert_eq!(relay_height(5, None, true), Some(4));
+        assert_eq!(relay_height(5, None, false), None);
+        assert_eq!(relay_height(0, Some(Propagation::Propagate), true), None);
+        assert_eq!(relay_height(5, Some(Propagation::Drop), true), None);
+        // The verdict overrides the global flag
+        assert_eq!(
+            relay_height(5, Some(Propagation::Propagate), false),
+            Some(4)
+        );
+        // but can't extend the propagation
+        let with_height = |h| Some(Propagation::PropagateWithHeight(h));
+        assert_eq!(relay_height(5, with_height(2), false), Some(2));
+        assert_eq!(relay_height(5, with_height(9), true), Some(4));
     }
 }
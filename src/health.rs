@@ -0,0 +1,85 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use crate::channel::{ChannelOverflows, OverflowCounters};
+
+/// Liveness report of a [Peer](crate::Peer), see
+/// [Peer::health](crate::Peer::health)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Health {
+    pub(crate) tasks_running: bool,
+    pub(crate) inbound_queue: usize,
+    pub(crate) outbound_queue: usize,
+    pub(crate) alive_peers: usize,
+    pub(crate) last_round_trip: Option<Instant>,
+    pub(crate) decoder_cache: usize,
+    pub(crate) channel_overflows: ChannelOverflows,
+}
+
+impl Health {
+    /// Every task of the peer is running, ie: none of them has failed
+    pub fn tasks_running(&self) -> bool {
+        self.tasks_running
+    }
+
+    /// Received messages waiting to be handled
+    pub fn inbound_queue(&self) -> usize {
+        self.inbound_queue
+    }
+
+    /// Messages waiting to be encoded and sent
+    pub fn outbound_queue(&self) -> usize {
+        self.outbound_queue
+    }
+
+    /// Peers of the routing table which are not idle
+    pub fn alive_peers(&self) -> usize {
+        self.alive_peers
+    }
+
+    /// Last time a peer answered a ping of this one. `None` if no ping has
+    /// been answered yet
+    pub fn last_round_trip(&self) -> Option<Instant> {
+        self.last_round_trip
+    }
+
+    /// Broadcast messages being decoded, or recently decoded
+    pub fn decoder_cache(&self) -> usize {
+        self.decoder_cache
+    }
+
+    /// Messages dropped by the internal channels because they were full,
+    /// according to their [OverflowPolicy](crate::config::OverflowPolicy)
+    pub fn channel_overflows(&self) -> ChannelOverflows {
+        self.channel_overflows
+    }
+
+    /// Check if the peer is running with at least `min_peers` alive peers,
+    /// eg: for a readiness probe
+    pub fn is_ready(&self, min_peers: usize) -> bool {
+        self.tasks_running && self.alive_peers >= min_peers
+    }
+}
+
+/// State of the transport tasks sampled by the health reports
+#[derive(Default)]
+pub(crate) struct HealthProbe {
+    decoder_cache: AtomicUsize,
+    pub(crate) overflows: OverflowCounters,
+}
+
+impl HealthProbe {
+    pub(crate) fn set_decoder_cache(&self, size: usize) {
+        self.decoder_cache.store(size, Ordering::Relaxed);
+    }
+
+    pub(crate) fn decoder_cache(&self) -> usize {
+        self.decoder_cache.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,239 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use blake2::{Blake2s, Digest};
+use ed25519_dalek::{
+    Keypair, PublicKey, SecretKey, Signature, Signer, Verifier,
+};
+
+use crate::config::Config;
+use crate::encoding::message::{
+    ChallengeToken, Header, IdentityKey, IdentitySignature,
+};
+use crate::kbucket::BinaryKey;
+
+/// Time a peer is given to answer a challenge
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Max number of challenges waiting for an answer, the new peers are not
+/// challenged beyond it
+const MAX_PENDING_CHALLENGES: usize = 1024;
+
+/// Ed25519 public key of an identity secret key, see
+/// [Config::identity_key]
+pub(crate) fn public_key(secret: &[u8; 32]) -> IdentityKey {
+    let secret = SecretKey::from_bytes(secret).expect("Invalid identity key");
+    PublicKey::from(&secret).to_bytes()
+}
+
+/// Proofs of possession of the key identities: the challenges sent to the
+/// peers before inserting them in the routing table, and the answers to the
+/// ones received
+pub(crate) struct Challenges {
+    keypair: Option<Keypair>,
+    pending: HashMap<BinaryKey, (ChallengeToken, Instant)>,
+}
+
+impl Challenges {
+    pub(crate) fn new(config: &Config) -> Self {
+        let keypair = config.identity_key.map(|key| {
+            let secret =
+                SecretKey::from_bytes(&key).expect("Invalid identity key");
+            let public = PublicKey::from(&secret);
+            Keypair { secret, public }
+        });
+        Self {
+            keypair,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Token to challenge the peer `id` with. `None` if a challenge is
+    /// already pending for it, or if too many are
+    pub(crate) fn challenge(
+        &mut self,
+        id: &BinaryKey,
+        now: Instant,
+    ) -> Option<ChallengeToken> {
+        self.pending.retain(|_, (_, sent)| {
+            now.duration_since(*sent) < CHALLENGE_TIMEOUT
+        });
+        if self.pending.contains_key(id)
+            || self.pending.len() >= MAX_PENDING_CHALLENGES
+        {
+            return None;
+        }
+        let token = rand::random();
+        self.pending.insert(*id, (token, now));
+        Some(token)
+    }
+
+    /// Sign the token of a challenge sent by `challenger`, if the local peer
+    /// has a key identity
+    pub(crate) fn answer(
+        &self,
+        challenger: &BinaryKey,
+        token: &ChallengeToken,
+    ) -> Option<IdentitySignature> {
+        self.keypair
+            .as_ref()
+            .map(|keypair| keypair.sign(&digest(challenger, token)).to_bytes())
+    }
+
+    /// Check the answer of the sender of `header` to the pending challenge
+    /// of the local peer `challenger`, which is resolved if the signature is
+    /// valid
+    pub(crate) fn verify(
+        &mut self,
+        challenger: &BinaryKey,
+        header: &Header,
+        token: &ChallengeToken,
+        signature: &IdentitySignature,
+        now: Instant,
+    ) -> bool {
+        let id = header.binary_id().as_binary();
+        match self.pending.get(id) {
+            Some((pending, sent))
+                if pending == token
+                    && now.duration_since(*sent) < CHALLENGE_TIMEOUT => {}
+            _ => return false,
+        }
+        let public_key =
+            match header.identity().map(|key| PublicKey::from_bytes(key)) {
+                Some(Ok(public_key)) => public_key,
+                _ => return false,
+            };
+        let valid = Signature::try_from(&signature[..])
+            .and_then(|signature| {
+                public_key.verify(&digest(challenger, token), &signature)
+            })
+            .is_ok();
+        if valid {
+            self.pending.remove(id);
+        }
+        valid
+    }
+}
+
+// The id of the challenger is signed along with the token, so that an
+// answer can't be relayed to another challenger
+fn digest(challenger: &BinaryKey, token: &ChallengeToken) -> [u8; 32] {
+    let mut hasher = Blake2s::new();
+    hasher.update(b"kadcast-identity");
+    hasher.update(challenger);
+    hasher.update(token);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::PeerNode;
+
+    fn identity_config(key: u8) -> Config {
+        Config {
+            public_address: "192.168.1.1:666".to_string(),
+            identity_key: Some([key; 32]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_identity_challenge() {
+        let config = identity_config(1);
+        let node = PeerNode::root(&config);
+        let header = node.as_header();
+        assert_eq!(header.identity(), Some(&public_key(&[1; 32])));
+        assert!(header.has_custom_id());
+
+        let challenger = [9; crate::K_ID_LEN_BYTES];
+        let mut challenges = Challenges::new(&Config::default());
+        let responder = Challenges::new(&config);
+        let now = Instant::now();
+        let id = node.id().as_binary();
+        let token = challenges.challenge(id, now).unwrap();
+        // A single challenge is pending per peer
+        assert_eq!(challenges.challenge(id, now), None);
+
+        // Answers to another challenger or for another token are rejected
+        let other = responder.answer(&[8; crate::K_ID_LEN_BYTES], &token);
+        assert!(!challenges.verify(
+            &challenger,
+            &header,
+            &token,
+            &other.unwrap(),
+            now
+        ));
+        let signature = responder.answer(&challenger, &token).unwrap();
+        assert!(!challenges.verify(
+            &challenger,
+            &header,
+            &[0; 32],
+            &signature,
+            now
+        ));
+
+        // Nor a key which isn't the one of the challenged peer
+        let impostor = PeerNode::root(&identity_config(2)).as_header();
+        let forged = Header {
+            binary_id: header.binary_id,
+            ..impostor
+        };
+        assert!(!challenges.verify(
+            &challenger,
+            &forged,
+            &token,
+            &signature,
+            now
+        ));
+
+        assert!(challenges.verify(
+            &challenger,
+            &header,
+            &token,
+            &signature,
+            now
+        ));
+        // The challenge is resolved once answered
+        assert!(!challenges.verify(
+            &challenger,
+            &header,
+            &token,
+            &signature,
+            now
+        ));
+
+        // Peers without an identity key can't answer
+        assert_eq!(challenges.answer(&challenger, &token), None);
+    }
+
+    #[test]
+    fn test_challenge_timeout() {
+        let config = identity_config(1);
+        let node = PeerNode::root(&config);
+        let challenger = [9; crate::K_ID_LEN_BYTES];
+        let mut challenges = Challenges::new(&Config::default());
+        let responder = Challenges::new(&config);
+        let now = Instant::now();
+        let id = node.id().as_binary();
+        let token = challenges.challenge(id, now).unwrap();
+        let signature = responder.answer(&challenger, &token).unwrap();
+        let late = now + CHALLENGE_TIMEOUT;
+        assert!(!challenges.verify(
+            &challenger,
+            &node.as_header(),
+            &token,
+            &signature,
+            late
+        ));
+        // The expired challenge is replaced by a new one
+        assert!(challenges.challenge(id, late).is_some());
+    }
+}
@@ -4,42 +4,153 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
 
 use bucket::Bucket;
 pub use bucket::{NodeInsertError, NodeInsertOk};
+use diversity::Subnetwork;
 use itertools::Itertools;
+pub use key::{bucket_height, derive_key, xor_distance};
 pub use key::{BinaryID, BinaryKey, BinaryNonce};
-pub use node::Node;
+pub use node::{Node, NodeStats};
 
 pub use bucket::InsertError;
 pub use bucket::InsertOk;
+pub(crate) use bucket::TableEvent;
+use stats::TableMetric;
 use tracing::info;
 
+mod allow;
+mod ban;
 mod bucket;
+mod cache;
+mod delegate;
+mod diversity;
+mod eviction;
+mod export;
+mod failures;
+mod fallback;
+mod keepalive;
 mod key;
+mod memory;
 mod node;
+mod score;
+mod snapshot;
+mod stats;
+mod store;
+mod target;
+mod trust;
+mod view;
 use crate::config::BucketConfig;
-use crate::K_ALPHA;
-use crate::K_BETA;
-use crate::K_ID_LEN_BYTES;
+pub(crate) use allow::AllowList;
+pub(crate) use ban::BanList;
+pub(crate) use cache::BootstrapCache;
+pub use delegate::{AdaptiveDelegates, DelegatePolicy, FixedDelegates};
+pub use eviction::{EvictionAction, EvictionPolicy, LruEviction};
+pub(crate) use export::{to_hex, ExportedTable};
+pub(crate) use failures::DeliveryFailures;
+pub(crate) use fallback::FallbackAddresses;
+pub use keepalive::{KeepAliveAction, KeepAlivePolicy, NoKeepAlive};
+pub use memory::MemoryUsage;
+pub use score::{Score, DEFAULT_SCORE, MAX_SCORE, MIN_SCORE};
+pub(crate) use score::{
+    ScoreReports, INVALID_MESSAGE_PENALTY, MALFORMED_MESSAGE_PENALTY,
+    RATE_LIMIT_PENALTY,
+};
+pub use snapshot::{BucketOccupancy, BucketSnapshot, PeerSnapshot, RouteTable};
+pub use snapshot::{PeerRef, PeerStatus};
+pub(crate) use store::{FilePeerStore, PeerStore, StoredPeer, StoredTable};
+pub use target::PeerTarget;
+pub(crate) use target::{Host, PeerFilter};
+pub(crate) use trust::TrustedPeers;
+pub(crate) use view::TableView;
 
 pub type BucketHeight = usize;
 
 pub(crate) struct Tree<V> {
     root: Node<V>,
     buckets: HashMap<BucketHeight, Bucket<V>>,
+    filter: PeerFilter,
+    view: TableView<V>,
+    anchors: HashSet<BinaryKey>,
     pub(crate) config: BucketConfig,
+    // Last time a ping has been answered by a peer of the table
+    pub(crate) last_round_trip: Option<Instant>,
 }
 
 impl<V> Tree<V> {
     pub fn insert(
         &mut self,
-        node: Node<V>,
-    ) -> Result<InsertOk<V>, InsertError<V>> {
+        mut node: Node<V>,
+    ) -> Result<InsertOk<V>, InsertError<V>>
+    where
+        V: Clone + Subnetwork + Host,
+    {
+        if self
+            .filter
+            .bans
+            .is_banned(&PeerTarget::Id(*node.id().as_binary()))
+        {
+            return Err(NodeInsertError::Banned(node));
+        }
+        node.anchor = self.anchors.contains(node.id().as_binary());
+        node.trusted = self
+            .filter
+            .trusted
+            .is_trusted_peer(&node.value().host(), node.id().as_binary());
         match self.root.calculate_distance(&node) {
             None => Err(NodeInsertError::Invalid(node)),
-            Some(height) => self.get_or_create_bucket(height).insert(node),
+            Some(height) => {
+                if self.exceeds_subnet_limits(&node, height) {
+                    stats::record(TableMetric::RejectedSubnet);
+                    return Err(NodeInsertError::SubnetLimit(node));
+                }
+                self.get_or_create_bucket(height).insert(node)
+            }
+        }
+    }
+
+    // Check if a new node would exceed the nodes allowed from its subnet.
+    // The nodes already in the table are always refreshed
+    fn exceeds_subnet_limits(
+        &self,
+        node: &Node<V>,
+        height: BucketHeight,
+    ) -> bool
+    where
+        V: Subnetwork,
+    {
+        let per_bucket = self.config.max_subnet_nodes_per_bucket;
+        let per_table = self.config.max_subnet_nodes_per_table;
+        if per_bucket.is_none() && per_table.is_none() {
+            return false;
+        }
+        let bucket = match self.buckets.get(&height) {
+            Some(bucket) if bucket.has_node(node.id().as_binary()) => {
+                return false
+            }
+            Some(bucket) => Some(bucket),
+            None => None,
+        };
+        let subnet = node.value().subnet();
+        let same_subnet = |n: &&Node<V>| n.value().subnet() == subnet;
+        if let (Some(max), Some(bucket)) = (per_bucket, bucket) {
+            if bucket.peers().filter(same_subnet).count() >= max {
+                return true;
+            }
+        }
+        match per_table {
+            Some(max) => {
+                self.buckets
+                    .values()
+                    .flat_map(|bucket| bucket.peers())
+                    .filter(same_subnet)
+                    .count()
+                    >= max
+            }
+            None => false,
         }
     }
 
@@ -47,31 +158,31 @@ impl<V> Tree<V> {
         return match self.buckets.entry(height) {
             std::collections::hash_map::Entry::Occupied(o) => o.into_mut(),
             std::collections::hash_map::Entry::Vacant(v) => {
-                v.insert(Bucket::new(self.config))
+                // Split buckets are bigger, their nodes are ruled by the same
+                // policies
+                let mut config = self.config.clone();
+                config.capacity = self.config.capacity_at(height);
+                v.insert(
+                    Bucket::new(config).with_view(self.view.clone(), height),
+                )
             }
         };
     }
 
-    //iter the buckets (up to max_height, inclusive) and pick at most Beta
-    // nodes for each bucket
-    pub(crate) fn extract(
-        &self,
-        max_h: Option<usize>,
-    ) -> impl Iterator<Item = (BucketHeight, impl Iterator<Item = &Node<V>>)>
-    {
-        self.buckets
-            .iter()
-            .filter(move |(&height, _)| height <= max_h.unwrap_or(usize::MAX))
-            .map(|(&height, bucket)| (height, bucket.pick::<K_BETA>()))
+    /// The read side of the table, used to pick the delegates of the
+    /// broadcast messages without locking the table
+    pub(crate) fn view(&self) -> &TableView<V> {
+        &self.view
     }
 
     pub(crate) fn root(&self) -> &Node<V> {
         &self.root
     }
 
-    pub(crate) fn closest_peers<const ITEM_COUNT: usize>(
+    pub(crate) fn closest_peers(
         &self,
         other: &BinaryKey,
+        count: usize,
     ) -> impl Iterator<Item = &Node<V>> {
         self.buckets
             .iter()
@@ -83,7 +194,21 @@ impl<V> Tree<V> {
                     &b.id().calculate_distance(other),
                 )
             })
-            .take(ITEM_COUNT)
+            .take(count)
+    }
+
+    /// The `count` nodes closest to the key in the XOR metric, the closest
+    /// first
+    pub(crate) fn closest_nodes(
+        &self,
+        key: &BinaryKey,
+        count: usize,
+    ) -> impl Iterator<Item = &Node<V>> {
+        self.buckets
+            .values()
+            .flat_map(|bucket| bucket.peers())
+            .sorted_by_cached_key(|node| node.id().xor_distance(key))
+            .take(count)
     }
 
     pub(crate) fn all_sorted(
@@ -96,24 +221,29 @@ impl<V> Tree<V> {
             .map(|(&height, bucket)| (height, bucket.peers()))
     }
 
-    #[allow(dead_code)]
-    pub(crate) fn idle_or_empty_heigth(
-        &'static self,
-    ) -> impl Iterator<Item = BucketHeight> {
-        (0..K_ID_LEN_BYTES * 8).filter(move |h| {
-            self.buckets.get(h).map_or_else(|| true, |b| b.is_idle())
-        })
+    /// The heights of the buckets to refresh, which are marked as refreshed
+    pub(crate) fn buckets_to_refresh(&mut self) -> Vec<BucketHeight> {
+        let now = Instant::now();
+        self.buckets
+            .iter_mut()
+            .filter(|(_, bucket)| bucket.refresh_at() <= now)
+            .map(|(&height, bucket)| {
+                bucket.mark_refreshed();
+                height
+            })
+            .collect()
     }
 
-    //pick at most Alpha nodes for each idle bucket
-    pub(crate) fn idle_buckets(
-        &self,
-    ) -> impl Iterator<Item = (BucketHeight, impl Iterator<Item = &Node<V>>)>
-    {
+    /// When the next bucket should be refreshed
+    pub(crate) fn next_refresh(&self) -> Option<Instant> {
         self.buckets
-            .iter()
-            .filter(move |(_, bucket)| bucket.is_idle())
-            .map(|(&height, bucket)| (height, bucket.pick::<K_ALPHA>()))
+            .values()
+            .map(|bucket| bucket.refresh_at())
+            .min()
+    }
+
+    pub(crate) fn last_refresh(&self, height: BucketHeight) -> Option<Instant> {
+        self.buckets.get(&height).and_then(|b| b.last_refresh())
     }
 
     pub(crate) fn has_peer(&self, peer: &BinaryKey) -> Option<usize> {
@@ -129,10 +259,86 @@ impl<V> Tree<V> {
         }
     }
 
-    pub(crate) fn remove_idle_nodes(&mut self) {
+    /// Apply the keep alive policy to the idle nodes, returning the ones to
+    /// ping
+    pub(crate) fn keep_alive(&mut self, policy: &dyn KeepAlivePolicy) -> Vec<V>
+    where
+        V: Clone,
+    {
+        self.buckets
+            .values_mut()
+            .flat_map(|bucket| bucket.keep_alive(policy))
+            .collect()
+    }
+
+    /// Remove the nodes matching the predicate
+    pub(crate) fn remove_nodes<F>(&mut self, predicate: F) -> usize
+    where
+        V: Clone,
+        F: Fn(&Node<V>) -> bool,
+    {
+        self.buckets
+            .values_mut()
+            .map(|bucket| bucket.remove_nodes(&predicate))
+            .sum()
+    }
+
+    /// Adjust the score of the nodes matching the predicate
+    pub(crate) fn adjust_scores<F>(&mut self, predicate: F, delta: Score)
+    where
+        V: Clone,
+        F: Fn(&Node<V>) -> bool,
+    {
+        self.buckets
+            .values_mut()
+            .for_each(|bucket| bucket.adjust_scores(&predicate, delta))
+    }
+
+    /// The peers banned from the table
+    pub(crate) fn bans(&self) -> &Arc<BanList> {
+        &self.filter.bans
+    }
+
+    /// The peers allowed in the table
+    pub(crate) fn allowlist(&self) -> &Arc<AllowList> {
+        &self.filter.allowlist
+    }
+
+    /// The access control of the peers
+    pub(crate) fn filter(&self) -> &PeerFilter {
+        &self.filter
+    }
+
+    pub(crate) fn node_mut(
+        &mut self,
+        peer: &BinaryKey,
+    ) -> Option<&mut Node<V>> {
+        let height = self.root.id().calculate_distance(peer)?;
+        self.buckets.get_mut(&height)?.node_mut(peer)
+    }
+
+    /// Change the configuration of the table and of its buckets at once,
+    /// keeping the nodes and the capacity of each bucket
+    pub(crate) fn reconfigure<F>(&mut self, update: F)
+    where
+        F: Fn(&mut BucketConfig),
+        V: Clone,
+    {
+        update(&mut self.config);
+        for bucket in self.buckets.values_mut() {
+            bucket.reconfigure(&update);
+        }
+    }
+
+    /// Changes of the table since the last call
+    pub(crate) fn drain_events(&mut self) -> Vec<TableEvent<V>> {
         self.buckets
             .iter_mut()
-            .for_each(|(_, b)| b.remove_idle_nodes())
+            .flat_map(|(&height, bucket)| {
+                stats::bucket_nodes(height, bucket.len());
+                bucket.drain_events()
+            })
+            .collect()
     }
 
     pub(crate) fn alive_nodes(&self) -> impl Iterator<Item = &Node<V>> {
@@ -144,20 +350,31 @@ impl<V> Tree<V> {
     pub(crate) fn is_bucket_full(&self, height: usize) -> bool {
         self.buckets
             .get(&height)
-            .map_or(false, |bucket| bucket.is_full())
+            .is_some_and(|bucket| bucket.is_full())
     }
     pub(crate) fn new(root: Node<V>, config: BucketConfig) -> Tree<V> {
         info!(
             "Building table [K={}] with root: {:?}",
-            crate::K_K,
+            config.capacity,
             root.id()
         );
         Tree {
             root,
             config,
             buckets: HashMap::new(),
+            filter: PeerFilter::default(),
+            view: TableView::new(),
+            anchors: HashSet::new(),
+            last_round_trip: None,
         }
     }
+
+    /// Set the ids of the anchor nodes, which are never evicted from their
+    /// bucket to make room for other nodes, and are pinged more often
+    pub(crate) fn with_anchors(mut self, anchors: HashSet<BinaryKey>) -> Self {
+        self.anchors = anchors;
+        self
+    }
 }
 
 // pub struct TreeBuilder<V> {
@@ -221,7 +438,7 @@ mod tests {
 
     use crate::{
         config::BucketConfig,
-        kbucket::{NodeInsertError, Tree},
+        kbucket::{derive_key, NodeInsertError, Tree},
         peer::PeerNode,
     };
 
@@ -256,4 +473,126 @@ mod tests {
             false
         });
     }
+
+    #[test]
+    fn test_bucket_refresh() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let config = BucketConfig {
+            bucket_ttl: Duration::ZERO,
+            refresh_jitter: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut route_table = Tree::new(root, config);
+        assert_eq!(route_table.next_refresh(), None);
+        for i in 2..10 {
+            let _ = route_table.insert(PeerNode::generate(
+                &format!("192.168.0.{}:666", i)[..],
+            ));
+        }
+        assert!(route_table
+            .snapshot()
+            .buckets()
+            .iter()
+            .all(|b| b.last_refresh().is_none()));
+        let refreshed = route_table.buckets_to_refresh();
+        assert_eq!(refreshed.len(), route_table.snapshot().buckets().len());
+        assert!(route_table
+            .snapshot()
+            .buckets()
+            .iter()
+            .all(|b| b.last_refresh().is_some()));
+    }
+
+    #[test]
+    fn test_split_buckets() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let config = BucketConfig {
+            capacity: 2,
+            split_buckets: 1,
+            split_bits: 2,
+            ..Default::default()
+        };
+        let max_height = crate::K_ID_LEN_BYTES * 8 - 1;
+        assert_eq!(config.capacity_at(max_height), 8);
+        assert_eq!(config.capacity_at(max_height - 1), 2);
+
+        let mut route_table = Tree::new(root, config);
+        for i in 2..60 {
+            let _ = route_table.insert(PeerNode::generate(
+                &format!("192.168.0.{}:666", i)[..],
+            ));
+        }
+        let snapshot = route_table.snapshot();
+        for bucket in snapshot.buckets() {
+            let capacity = if bucket.height() == max_height { 8 } else { 2 };
+            assert!(bucket.peers().len() <= capacity);
+        }
+        assert!(route_table.is_bucket_full(max_height));
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let config = BucketConfig {
+            capacity: 2,
+            split_buckets: 1,
+            split_bits: 2,
+            refresh_jitter: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut route_table = Tree::new(root, config);
+        for i in 2..60 {
+            let _ = route_table.insert(PeerNode::generate(
+                &format!("192.168.0.{}:666", i)[..],
+            ));
+        }
+        let alive = route_table.alive_nodes().count();
+        assert!(alive > 0);
+        assert!(route_table.buckets_to_refresh().is_empty());
+
+        route_table.reconfigure(|config| config.bucket_ttl = Duration::ZERO);
+        assert_eq!(route_table.alive_nodes().count(), alive);
+        assert!(!route_table.buckets_to_refresh().is_empty());
+        // The capacity of the split buckets is preserved
+        let max_height = crate::K_ID_LEN_BYTES * 8 - 1;
+        assert!(route_table.is_bucket_full(max_height));
+
+        route_table.reconfigure(|config| config.node_ttl = Duration::ZERO);
+        assert_eq!(route_table.alive_nodes().count(), 0);
+        assert_eq!(route_table.config.node_ttl, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_closest_nodes() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let mut route_table = Tree::new(root, BucketConfig::default());
+        for i in 2..100 {
+            let _ = route_table.insert(PeerNode::generate(
+                &format!("192.168.0.{}:666", i)[..],
+            ));
+        }
+        let target = PeerNode::generate("192.168.0.42:666");
+        let key = derive_key(target.id().as_binary());
+        assert_eq!(&key, target.id().as_binary());
+
+        let closest: Vec<_> = route_table.closest_nodes(&key, 5).collect();
+        assert_eq!(closest.len(), 5);
+        assert_eq!(closest[0].id(), target.id());
+        let distances: Vec<_> = closest
+            .iter()
+            .map(|node| node.id().xor_distance(&key))
+            .collect();
+        let mut sorted = distances.clone();
+        sorted.sort();
+        assert_eq!(distances, sorted);
+        let farthest = distances.last().unwrap();
+        assert!(route_table
+            .alive_nodes()
+            .filter(|n| !closest.contains(n))
+            .all(|n| &n.id().xor_distance(&key) >= farthest));
+
+        // Keys of a different length are hashed
+        assert_eq!(derive_key(b"key"), derive_key(b"key"));
+        assert_ne!(derive_key(b"key"), derive_key(b"other key"));
+    }
 }
@@ -0,0 +1,63 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+use super::{BinaryKey, PeerTarget};
+
+/// Peers allowed in the routing table, when running in allowlist-only mode.
+///
+/// Like the [BanList](super::BanList), it's shared by the routing table and
+/// the transport. `None` allows every peer
+#[derive(Default)]
+pub(crate) struct AllowList {
+    targets: RwLock<Option<HashSet<PeerTarget>>>,
+}
+
+impl AllowList {
+    pub(crate) fn set(&self, targets: Option<Vec<PeerTarget>>) {
+        *self.targets.write().expect("Poisoned allowlist") =
+            targets.map(|targets| targets.into_iter().collect());
+    }
+
+    /// Check if either the host or the id of a peer is allowed
+    pub(crate) fn is_allowed_peer(&self, ip: &IpAddr, id: &BinaryKey) -> bool {
+        self.targets
+            .read()
+            .expect("Poisoned allowlist")
+            .as_ref()
+            .is_none_or(|targets| {
+                targets.iter().any(|target| target.matches(ip, id))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AllowList;
+    use crate::kbucket::PeerTarget;
+
+    #[test]
+    fn test_allowlist() {
+        let ip = "10.0.0.1".parse().unwrap();
+        let other_ip = "10.0.0.2".parse().unwrap();
+        let id = [1; crate::K_ID_LEN_BYTES];
+        let other_id = [2; crate::K_ID_LEN_BYTES];
+
+        let allowlist = AllowList::default();
+        assert!(allowlist.is_allowed_peer(&other_ip, &other_id));
+
+        allowlist.set(Some(vec![PeerTarget::Ip(ip), PeerTarget::Id(id)]));
+        assert!(allowlist.is_allowed_peer(&ip, &other_id));
+        assert!(allowlist.is_allowed_peer(&other_ip, &id));
+        assert!(!allowlist.is_allowed_peer(&other_ip, &other_id));
+
+        allowlist.set(Some(vec![]));
+        assert!(!allowlist.is_allowed_peer(&ip, &id));
+    }
+}
@@ -0,0 +1,114 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{BinaryKey, PeerTarget};
+
+/// Ban of a peer, as persisted by a [PeerStore](super::PeerStore)
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct StoredBan {
+    pub(crate) target: PeerTarget,
+    /// Expiration of the ban (seconds since UNIX epoch)
+    pub(crate) expires_at: u64,
+}
+
+/// Peers banned until a given instant.
+///
+/// It's shared by the routing table, which refuses to insert the banned
+/// peers, and the transport, which discards their messages.
+#[derive(Default)]
+pub(crate) struct BanList {
+    bans: RwLock<HashMap<PeerTarget, Instant>>,
+}
+
+impl BanList {
+    pub(crate) fn ban(&self, target: PeerTarget, duration: Duration) {
+        let now = Instant::now();
+        let mut bans = self.bans.write().expect("Poisoned ban list");
+        bans.retain(|_, expires_at| *expires_at > now);
+        let expires_at = now + duration;
+        let current = bans.entry(target).or_insert(expires_at);
+        *current = (*current).max(expires_at);
+    }
+
+    pub(crate) fn is_banned(&self, target: &PeerTarget) -> bool {
+        self.bans
+            .read()
+            .expect("Poisoned ban list")
+            .get(target)
+            .is_some_and(|expires_at| *expires_at > Instant::now())
+    }
+
+    /// Check if either the host or the id of a peer is banned
+    pub(crate) fn is_banned_peer(&self, ip: &IpAddr, id: &BinaryKey) -> bool {
+        self.is_banned(&PeerTarget::Ip(*ip))
+            || self.is_banned(&PeerTarget::Id(*id))
+    }
+
+    /// The bans not expired yet
+    pub(crate) fn stored(&self) -> Vec<StoredBan> {
+        let now = Instant::now();
+        let unix_now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        self.bans
+            .read()
+            .expect("Poisoned ban list")
+            .iter()
+            .filter(|(_, &expires_at)| expires_at > now)
+            .map(|(target, expires_at)| StoredBan {
+                target: *target,
+                expires_at: (unix_now + (*expires_at - now)).as_secs(),
+            })
+            .collect()
+    }
+
+    /// Restore the bans loaded from a store
+    pub(crate) fn restore(&self, bans: Vec<StoredBan>) {
+        let unix_now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+        for ban in bans {
+            if ban.expires_at > unix_now {
+                let duration = Duration::from_secs(ban.expires_at - unix_now);
+                self.ban(ban.target, duration);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::{BanList, PeerTarget};
+
+    #[test]
+    fn test_ban_expiration() {
+        let bans = BanList::default();
+        let ip = PeerTarget::Ip("10.0.0.1".parse().unwrap());
+        let id = PeerTarget::Id([1; crate::K_ID_LEN_BYTES]);
+        bans.ban(ip, Duration::from_millis(200));
+        bans.ban(id, Duration::from_secs(60));
+        assert!(bans.is_banned(&ip));
+        assert!(bans.is_banned(&id));
+        assert!(!bans.is_banned(&PeerTarget::Id([2; crate::K_ID_LEN_BYTES])));
+        assert_eq!(bans.stored().len(), 2);
+
+        let restored = BanList::default();
+        restored.restore(bans.stored());
+        assert!(restored.is_banned(&id));
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(!bans.is_banned(&ip));
+        assert_eq!(bans.stored().len(), 1);
+    }
+}
@@ -5,18 +5,42 @@
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
 use crate::config::BucketConfig;
-use crate::K_K;
 
+use super::eviction::{EvictionAction, EvictionPolicy, LruEviction};
+use super::keepalive::{KeepAliveAction, KeepAlivePolicy};
 use super::node::{Node, NodeEvictionStatus};
-use super::BinaryKey;
-use arrayvec::ArrayVec;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use super::score::{Score, VALID_MESSAGE_REWARD};
+use super::stats::{self, TableMetric};
+use super::view::{BucketView, TableView};
+use super::{BinaryKey, BucketHeight};
+use rand::{thread_rng, Rng};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Max amount of candidates waiting to replace a node of a full bucket
+const MAX_PENDING_NODES: usize = 5;
 
 pub(super) struct Bucket<V> {
-    nodes: arrayvec::ArrayVec<Node<V>, K_K>,
-    pending_node: Option<Node<V>>,
+    // Never exceeds the bucket capacity
+    nodes: Vec<Node<V>>,
+    // Candidates waiting for a free slot, the first received first
+    pending_nodes: VecDeque<Node<V>>,
     bucket_config: BucketConfig,
+    events: Vec<TableEvent<V>>,
+    created_at: Instant,
+    last_refresh: Option<Instant>,
+    // Random delay added to the bucket TTL before the next refresh
+    refresh_jitter: Duration,
+    // Where the nodes are published after each change, if any
+    view: Option<(TableView<V>, BucketHeight)>,
+}
+
+/// Change of the nodes of a bucket
+#[derive(Debug, PartialEq)]
+pub(crate) enum TableEvent<V> {
+    Added(V),
+    Refreshed(V),
+    Evicted(V),
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -38,6 +62,10 @@ pub enum NodeInsertOk<'a, TNode> {
 pub enum NodeInsertError<TNode> {
     Invalid(TNode),
     Full(TNode),
+    Banned(TNode),
+    /// The table already holds the max amount of nodes allowed from the
+    /// subnet of the node
+    SubnetLimit(TNode),
 }
 
 impl<'a, TNode> NodeInsertOk<'a, TNode> {
@@ -60,63 +88,148 @@ pub type InsertError<V> = NodeInsertError<Node<V>>;
 impl<V> Bucket<V> {
     pub(super) fn new(bucket_config: BucketConfig) -> Self {
         Bucket {
-            nodes: ArrayVec::<Node<V>, K_K>::new(),
-            pending_node: None,
+            nodes: Vec::new(),
+            pending_nodes: VecDeque::new(),
+            refresh_jitter: Self::draw_jitter(&bucket_config),
             bucket_config,
+            events: vec![],
+            created_at: Instant::now(),
+            last_refresh: None,
+            view: None,
         }
     }
 
+    /// Publish the nodes of the bucket to the view of the table
+    pub(super) fn with_view(
+        mut self,
+        view: TableView<V>,
+        height: BucketHeight,
+    ) -> Self {
+        self.view = Some((view, height));
+        self
+    }
+
+    fn publish(&self)
+    where
+        V: Clone,
+    {
+        if let Some((view, height)) = &self.view {
+            let bucket =
+                BucketView::new(self.nodes.clone(), self.bucket_config.clone());
+            view.publish(*height, bucket);
+        }
+    }
+
+    fn draw_jitter(bucket_config: &BucketConfig) -> Duration {
+        thread_rng().gen_range(Duration::ZERO..=bucket_config.refresh_jitter)
+    }
+
+    /// Changes of the bucket since the last call
+    pub(super) fn drain_events(
+        &mut self,
+    ) -> impl Iterator<Item = TableEvent<V>> + '_ {
+        self.events.drain(..)
+    }
+
     //Refreshes the node's last usage time corresponding to the given key and
     // return his ref
-    fn refresh_node(&mut self, key: &BinaryKey) -> Option<&Node<V>> {
+    fn refresh_node(&mut self, key: &BinaryKey) -> Option<&Node<V>>
+    where
+        V: Clone,
+    {
         let old_index =
             self.nodes.iter().position(|s| s.id().as_binary() == key)?;
         self.nodes[old_index..].rotate_left(1);
-        self.nodes.last_mut()?.refresh();
+        let node = self.nodes.last_mut()?;
+        node.refresh();
+        node.adjust_score(VALID_MESSAGE_REWARD);
+        stats::record(TableMetric::Update);
+        self.events
+            .push(TableEvent::Refreshed(node.value().clone()));
         self.nodes.last()
     }
 
-    fn insert_pending(&mut self) {
-        if self.nodes.is_full() {
+    fn insert_pending(&mut self)
+    where
+        V: Clone,
+    {
+        if self.is_full() {
             return;
         };
-        if let Some(pending) = self.pending_node.take() {
+        // The candidates which are not alive anymore are discarded
+        while let Some(pending) = self.pending_nodes.pop_front() {
             if pending.is_alive(self.bucket_config.node_ttl) {
-                //FIXME: use try_push instead of push
-                //FIXME2: we are breaking the LRU policy, maybe in
+                //FIXME: we are breaking the LRU policy, maybe in
                 // the meanwhile other records have been updated. Btw
                 // it's mitigated with is_alive check
+                self.events.push(TableEvent::Added(pending.value().clone()));
                 self.nodes.push(pending);
+                stats::record(TableMetric::PendingPromotion);
+                if self.is_full() {
+                    return;
+                }
             }
         }
     }
 
+    // The action of the eviction policy for a full bucket
+    fn eviction_action(&self) -> EvictionAction {
+        if !self.is_full() {
+            return EvictionAction::Keep;
+        }
+        let now = Instant::now();
+        // Anchors are not candidates, the policy only sees the other nodes
+        let (idxs, candidates): (Vec<_>, Vec<_>) = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| !node.anchor)
+            .map(|(idx, node)| (idx, node.stats(now)))
+            .unzip();
+        if candidates.is_empty() {
+            return EvictionAction::Keep;
+        }
+        let policy: &dyn EvictionPolicy = self
+            .bucket_config
+            .eviction_policy
+            .as_deref()
+            .unwrap_or(&LruEviction);
+        match policy.action(&candidates, &self.bucket_config) {
+            EvictionAction::Probe(idx) | EvictionAction::Evict(idx)
+                if idx >= idxs.len() =>
+            {
+                EvictionAction::Keep
+            }
+            EvictionAction::Probe(idx) => EvictionAction::Probe(idxs[idx]),
+            EvictionAction::Evict(idx) => EvictionAction::Evict(idxs[idx]),
+            EvictionAction::Keep => EvictionAction::Keep,
+        }
+    }
+
     /*
-        If the bucket is full, flag the least recent used for eviction.
-        If it's already flagged, check if timeout is expired and then replace with the pending node.
+        If the bucket is full, apply the eviction policy: flag the node to
+        probe for eviction, or replace the evicted node with the pending one.
         The method return the candidate for eviction (if any)
     */
-    fn try_perform_eviction(&mut self) -> Option<&Node<V>> {
-        if !self.nodes.is_full() {
-            return None;
-        }
-        match self.nodes.first()?.eviction_status {
-            NodeEvictionStatus::Requested(instant) => {
-                if instant.elapsed() < self.bucket_config.node_evict_after {
-                    self.nodes.first()
-                } else {
-                    self.nodes.pop_at(0);
-                    self.insert_pending();
-                    None
+    fn try_perform_eviction(&mut self) -> Option<&Node<V>>
+    where
+        V: Clone,
+    {
+        match self.eviction_action() {
+            EvictionAction::Keep => None,
+            EvictionAction::Probe(idx) => {
+                let node = &mut self.nodes[idx];
+                if node.eviction_status == NodeEvictionStatus::None {
+                    node.flag_for_check();
                 }
+                self.nodes.get(idx)
             }
-            NodeEvictionStatus::None => {
-                if self.nodes.first()?.is_alive(self.bucket_config.node_ttl) {
-                    None
-                } else {
-                    self.nodes.first_mut()?.flag_for_check();
-                    self.nodes.first()
-                }
+            EvictionAction::Evict(idx) => {
+                let evicted = self.nodes.remove(idx);
+                self.events.push(TableEvent::Evicted(evicted.into_value()));
+                stats::record(TableMetric::Eviction);
+                self.insert_pending();
+                None
             }
         }
     }
@@ -124,36 +237,64 @@ impl<V> Bucket<V> {
     pub fn insert(
         &mut self,
         node: Node<V>,
-    ) -> Result<InsertOk<V>, InsertError<V>> {
-        if !node.is_id_valid() {
+    ) -> Result<InsertOk<V>, InsertError<V>>
+    where
+        V: Clone,
+    {
+        if !node.is_id_valid(self.bucket_config.id_difficulty) {
             return Err(NodeInsertError::Invalid(node));
         }
         if self.refresh_node(node.id().as_binary()).is_some() {
             self.try_perform_eviction();
+            self.publish();
             return Ok(NodeInsertOk::Updated {
                 pending_eviction: self.pending_eviction_node(),
                 updated: self.nodes.last().unwrap(),
             });
         }
         self.try_perform_eviction();
-        match self.nodes.try_push(node) {
-            Ok(_) => Ok(NodeInsertOk::Inserted {
-                inserted: self.nodes.last().unwrap(),
-            }),
-            Err(err) => {
-                if self
-                    .nodes
-                    .first()
-                    .expect("Bucket full but no node as .first()")
-                    .is_alive(self.bucket_config.node_ttl)
-                {
-                    Err(NodeInsertError::Full(err.element()))
+        match self.is_full() {
+            false => {
+                self.events.push(TableEvent::Added(node.value().clone()));
+                self.nodes.push(node);
+                stats::record(TableMetric::Insert);
+                self.publish();
+                Ok(NodeInsertOk::Inserted {
+                    inserted: self.nodes.last().unwrap(),
+                })
+            }
+            true => {
+                // Penalized nodes are replaced straight away, regardless of
+                // their liveness. Anchors take the slot of the least recently
+                // used node
+                let replaced =
+                    self.penalized_node_idx().or_else(|| match node.anchor {
+                        true => self.nodes.iter().position(|n| !n.anchor),
+                        false => None,
+                    });
+                if let Some(idx) = replaced {
+                    let evicted = self.nodes.remove(idx);
+                    self.events.push(TableEvent::Evicted(evicted.into_value()));
+                    self.events.push(TableEvent::Added(node.value().clone()));
+                    self.nodes.push(node);
+                    stats::record(TableMetric::Eviction);
+                    stats::record(TableMetric::Insert);
+                    self.publish();
+                    return Ok(NodeInsertOk::Inserted {
+                        inserted: self.nodes.last().unwrap(),
+                    });
+                }
+                // The eviction may have changed the nodes
+                self.publish();
+                if self.eviction_action() == EvictionAction::Keep {
+                    stats::record(TableMetric::RejectedFull);
+                    Err(NodeInsertError::Full(node))
                 } else {
-                    self.pending_node = Some(err.element());
+                    let idx = self.push_pending(node);
                     Ok(NodeInsertOk::Pending {
                         pending_insert: self
-                            .pending_node
-                            .as_ref()
+                            .pending_nodes
+                            .get(idx)
                             .expect("Unable to get the pending node back"),
                         pending_eviction: self.pending_eviction_node(),
                     })
@@ -162,39 +303,174 @@ impl<V> Bucket<V> {
         }
     }
 
-    //pick at most `ITEM_COUNT` random nodes from this bucket
-    pub fn pick<const ITEM_COUNT: usize>(
-        &self,
-    ) -> impl Iterator<Item = &Node<V>> {
-        let mut idxs: Vec<usize> = (0..self.nodes.len()).collect();
-        idxs.shuffle(&mut thread_rng());
-        idxs.into_iter()
-            .take(ITEM_COUNT)
-            .filter_map(move |idx| self.nodes.get(idx))
+    // Queue a candidate, returning its position. A candidate already queued
+    // is refreshed keeping its position, while the oldest candidate is
+    // dropped if the queue is full
+    fn push_pending(&mut self, node: Node<V>) -> usize {
+        let id = node.id().as_binary();
+        if let Some(idx) = self
+            .pending_nodes
+            .iter()
+            .position(|pending| pending.id().as_binary() == id)
+        {
+            self.pending_nodes[idx] = node;
+            return idx;
+        }
+        if self.pending_nodes.len() >= MAX_PENDING_NODES {
+            self.pending_nodes.pop_front();
+        }
+        self.pending_nodes.push_back(node);
+        self.pending_nodes.len() - 1
+    }
+
+    // The node with the lowest negative score, the least recently used first.
+    // Anchors are never replaced
+    fn penalized_node_idx(&self) -> Option<usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.score() < 0 && !node.anchor)
+            .min_by_key(|(_, node)| node.score())
+            .map(|(idx, _)| idx)
     }
 
-    /* The method return the least recent used node to query if flagged for
-     * eviction */
+    /* The method return the node to query if flagged for eviction */
     fn pending_eviction_node(&self) -> Option<&Node<V>> {
         self.nodes
-            .first()
-            .filter(|n| n.eviction_status != NodeEvictionStatus::None)
+            .iter()
+            .find(|n| n.eviction_status != NodeEvictionStatus::None)
     }
 
     pub(super) fn peers(&self) -> impl Iterator<Item = &Node<V>> {
         self.nodes.iter()
     }
 
-    pub(crate) fn is_idle(&self) -> bool {
-        self.nodes.first().map_or(false, |n| {
-            n.seen_at.elapsed() > self.bucket_config.bucket_ttl
-        })
+    pub(super) fn pending(&self) -> impl Iterator<Item = &Node<V>> {
+        self.pending_nodes.iter()
+    }
+
+    /// Drop the oldest pending node
+    pub(super) fn pop_pending(&mut self) -> Option<Node<V>> {
+        self.pending_nodes.pop_front()
+    }
+
+    /// When the bucket should be refreshed, if it doesn't see any traffic
+    /// in the meanwhile
+    pub(crate) fn refresh_at(&self) -> Instant {
+        let last_activity = self
+            .nodes
+            .iter()
+            .map(|n| n.seen_at)
+            .chain(self.last_refresh)
+            .fold(self.created_at, Instant::max);
+        last_activity + self.bucket_config.bucket_ttl + self.refresh_jitter
+    }
+
+    pub(crate) fn last_refresh(&self) -> Option<Instant> {
+        self.last_refresh
+    }
+
+    /// Change the configuration of the bucket, keeping its nodes
+    pub(super) fn reconfigure<F>(&mut self, update: F)
+    where
+        F: Fn(&mut BucketConfig),
+        V: Clone,
+    {
+        update(&mut self.bucket_config);
+        self.publish();
+    }
+
+    pub(crate) fn mark_refreshed(&mut self) {
+        self.last_refresh = Some(Instant::now());
+        self.refresh_jitter = Self::draw_jitter(&self.bucket_config);
     }
 
-    pub(crate) fn remove_idle_nodes(&mut self) {
+    pub(crate) fn keep_alive(&mut self, policy: &dyn KeepAlivePolicy) -> Vec<V>
+    where
+        V: Clone,
+    {
         let ttl = self.bucket_config.node_ttl;
-        self.nodes.retain(|n| n.is_alive(ttl));
+        let now = Instant::now();
+        let mut to_ping = vec![];
+        let mut dead = vec![];
+        // Anchors are pinged once idle for half the TTL, and they are never
+        // removed
+        let is_idle = |node: &Node<V>| match node.anchor {
+            true => !node.is_alive(ttl / 2),
+            false => !node.is_alive(ttl),
+        };
+        for node in self.nodes.iter_mut().filter(|n| is_idle(n)) {
+            let since_last_ping = node.last_ping.map(|at| now - at);
+            match policy.action(node.pings, since_last_ping) {
+                KeepAliveAction::Remove if node.anchor => {
+                    node.pings += 1;
+                    node.last_ping = Some(now);
+                    to_ping.push(node.value().clone());
+                }
+                KeepAliveAction::Ping => {
+                    node.pings += 1;
+                    node.last_ping = Some(now);
+                    to_ping.push(node.value().clone());
+                }
+                KeepAliveAction::Wait => {}
+                KeepAliveAction::Remove => dead.push(*node.id()),
+            }
+        }
+        if !dead.is_empty() {
+            stats::record_many(TableMetric::Eviction, dead.len());
+            self.remove_nodes(|node| dead.contains(node.id()));
+        } else {
+            self.publish();
+        }
+        to_ping
+    }
+
+    pub(crate) fn adjust_scores<F>(&mut self, predicate: F, delta: Score)
+    where
+        V: Clone,
+        F: Fn(&Node<V>) -> bool,
+    {
+        self.nodes
+            .iter_mut()
+            .filter(|node| predicate(node))
+            .for_each(|node| node.adjust_score(delta));
+        self.publish();
+    }
+
+    /// Mark the nodes matching the predicate as trusted, and the other ones
+    /// as not trusted
+    pub(crate) fn mark_trusted<F>(&mut self, predicate: F)
+    where
+        V: Clone,
+        F: Fn(&Node<V>) -> bool,
+    {
+        self.nodes
+            .iter_mut()
+            .chain(self.pending_nodes.iter_mut())
+            .for_each(|node| node.trusted = predicate(node));
+        self.publish();
+    }
+
+    /// Remove the nodes matching the predicate, replacing them with the
+    /// pending ones. Returns the amount of nodes removed
+    pub(crate) fn remove_nodes<F>(&mut self, predicate: F) -> usize
+    where
+        V: Clone,
+        F: Fn(&Node<V>) -> bool,
+    {
+        self.pending_nodes.retain(|pending| !predicate(pending));
+        let (removed, kept): (Vec<_>, Vec<_>) =
+            self.nodes.drain(..).partition(&predicate);
+        self.nodes = kept;
+        let count = removed.len();
+        self.events.extend(
+            removed
+                .into_iter()
+                .map(|node| TableEvent::Evicted(node.into_value())),
+        );
         self.insert_pending();
+        self.publish();
+        count
     }
 
     pub(crate) fn alive_nodes(&self) -> impl Iterator<Item = &Node<V>> {
@@ -202,30 +478,56 @@ impl<V> Bucket<V> {
         self.nodes.iter().filter(move |&n| n.is_alive(ttl))
     }
 
+    pub(crate) fn node_mut(
+        &mut self,
+        peer: &BinaryKey,
+    ) -> Option<&mut Node<V>> {
+        self.nodes.iter_mut().find(|n| n.id().as_binary() == peer)
+    }
+
     pub(crate) fn has_node(&self, peer: &BinaryKey) -> bool {
         self.nodes.iter().any(|n| n.id().as_binary() == peer)
     }
 
+    pub(super) fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub(super) fn capacity(&self) -> usize {
+        self.bucket_config.capacity
+    }
+
     pub(crate) fn is_full(&self) -> bool {
-        self.nodes.is_full()
+        self.nodes.len() >= self.bucket_config.capacity
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{thread, time::Duration};
+    use std::{sync::Arc, thread, time::Duration};
 
     use crate::{
-        config::BucketConfig,
+        config::{BucketConfig, KeepAliveConfig},
+        kbucket::delegate::select_delegates,
         kbucket::{
-            bucket::NodeInsertError, key::BinaryKey, Bucket, Node,
-            NodeInsertOk, Tree,
+            bucket::NodeInsertError, key::BinaryKey, Bucket, EvictionAction,
+            EvictionPolicy, NoKeepAlive, Node, NodeInsertOk, NodeStats,
+            TableEvent, Tree,
         },
         peer::PeerNode,
         K_BETA,
     };
 
     impl<V> Bucket<V> {
+        //pick at most `ITEM_COUNT` nodes from this bucket
+        pub fn pick<const ITEM_COUNT: usize>(
+            &self,
+        ) -> impl Iterator<Item = &Node<V>> {
+            select_delegates(&self.nodes, &self.bucket_config)
+                .into_iter()
+                .take(ITEM_COUNT)
+        }
+
         pub fn last_id(&self) -> Option<&BinaryKey> {
             self.nodes.last().map(|n| n.id().as_binary())
         }
@@ -238,8 +540,8 @@ mod tests {
             let update_idx =
                 self.nodes.iter().position(|s| s.id().as_binary() == id)?;
 
-            let removed = self.nodes.pop_at(update_idx);
-            if let Some(pending) = self.pending_node.take() {
+            let removed = Some(self.nodes.remove(update_idx));
+            if let Some(pending) = self.pending_nodes.pop_front() {
                 self.nodes.push(pending);
             }
             removed
@@ -252,6 +554,233 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_bucket_capacity() {
+        let root = PeerNode::generate("127.0.0.1:666");
+        let config = BucketConfig {
+            capacity: 2,
+            ..Default::default()
+        };
+        let mut route_table = Tree::new(root, config);
+        let bucket = route_table.bucket_for_test();
+        for i in 0..2 {
+            bucket
+                .insert(PeerNode::generate(&format!("192.168.1.{}:8080", i)))
+                .expect("Node inserted");
+        }
+        assert!(bucket.is_full());
+        match bucket.insert(PeerNode::generate("192.168.1.2:8080")) {
+            Err(NodeInsertError::Full(_)) => {}
+            _ => panic!("Bucket should be full"),
+        }
+    }
+
+    #[test]
+    fn test_table_events() {
+        let root = PeerNode::generate("127.0.0.1:666");
+        let config = BucketConfig {
+            node_ttl: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let mut route_table = Tree::new(root, config);
+        let node = || PeerNode::generate("192.168.1.1:8080");
+        let info = node().value().clone();
+        route_table.insert(node()).expect("Node inserted");
+        route_table.insert(node()).expect("Node refreshed");
+        assert_eq!(
+            route_table.drain_events(),
+            vec![
+                TableEvent::Added(info.clone()),
+                TableEvent::Refreshed(info.clone())
+            ]
+        );
+        assert!(route_table.drain_events().is_empty());
+
+        thread::sleep(Duration::from_millis(500));
+        let policy = KeepAliveConfig {
+            attempts: 0,
+            ..Default::default()
+        };
+        assert!(route_table.keep_alive(&NoKeepAlive).is_empty());
+        assert!(route_table.drain_events().is_empty());
+        route_table.keep_alive(&policy);
+        assert_eq!(route_table.drain_events(), vec![TableEvent::Evicted(info)]);
+    }
+
+    // Never probe, evict the most recently used node straight away
+    struct EvictNewest;
+
+    impl EvictionPolicy for EvictNewest {
+        fn action(
+            &self,
+            candidates: &[NodeStats],
+            _: &BucketConfig,
+        ) -> EvictionAction {
+            EvictionAction::Evict(candidates.len() - 1)
+        }
+    }
+
+    #[test]
+    fn test_eviction_policy() {
+        let root = PeerNode::generate("127.0.0.1:666");
+        let config = BucketConfig {
+            capacity: 2,
+            eviction_policy: Some(Arc::new(EvictNewest)),
+            ..Default::default()
+        };
+        let mut route_table = Tree::new(root, config);
+        let bucket = route_table.bucket_for_test();
+        let node = |i| PeerNode::generate(&format!("192.168.1.{}:8080", i));
+        let info = |i| node(i).value().clone();
+        for i in 1..5 {
+            bucket.insert(node(i)).expect("Node inserted");
+        }
+        let events: Vec<_> = bucket.drain_events().collect();
+        assert_eq!(
+            events,
+            vec![
+                TableEvent::Added(info(1)),
+                TableEvent::Added(info(2)),
+                TableEvent::Evicted(info(2)),
+                TableEvent::Added(info(3)),
+                TableEvent::Evicted(info(3)),
+                TableEvent::Added(info(4)),
+            ]
+        );
+        assert_eq!(bucket.least_used_id(), Some(node(1).id().as_binary()));
+        assert_eq!(bucket.last_id(), Some(node(4).id().as_binary()));
+    }
+
+    // Evict the least recently used node straight away
+    struct EvictOldest;
+
+    impl EvictionPolicy for EvictOldest {
+        fn action(&self, _: &[NodeStats], _: &BucketConfig) -> EvictionAction {
+            EvictionAction::Evict(0)
+        }
+    }
+
+    #[test]
+    fn test_anchor_nodes() {
+        let root = PeerNode::generate("127.0.0.1:666");
+        let config = BucketConfig {
+            capacity: 2,
+            node_ttl: Duration::from_millis(200),
+            eviction_policy: Some(Arc::new(EvictOldest)),
+            ..Default::default()
+        };
+        let mut route_table = Tree::new(root, config);
+        let bucket = route_table.bucket_for_test();
+        let node = |i| PeerNode::generate(&format!("192.168.1.{}:8080", i));
+        let anchor = |i| {
+            let mut node = node(i);
+            node.anchor = true;
+            node
+        };
+        bucket.insert(anchor(1)).expect("Node inserted");
+        bucket.insert(node(2)).expect("Node inserted");
+        bucket.insert(node(3)).expect("Node inserted");
+        assert_eq!(bucket.least_used_id(), Some(node(1).id().as_binary()));
+        assert!(!bucket.has_node(node(2).id().as_binary()));
+
+        // Anchors replace the other nodes of a full bucket
+        bucket.insert(anchor(4)).expect("Node inserted");
+        assert!(bucket.peers().all(|n| n.is_anchor()));
+        match bucket.insert(node(5)) {
+            Err(NodeInsertError::Full(_)) => {}
+            _ => panic!("Anchors should not be evicted"),
+        }
+
+        // Anchors are pinged earlier and never removed
+        thread::sleep(Duration::from_millis(100));
+        let policy = KeepAliveConfig {
+            attempts: 0,
+            ..Default::default()
+        };
+        assert_eq!(bucket.keep_alive(&policy).len(), 2);
+        assert_eq!(bucket.len(), 2);
+    }
+
+    #[test]
+    fn test_remove_nodes() {
+        let root = PeerNode::generate("127.0.0.1:666");
+        let config = BucketConfig {
+            capacity: 2,
+            node_ttl: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let mut route_table = Tree::new(root, config);
+        let bucket = route_table.bucket_for_test();
+        let node = |i| PeerNode::generate(&format!("192.168.1.{}:8080", i));
+        let info = |i| node(i).value().clone();
+        bucket.insert(node(1)).expect("Node inserted");
+        bucket.insert(node(2)).expect("Node inserted");
+        thread::sleep(Duration::from_millis(200));
+        bucket.insert(node(3)).expect("Node queued");
+        bucket.drain_events().for_each(drop);
+
+        // The removed node is replaced by the pending one straight away
+        let id = *node(2).id().as_binary();
+        assert_eq!(bucket.remove_nodes(|n| n.id().as_binary() == &id), 1);
+        assert_eq!(bucket.remove_nodes(|n| n.id().as_binary() == &id), 0);
+        let events: Vec<_> = bucket.drain_events().collect();
+        assert_eq!(
+            events,
+            vec![TableEvent::Evicted(info(2)), TableEvent::Added(info(3))]
+        );
+        assert_eq!(bucket.last_id(), Some(node(3).id().as_binary()));
+    }
+
+    #[test]
+    fn test_pending_queue() {
+        let root = PeerNode::generate("127.0.0.1:666");
+        let config = BucketConfig {
+            capacity: 2,
+            node_ttl: Duration::from_secs(1),
+            node_evict_after: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let mut route_table = Tree::new(root, config);
+        let bucket = route_table.bucket_for_test();
+        let node = |i| PeerNode::generate(&format!("192.168.1.{}:8080", i));
+        let info = |i| node(i).value().clone();
+
+        bucket.insert(node(1)).expect("Node inserted");
+        bucket.insert(node(2)).expect("Node inserted");
+        thread::sleep(Duration::from_secs(1));
+
+        // Both candidates are queued while the idle head is checked
+        for i in 3..5 {
+            match bucket.insert(node(i)).expect("Node queued") {
+                NodeInsertOk::Pending { pending_insert, .. } => {
+                    assert_eq!(pending_insert.id(), node(i).id())
+                }
+                _ => panic!("Node should be pending"),
+            }
+        }
+        thread::sleep(Duration::from_millis(200));
+        bucket.insert(node(5)).expect("Node queued");
+        bucket.insert(node(6)).expect("Node queued");
+        thread::sleep(Duration::from_millis(200));
+        assert!(bucket.insert(node(7)).is_err());
+
+        // The evicted nodes are replaced in the order the candidates arrived
+        let events: Vec<_> = bucket.drain_events().collect();
+        assert_eq!(
+            events,
+            vec![
+                TableEvent::Added(info(1)),
+                TableEvent::Added(info(2)),
+                TableEvent::Evicted(info(1)),
+                TableEvent::Added(info(3)),
+                TableEvent::Evicted(info(2)),
+                TableEvent::Added(info(4)),
+            ]
+        );
+        assert_eq!(bucket.least_used_id(), Some(node(3).id().as_binary()));
+        assert_eq!(bucket.last_id(), Some(node(4).id().as_binary()));
+    }
+
     #[test]
     fn test_lru_base_5secs() {
         let root = PeerNode::generate("127.0.0.1:666");
@@ -0,0 +1,202 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use tracing::{error, warn};
+
+use super::store::{FilePeerStore, StoredPeer};
+use super::BinaryKey;
+
+/// Peer of the bootstrap cache, annotated with the outcome of the previous
+/// contacts
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct CachedPeer {
+    pub(crate) address: SocketAddr,
+    pub(crate) id: BinaryKey,
+    /// Last time the peer has been seen (seconds since UNIX epoch)
+    pub(crate) last_seen: u64,
+    /// Times the peer has been found alive in the routing table
+    pub(crate) successes: u32,
+    /// Times the peer didn't reply when contacted from the cache
+    pub(crate) failures: u32,
+}
+
+impl CachedPeer {
+    fn quality(&self) -> i64 {
+        self.successes as i64 - self.failures as i64
+    }
+}
+
+/// Durable set of known good peers, larger than the routing table.
+///
+/// The cache survives the peers evicted from the routing table, and it's
+/// sampled at startup when the bootstrapping nodes are unreachable. It's
+/// persisted to a text file with one peer per line
+/// (`address id last_seen successes failures`)
+pub(crate) struct BootstrapCache {
+    path: PathBuf,
+    capacity: usize,
+    peers: Mutex<HashMap<SocketAddr, CachedPeer>>,
+}
+
+impl BootstrapCache {
+    /// Load the cache from the file, starting empty if it can't be read
+    pub(crate) fn load(path: PathBuf, capacity: usize) -> Self {
+        let peers = match fs::read_to_string(&path) {
+            Ok(content) => content
+                .lines()
+                .filter_map(|line| {
+                    let peer = Self::parse_peer(line);
+                    if peer.is_none() {
+                        warn!("Skipping invalid cached peer: {}", line);
+                    }
+                    peer
+                })
+                .map(|peer| (peer.address, peer))
+                .collect(),
+            Err(e) => {
+                if e.kind() != ErrorKind::NotFound {
+                    error!("Unable to load bootstrap cache - {}", e);
+                }
+                HashMap::new()
+            }
+        };
+        Self {
+            path,
+            capacity,
+            peers: Mutex::new(peers),
+        }
+    }
+
+    fn parse_peer(line: &str) -> Option<CachedPeer> {
+        let mut fields = line.split_whitespace();
+        Some(CachedPeer {
+            address: fields.next()?.parse().ok()?,
+            id: FilePeerStore::parse_id(fields.next()?)?,
+            last_seen: fields.next()?.parse().ok()?,
+            successes: fields.next()?.parse().ok()?,
+            failures: fields.next()?.parse().ok()?,
+        })
+    }
+
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for peer in self.sorted() {
+            let _ = writeln!(
+                content,
+                "{} {} {} {} {}",
+                peer.address,
+                FilePeerStore::format_id(&peer.id),
+                peer.last_seen,
+                peer.successes,
+                peer.failures
+            );
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(tmp, &self.path)
+    }
+
+    /// Record the peers found alive in the routing table, dropping the worst
+    /// peers if the cache exceeds its capacity
+    pub(crate) fn record_alive(&self, alive: &[StoredPeer]) {
+        let mut peers = self.peers.lock().expect("Cache lock poisoned");
+        for peer in alive {
+            let cached =
+                peers.entry(peer.address).or_insert_with(|| CachedPeer {
+                    address: peer.address,
+                    id: peer.id,
+                    last_seen: peer.last_seen,
+                    successes: 0,
+                    failures: 0,
+                });
+            cached.id = peer.id;
+            cached.last_seen = cached.last_seen.max(peer.last_seen);
+            cached.successes = cached.successes.saturating_add(1);
+        }
+        if peers.len() > self.capacity {
+            let mut ranked: Vec<_> = peers.values().cloned().collect();
+            ranked.sort_by_key(Self::rank);
+            for peer in &ranked[self.capacity..] {
+                peers.remove(&peer.address);
+            }
+        }
+    }
+
+    /// Record the cached peers which didn't reply
+    pub(crate) fn record_failures(&self, addresses: &[SocketAddr]) {
+        let mut peers = self.peers.lock().expect("Cache lock poisoned");
+        for address in addresses {
+            if let Some(peer) = peers.get_mut(address) {
+                peer.failures = peer.failures.saturating_add(1);
+            }
+        }
+    }
+
+    /// The best `amount` peers of the cache
+    pub(crate) fn sample(&self, amount: usize) -> Vec<CachedPeer> {
+        self.sorted().into_iter().take(amount).collect()
+    }
+
+    // The best quality first, the most recently seen among the same quality
+    fn rank(peer: &CachedPeer) -> (Reverse<i64>, Reverse<u64>) {
+        (Reverse(peer.quality()), Reverse(peer.last_seen))
+    }
+
+    fn sorted(&self) -> Vec<CachedPeer> {
+        let peers = self.peers.lock().expect("Cache lock poisoned");
+        let mut sorted: Vec<_> = peers.values().cloned().collect();
+        sorted.sort_by_key(Self::rank);
+        sorted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::BootstrapCache;
+    use crate::kbucket::StoredPeer;
+
+    #[test]
+    fn test_bootstrap_cache() {
+        let peer = |i: u8| StoredPeer {
+            address: SocketAddr::new([10, 0, 0, i].into(), 666),
+            id: [i; crate::K_ID_LEN_BYTES],
+            last_seen: i as u64,
+        };
+        let path = std::env::temp_dir()
+            .join(format!("kadcast-cache-{}", std::process::id()));
+        let cache = BootstrapCache::load(path.clone(), 3);
+        assert!(cache.sample(10).is_empty());
+
+        cache.record_alive(&[peer(1), peer(2)]);
+        cache.record_alive(&[peer(2), peer(3)]);
+        cache.record_failures(&[peer(3).address]);
+        // The cache is full, the worst peer is dropped
+        cache.record_alive(&[peer(4)]);
+        let sampled: Vec<_> =
+            cache.sample(10).iter().map(|p| p.address).collect();
+        assert_eq!(
+            sampled,
+            vec![peer(2).address, peer(4).address, peer(1).address]
+        );
+
+        cache.save().unwrap();
+        let loaded = BootstrapCache::load(path.clone(), 3);
+        assert_eq!(loaded.sample(10), cache.sample(10));
+        assert_eq!(loaded.sample(1)[0].successes, 2);
+        std::fs::remove_file(path).unwrap();
+    }
+}
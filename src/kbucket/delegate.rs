@@ -0,0 +1,261 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+
+use super::node::{Node, NodeStats};
+use super::score::MIN_SCORE;
+use crate::config::BucketConfig;
+use crate::K_BETA;
+
+// Weight reduction of the idle nodes, which are less likely to relay
+const IDLE_WEIGHT_FACTOR: f64 = 0.25;
+
+// Round trip time halving the weight of a node, when the delegates are
+// latency aware. It's also assumed for the nodes whose latency is unknown
+const REFERENCE_RTT: Duration = Duration::from_millis(100);
+
+/// Policy choosing the delegates of a bucket, which are the peers a
+/// broadcast message is sent to for the bucket height
+pub trait DelegatePolicy: Send + Sync {
+    /// The indexes of the delegates among the nodes of a bucket
+    fn select(&self, nodes: &[NodeStats], config: &BucketConfig) -> Vec<usize>;
+}
+
+impl fmt::Debug for dyn DelegatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DelegatePolicy")
+    }
+}
+
+/// The delegates among the nodes of a bucket, according to the delegate
+/// policy of its config
+pub(super) fn select_delegates<'a, V>(
+    nodes: &'a [Node<V>],
+    config: &BucketConfig,
+) -> Vec<&'a Node<V>> {
+    let now = Instant::now();
+    let stats: Vec<_> = nodes.iter().map(|node| node.stats(now)).collect();
+    let selected = match &config.delegate_policy {
+        Some(policy) => policy.select(&stats, config),
+        None => AdaptiveDelegates {
+            beta: config.beta,
+            max_delegates: 2 * config.beta,
+        }
+        .select(&stats, config),
+    };
+    // The trusted nodes are delegates in any case
+    let trusted = (0..nodes.len())
+        .filter(|idx| nodes[*idx].trusted && !selected.contains(idx))
+        .collect::<Vec<_>>();
+    trusted
+        .into_iter()
+        .chain(selected)
+        .filter_map(|idx| nodes.get(idx))
+        .collect()
+}
+
+/// [DelegatePolicy] selecting a fixed amount of delegates per bucket, the
+/// ones with the highest score first and random among the same score
+pub struct FixedDelegates(pub usize);
+
+impl DelegatePolicy for FixedDelegates {
+    fn select(&self, nodes: &[NodeStats], _: &BucketConfig) -> Vec<usize> {
+        let mut idxs: Vec<usize> = (0..nodes.len()).collect();
+        idxs.shuffle(&mut thread_rng());
+        idxs.sort_by_key(|&idx| Reverse(nodes[idx].score()));
+        idxs.truncate(self.0);
+        idxs
+    }
+}
+
+/// Default [DelegatePolicy], adapting the amount of delegates to the
+/// reliability of the bucket. When no policy is configured, `beta` is the one
+/// of the [BucketConfig] and `max_delegates` is twice as much.
+///
+/// A node is reliable if it's alive and its score is not negative. The
+/// amount of delegates is raised so that `beta` reliable delegates are
+/// expected, up to `max_delegates`. The delegates are drawn at random,
+/// weighted by their score and liveness, and by their round trip time if
+/// [BucketConfig::latency_aware_delegates] is set
+pub struct AdaptiveDelegates {
+    pub beta: usize,
+    pub max_delegates: usize,
+}
+
+impl Default for AdaptiveDelegates {
+    fn default() -> Self {
+        Self {
+            beta: K_BETA,
+            max_delegates: 2 * K_BETA,
+        }
+    }
+}
+
+impl AdaptiveDelegates {
+    fn is_reliable(node: &NodeStats, config: &BucketConfig) -> bool {
+        node.idle() < config.node_ttl && node.score() >= 0
+    }
+
+    fn amount(&self, nodes: &[NodeStats], config: &BucketConfig) -> usize {
+        let reliable = nodes
+            .iter()
+            .filter(|node| Self::is_reliable(node, config))
+            .count();
+        let amount = match reliable {
+            0 => nodes.len(),
+            _ => (self.beta * nodes.len()).div_ceil(reliable),
+        };
+        amount
+            .max(self.beta)
+            .min(self.max_delegates)
+            .min(nodes.len())
+    }
+
+    fn weight(node: &NodeStats, config: &BucketConfig) -> f64 {
+        let mut weight = (node.score() - MIN_SCORE + 1) as f64;
+        if node.idle() >= config.node_ttl {
+            weight *= IDLE_WEIGHT_FACTOR;
+        }
+        if config.latency_aware_delegates {
+            let rtt = node.rtt().unwrap_or(REFERENCE_RTT).as_secs_f64();
+            let reference = REFERENCE_RTT.as_secs_f64();
+            weight *= reference / (reference + rtt);
+        }
+        weight
+    }
+}
+
+impl DelegatePolicy for AdaptiveDelegates {
+    fn select(&self, nodes: &[NodeStats], config: &BucketConfig) -> Vec<usize> {
+        let idxs: Vec<usize> = (0..nodes.len()).collect();
+        let amount = self.amount(nodes, config);
+        match idxs.choose_multiple_weighted(&mut thread_rng(), amount, |&i| {
+            Self::weight(&nodes[i], config)
+        }) {
+            Ok(selected) => selected.copied().collect(),
+            Err(_) => FixedDelegates(amount).select(nodes, config),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        select_delegates, AdaptiveDelegates, DelegatePolicy, FixedDelegates,
+        NodeStats,
+    };
+    use crate::config::BucketConfig;
+    use crate::peer::PeerNode;
+    use crate::K_BETA;
+
+    fn node(score: i32, alive: bool) -> NodeStats {
+        NodeStats {
+            idle: Duration::from_secs(if alive { 0 } else { 3600 }),
+            age: Duration::ZERO,
+            score,
+            rtt: None,
+            probed: None,
+        }
+    }
+
+    #[test]
+    fn test_latency_aware_weight() {
+        let mut config = BucketConfig::default();
+        let with_rtt = |millis| NodeStats {
+            rtt: Some(Duration::from_millis(millis)),
+            ..node(0, true)
+        };
+        let weight = |node: &NodeStats, config: &BucketConfig| {
+            AdaptiveDelegates::weight(node, config)
+        };
+        assert_eq!(
+            weight(&with_rtt(10), &config),
+            weight(&with_rtt(500), &config)
+        );
+
+        config.latency_aware_delegates = true;
+        assert!(
+            weight(&with_rtt(10), &config) > weight(&node(0, true), &config)
+        );
+        assert!(
+            weight(&node(0, true), &config) > weight(&with_rtt(500), &config)
+        );
+        assert_eq!(
+            weight(&with_rtt(100), &config),
+            weight(&node(0, true), &config)
+        );
+    }
+
+    #[test]
+    fn test_adaptive_amount() {
+        let config = BucketConfig::default();
+        let policy = AdaptiveDelegates {
+            beta: 3,
+            max_delegates: 6,
+        };
+        let select = |nodes: &[NodeStats]| policy.select(nodes, &config);
+
+        assert_eq!(select(&[node(0, true), node(0, true)]).len(), 2);
+        assert_eq!(select(&vec![node(0, true); 10]).len(), 3);
+        let mut half = vec![node(0, true); 5];
+        half.extend(vec![node(-10, true); 5]);
+        assert_eq!(select(&half).len(), 6);
+        assert_eq!(select(&vec![node(0, false); 10]).len(), 6);
+
+        let mut selected = select(&vec![node(0, true); 10]);
+        selected.sort_unstable();
+        selected.dedup();
+        assert_eq!(selected.len(), 3);
+    }
+
+    #[test]
+    fn test_configured_beta() {
+        let nodes: Vec<_> = (2..20)
+            .map(|i| PeerNode::generate(&format!("192.168.0.{}:666", i)))
+            .collect();
+        let mut config = BucketConfig::default();
+        assert_eq!(select_delegates(&nodes, &config).len(), K_BETA);
+        config.beta = 6;
+        assert_eq!(select_delegates(&nodes, &config).len(), 6);
+        config.beta = 1;
+        assert_eq!(select_delegates(&nodes, &config).len(), 1);
+    }
+
+    #[test]
+    fn test_trusted_delegates() {
+        let mut nodes: Vec<_> = (2..20)
+            .map(|i| PeerNode::generate(&format!("192.168.0.{}:666", i)))
+            .collect();
+        nodes[7].trusted = true;
+        nodes[7].adjust_score(-50);
+        assert_eq!(nodes[7].score(), 0);
+        let config = BucketConfig {
+            beta: 1,
+            ..Default::default()
+        };
+        for _ in 0..10 {
+            let delegates = select_delegates(&nodes, &config);
+            assert!(delegates.iter().any(|node| node.is_trusted()));
+            assert!(delegates.len() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_fixed_delegates() {
+        let config = BucketConfig::default();
+        let nodes = [node(0, true), node(5, true), node(-5, true)];
+        assert_eq!(FixedDelegates(2).select(&nodes, &config), vec![1, 0]);
+        assert_eq!(FixedDelegates(5).select(&nodes, &config).len(), 3);
+    }
+}
@@ -0,0 +1,95 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::net::IpAddr;
+
+use crate::peer::PeerInfo;
+
+/// Subnet a node is hosted on: the /24 of IPv4 addresses and the /64 of IPv6
+/// ones, which is usually the smallest block assigned by a hosting provider
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub(crate) enum Subnet {
+    V4([u8; 3]),
+    V6([u8; 8]),
+}
+
+impl From<&IpAddr> for Subnet {
+    fn from(ip: &IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => {
+                let [a, b, c, _] = ip.octets();
+                Subnet::V4([a, b, c])
+            }
+            IpAddr::V6(ip) => {
+                let mut prefix = [0; 8];
+                prefix.copy_from_slice(&ip.octets()[..8]);
+                Subnet::V6(prefix)
+            }
+        }
+    }
+}
+
+/// Value of a node whose subnet is limited by the IP diversity of the table
+pub(crate) trait Subnetwork {
+    fn subnet(&self) -> Subnet;
+}
+
+impl Subnetwork for PeerInfo {
+    fn subnet(&self) -> Subnet {
+        Subnet::from(&self.address().ip())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::Subnet;
+    use crate::config::BucketConfig;
+    use crate::kbucket::{NodeInsertError, Tree};
+    use crate::peer::PeerNode;
+
+    #[test]
+    fn test_subnet() {
+        let subnet = |ip: &str| Subnet::from(&ip.parse::<IpAddr>().unwrap());
+        assert_eq!(subnet("10.0.0.1"), subnet("10.0.0.254"));
+        assert_ne!(subnet("10.0.0.1"), subnet("10.0.1.1"));
+        assert_eq!(subnet("2001:db8::1"), subnet("2001:db8::ffff:1"));
+        assert_ne!(subnet("2001:db8::1"), subnet("2001:db8:0:1::1"));
+    }
+
+    #[test]
+    fn test_subnet_limits() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let config = BucketConfig {
+            max_subnet_nodes_per_bucket: Some(2),
+            max_subnet_nodes_per_table: Some(5),
+            ..Default::default()
+        };
+        let mut tree = Tree::new(root, config);
+        let mut inserted = 0;
+        for i in 2..100 {
+            let node = PeerNode::generate(&format!("10.0.0.{}:666", i));
+            match tree.insert(node) {
+                Ok(_) => inserted += 1,
+                Err(NodeInsertError::SubnetLimit(_)) => {}
+                Err(_) => panic!("Unexpected insert error"),
+            }
+        }
+        assert_eq!(inserted, 5);
+        assert!(tree.all_sorted().all(|(_, nodes)| nodes.count() <= 2));
+
+        // Nodes already in the table are still refreshed
+        let known = tree.alive_nodes().next().unwrap().clone();
+        assert!(tree.insert(known).is_ok());
+
+        // Other subnets are not affected
+        for i in 2..10 {
+            let node = PeerNode::generate(&format!("10.0.{}.1:666", i));
+            assert!(tree.insert(node).is_ok());
+        }
+    }
+}
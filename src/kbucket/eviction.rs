@@ -0,0 +1,96 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fmt;
+
+use super::node::NodeStats;
+use crate::config::BucketConfig;
+
+/// What to do with the nodes of a full bucket when a new node is received
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EvictionAction {
+    /// Keep every node, the new node is discarded
+    Keep,
+    /// Check the liveness of the node at the given index. The new node is
+    /// queued until the node is evicted
+    Probe(usize),
+    /// Evict the node at the given index, replacing it with the first queued
+    /// node
+    Evict(usize),
+}
+
+/// Policy choosing which node of a full bucket is replaced by new nodes.
+///
+/// The candidates are ordered from the least recently used
+pub trait EvictionPolicy: Send + Sync {
+    fn action(
+        &self,
+        candidates: &[NodeStats],
+        config: &BucketConfig,
+    ) -> EvictionAction;
+}
+
+impl fmt::Debug for dyn EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EvictionPolicy")
+    }
+}
+
+/// Default [EvictionPolicy]: the least recently used node is probed once
+/// idle for `node_ttl`, and evicted if it doesn't reply within
+/// `node_evict_after`
+pub struct LruEviction;
+
+impl EvictionPolicy for LruEviction {
+    fn action(
+        &self,
+        candidates: &[NodeStats],
+        config: &BucketConfig,
+    ) -> EvictionAction {
+        match candidates.first() {
+            Some(lru) => match lru.probed {
+                Some(elapsed) if elapsed >= config.node_evict_after => {
+                    EvictionAction::Evict(0)
+                }
+                Some(_) => EvictionAction::Probe(0),
+                None if lru.idle < config.node_ttl => EvictionAction::Keep,
+                None => EvictionAction::Probe(0),
+            },
+            None => EvictionAction::Keep,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{EvictionAction, EvictionPolicy, LruEviction, NodeStats};
+    use crate::config::BucketConfig;
+
+    #[test]
+    fn test_lru_policy() {
+        let config = BucketConfig {
+            node_ttl: Duration::from_secs(30),
+            node_evict_after: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let candidate = |idle, probed: Option<u64>| NodeStats {
+            idle: Duration::from_secs(idle),
+            age: Duration::from_secs(idle),
+            score: 0,
+            rtt: None,
+            probed: probed.map(Duration::from_secs),
+        };
+        let action =
+            |lru| LruEviction.action(&[lru, candidate(0, None)], &config);
+        assert_eq!(action(candidate(10, None)), EvictionAction::Keep);
+        assert_eq!(action(candidate(30, None)), EvictionAction::Probe(0));
+        assert_eq!(action(candidate(32, Some(2))), EvictionAction::Probe(0));
+        assert_eq!(action(candidate(35, Some(5))), EvictionAction::Evict(0));
+        assert_eq!(LruEviction.action(&[], &config), EvictionAction::Keep);
+    }
+}
@@ -0,0 +1,241 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::convert::TryInto;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::node::NodeEvictionStatus;
+use super::{BinaryID, BucketHeight, Score, Tree};
+use crate::encoding::payload::is_valid_fallback;
+use crate::peer::{PeerInfo, PeerNode};
+
+/// Routing table exported with
+/// [Peer::export_table](crate::Peer::export_table).
+///
+/// Timestamps are seconds since UNIX epoch, binary fields are hex encoded
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ExportedTable {
+    exported_at: u64,
+    root: ExportedPeer,
+    buckets: Vec<ExportedBucket>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ExportedBucket {
+    height: BucketHeight,
+    last_refresh: Option<u64>,
+    peers: Vec<ExportedPeer>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ExportedPeer {
+    address: SocketAddr,
+    #[serde(default)]
+    fallback: Option<SocketAddr>,
+    id: String,
+    nonce: String,
+    #[serde(default)]
+    last_seen: u64,
+    #[serde(default)]
+    score: Score,
+    #[serde(default)]
+    rtt_millis: Option<u64>,
+    #[serde(default)]
+    pending_eviction: bool,
+    #[serde(default)]
+    metadata: String,
+    #[serde(default)]
+    custom_id: bool,
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn unix_time(at: Instant, now: SystemTime) -> u64 {
+    now.checked_sub(at.elapsed())
+        .unwrap_or(now)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+impl ExportedPeer {
+    fn from_node(node: &PeerNode, now: SystemTime) -> Self {
+        ExportedPeer {
+            address: *node.value().address(),
+            fallback: node.value().fallback_address().copied(),
+            id: to_hex(node.id().as_binary()),
+            nonce: to_hex(node.id().nonce()),
+            last_seen: unix_time(node.seen_at, now),
+            score: node.score(),
+            rtt_millis: node.rtt().map(|rtt| rtt.as_millis() as u64),
+            pending_eviction: matches!(
+                node.eviction_status,
+                NodeEvictionStatus::Requested(_)
+            ),
+            metadata: to_hex(node.value().metadata()),
+            custom_id: node.value().has_custom_id(),
+        }
+    }
+
+    // The node of the peer, `None` if its id is not valid
+    fn to_node(&self, now: SystemTime) -> Option<PeerNode> {
+        let id = from_hex(&self.id)?.try_into().ok()?;
+        let nonce = from_hex(&self.nonce)?.try_into().ok()?;
+        let id = BinaryID::from_nonce(id, nonce);
+        // Unless it's custom, the id is bound to the address of the peer
+        let bound = self.custom_id
+            || PeerNode::compute_id(&self.address.ip(), self.address.port())
+                == *id.as_binary();
+        if !id.verify_nonce() || !bound {
+            return None;
+        }
+        let mut node = PeerNode::from_socket(self.address, id);
+        node.value_mut().set_custom_id(self.custom_id);
+        if let Some(fallback) = self.fallback {
+            if is_valid_fallback(&self.address.ip(), &fallback) {
+                node = node.with_fallback(fallback);
+            }
+        }
+        node.value_mut().set_metadata(from_hex(&self.metadata)?);
+        let last_seen = UNIX_EPOCH + Duration::from_secs(self.last_seen);
+        let idle = now.duration_since(last_seen).unwrap_or(Duration::ZERO);
+        node.seen_at = Instant::now().checked_sub(idle).unwrap_or(node.seen_at);
+        node.adjust_score(self.score - node.score());
+        node.rtt = self.rtt_millis.map(Duration::from_millis);
+        Some(node)
+    }
+}
+
+impl Tree<PeerInfo> {
+    /// Export the buckets and the peers of the table
+    pub(crate) fn export(&self) -> ExportedTable {
+        let now = SystemTime::now();
+        let buckets = self
+            .all_sorted()
+            .map(|(height, nodes)| ExportedBucket {
+                height,
+                last_refresh: self
+                    .last_refresh(height)
+                    .map(|at| unix_time(at, now)),
+                peers: nodes
+                    .map(|node| ExportedPeer::from_node(node, now))
+                    .collect(),
+            })
+            .filter(|bucket| !bucket.peers.is_empty())
+            .collect();
+        ExportedTable {
+            exported_at: unix_time(Instant::now(), now),
+            root: ExportedPeer::from_node(self.root(), now),
+            buckets,
+        }
+    }
+
+    /// Insert the peers of an exported table, keeping their last seen time,
+    /// score and latency. The root and the bucket layout of the export are
+    /// ignored, since they depend on the exporting peer.
+    ///
+    /// Returns the amount of peers inserted
+    pub(crate) fn import(&mut self, table: &ExportedTable) -> usize {
+        let now = SystemTime::now();
+        let mut inserted = 0;
+        for peer in table.buckets.iter().flat_map(|b| b.peers.iter()) {
+            match peer.to_node(now) {
+                Some(node) => {
+                    if self.insert(node).is_ok() {
+                        inserted += 1;
+                    }
+                }
+                None => warn!("Skipping invalid exported peer {}", peer.id),
+            }
+        }
+        inserted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_hex, to_hex};
+    use crate::config::BucketConfig;
+    use crate::kbucket::Tree;
+    use crate::peer::PeerNode;
+
+    #[test]
+    fn test_export_import() {
+        assert_eq!(from_hex(&to_hex(&[0, 1, 0xab])), Some(vec![0, 1, 0xab]));
+        assert_eq!(from_hex("abc"), None);
+
+        let root = PeerNode::generate("192.168.0.1:666");
+        let mut tree = Tree::new(root, BucketConfig::default());
+        for i in 2..12 {
+            tree.insert(PeerNode::generate(&format!("192.168.0.{}:666", i)))
+                .unwrap();
+        }
+        let fallback = "[2001:db8::1]:666".parse().unwrap();
+        tree.insert(
+            PeerNode::generate("192.168.0.12:666").with_fallback(fallback),
+        )
+        .unwrap();
+        tree.adjust_scores(|_| true, 5);
+        let exported = tree.export();
+        let json = serde_json::to_value(&exported).unwrap();
+        let parsed = serde_json::from_value(json).unwrap();
+        assert_eq!(exported, parsed);
+
+        let other = PeerNode::generate("192.168.1.1:666");
+        let mut imported = Tree::new(other, BucketConfig::default());
+        assert_eq!(imported.import(&parsed), 11);
+        let peers: Vec<_> = imported.snapshot().peers().cloned().collect();
+        assert!(peers.iter().all(|p| p.score() == 5));
+        assert!(peers
+            .iter()
+            .any(|p| p.fallback_address() == Some(&fallback)));
+
+        // Peers whose id is not bound to their address are skipped
+        let mut json = serde_json::to_value(&exported).unwrap();
+        json["buckets"][0]["peers"][0]["address"] = "10.0.0.1:666".into();
+        let tampered = serde_json::from_value(json).unwrap();
+        let mut imported = Tree::new(
+            PeerNode::generate("192.168.1.1:666"),
+            BucketConfig::default(),
+        );
+        assert_eq!(imported.import(&tampered), 10);
+
+        // Unless their id is custom
+        let mut json = serde_json::to_value(&exported).unwrap();
+        let moved = "10.0.0.1:666".parse().unwrap();
+        json["buckets"][0]["peers"][0]["address"] = "10.0.0.1:666".into();
+        json["buckets"][0]["peers"][0]["custom_id"] = true.into();
+        let id = json["buckets"][0]["peers"][0]["id"].clone();
+        let custom = serde_json::from_value(json).unwrap();
+        let mut imported = Tree::new(
+            PeerNode::generate("192.168.1.1:666"),
+            BucketConfig::default(),
+        );
+        assert_eq!(imported.import(&custom), 11);
+        assert_eq!(id, to_hex(&imported.id_of(&moved)).as_str());
+    }
+}
@@ -0,0 +1,108 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+use super::stats::{self, TableMetric};
+use super::Tree;
+use crate::peer::PeerInfo;
+
+// Failures exceeding this amount are dropped until the table applies them
+const MAX_PENDING_FAILURES: usize = 1024;
+
+/// Peers the transport has been unable to send to, even after the retries
+/// and the fallback address.
+///
+/// Each peer gets a single strike every time the failures are applied,
+/// regardless of the amount of chunks which couldn't be sent
+#[derive(Default)]
+pub(crate) struct DeliveryFailures {
+    pending: Mutex<HashSet<SocketAddr>>,
+}
+
+impl DeliveryFailures {
+    pub(crate) fn report(&self, address: SocketAddr) {
+        let mut pending =
+            self.pending.lock().expect("Poisoned delivery failures");
+        if pending.len() < MAX_PENDING_FAILURES {
+            pending.insert(address);
+        }
+    }
+
+    fn drain(&self) -> HashSet<SocketAddr> {
+        std::mem::take(
+            &mut *self.pending.lock().expect("Poisoned delivery failures"),
+        )
+    }
+}
+
+impl Tree<PeerInfo> {
+    /// Strike the peers the transport has been unable to send to. Peers
+    /// reaching `max_delivery_failures` strikes without sending any message
+    /// are removed straight away, instead of waiting for the keep alive.
+    /// Anchors are never removed.
+    pub(crate) fn apply_delivery_failures(&mut self) {
+        let max_failures = self.config.max_delivery_failures;
+        let mut dead = vec![];
+        for address in self.filter().failures.drain() {
+            let id = self.id_of(&address);
+            if let Some(node) = self.node_mut(&id) {
+                node.failures += 1;
+                if max_failures > 0
+                    && node.failures >= max_failures
+                    && !node.is_anchor()
+                {
+                    dead.push(id);
+                }
+            }
+        }
+        if !dead.is_empty() {
+            let removed =
+                self.remove_nodes(|node| dead.contains(node.id().as_binary()));
+            stats::record_many(TableMetric::Eviction, removed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::BucketConfig;
+    use crate::kbucket::Tree;
+    use crate::peer::PeerNode;
+
+    #[test]
+    fn test_delivery_failures() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let config = BucketConfig {
+            max_delivery_failures: 2,
+            ..Default::default()
+        };
+        let mut tree = Tree::new(root, config);
+        let node = || PeerNode::generate("192.168.0.2:666");
+        let id = *node().id().as_binary();
+        let address = *node().value().address();
+        tree.insert(node()).unwrap();
+
+        // Many failures between two applications are a single strike
+        for _ in 0..10 {
+            tree.filter().failures.report(address);
+        }
+        tree.apply_delivery_failures();
+        assert!(tree.has_peer(&id).is_some());
+
+        // A message received from the peer resets its strikes
+        tree.insert(node()).unwrap();
+        tree.filter().failures.report(address);
+        tree.apply_delivery_failures();
+        assert!(tree.has_peer(&id).is_some());
+
+        tree.filter().failures.report(address);
+        tree.apply_delivery_failures();
+        assert!(tree.has_peer(&id).is_none());
+    }
+}
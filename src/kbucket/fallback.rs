@@ -0,0 +1,67 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+// Max amount of dual-homed peers tracked. It's way larger than the routing
+// table, so that the peers learned from `Nodes` messages fit as well
+const MAX_FALLBACK_ADDRESSES: usize = 4096;
+
+/// Fallback addresses of the dual-homed peers, indexed by their primary
+/// address.
+///
+/// The transport sends to the fallback address when the primary one is
+/// unreachable (eg: the local host has no route for its IP family)
+#[derive(Default)]
+pub(crate) struct FallbackAddresses {
+    addresses: Mutex<HashMap<SocketAddr, SocketAddr>>,
+}
+
+impl FallbackAddresses {
+    pub(crate) fn record(&self, primary: SocketAddr, fallback: SocketAddr) {
+        let mut addresses =
+            self.addresses.lock().expect("Fallback lock poisoned");
+        if addresses.len() >= MAX_FALLBACK_ADDRESSES
+            && !addresses.contains_key(&primary)
+        {
+            // Make room dropping an arbitrary peer
+            if let Some(&dropped) = addresses.keys().next() {
+                addresses.remove(&dropped);
+            }
+        }
+        addresses.insert(primary, fallback);
+    }
+
+    pub(crate) fn get(&self, primary: &SocketAddr) -> Option<SocketAddr> {
+        let addresses = self.addresses.lock().expect("Fallback lock poisoned");
+        addresses.get(primary).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::{FallbackAddresses, MAX_FALLBACK_ADDRESSES};
+
+    #[test]
+    fn test_fallback_addresses() {
+        let fallbacks = FallbackAddresses::default();
+        let primary = |i: u32| SocketAddr::new(i.to_be_bytes().into(), 666);
+        let fallback: SocketAddr = "[2001:db8::1]:666".parse().unwrap();
+        fallbacks.record(primary(1), fallback);
+        assert_eq!(fallbacks.get(&primary(1)), Some(fallback));
+        assert_eq!(fallbacks.get(&primary(2)), None);
+
+        for i in 0..MAX_FALLBACK_ADDRESSES as u32 * 2 {
+            fallbacks.record(primary(i), fallback);
+        }
+        let len = fallbacks.addresses.lock().unwrap().len();
+        assert_eq!(len, MAX_FALLBACK_ADDRESSES);
+    }
+}
@@ -0,0 +1,81 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::time::Duration;
+
+use crate::config::KeepAliveConfig;
+
+/// What to do with a node of the routing table which has been idle (no
+/// message received) for the `node_ttl`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KeepAliveAction {
+    /// Send a `Ping` to the node
+    Ping,
+    /// Wait for the reply to the previous pings
+    Wait,
+    /// Declare the node dead and remove it from the routing table
+    Remove,
+}
+
+/// Policy deciding how the idle nodes are pinged before being removed from
+/// the routing table.
+///
+/// Any message received from a node resets its pings
+pub trait KeepAlivePolicy: Send + Sync {
+    /// The action for an idle node, which has already been sent `pings`
+    /// pings without reply, the last one `since_last_ping` ago
+    fn action(
+        &self,
+        pings: usize,
+        since_last_ping: Option<Duration>,
+    ) -> KeepAliveAction;
+}
+
+impl KeepAlivePolicy for KeepAliveConfig {
+    fn action(
+        &self,
+        pings: usize,
+        since_last_ping: Option<Duration>,
+    ) -> KeepAliveAction {
+        match since_last_ping {
+            Some(elapsed) if elapsed < self.interval => KeepAliveAction::Wait,
+            _ if pings < self.attempts => KeepAliveAction::Ping,
+            _ => KeepAliveAction::Remove,
+        }
+    }
+}
+
+/// [KeepAlivePolicy] which never pings nor removes the idle nodes
+pub struct NoKeepAlive;
+
+impl KeepAlivePolicy for NoKeepAlive {
+    fn action(&self, _: usize, _: Option<Duration>) -> KeepAliveAction {
+        KeepAliveAction::Wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{KeepAliveAction, KeepAlivePolicy};
+    use crate::config::KeepAliveConfig;
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = KeepAliveConfig {
+            attempts: 2,
+            interval: Duration::from_secs(5),
+        };
+        let second = Duration::from_secs(1);
+        let interval = Duration::from_secs(5);
+        assert_eq!(policy.action(0, None), KeepAliveAction::Ping);
+        assert_eq!(policy.action(1, Some(second)), KeepAliveAction::Wait);
+        assert_eq!(policy.action(1, Some(interval)), KeepAliveAction::Ping);
+        assert_eq!(policy.action(2, Some(second)), KeepAliveAction::Wait);
+        assert_eq!(policy.action(2, Some(interval)), KeepAliveAction::Remove);
+    }
+}
@@ -4,8 +4,10 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
+use std::convert::TryInto;
 use std::io;
 
+use super::BucketHeight;
 use crate::encoding::Marshallable;
 use crate::K_ID_LEN_BYTES;
 use crate::K_NONCE_LEN;
@@ -13,7 +15,52 @@ use crate::K_NONCE_LEN;
 pub type BinaryKey = [u8; K_ID_LEN_BYTES];
 pub type BinaryNonce = [u8; K_NONCE_LEN];
 
+/// Returns the full XOR distance between 2 keys, with the most significant
+/// byte first so that distances can be compared.
+///
+/// The last byte of a key is the most significant one
+pub fn xor_distance(a: &BinaryKey, b: &BinaryKey) -> BinaryKey {
+    let mut distance = [0; K_ID_LEN_BYTES];
+    a.iter()
+        .zip(b.iter())
+        .rev()
+        .zip(distance.iter_mut())
+        .for_each(|((&a, &b), d)| *d = a ^ b);
+    distance
+}
+
+/// Returns the height of the bucket a key belongs to in the routing table of
+/// `root`, which is the position of the most significant bit of their XOR
+/// distance. `None` if the keys are identical
+pub fn bucket_height(
+    root: &BinaryKey,
+    key: &BinaryKey,
+) -> Option<BucketHeight> {
+    root.iter()
+        .zip(key.iter())
+        .map(|(&a, &b)| a ^ b)
+        .enumerate()
+        .rev()
+        .find(|(_, b)| b != &0b0)
+        .map(|(i, b)| BinaryID::msb(b).expect("Can't be None") + (i << 3) - 1)
+}
+
+/// Derive a [BinaryKey] from an arbitrary key.
+///
+/// Keys of [ID_LEN](crate::ID_LEN) bytes are used as they are, any other key
+/// is hashed
+pub fn derive_key(key: &[u8]) -> BinaryKey {
+    match key.try_into() {
+        Ok(key) => key,
+        Err(_) => {
+            let digest = Blake2s::digest(key);
+            digest[..K_ID_LEN_BYTES].try_into().expect("Wrong length")
+        }
+    }
+}
+
 use blake2::{Blake2s, Digest};
+use rand::{thread_rng, Rng};
 
 use crate::{K_DIFF_MIN_BIT, K_DIFF_PRODUCED_BIT};
 
@@ -50,19 +97,30 @@ impl BinaryID {
         &self.nonce
     }
 
-    // Returns the 0-based kadcast distance between 2 ID
-    // `None` if they are identical
+    /// Returns the 0-based kadcast distance between 2 ID, which is the
+    /// [bucket_height] of `other`. `None` if they are identical
     pub fn calculate_distance(&self, other: &BinaryKey) -> Option<usize> {
-        self.as_binary()
-            .iter()
-            .zip(other.iter())
-            .map(|(&a, &b)| a ^ b)
-            .enumerate()
-            .rev()
-            .find(|(_, b)| b != &0b0)
-            .map(|(i, b)| {
-                BinaryID::msb(b).expect("Can't be None") + (i << 3) - 1
-            })
+        bucket_height(self.as_binary(), other)
+    }
+
+    /// Returns the full [xor_distance] between 2 ID
+    pub fn xor_distance(&self, other: &BinaryKey) -> BinaryKey {
+        xor_distance(self.as_binary(), other)
+    }
+
+    // Returns a random key at the given distance, in the range of the bucket
+    // with the same height
+    pub(crate) fn random_key_at(&self, height: usize) -> BinaryKey {
+        let mut distance = [0; K_ID_LEN_BYTES];
+        let (byte, bit) = (height / 8, height % 8);
+        thread_rng().fill(&mut distance[..byte]);
+        distance[byte] =
+            (1 << bit) | (thread_rng().gen::<u8>() & ((1 << bit) - 1));
+        let mut key = self.bytes;
+        key.iter_mut()
+            .zip(distance.iter())
+            .for_each(|(k, d)| *k ^= d);
+        key
     }
 
     // Returns the position of the most-significant bit set in a byte,
@@ -82,6 +140,14 @@ impl BinaryID {
         if K_DIFF_PRODUCED_BIT < K_DIFF_MIN_BIT {
             panic!("PoW is less than minimum required, review your build config...")
         }
+        BinaryID::generate_with_difficulty(id, K_DIFF_PRODUCED_BIT)
+    }
+
+    /// Search the nonce proving `difficulty` bits of work on `id`
+    pub(crate) fn generate_with_difficulty(
+        id: BinaryKey,
+        difficulty: usize,
+    ) -> Self {
         let mut nonce: u32 = 0;
         let mut hasher = Blake2s::new();
         loop {
@@ -90,7 +156,7 @@ impl BinaryID {
             hasher.update(nonce_bytes);
             if BinaryID::verify_difficulty(
                 &mut hasher.finalize_reset().iter().rev(),
-                K_DIFF_PRODUCED_BIT,
+                difficulty,
             ) {
                 return Self {
                     bytes: id,
@@ -102,12 +168,18 @@ impl BinaryID {
     }
 
     pub fn verify_nonce(&self) -> bool {
+        self.has_difficulty(K_DIFF_MIN_BIT)
+    }
+
+    /// Check if the nonce proves at least `difficulty` bits of work, see
+    /// [BucketConfig::id_difficulty](crate::config::BucketConfig::id_difficulty)
+    pub fn has_difficulty(&self, difficulty: usize) -> bool {
         let mut hasher = Blake2s::new();
         hasher.update(self.bytes);
         hasher.update(self.nonce);
         BinaryID::verify_difficulty(
             &mut hasher.finalize().iter().rev(),
-            K_DIFF_MIN_BIT,
+            difficulty,
         )
     }
 
@@ -118,7 +190,7 @@ impl BinaryID {
     where
         I: Iterator<Item = &'a u8>,
     {
-        bytes.next().map_or(false, |b| {
+        bytes.next().is_some_and(|b| {
             if difficulty <= 8 {
                 b.trailing_zeros() as usize >= difficulty
             } else if b != &0 {
@@ -132,19 +204,16 @@ impl BinaryID {
 
 #[cfg(test)]
 mod tests {
+    use super::{bucket_height, xor_distance};
     use crate::{kbucket::BinaryID, peer::PeerNode};
 
     impl BinaryID {
         fn calculate_distance_native(&self, other: &BinaryID) -> Option<usize> {
-            let a = u128::from_le_bytes(*self.as_binary());
-            let b = u128::from_le_bytes(*other.as_binary());
-            let xor = a ^ b;
-            let ret = 128 - xor.leading_zeros() as usize;
-            if ret == 0 {
-                None
-            } else {
-                Some(ret - 1)
-            }
+            // Bit by bit comparison, to not depend on the id length
+            let (a, b) = (self.as_binary(), other.as_binary());
+            (0..a.len() * 8)
+                .rev()
+                .find(|&bit| (a[bit / 8] ^ b[bit / 8]) >> (bit % 8) & 1 == 1)
         }
     }
 
@@ -163,6 +232,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_random_key_at() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        for height in 0..crate::K_ID_LEN_BYTES * 8 {
+            let key = root.id().random_key_at(height);
+            assert_eq!(root.id().calculate_distance(&key), Some(height));
+        }
+    }
+
+    #[test]
+    fn test_bucket_height() {
+        let node = PeerNode::generate("192.168.0.1:666");
+        let root = node.id().as_binary();
+        assert_eq!(bucket_height(root, root), None);
+        let keys: Vec<_> = (0..crate::K_ID_LEN_BYTES * 8)
+            .map(|height| node.id().random_key_at(height))
+            .collect();
+        // Farther buckets hold keys with a greater XOR distance
+        for (height, pair) in keys.windows(2).enumerate() {
+            assert_eq!(bucket_height(root, &pair[0]), Some(height));
+            assert!(
+                xor_distance(root, &pair[0]) < xor_distance(root, &pair[1])
+            );
+        }
+    }
+
     #[test]
     fn test_id_nonce() {
         let root = PeerNode::generate("192.168.0.1:666");
@@ -170,6 +265,18 @@ mod tests {
         assert!(root.id().verify_nonce());
     }
 
+    #[test]
+    fn test_id_difficulty() {
+        let key = [3; crate::K_ID_LEN_BYTES];
+        let id = BinaryID::generate_with_difficulty(key, 14);
+        assert!(id.verify_nonce());
+        assert!(id.has_difficulty(14));
+        // The nonces are searched in order, the first one proving the
+        // minimum difficulty is the same only if it proves both
+        let weak = BinaryID::generate(key);
+        assert_eq!(weak.has_difficulty(14), weak == id);
+    }
+
     #[test]
     fn test_difficulty() {
         let b: [u8; 2] = [0b11110000, 0];
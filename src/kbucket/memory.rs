@@ -0,0 +1,154 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::mem::size_of;
+
+use super::stats::{self, TableMetric};
+use super::{Node, Tree};
+use crate::peer::PeerInfo;
+
+/// Estimate of the memory used by the routing table, in bytes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    tree: usize,
+    pending: usize,
+    metadata: usize,
+}
+
+impl MemoryUsage {
+    /// Bytes used by the nodes of the buckets
+    pub fn tree(&self) -> usize {
+        self.tree
+    }
+
+    /// Bytes used by the nodes waiting for a free slot of a full bucket
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Bytes used by the metadata attached to the peers
+    pub fn metadata(&self) -> usize {
+        self.metadata
+    }
+
+    pub fn total(&self) -> usize {
+        self.tree + self.pending + self.metadata
+    }
+}
+
+fn node_size(node: &Node<PeerInfo>) -> usize {
+    size_of::<Node<PeerInfo>>() + node.value().metadata().len()
+}
+
+impl Tree<PeerInfo> {
+    pub(crate) fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        for bucket in self.buckets.values() {
+            usage.tree += bucket.peers().count() * size_of::<Node<PeerInfo>>();
+            usage.pending +=
+                bucket.pending().count() * size_of::<Node<PeerInfo>>();
+            usage.metadata += bucket
+                .peers()
+                .chain(bucket.pending())
+                .map(|node| node.value().metadata().len())
+                .sum::<usize>();
+        }
+        usage
+    }
+
+    /// Shed entries until the table fits the configured `max_memory`: the
+    /// pending nodes go first, the oldest first, then the nodes with the
+    /// lowest score, the least recently seen first. Anchors are never shed.
+    ///
+    /// Returns the amount of nodes shed
+    pub(crate) fn shed_memory(&mut self) -> usize {
+        let max_memory = match self.config.max_memory {
+            Some(max_memory) => max_memory,
+            None => return 0,
+        };
+        let mut used = self.memory_usage().total();
+        let mut shed = 0;
+        while used > max_memory {
+            let oldest = self
+                .buckets
+                .values_mut()
+                .filter_map(|bucket| {
+                    let seen_at = bucket.pending().next()?.seen_at;
+                    Some((seen_at, bucket))
+                })
+                .min_by_key(|(seen_at, _)| *seen_at)
+                .and_then(|(_, bucket)| bucket.pop_pending());
+            match oldest {
+                Some(node) => used -= node_size(&node),
+                None => break,
+            }
+            shed += 1;
+        }
+        if used <= max_memory {
+            return shed;
+        }
+
+        let mut candidates: Vec<_> = self
+            .buckets
+            .values()
+            .flat_map(|bucket| bucket.peers())
+            .filter(|node| !node.anchor)
+            .map(|node| {
+                (node.score(), node.seen_at, *node.id(), node_size(node))
+            })
+            .collect();
+        candidates.sort_by_key(|(score, seen_at, ..)| (*score, *seen_at));
+        let mut to_remove = vec![];
+        for (_, _, id, size) in candidates {
+            if used <= max_memory {
+                break;
+            }
+            used -= size;
+            to_remove.push(id);
+        }
+        let removed = self.remove_nodes(|node| to_remove.contains(node.id()));
+        stats::record_many(TableMetric::Eviction, removed);
+        shed + removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use crate::config::BucketConfig;
+    use crate::kbucket::{Node, Tree};
+    use crate::peer::{PeerInfo, PeerNode};
+
+    #[test]
+    fn test_memory_cap() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let mut tree = Tree::new(root, BucketConfig::default());
+        for i in 2..30 {
+            let node = PeerNode::generate(&format!("192.168.0.{}:666", i));
+            let id = *node.id().as_binary();
+            let _ = tree.insert(node);
+            if let Some(node) = tree.node_mut(&id) {
+                node.value_mut().set_metadata(vec![0; 10]);
+                node.adjust_score(i % 4);
+            }
+        }
+        let nodes = tree.alive_nodes().count();
+        let usage = tree.memory_usage();
+        assert_eq!(usage.tree(), nodes * size_of::<Node<PeerInfo>>());
+        assert_eq!(usage.metadata(), nodes * 10);
+        assert_eq!(usage.pending(), 0);
+        assert_eq!(tree.shed_memory(), 0);
+
+        tree.config.max_memory = Some(usage.total() / 2);
+        let shed = tree.shed_memory();
+        assert_eq!(tree.alive_nodes().count(), nodes - shed);
+        assert!(tree.memory_usage().total() <= usage.total() / 2);
+
+        // The nodes with the lowest score are shed first
+        assert!(tree.alive_nodes().all(|node| node.score() > 0));
+    }
+}
@@ -7,15 +7,34 @@
 use std::time::{Duration, Instant};
 
 use super::key::BinaryID;
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+use super::score::{Score, DEFAULT_SCORE, MAX_SCORE, MIN_SCORE};
+
+// Weight of the previous round trip time when a new sample is recorded,
+// the smoothed value is `(rtt * (RTT_SMOOTHING - 1) + sample) / RTT_SMOOTHING`
+const RTT_SMOOTHING: u32 = 8;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Node<TValue> {
     id: BinaryID,
     value: TValue,
     pub(super) eviction_status: NodeEvictionStatus,
     pub(super) seen_at: Instant,
+    pub(super) added_at: Instant,
+    score: Score,
+    // Keep alive pings sent since the last message received
+    pub(super) pings: usize,
+    pub(super) last_ping: Option<Instant>,
+    // Smoothed round trip time of the pings answered by the node
+    pub(super) rtt: Option<Duration>,
+    // Consecutive strikes for failing deliveries, reset by any message
+    pub(super) failures: usize,
+    // Anchors are never evicted to make room for other nodes
+    pub(super) anchor: bool,
+    // Trusted nodes are exempt from the penalties and always delegates
+    pub(super) trusted: bool,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub enum NodeEvictionStatus {
     None,
     Requested(Instant),
@@ -27,7 +46,15 @@ impl<TValue> Node<TValue> {
             id,
             value,
             seen_at: Instant::now(),
+            added_at: Instant::now(),
             eviction_status: NodeEvictionStatus::None,
+            score: DEFAULT_SCORE,
+            pings: 0,
+            last_ping: None,
+            rtt: None,
+            failures: 0,
+            anchor: false,
+            trusted: false,
         }
     }
 
@@ -37,8 +64,21 @@ impl<TValue> Node<TValue> {
 
     //maybe we can move this outside of node impl, nonce must be verified when
     // a node is deserialized IMHO
-    pub fn is_id_valid(&self) -> bool {
-        self.id.verify_nonce()
+    /// Check if the nonce of the id proves `difficulty` bits of work, see
+    /// [BucketConfig::id_difficulty](crate::config::BucketConfig::id_difficulty)
+    pub fn is_id_valid(&self, difficulty: usize) -> bool {
+        self.id.has_difficulty(difficulty)
+    }
+
+    /// Check if the node is one of the anchors of the table
+    pub fn is_anchor(&self) -> bool {
+        self.anchor
+    }
+
+    /// Check if the node is trusted, see
+    /// [Config::trusted_peers](crate::config::Config::trusted_peers)
+    pub fn is_trusted(&self) -> bool {
+        self.trusted
     }
 
     pub fn id(&self) -> &BinaryID {
@@ -49,9 +89,61 @@ impl<TValue> Node<TValue> {
         &self.value
     }
 
+    pub fn score(&self) -> Score {
+        self.score
+    }
+
+    /// Smoothed round trip time, measured when the node answers a ping.
+    /// `None` if no ping has been answered yet
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// Record the round trip time of a `Pong`, answering the last keep alive
+    /// or liveness check ping. Unsolicited pongs are ignored.
+    ///
+    /// Returns `true` if the round trip has been measured
+    pub(crate) fn record_pong(&mut self, now: Instant) -> bool {
+        let probed_at = match self.eviction_status {
+            NodeEvictionStatus::Requested(at) => Some(at),
+            NodeEvictionStatus::None => None,
+        };
+        match probed_at.max(self.last_ping) {
+            Some(sent_at) => {
+                let sample = now.saturating_duration_since(sent_at);
+                self.rtt = Some(match self.rtt {
+                    Some(rtt) => {
+                        (rtt * (RTT_SMOOTHING - 1) + sample) / RTT_SMOOTHING
+                    }
+                    None => sample,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(super) fn adjust_score(&mut self, delta: Score) {
+        if self.trusted && delta < 0 {
+            return;
+        }
+        self.score = (self.score + delta).clamp(MIN_SCORE, MAX_SCORE);
+    }
+
+    pub(crate) fn value_mut(&mut self) -> &mut TValue {
+        &mut self.value
+    }
+
+    pub(super) fn into_value(self) -> TValue {
+        self.value
+    }
+
     pub(super) fn refresh(&mut self) {
         self.eviction_status = NodeEvictionStatus::None;
         self.seen_at = Instant::now();
+        self.pings = 0;
+        self.last_ping = None;
+        self.failures = 0;
     }
 
     pub(super) fn flag_for_check(&mut self) {
@@ -61,4 +153,84 @@ impl<TValue> Node<TValue> {
     pub(super) fn is_alive(&self, duration: Duration) -> bool {
         self.seen_at.elapsed() < duration
     }
+
+    pub(super) fn stats(&self, now: Instant) -> NodeStats {
+        NodeStats {
+            idle: now.saturating_duration_since(self.seen_at),
+            age: now.saturating_duration_since(self.added_at),
+            score: self.score,
+            rtt: self.rtt,
+            probed: match self.eviction_status {
+                NodeEvictionStatus::Requested(at) => {
+                    Some(now.saturating_duration_since(at))
+                }
+                NodeEvictionStatus::None => None,
+            },
+        }
+    }
+}
+
+/// State of a node of a bucket, as seen by the eviction and delegate
+/// policies
+#[derive(Debug, Clone, Copy)]
+pub struct NodeStats {
+    pub(super) idle: Duration,
+    pub(super) age: Duration,
+    pub(super) score: Score,
+    pub(super) rtt: Option<Duration>,
+    pub(super) probed: Option<Duration>,
+}
+
+impl NodeStats {
+    /// Time since the last message received from the node
+    pub fn idle(&self) -> Duration {
+        self.idle
+    }
+
+    /// Time since the node has been added to the bucket
+    pub fn age(&self) -> Duration {
+        self.age
+    }
+
+    pub fn score(&self) -> Score {
+        self.score
+    }
+
+    /// Smoothed round trip time of the node, if known
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// Time since the liveness check of the node has been requested, if any
+    pub fn probed(&self) -> Option<Duration> {
+        self.probed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::peer::PeerNode;
+
+    #[test]
+    fn test_rtt() {
+        let mut node = PeerNode::generate("192.168.0.1:666");
+        let now = Instant::now();
+        assert!(!node.record_pong(now));
+        assert_eq!(node.rtt(), None);
+
+        node.last_ping = Some(now);
+        assert!(node.record_pong(now + Duration::from_millis(80)));
+        assert_eq!(node.rtt(), Some(Duration::from_millis(80)));
+        node.record_pong(now + Duration::from_millis(160));
+        assert_eq!(node.rtt(), Some(Duration::from_millis(90)));
+
+        // The liveness check is the most recent ping
+        node.flag_for_check();
+        let probed_at = Instant::now();
+        node.record_pong(probed_at + Duration::from_millis(10));
+        assert!(node.rtt().unwrap() < Duration::from_millis(90));
+        assert_eq!(node.stats(probed_at).rtt(), node.rtt());
+    }
 }
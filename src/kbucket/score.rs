@@ -0,0 +1,137 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use super::{PeerTarget, Tree};
+use crate::peer::PeerInfo;
+
+/// Reputation of a peer of the routing table.
+///
+/// Peers with a negative score are evicted first when a bucket is full,
+/// peers with the highest score are preferred as broadcast delegates
+pub type Score = i32;
+
+/// Score of a newly inserted peer
+pub const DEFAULT_SCORE: Score = 0;
+pub const MIN_SCORE: Score = -100;
+pub const MAX_SCORE: Score = 100;
+
+/// Score adjustment for each valid message received from a peer
+pub(crate) const VALID_MESSAGE_REWARD: Score = 1;
+
+/// Score adjustment for a message which can't be parsed
+pub(crate) const MALFORMED_MESSAGE_PENALTY: Score = -10;
+
+/// Score adjustment for a message failing the authentication (network
+/// secret, originator signature or peer id)
+pub(crate) const INVALID_MESSAGE_PENALTY: Score = -20;
+
+/// Score adjustment for a source exceeding its rate limit, applied once per
+/// second while the limit is exceeded
+pub(crate) const RATE_LIMIT_PENALTY: Score = -5;
+
+// Reports exceeding this amount are dropped until the table applies them
+const MAX_PENDING_REPORTS: usize = 1024;
+
+/// Score adjustments reported by the transport, which doesn't own the
+/// routing table.
+///
+/// The reports are attributed to the sender host, since malformed messages
+/// can't be attributed to a peer id
+#[derive(Default)]
+pub(crate) struct ScoreReports {
+    pending: Mutex<Vec<(IpAddr, Score)>>,
+}
+
+impl ScoreReports {
+    pub(crate) fn report(&self, ip: IpAddr, delta: Score) {
+        let mut pending = self.pending.lock().expect("Poisoned score reports");
+        if pending.len() < MAX_PENDING_REPORTS {
+            pending.push((ip, delta));
+        }
+    }
+
+    fn drain(&self) -> Vec<(IpAddr, Score)> {
+        std::mem::take(
+            &mut *self.pending.lock().expect("Poisoned score reports"),
+        )
+    }
+}
+
+impl Tree<PeerInfo> {
+    /// Adjust the score of the peers included in the target
+    pub(crate) fn adjust_score(&mut self, target: &PeerTarget, delta: Score) {
+        self.adjust_scores(
+            |node| {
+                target.matches(
+                    &node.value().address().ip(),
+                    node.id().as_binary(),
+                )
+            },
+            delta,
+        )
+    }
+
+    /// Apply the score adjustments reported by the transport
+    pub(crate) fn apply_score_reports(&mut self) {
+        for (ip, delta) in self.filter().reports.drain() {
+            self.adjust_score(&PeerTarget::Ip(ip), delta);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MALFORMED_MESSAGE_PENALTY, MIN_SCORE};
+    use crate::{config::BucketConfig, kbucket::Tree, peer::PeerNode};
+
+    #[test]
+    fn test_score_eviction() {
+        let config = BucketConfig {
+            capacity: 3,
+            ..Default::default()
+        };
+        let root = PeerNode::generate("192.168.0.1:666");
+        let mut tree = Tree::new(PeerNode::generate("192.168.0.1:666"), config);
+        let mut nodes = (2..255)
+            .map(|i| PeerNode::generate(&format!("192.168.0.{}:666", i)));
+
+        // Fill a bucket
+        let first = nodes.next().unwrap();
+        let height = root.calculate_distance(&first).unwrap();
+        let first_ip = first.value().address().ip();
+        tree.insert(first).unwrap();
+        let mut same_bucket =
+            nodes.filter(|n| root.calculate_distance(n) == Some(height));
+        for _ in 0..2 {
+            tree.insert(same_bucket.next().unwrap()).unwrap();
+        }
+        assert!(tree.is_bucket_full(height));
+        assert!(tree.insert(same_bucket.next().unwrap()).is_err());
+
+        // A penalized peer is replaced by the new one
+        for _ in 0..3 {
+            tree.filter()
+                .reports
+                .report(first_ip, MALFORMED_MESSAGE_PENALTY);
+        }
+        tree.apply_score_reports();
+        let penalized = tree.snapshot();
+        let penalized = penalized
+            .peers()
+            .find(|p| p.address().ip() == first_ip)
+            .unwrap();
+        assert_eq!(penalized.score(), 3 * MALFORMED_MESSAGE_PENALTY);
+        assert!(penalized.score() > MIN_SCORE);
+        tree.insert(same_bucket.next().unwrap()).unwrap();
+        assert!(tree
+            .snapshot()
+            .peers()
+            .all(|p| p.address().ip() != first_ip));
+    }
+}
@@ -0,0 +1,424 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+
+use super::node::NodeEvictionStatus;
+use super::{BinaryKey, BucketHeight, Node, Score, Tree};
+use crate::peer::{PeerInfo, PeerNode};
+
+/// Read-only snapshot of the routing table of a [Peer](crate::Peer).
+///
+/// See [Peer::route_table](crate::Peer::route_table)
+#[derive(Debug, Clone)]
+pub struct RouteTable {
+    buckets: Vec<BucketSnapshot>,
+}
+
+/// Peers of a routing table bucket
+#[derive(Debug, Clone)]
+pub struct BucketSnapshot {
+    height: BucketHeight,
+    peers: Vec<PeerSnapshot>,
+    last_refresh: Option<Instant>,
+}
+
+/// Peer of a routing table bucket
+#[derive(Debug, Clone)]
+pub struct PeerSnapshot {
+    address: SocketAddr,
+    fallback: Option<SocketAddr>,
+    id: BinaryKey,
+    last_seen: Instant,
+    pending_eviction: bool,
+    score: Score,
+    rtt: Option<Duration>,
+    anchor: bool,
+    trusted: bool,
+    metadata: Vec<u8>,
+}
+
+/// Occupancy of a routing table bucket, see
+/// [Peer::bucket_occupancy](crate::Peer::bucket_occupancy)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BucketOccupancy {
+    height: BucketHeight,
+    peers: usize,
+    pending: usize,
+    capacity: usize,
+}
+
+/// Peer looked up with [Peer::knows](crate::Peer::knows), by its address or
+/// by its id
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PeerRef {
+    Address(SocketAddr),
+    Id(BinaryKey),
+}
+
+impl From<SocketAddr> for PeerRef {
+    fn from(address: SocketAddr) -> Self {
+        PeerRef::Address(address)
+    }
+}
+
+impl From<BinaryKey> for PeerRef {
+    fn from(id: BinaryKey) -> Self {
+        PeerRef::Id(id)
+    }
+}
+
+impl PeerRef {
+    fn id(&self, tree: &Tree<PeerInfo>) -> BinaryKey {
+        match self {
+            PeerRef::Address(address) => tree.id_of(address),
+            PeerRef::Id(id) => *id,
+        }
+    }
+}
+
+/// Membership of a peer in the routing table, see
+/// [Peer::knows](crate::Peer::knows)
+#[derive(Debug, Clone)]
+pub struct PeerStatus {
+    height: BucketHeight,
+    queued: bool,
+    peer: PeerSnapshot,
+}
+
+impl PeerStatus {
+    /// Height of the bucket of the peer
+    pub fn height(&self) -> BucketHeight {
+        self.height
+    }
+
+    /// The peer is waiting for a free slot of its full bucket. Queued peers
+    /// are never picked as delegates of the broadcast messages
+    pub fn is_queued(&self) -> bool {
+        self.queued
+    }
+
+    /// The peer, with its last seen time and pending eviction
+    pub fn peer(&self) -> &PeerSnapshot {
+        &self.peer
+    }
+}
+
+impl RouteTable {
+    /// The non empty buckets, sorted by height
+    pub fn buckets(&self) -> &[BucketSnapshot] {
+        &self.buckets
+    }
+
+    /// All the peers of the table
+    pub fn peers(&self) -> impl Iterator<Item = &PeerSnapshot> {
+        self.buckets.iter().flat_map(|bucket| bucket.peers.iter())
+    }
+}
+
+impl BucketSnapshot {
+    /// Height of the bucket, which is the XOR distance from the local peer
+    pub fn height(&self) -> BucketHeight {
+        self.height
+    }
+
+    /// The peers of the bucket, the least recently seen first
+    pub fn peers(&self) -> &[PeerSnapshot] {
+        &self.peers
+    }
+
+    /// Last time the bucket has been refreshed with a lookup, because of no
+    /// traffic. `None` if it has never been refreshed
+    pub fn last_refresh(&self) -> Option<Instant> {
+        self.last_refresh
+    }
+}
+
+impl BucketOccupancy {
+    /// Height of the bucket, which is the XOR distance from the local peer
+    pub fn height(&self) -> BucketHeight {
+        self.height
+    }
+
+    /// Amount of peers of the bucket, which are the delegate candidates at
+    /// this height
+    pub fn peers(&self) -> usize {
+        self.peers
+    }
+
+    /// Amount of peers waiting for a free slot of the bucket
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Max amount of peers of the bucket
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.peers >= self.capacity
+    }
+}
+
+impl PeerSnapshot {
+    pub fn address(&self) -> &SocketAddr {
+        &self.address
+    }
+
+    /// Fallback address of a dual-homed peer
+    pub fn fallback_address(&self) -> Option<&SocketAddr> {
+        self.fallback.as_ref()
+    }
+
+    pub fn id(&self) -> &BinaryKey {
+        &self.id
+    }
+
+    /// Last time a message has been received from the peer
+    pub fn last_seen(&self) -> Instant {
+        self.last_seen
+    }
+
+    /// The peer has been pinged in order to be evicted in favour of a new
+    /// one, and it didn't reply yet
+    pub fn pending_eviction(&self) -> bool {
+        self.pending_eviction
+    }
+
+    /// Reputation of the peer
+    pub fn score(&self) -> Score {
+        self.score
+    }
+
+    /// Smoothed round trip time, measured when the peer answers a ping
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// Check if the peer is one of the configured
+    /// [anchor_nodes](crate::config::Config::anchor_nodes)
+    pub fn is_anchor(&self) -> bool {
+        self.anchor
+    }
+
+    /// Check if the peer is trusted, see
+    /// [Config::trusted_peers](crate::config::Config::trusted_peers)
+    pub fn is_trusted(&self) -> bool {
+        self.trusted
+    }
+
+    /// Metadata attached with [Peer::annotate](crate::Peer::annotate)
+    pub fn metadata(&self) -> &[u8] {
+        &self.metadata
+    }
+}
+
+impl PeerSnapshot {
+    pub(crate) fn from_node(node: &Node<PeerInfo>) -> Self {
+        PeerSnapshot {
+            address: *node.value().address(),
+            fallback: node.value().fallback_address().copied(),
+            id: *node.id().as_binary(),
+            last_seen: node.seen_at,
+            pending_eviction: matches!(
+                node.eviction_status,
+                NodeEvictionStatus::Requested(_)
+            ),
+            score: node.score(),
+            rtt: node.rtt(),
+            anchor: node.is_anchor(),
+            trusted: node.is_trusted(),
+            metadata: node.value().metadata().to_vec(),
+        }
+    }
+}
+
+impl Tree<PeerInfo> {
+    /// The id of the peer listening on `address`. It's derived from the
+    /// address, unless a peer with a custom id is known at that address
+    pub(crate) fn id_of(&self, address: &SocketAddr) -> BinaryKey {
+        self.buckets
+            .values()
+            .flat_map(|bucket| bucket.peers().chain(bucket.pending()))
+            .find(|node| {
+                node.value().has_custom_id()
+                    && node.value().address() == address
+            })
+            .map(|node| *node.id().as_binary())
+            .unwrap_or_else(|| {
+                PeerNode::compute_id(&address.ip(), address.port())
+            })
+    }
+
+    pub(crate) fn snapshot(&self) -> RouteTable {
+        let buckets = self
+            .all_sorted()
+            .map(|(height, nodes)| BucketSnapshot {
+                height,
+                peers: nodes.map(PeerSnapshot::from_node).collect(),
+                last_refresh: self.last_refresh(height),
+            })
+            .filter(|bucket| !bucket.peers.is_empty())
+            .collect();
+        RouteTable { buckets }
+    }
+
+    /// The peers of the bucket at the given height, the least recently seen
+    /// first
+    pub(crate) fn peers_at_height(
+        &self,
+        height: BucketHeight,
+    ) -> Vec<PeerSnapshot> {
+        self.buckets.get(&height).map_or(vec![], |bucket| {
+            bucket.peers().map(PeerSnapshot::from_node).collect()
+        })
+    }
+
+    /// The occupancy of the non empty buckets, sorted by height
+    pub(crate) fn occupancy(&self) -> Vec<BucketOccupancy> {
+        self.buckets
+            .iter()
+            .map(|(height, bucket)| BucketOccupancy {
+                height: *height,
+                peers: bucket.len(),
+                pending: bucket.pending().count(),
+                capacity: bucket.capacity(),
+            })
+            .filter(|occupancy| occupancy.peers + occupancy.pending > 0)
+            .sorted_by_key(|occupancy| occupancy.height)
+            .collect()
+    }
+
+    /// The status of a peer, `None` if it's neither in the table nor
+    /// queued for a free slot
+    pub(crate) fn status(&self, peer: &PeerRef) -> Option<PeerStatus> {
+        let id = peer.id(self);
+        let height = self.root.id().calculate_distance(&id)?;
+        let bucket = self.buckets.get(&height)?;
+        let is_peer = |node: &&Node<PeerInfo>| node.id().as_binary() == &id;
+        let (queued, node) = match bucket.peers().find(is_peer) {
+            Some(node) => (false, node),
+            None => (true, bucket.pending().find(is_peer)?),
+        };
+        Some(PeerStatus {
+            height,
+            queued,
+            peer: PeerSnapshot::from_node(node),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PeerRef;
+    use crate::{config::BucketConfig, kbucket::Tree, peer::PeerNode};
+
+    #[test]
+    fn test_snapshot() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let mut tree = Tree::new(root, BucketConfig::default());
+        assert_eq!(tree.snapshot().buckets().len(), 0);
+        let nodes: Vec<_> = (2..20)
+            .map(|i| PeerNode::generate(&format!("192.168.0.{}:666", i)))
+            .collect();
+        for node in &nodes {
+            tree.insert(PeerNode::generate(
+                &node.value().address().to_string(),
+            ))
+            .unwrap();
+        }
+        let snapshot = tree.snapshot();
+        assert_eq!(snapshot.peers().count(), nodes.len());
+        let heights: Vec<_> =
+            snapshot.buckets().iter().map(|b| b.height()).collect();
+        let mut sorted = heights.clone();
+        sorted.sort_unstable();
+        assert_eq!(heights, sorted);
+        for bucket in snapshot.buckets() {
+            for peer in bucket.peers() {
+                let node = nodes
+                    .iter()
+                    .find(|n| n.value().address() == peer.address())
+                    .unwrap();
+                assert_eq!(peer.id(), node.id().as_binary());
+                assert_eq!(tree.has_peer(peer.id()), Some(bucket.height()));
+                assert!(!peer.pending_eviction());
+                assert!(peer.metadata().is_empty());
+            }
+        }
+
+        let id = *nodes[0].id().as_binary();
+        tree.node_mut(&id)
+            .unwrap()
+            .value_mut()
+            .set_metadata(b"v1.0".to_vec());
+        let snapshot = tree.snapshot();
+        let annotated = snapshot.peers().find(|p| p.id() == &id).unwrap();
+        assert_eq!(annotated.metadata(), b"v1.0");
+        // Refreshing a peer keeps its metadata
+        tree.insert(PeerNode::generate(
+            &nodes[0].value().address().to_string(),
+        ))
+        .unwrap();
+        let snapshot = tree.snapshot();
+        let annotated = snapshot.peers().find(|p| p.id() == &id).unwrap();
+        assert_eq!(annotated.metadata(), b"v1.0");
+    }
+
+    #[test]
+    fn test_peer_status() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let mut tree = Tree::new(root, BucketConfig::default());
+        let node = PeerNode::generate("192.168.0.2:666");
+        let id = *node.id().as_binary();
+        let address = *node.value().address();
+        assert!(tree.status(&PeerRef::Id(id)).is_none());
+
+        tree.insert(node).unwrap();
+        let status = tree.status(&PeerRef::Id(id)).unwrap();
+        assert_eq!(Some(status.height()), tree.has_peer(&id));
+        assert!(!status.is_queued());
+        assert!(!status.peer().pending_eviction());
+        assert_eq!(status.peer().address(), &address);
+        let by_address = tree.status(&address.into()).unwrap();
+        assert_eq!(by_address.peer().id(), &id);
+
+        let other = "192.168.0.3:666".parse().unwrap();
+        assert!(tree.status(&PeerRef::Address(other)).is_none());
+    }
+
+    #[test]
+    fn test_occupancy() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let config = BucketConfig {
+            capacity: 1,
+            ..Default::default()
+        };
+        let mut tree = Tree::new(root, config);
+        assert!(tree.occupancy().is_empty());
+        for i in 2..40 {
+            let _ = tree
+                .insert(PeerNode::generate(&format!("192.168.0.{}:666", i)));
+        }
+        let occupancy = tree.occupancy();
+        let snapshot = tree.snapshot();
+        assert_eq!(occupancy.len(), snapshot.buckets().len());
+        for (bucket, snapshot) in occupancy.iter().zip(snapshot.buckets()) {
+            assert_eq!(bucket.height(), snapshot.height());
+            assert_eq!(bucket.peers(), snapshot.peers().len());
+            assert_eq!(bucket.capacity(), 1);
+            assert!(bucket.is_full());
+            let peers = tree.peers_at_height(bucket.height());
+            assert_eq!(peers.len(), 1);
+            assert_eq!(peers[0].id(), snapshot.peers()[0].id());
+        }
+        assert!(tree.peers_at_height(0).is_empty());
+    }
+}
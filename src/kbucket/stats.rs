@@ -0,0 +1,61 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use metrics::{counter, gauge};
+
+use super::BucketHeight;
+
+/// Nodes added to a bucket with a free slot
+const INSERTS: &str = "kadcast_table_inserts_total";
+
+/// Nodes of the table refreshed by a message
+const UPDATES: &str = "kadcast_table_updates_total";
+
+/// Nodes evicted from the table
+const EVICTIONS: &str = "kadcast_table_evictions_total";
+
+/// Nodes discarded because their bucket is full of alive nodes
+const REJECTED_FULL: &str = "kadcast_table_rejected_full_total";
+
+/// Nodes discarded because of the IP diversity limits
+const REJECTED_SUBNET: &str = "kadcast_table_rejected_subnet_total";
+
+/// Pending nodes moved into their bucket after an eviction
+const PENDING_PROMOTIONS: &str = "kadcast_table_pending_promotions_total";
+
+/// Nodes of a bucket, labeled with the bucket `height`
+const BUCKET_NODES: &str = "kadcast_bucket_nodes";
+
+/// Routing table changes tracked by the counters
+#[derive(Debug, Clone, Copy)]
+pub(super) enum TableMetric {
+    Insert,
+    Update,
+    Eviction,
+    RejectedFull,
+    RejectedSubnet,
+    PendingPromotion,
+}
+
+pub(super) fn record(metric: TableMetric) {
+    record_many(metric, 1)
+}
+
+pub(super) fn record_many(metric: TableMetric, count: usize) {
+    let name = match metric {
+        TableMetric::Insert => INSERTS,
+        TableMetric::Update => UPDATES,
+        TableMetric::Eviction => EVICTIONS,
+        TableMetric::RejectedFull => REJECTED_FULL,
+        TableMetric::RejectedSubnet => REJECTED_SUBNET,
+        TableMetric::PendingPromotion => PENDING_PROMOTIONS,
+    };
+    counter!(name, count as u64);
+}
+
+pub(super) fn bucket_nodes(height: BucketHeight, nodes: usize) {
+    gauge!(BUCKET_NODES, nodes as f64, "height" => height.to_string());
+}
@@ -0,0 +1,221 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::cmp::Reverse;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::warn;
+
+use super::ban::StoredBan;
+use super::PeerTarget;
+use super::{BinaryKey, Tree};
+use crate::peer::PeerInfo;
+use crate::K_ID_LEN_BYTES;
+
+/// Peer of the routing table, as persisted by a [PeerStore]
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct StoredPeer {
+    pub(crate) address: SocketAddr,
+    pub(crate) id: BinaryKey,
+    /// Last time the peer has been seen (seconds since UNIX epoch)
+    pub(crate) last_seen: u64,
+}
+
+/// Content of a [PeerStore]
+#[derive(Debug, PartialEq, Default)]
+pub(crate) struct StoredTable {
+    pub(crate) peers: Vec<StoredPeer>,
+    pub(crate) bans: Vec<StoredBan>,
+}
+
+/// Storage of the known peers, used to rejoin the network after a restart
+pub(crate) trait PeerStore: Send + Sync {
+    fn load(&self) -> io::Result<StoredTable>;
+    fn save(&self, table: &StoredTable) -> io::Result<()>;
+}
+
+/// [PeerStore] backed by a text file, with one peer per line
+/// (`address id last_seen`) and one ban per line (`ban ip address expires_at`
+/// or `ban id id expires_at`)
+pub(crate) struct FilePeerStore {
+    path: PathBuf,
+}
+
+impl FilePeerStore {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub(super) fn parse_id(hex: &str) -> Option<BinaryKey> {
+        if hex.len() != K_ID_LEN_BYTES * 2 {
+            return None;
+        }
+        let mut id = [0; K_ID_LEN_BYTES];
+        for (i, byte) in id.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+        }
+        Some(id)
+    }
+
+    pub(super) fn format_id(id: &BinaryKey) -> String {
+        id.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn parse_peer(line: &str) -> Option<StoredPeer> {
+        let mut fields = line.split_whitespace();
+        let address = fields.next()?.parse().ok()?;
+        let id = Self::parse_id(fields.next()?)?;
+        let last_seen = fields.next()?.parse().ok()?;
+        Some(StoredPeer {
+            address,
+            id,
+            last_seen,
+        })
+    }
+
+    fn parse_ban(line: &str) -> Option<StoredBan> {
+        let mut fields = line.split_whitespace().skip(1);
+        let target = match fields.next()? {
+            "ip" => PeerTarget::Ip(fields.next()?.parse().ok()?),
+            "id" => PeerTarget::Id(Self::parse_id(fields.next()?)?),
+            _ => return None,
+        };
+        let expires_at = fields.next()?.parse().ok()?;
+        Some(StoredBan { target, expires_at })
+    }
+}
+
+impl PeerStore for FilePeerStore {
+    fn load(&self) -> io::Result<StoredTable> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(StoredTable::default())
+            }
+            Err(e) => return Err(e),
+        };
+        let mut table = StoredTable::default();
+        for line in content.lines() {
+            let parsed = if line.starts_with("ban ") {
+                Self::parse_ban(line).map(|ban| table.bans.push(ban))
+            } else {
+                Self::parse_peer(line).map(|peer| table.peers.push(peer))
+            };
+            if parsed.is_none() {
+                warn!("Skipping invalid stored line: {}", line);
+            }
+        }
+        Ok(table)
+    }
+
+    fn save(&self, table: &StoredTable) -> io::Result<()> {
+        let mut content = String::new();
+        for peer in &table.peers {
+            let id = Self::format_id(&peer.id);
+            let _ =
+                writeln!(content, "{} {} {}", peer.address, id, peer.last_seen);
+        }
+        for ban in &table.bans {
+            let _ = match ban.target {
+                PeerTarget::Ip(ip) => {
+                    writeln!(content, "ban ip {} {}", ip, ban.expires_at)
+                }
+                PeerTarget::Id(id) => writeln!(
+                    content,
+                    "ban id {} {}",
+                    Self::format_id(&id),
+                    ban.expires_at
+                ),
+            };
+        }
+        // Write a temporary file first, so that a crash never leaves a
+        // truncated store behind
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(tmp, &self.path)
+    }
+}
+
+impl Tree<PeerInfo> {
+    /// The alive peers of the table, most recently seen first
+    pub(crate) fn stored_peers(&self) -> Vec<StoredPeer> {
+        let now = SystemTime::now();
+        let mut peers: Vec<_> = self
+            .alive_nodes()
+            .map(|node| {
+                let seen_at =
+                    now.checked_sub(node.seen_at.elapsed()).unwrap_or(now);
+                StoredPeer {
+                    address: *node.value().address(),
+                    id: *node.id().as_binary(),
+                    last_seen: seen_at
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or(Duration::ZERO)
+                        .as_secs(),
+                }
+            })
+            .collect();
+        peers.sort_by_key(|peer| Reverse(peer.last_seen));
+        peers
+    }
+
+    /// The content to persist: the alive peers and the active bans
+    pub(crate) fn stored(&self) -> StoredTable {
+        StoredTable {
+            peers: self.stored_peers(),
+            bans: self.bans().stored(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{FilePeerStore, PeerStore, StoredTable};
+    use crate::kbucket::{PeerTarget, Tree};
+    use crate::{config::BucketConfig, peer::PeerNode};
+
+    #[test]
+    fn test_store_roundtrip() {
+        let mut tree = Tree::new(
+            PeerNode::generate("192.168.0.1:666"),
+            BucketConfig::default(),
+        );
+        for i in 2..6 {
+            tree.insert(PeerNode::generate(&format!("192.168.0.{}:666", i)))
+                .unwrap();
+        }
+        tree.insert(PeerNode::generate("[::1]:666")).unwrap();
+        tree.bans().ban(
+            PeerTarget::Ip("10.0.0.1".parse().unwrap()),
+            Duration::from_secs(3600),
+        );
+        tree.bans().ban(
+            PeerTarget::Id([7; crate::K_ID_LEN_BYTES]),
+            Duration::from_secs(3600),
+        );
+        let stored = tree.stored();
+        assert_eq!(stored.peers.len(), 5);
+        assert_eq!(stored.bans.len(), 2);
+
+        let path = std::env::temp_dir()
+            .join(format!("kadcast-peers-{}", std::process::id()));
+        let store = FilePeerStore::new(path.clone());
+        assert_eq!(store.load().unwrap(), StoredTable::default());
+        store.save(&stored).unwrap();
+        let mut content = std::fs::read_to_string(&path).unwrap();
+        content.push_str("invalid line\n");
+        std::fs::write(&path, content).unwrap();
+        assert_eq!(store.load().unwrap(), stored);
+        std::fs::remove_file(path).unwrap();
+    }
+}
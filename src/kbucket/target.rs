@@ -0,0 +1,67 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+    AllowList, BanList, BinaryKey, DeliveryFailures, FallbackAddresses,
+    ScoreReports, TrustedPeers,
+};
+use crate::peer::PeerInfo;
+
+/// Peer (or set of peers) banned with [Peer::ban](crate::Peer::ban) or
+/// listed in the allowlist
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum PeerTarget {
+    /// Every peer running on the host
+    Ip(IpAddr),
+    /// The peer with the given id
+    Id(BinaryKey),
+}
+
+impl PeerTarget {
+    /// Check if the target includes the peer
+    pub(crate) fn matches(&self, ip: &IpAddr, id: &BinaryKey) -> bool {
+        match self {
+            PeerTarget::Ip(target) => target == ip,
+            PeerTarget::Id(target) => target == id,
+        }
+    }
+}
+
+/// Host of the value of a node, matched by the [PeerTarget::Ip] targets
+pub(crate) trait Host {
+    fn host(&self) -> IpAddr;
+}
+
+impl Host for PeerInfo {
+    fn host(&self) -> IpAddr {
+        self.address().ip()
+    }
+}
+
+/// Access control, score reports, fallback addresses, delivery failures and
+/// trusted peers, shared by the routing table and the transport
+#[derive(Clone, Default)]
+pub(crate) struct PeerFilter {
+    pub(crate) bans: Arc<BanList>,
+    pub(crate) allowlist: Arc<AllowList>,
+    pub(crate) reports: Arc<ScoreReports>,
+    pub(crate) fallbacks: Arc<FallbackAddresses>,
+    pub(crate) failures: Arc<DeliveryFailures>,
+    pub(crate) trusted: Arc<TrustedPeers>,
+}
+
+impl PeerFilter {
+    /// Check if a peer is allowed and not banned
+    pub(crate) fn accepts(&self, ip: &IpAddr, id: &BinaryKey) -> bool {
+        !self.bans.is_banned_peer(ip, id)
+            && self.allowlist.is_allowed_peer(ip, id)
+    }
+}
@@ -0,0 +1,90 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+use super::{BinaryKey, PeerTarget, Tree};
+use crate::peer::PeerInfo;
+
+/// Peers trusted by the operator (eg: the relays of a backbone), which are
+/// exempt from the rate limits and the reputation penalties, and are always
+/// picked as delegates.
+///
+/// Like the [AllowList](super::AllowList), it's shared by the routing table
+/// and the transport
+#[derive(Default)]
+pub(crate) struct TrustedPeers {
+    targets: RwLock<HashSet<PeerTarget>>,
+}
+
+impl TrustedPeers {
+    pub(crate) fn set(&self, targets: Vec<PeerTarget>) {
+        *self.targets.write().expect("Poisoned trusted peers") =
+            targets.into_iter().collect();
+    }
+
+    /// Check if either the host or the id of a peer is trusted
+    pub(crate) fn is_trusted_peer(&self, ip: &IpAddr, id: &BinaryKey) -> bool {
+        let targets = self.targets.read().expect("Poisoned trusted peers");
+        !targets.is_empty()
+            && (targets.contains(&PeerTarget::Ip(*ip))
+                || targets.contains(&PeerTarget::Id(*id)))
+    }
+
+    /// Check if a host is trusted, for the datagrams which are not decoded
+    /// yet
+    pub(crate) fn is_trusted_host(&self, ip: &IpAddr) -> bool {
+        self.targets
+            .read()
+            .expect("Poisoned trusted peers")
+            .contains(&PeerTarget::Ip(*ip))
+    }
+}
+
+impl Tree<PeerInfo> {
+    /// Replace the trusted peers, marking the nodes of the table accordingly
+    pub(crate) fn set_trusted(&mut self, targets: Vec<PeerTarget>) {
+        let trusted = self.filter().trusted.clone();
+        trusted.set(targets);
+        for bucket in self.buckets.values_mut() {
+            bucket.mark_trusted(|node| {
+                trusted.is_trusted_peer(
+                    &node.value().address().ip(),
+                    node.id().as_binary(),
+                )
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrustedPeers;
+    use crate::kbucket::PeerTarget;
+
+    #[test]
+    fn test_trusted_peers() {
+        let ip = "10.0.0.1".parse().unwrap();
+        let other_ip = "10.0.0.2".parse().unwrap();
+        let id = [1; crate::K_ID_LEN_BYTES];
+        let other_id = [2; crate::K_ID_LEN_BYTES];
+
+        let trusted = TrustedPeers::default();
+        assert!(!trusted.is_trusted_peer(&ip, &id));
+
+        trusted.set(vec![PeerTarget::Ip(ip), PeerTarget::Id(id)]);
+        assert!(trusted.is_trusted_peer(&ip, &other_id));
+        assert!(trusted.is_trusted_peer(&other_ip, &id));
+        assert!(!trusted.is_trusted_peer(&other_ip, &other_id));
+        assert!(trusted.is_trusted_host(&ip));
+        assert!(!trusted.is_trusted_host(&other_ip));
+
+        trusted.set(vec![]);
+        assert!(!trusted.is_trusted_peer(&ip, &id));
+    }
+}
@@ -0,0 +1,157 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use super::delegate::select_delegates;
+use super::{BucketHeight, Node};
+use crate::config::BucketConfig;
+
+/// Copy of the nodes of a bucket, as of its last change
+pub(crate) struct BucketView<V> {
+    nodes: Vec<Node<V>>,
+    config: BucketConfig,
+}
+
+impl<V> BucketView<V> {
+    pub(super) fn new(nodes: Vec<Node<V>>, config: BucketConfig) -> Self {
+        Self { nodes, config }
+    }
+
+    // The delegates picked by the delegate policy, `beta` overriding the
+    // one of the bucket config
+    fn delegates(&self, beta: Option<usize>) -> Vec<&Node<V>> {
+        match beta {
+            Some(beta) => {
+                let config = BucketConfig {
+                    beta,
+                    ..self.config.clone()
+                };
+                select_delegates(&self.nodes, &config)
+            }
+            None => select_delegates(&self.nodes, &self.config),
+        }
+    }
+}
+
+/// Immutable copy of the whole routing table
+pub(crate) struct ViewEpoch<V> {
+    buckets: BTreeMap<BucketHeight, Arc<BucketView<V>>>,
+}
+
+impl<V> ViewEpoch<V> {
+    /// Pick the delegates of the buckets up to `max_h` (inclusive), according
+    /// to the delegate policy. `beta` overrides the one of the bucket config
+    pub(crate) fn extract(
+        &self,
+        max_h: Option<usize>,
+        beta: Option<usize>,
+    ) -> impl Iterator<Item = (BucketHeight, Vec<&Node<V>>)> {
+        self.buckets
+            .range(..=max_h.unwrap_or(usize::MAX))
+            .map(move |(&height, bucket)| (height, bucket.delegates(beta)))
+    }
+
+    /// Pick the delegates of the given buckets only, see
+    /// [ViewEpoch::extract]
+    pub(crate) fn extract_only<'a>(
+        &'a self,
+        heights: &'a [BucketHeight],
+        beta: Option<usize>,
+    ) -> impl Iterator<Item = (BucketHeight, Vec<&'a Node<V>>)> {
+        self.buckets
+            .iter()
+            .filter(move |(height, _)| heights.contains(height))
+            .map(move |(&height, bucket)| (height, bucket.delegates(beta)))
+    }
+}
+
+/// Read side of the routing table, which never waits for the table lock.
+///
+/// Each bucket publishes a copy of its nodes whenever they change, replacing
+/// the current epoch. Readers get the latest epoch and keep using it while
+/// new ones are published, this way picking the delegates of a broadcast
+/// never blocks behind the table maintenance
+pub(crate) struct TableView<V> {
+    epoch: Arc<RwLock<Arc<ViewEpoch<V>>>>,
+}
+
+impl<V> TableView<V> {
+    pub(super) fn new() -> Self {
+        Self {
+            epoch: Arc::new(RwLock::new(Arc::new(ViewEpoch {
+                buckets: BTreeMap::new(),
+            }))),
+        }
+    }
+
+    /// The latest epoch of the table
+    pub(crate) fn load(&self) -> Arc<ViewEpoch<V>> {
+        self.epoch.read().expect("View lock poisoned").clone()
+    }
+
+    pub(super) fn publish(&self, height: BucketHeight, bucket: BucketView<V>) {
+        let mut epoch = self.epoch.write().expect("View lock poisoned");
+        let mut buckets = epoch.buckets.clone();
+        buckets.insert(height, Arc::new(bucket));
+        *epoch = Arc::new(ViewEpoch { buckets });
+    }
+}
+
+impl<V> Clone for TableView<V> {
+    fn clone(&self) -> Self {
+        Self {
+            epoch: self.epoch.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::BucketConfig;
+    use crate::kbucket::Tree;
+    use crate::peer::PeerNode;
+
+    #[test]
+    fn test_table_view() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let mut tree = Tree::new(root, BucketConfig::default());
+        let view = tree.view().clone();
+        assert_eq!(view.load().extract(None, None).count(), 0);
+
+        for i in 2..20 {
+            tree.insert(PeerNode::generate(&format!("192.168.0.{}:666", i)))
+                .unwrap();
+        }
+        let epoch = view.load();
+        let heights: Vec<_> =
+            epoch.extract(None, None).map(|(h, _)| h).collect();
+        let expected: Vec<_> = tree.all_sorted().map(|(h, _)| h).collect();
+        assert_eq!(heights, expected);
+        assert!(epoch
+            .extract(None, None)
+            .all(|(_, nodes)| !nodes.is_empty()));
+        let max_h = heights[heights.len() / 2];
+        assert!(epoch.extract(Some(max_h), None).all(|(h, _)| h <= max_h));
+        let only = [heights[0], max_h, 1000];
+        let picked: Vec<_> =
+            epoch.extract_only(&only, None).map(|(h, _)| h).collect();
+        assert_eq!(picked, vec![heights[0], max_h]);
+
+        // A loaded epoch is not affected by the later changes
+        let removed = tree.remove_nodes(|_| true);
+        assert_eq!(removed, 18);
+        let nodes = |epoch: &super::ViewEpoch<_>| {
+            epoch
+                .extract(None, None)
+                .map(|(_, nodes)| nodes.len())
+                .sum::<usize>()
+        };
+        assert!(nodes(&epoch) > 0);
+        assert_eq!(nodes(&view.load()), 0);
+    }
+}
@@ -4,41 +4,125 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use std::{convert::TryInto, net::SocketAddr, time::Duration};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use config::Config;
-use encoding::message::Header;
-use encoding::{message::Message, payload::BroadcastPayload};
-use handling::MessageHandler;
-pub use handling::MessageInfo;
+pub use broadcaster::{BroadcastOptions, ScheduledBroadcast};
+use broadcaster::{Broadcaster, Scheduled};
+pub use channel::ChannelOverflows;
+use channel::{Receiver, Sender};
+pub use codec::{BroadcastDecoder, BroadcastEncoder};
+use config::{
+    Config, ConfigError, FECConfig, NoiseKey, PartialConfig, RateLimitConfig,
+};
+pub use encoding::message::{BroadcastPayload, Header, Message};
+pub use encoding::payload::{MessageId, Priority, Topic};
+pub use encoding::payload::{DEFAULT_PRIORITY, DEFAULT_TOPIC};
+use event::EventSender;
+pub use event::{BootstrapProgress, DropReason, Flood, KadcastEvent};
+pub use event::{SecurityEvent, SecurityKind};
+use gossip::RecentMessages;
+use handling::{MessageHandler, Relayer, Replies};
+pub use handling::{MessageInfo, MessageRef, Propagation};
+pub use health::Health;
+use health::HealthProbe;
 use itertools::Itertools;
-use kbucket::Tree;
-use mantainer::TableMantainer;
+pub use kbucket::MemoryUsage;
+pub use kbucket::{bucket_height, derive_key, xor_distance};
+pub use kbucket::{AdaptiveDelegates, DelegatePolicy, FixedDelegates};
+pub use kbucket::{BinaryID, BinaryKey, BucketHeight};
+use kbucket::{BootstrapCache, FilePeerStore, PeerStore, StoredTable};
+pub use kbucket::{BucketOccupancy, BucketSnapshot, PeerSnapshot};
+pub use kbucket::{EvictionAction, EvictionPolicy, LruEviction, NodeStats};
+use kbucket::{ExportedTable, Tree};
+pub use kbucket::{KeepAliveAction, KeepAlivePolicy, NoKeepAlive};
+pub use kbucket::{PeerRef, PeerStatus};
+pub use kbucket::{PeerTarget, RouteTable};
+pub use kbucket::{Score, DEFAULT_SCORE, MAX_SCORE, MIN_SCORE};
+pub use listener::ListenerId;
+use listener::{combine, Listeners};
+use lookup::NodesReplySender;
+use mantainer::{save_known_peers, KnownPeers, TableMantainer};
+pub use mantainer::{LightMaintenance, MaintenanceStrategy};
+pub use middleware::{Interception, MessageMiddleware};
+pub use peer::MAX_PEER_METADATA_LEN;
 use peer::{PeerInfo, PeerNode};
+use queue::PriorityQueue;
 use rand::prelude::IteratorRandom;
+pub use report::{BucketReport, NetworkReport, PeerReport, TransportReport};
+use rpc::PendingRequests;
+pub use rpc::{RequestError, RequestHandler, RequestId, MAX_REQUEST_LEN};
 pub(crate) use rwlock::RwLock;
-use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio::task;
-use tracing::{error, info};
-use transport::{MessageBeanOut, WireNetwork};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+pub use transport::delivery::{BroadcastHandle, Delivery};
+pub use transport::encoding::DecodeProgress;
+use transport::{MessageBeanIn, MessageBeanOut, WireChannels, WireNetwork};
+use validation::Gate;
+pub use validation::{MessageValidator, Validation};
 
+mod audit;
+#[doc(hidden)]
+pub mod bench;
+mod broadcaster;
+mod channel;
+mod codec;
 pub mod config;
 mod encoding;
+mod event;
+mod gossip;
 mod handling;
+mod health;
+mod identity;
 mod kbucket;
+mod listener;
+mod lookup;
 mod mantainer;
+mod middleware;
 mod peer;
+pub mod proto;
+mod queue;
+mod report;
+mod rpc;
 mod rwlock;
 pub mod transport;
+mod validation;
 
 // Max amount of nodes a bucket should contain
 const DEFAULT_K_K: usize = 20;
 const K_K: usize = get_k_k();
-const K_ID_LEN_BYTES: usize = 16;
+const DEFAULT_K_ID_LEN_BYTES: usize = 16;
+const K_ID_LEN_BYTES: usize = get_id_len();
 const K_NONCE_LEN: usize = 4;
 const K_DIFF_MIN_BIT: usize = 8;
 const K_DIFF_PRODUCED_BIT: usize = 8;
 
+/// Length in bytes of the peer identifiers.
+///
+/// It can be set at compile time with the `KADCAST_ID_LEN` environment
+/// variable (between 16 and 32), in order to interoperate with systems using
+/// identifiers of a different length. Every peer of a network must use the
+/// same length
+pub const ID_LEN: usize = K_ID_LEN_BYTES;
+
+// Ids are derived from a BLAKE2s digest, which is 32 bytes long
+const _: () = assert!(
+    K_ID_LEN_BYTES >= 16 && K_ID_LEN_BYTES <= 32,
+    "KADCAST_ID_LEN must be between 16 and 32"
+);
+
+const fn get_id_len() -> usize {
+    match option_env!("KADCAST_ID_LEN") {
+        Some(v) => match konst::primitive::parse_usize(v) {
+            Ok(e) => e,
+            Err(_) => DEFAULT_K_ID_LEN_BYTES,
+        },
+        None => DEFAULT_K_ID_LEN_BYTES,
+    }
+}
+
 const fn get_k_k() -> usize {
     match option_env!("KADCAST_K") {
         Some(v) => match konst::primitive::parse_usize(v) {
@@ -57,14 +141,72 @@ const K_BETA: usize = 3;
 /// Struct representing the Kadcast Network Peer
 pub struct Peer {
     outbound_sender: Sender<MessageBeanOut>,
+    // Kept to sample the depth of the incoming messages queue
+    inbound_sender: Sender<MessageBeanIn>,
+    probe: Arc<HealthProbe>,
     ktable: RwLock<Tree<PeerInfo>>,
     header: Header,
+    broadcaster: Broadcaster,
+    schedule: mpsc::UnboundedSender<Scheduled>,
+    events: EventSender,
+    max_batch_len: usize,
+    relayer: Relayer,
+    observer: bool,
+    listeners: Listeners,
+    listen_addr: SocketAddr,
+    public_addr: SocketAddr,
+    noise_key: Option<NoiseKey>,
+    fec: watch::Sender<FECConfig>,
+    rate_limit: watch::Sender<Option<RateLimitConfig>>,
+    peer_store: Option<Arc<dyn PeerStore>>,
+    bootstrap_cache: Option<Arc<BootstrapCache>>,
+    nodes_reply: NodesReplySender,
+    lookup_size: usize,
+    requests: PendingRequests,
+    request_timeout: Duration,
+    request_retries: usize,
+    // Background tasks, the one sending the outgoing messages apart
+    tasks: Vec<JoinHandle<()>>,
+    sender_task: Option<JoinHandle<()>>,
+    send_goodbye: bool,
+    shutdown_timeout: Duration,
 }
 
 /// [NetworkListen] is notified each time a broadcasted
 /// message is received from the network
 pub trait NetworkListen: Send {
     fn on_message(&self, message: Vec<u8>, metadata: MessageInfo);
+
+    /// Judge the propagation of a received message, right before it's
+    /// notified through [NetworkListen::on_message]. This allows to relay
+    /// only the messages validated by the application.
+    ///
+    /// It's called only for the messages on subscribed topics which would
+    /// be relayed, ie: received with a height greater than 0. `None` (the
+    /// default) relays the message according to [Config::auto_propagate]
+    fn on_relay(
+        &self,
+        _message: &[u8],
+        _metadata: &MessageInfo,
+    ) -> Option<Propagation> {
+        None
+    }
+
+    /// Topics the listener is subscribed to.
+    ///
+    /// Messages broadcasted on other topics are still propagated but never
+    /// notified to the listener. `None` (the default) subscribes to every
+    /// topic
+    fn topics(&self) -> Option<&[Topic]> {
+        None
+    }
+
+    /// Notified while a large broadcast message is being received, each time
+    /// a further 10% of the symbols needed to decode it is collected.
+    ///
+    /// Once the message is decoded, it is notified through
+    /// [NetworkListen::on_message]. The default implementation does nothing
+    fn on_progress(&self, _progress: DecodeProgress) {}
 }
 
 impl Peer {
@@ -73,52 +215,782 @@ impl Peer {
     /// * `config` - The [Config] used to create the Peer
     /// * `listener` - The [NetworkListen] impl notified each time a broadcasted
     ///   message is received from the network
+    ///
+    /// # Panics
+    ///
+    /// If the [Config] is not valid, see [Peer::try_new]
     pub fn new<L: NetworkListen + 'static>(
         config: Config,
         listener: L,
     ) -> Self {
-        let tree = Tree::new(
-            PeerNode::generate(&config.public_address[..]),
-            config.bucket,
-        );
+        Peer::try_new(config, listener)
+            .unwrap_or_else(|e| panic!("Unable to create the peer - {}", e))
+    }
 
+    /// Create a [Peer], failing if the [Config] is not valid (see
+    /// [Config::validate]) or its listening addresses can't be bound
+    pub fn try_new<L: NetworkListen + 'static>(
+        mut config: Config,
+        listener: L,
+    ) -> Result<Self, ConfigError> {
+        config.validate()?;
+        let sockets = WireNetwork::bind(&mut config)?;
+        // The random Noise key is generated here, so that it can be known
+        let noise_key = config.noise.as_mut().map(|noise| {
+            let secret = noise.static_key.get_or_insert_with(rand::random);
+            transport::noise::public_key(secret)
+        });
+        let listen_addr = sockets[0].local_addr().expect("Bound socket");
+        let anchors = config
+            .anchor_nodes
+            .iter()
+            .flat_map(|anchor| {
+                anchor.to_socket_addrs().unwrap_or_else(|e| {
+                    error!("Unable to resolve domain for {} - {}", anchor, e);
+                    vec![].into_iter()
+                })
+            })
+            .map(|addr| PeerNode::compute_id(&addr.ip(), addr.port()))
+            .collect();
+        let tree = Tree::new(PeerNode::root(&config), config.bucket.clone())
+            .with_anchors(anchors);
+
+        let probe = Arc::new(HealthProbe::default());
+        let size = config.channel_size;
+        let policies = config.channels;
+        let overflows = &probe.overflows;
         let (inbound_channel_tx, inbound_channel_rx) =
-            mpsc::channel(config.channel_size);
-        let (outbound_channel_tx, outbound_channel_rx) =
-            mpsc::channel(config.channel_size);
-        let (notification_channel_tx, listener_channel_rx) =
-            mpsc::channel(config.channel_size);
+            channel::channel(size, policies.inbound, overflows.inbound.clone());
+        let (outbound_channel_tx, outbound_channel_rx) = channel::channel(
+            size,
+            policies.outbound,
+            overflows.outbound.clone(),
+        );
+        let (notification_channel_tx, listener_channel_rx) = channel::channel(
+            size,
+            policies.notifications,
+            overflows.notifications.clone(),
+        );
+        let (progress_channel_tx, progress_channel_rx) = channel::channel(
+            size,
+            policies.progress,
+            overflows.progress.clone(),
+        );
+        let (events, _) = broadcast::channel(config.channel_size);
+        let (nodes_reply, _) = broadcast::channel(config.channel_size);
+        let requests = PendingRequests::default();
+        let recent = config.pull_gossip.map(RecentMessages::new);
+        let (fec, fec_rx) = watch::channel(config.fec.clone());
+        let (rate_limit, rate_limit_rx) = watch::channel(config.rate_limit);
 
         let header = tree.root().as_header();
+        let public_addr = *tree.root().value().address();
+        let peer_store = config.peer_store.clone().map(|path| {
+            Arc::new(FilePeerStore::new(path)) as Arc<dyn PeerStore>
+        });
+        // Bans are restored before starting the network, so that banned
+        // peers are never accepted
+        let stored = peer_store
+            .as_ref()
+            .map(|store| {
+                store.load().unwrap_or_else(|e| {
+                    error!("Unable to load stored peers - {}", e);
+                    StoredTable::default()
+                })
+            })
+            .unwrap_or_default();
+        let bootstrap_cache = config.bootstrap_cache.clone().map(|path| {
+            Arc::new(BootstrapCache::load(path, config.bootstrap_cache_size))
+        });
+        tree.bans().restore(stored.bans);
+        tree.allowlist().set(config.allowlist.clone());
+        tree.filter().trusted.set(config.trusted_peers.clone());
+        let filter = tree.filter().clone();
+        let view = tree.view().clone();
         let table = RwLock::new(tree, Duration::from_secs(1));
-        let peer = Peer {
+        let relayer = Relayer::new(
+            view.clone(),
+            header,
+            outbound_channel_tx.clone(),
+            !config.observer,
+            &config,
+        );
+        let broadcaster = Broadcaster::new(
+            view,
+            header,
+            outbound_channel_tx.clone(),
+            recent.clone(),
+            &config,
+        );
+        let (schedule, schedule_rx) = mpsc::unbounded_channel();
+        let mut peer = Peer {
             outbound_sender: outbound_channel_tx.clone(),
+            inbound_sender: inbound_channel_tx.clone(),
+            probe: probe.clone(),
             ktable: table.clone(),
             header,
+            broadcaster: broadcaster.clone(),
+            schedule,
+            events: events.clone(),
+            max_batch_len: config.max_batch_len,
+            relayer: relayer.clone(),
+            observer: config.observer,
+            listeners: Listeners::default(),
+            listen_addr,
+            public_addr,
+            noise_key,
+            fec,
+            rate_limit,
+            peer_store: peer_store.clone(),
+            bootstrap_cache: bootstrap_cache.clone(),
+            nodes_reply: nodes_reply.clone(),
+            lookup_size: config.bucket.capacity,
+            requests: requests.clone(),
+            request_timeout: config.request_timeout,
+            request_retries: config.request_retries,
+            tasks: vec![],
+            sender_task: None,
+            send_goodbye: config.send_goodbye,
+            shutdown_timeout: config.shutdown_timeout,
         };
-        let bootstrapping_nodes = config.bootstrapping_nodes.clone();
-        MessageHandler::start(
+        let mut tasks = vec![MessageHandler::start(
             table.clone(),
             inbound_channel_rx,
             outbound_channel_tx.clone(),
             notification_channel_tx,
+            events.clone(),
+            Replies {
+                nodes: nodes_reply.clone(),
+                requests,
+                recent: recent.clone(),
+            },
             &config,
+        )];
+        tasks.extend(TableMantainer::start(
+            table,
+            outbound_channel_tx.clone(),
+            events.clone(),
+            nodes_reply,
+            KnownPeers {
+                store: peer_store,
+                bootstrap_cache,
+                stored: stored.peers,
+            },
+            recent,
+            &config,
+        ));
+        tasks.push(config.spawn(broadcaster.run_schedule(schedule_rx)));
+        if let Some(path) = &config.audit_log {
+            if let Err(e) = audit::start(path, events.subscribe()) {
+                error!("Unable to open the audit log {:?}: {}", path, e);
+            }
+        }
+        peer.listeners.add(Box::new(listener));
+        let gate = Gate::new(
+            config.validator.clone(),
+            filter.reports.clone(),
+            events.clone(),
         );
-        WireNetwork::start(inbound_channel_tx, outbound_channel_rx, config);
-        TableMantainer::start(bootstrapping_nodes, table, outbound_channel_tx);
-        task::spawn(Peer::notifier(listener_channel_rx, listener));
-        peer
+        tasks.push(config.spawn(Peer::notifier(
+            listener_channel_rx,
+            relayer,
+            progress_channel_rx,
+            peer.listeners.clone(),
+            gate,
+        )));
+        let channels = WireChannels {
+            inbound_tx: inbound_channel_tx,
+            outbound_rx: outbound_channel_rx,
+            outbound_tx: outbound_channel_tx,
+            progress_tx: progress_channel_tx,
+            events,
+            rate_limit: rate_limit_rx,
+        };
+        let (sender_task, network_tasks) = WireNetwork::start(
+            channels, filter, sockets, fec_rx, probe, header, config,
+        );
+        tasks.extend(network_tasks);
+        peer.tasks = tasks;
+        peer.sender_task = Some(sender_task);
+        Ok(peer)
+    }
+
+    /// Stop the peer gracefully: the maintenance and the processing of the
+    /// incoming messages are stopped, a `Goodbye` is sent to the peers of the
+    /// routing table (see [Config::send_goodbye]) and the pending outgoing
+    /// messages are sent for up to [Config::shutdown_timeout]. Then the
+    /// sockets are released.
+    ///
+    /// The known peers are persisted as when the peer is dropped. Dropping a
+    /// [Peer] stops its tasks straight away, discarding the pending messages
+    pub async fn shutdown(mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        // Wait for the tasks to release the table and the channels
+        for task in self.tasks.drain(..) {
+            let _ = task.await;
+        }
+        if self.send_goodbye {
+            let peers: Vec<_> = self
+                .ktable
+                .read()
+                .await
+                .all_sorted()
+                .flat_map(|(_, nodes)| nodes)
+                .map(|node| *node.value().address())
+                .collect();
+            if !peers.is_empty() {
+                self.outbound_sender
+                    .send((Message::Goodbye(self.header), peers, None))
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("Unable to send from shutdown {}", e)
+                    });
+            }
+        }
+        let sender_task = self.sender_task.take();
+        let timeout = self.shutdown_timeout;
+        // Dropping the last sender of the outgoing messages lets the task
+        // return once the pending ones are sent
+        drop(self);
+        if let Some(mut sender_task) = sender_task {
+            if tokio::time::timeout(timeout, &mut sender_task)
+                .await
+                .is_err()
+            {
+                warn!("Shutdown timeout, discarding the pending messages");
+                sender_task.abort();
+            }
+        }
+        info!("Peer shut down");
     }
 
     async fn notifier(
-        mut listener_channel_rx: Receiver<(Vec<u8>, MessageInfo)>,
-        listener: impl NetworkListen,
+        mut listener_channel_rx: Receiver<(BroadcastPayload, MessageInfo)>,
+        relayer: Relayer,
+        mut progress_channel_rx: Receiver<DecodeProgress>,
+        listeners: Listeners,
+        gate: Gate,
     ) {
-        while let Some(notif) = listener_channel_rx.recv().await {
-            listener.on_message(notif.0, notif.1);
+        let notify_progress = |progress: DecodeProgress| {
+            for listener in listeners.subscribed(progress.topic()) {
+                listener.lock().on_progress(progress);
+            }
+        };
+        // Pending notifications are dispatched according to their priority
+        let mut pending = PriorityQueue::new();
+        loop {
+            if pending.is_empty() {
+                tokio::select! {
+                    notif = listener_channel_rx.recv() => match notif {
+                        Some(notif) => pending.push(notif.1.priority, notif),
+                        None => return,
+                    },
+                    Some(progress) = progress_channel_rx.recv() => {
+                        notify_progress(progress);
+                        continue;
+                    }
+                }
+            }
+            // Once enough notifications are pending the channel is left to
+            // fill up, applying its overflow policy to the decoder
+            while pending.len() < listener_channel_rx.capacity() {
+                match listener_channel_rx.try_recv() {
+                    Ok(notif) => pending.push(notif.1.priority, notif),
+                    Err(_) => break,
+                }
+            }
+            while let Ok(progress) = progress_channel_rx.try_recv() {
+                notify_progress(progress);
+            }
+            if let Some((_, (payload, metadata))) = pending.pop() {
+                // The messages coalesced in a batch are judged and notified
+                // one by one
+                let messages = match payload.messages() {
+                    Some(messages) => messages,
+                    None => {
+                        warn!("Discarding malformed batch");
+                        continue;
+                    }
+                };
+                // Invalid messages are neither relayed nor notified
+                if !gate.admits(&messages, &metadata) {
+                    continue;
+                }
+                let subscribed = listeners.subscribed(metadata.topic);
+                let verdict = match payload.height > 0 && relayer.is_enabled() {
+                    true => combine(subscribed.iter().flat_map(|listener| {
+                        let metadata = &metadata;
+                        messages.iter().map(move |message| {
+                            listener.lock().on_relay(message, metadata)
+                        })
+                    })),
+                    false => None,
+                };
+                relayer
+                    .relay(&payload, metadata.origin_height, verdict)
+                    .await;
+                let messages = match payload.batch {
+                    true => messages.into_iter().map(<[u8]>::to_vec).collect(),
+                    false => vec![payload.gossip_frame],
+                };
+                if let Some((last, others)) = subscribed.split_last() {
+                    for message in messages {
+                        for listener in others {
+                            listener
+                                .lock()
+                                .on_message(message.clone(), metadata.clone());
+                        }
+                        last.lock().on_message(message, metadata.clone());
+                    }
+                }
+            }
         }
     }
 
+    /// Register a further [NetworkListen], notified alongside the other
+    /// ones. The listeners are notified in registration order, the one
+    /// given to [Peer::new] first.
+    ///
+    /// A message is relayed unless a listener drops it, see
+    /// [NetworkListen::on_relay]
+    pub fn add_listener<L: NetworkListen + 'static>(
+        &self,
+        listener: L,
+    ) -> ListenerId {
+        self.listeners.add(Box::new(listener))
+    }
+
+    /// Stop notifying a listener, including the one given to [Peer::new].
+    /// Returns `false` if the listener has already been removed
+    pub fn remove_listener(&self, id: ListenerId) -> bool {
+        self.listeners.remove(id)
+    }
+
+    /// Ids of the registered listeners, in registration order
+    pub fn listeners(&self) -> Vec<ListenerId> {
+        self.listeners.ids()
+    }
+
+    /// Change some runtime parameters without restarting the peer.
+    ///
+    /// The new values are checked first, then applied at once: the timeouts
+    /// to the routing table, the FEC parameters and limits to the messages
+    /// encoded and decoded from now on, and the rate limits to the messages
+    /// received from now on. A
+    /// [KadcastEvent::Reconfigured] is emitted once they are applied
+    pub async fn reconfigure(
+        &self,
+        partial: PartialConfig,
+    ) -> Result<(), ConfigError> {
+        partial.validate()?;
+        let mut table = self.ktable.write().await;
+        if partial.updates_table() {
+            table.reconfigure(|bucket| partial.apply_bucket(bucket));
+        }
+        self.fec.send_modify(|fec| partial.apply_fec(fec));
+        if let Some(limit) = partial.rate_limit {
+            self.rate_limit.send_replace(Some(limit));
+        }
+        drop(table);
+        info!("Peer reconfigured");
+        event::emit(&self.events, KadcastEvent::Reconfigured);
+        Ok(())
+    }
+
+    /// Subscribe to the [KadcastEvent]s emitted by the peer.
+    ///
+    /// Only the events emitted after the subscription are received. A
+    /// subscriber which doesn't keep up misses the oldest events
+    pub fn events(&self) -> broadcast::Receiver<KadcastEvent> {
+        self.events.subscribe()
+    }
+
+    /// Return the address the peer is actually listening on, eg: when bound
+    /// to an ephemeral port (0).
+    ///
+    /// It's the public address, or the listen address if configured (see
+    /// [Config::listen_address])
+    pub fn listen_addr(&self) -> SocketAddr {
+        self.listen_addr
+    }
+
+    /// Return the identifier of this peer, computed from its public address.
+    /// The nonce proving it is part of the [Header::binary_id]
+    pub fn id(&self) -> &BinaryKey {
+        self.header.binary_id.as_binary()
+    }
+
+    /// Return the public address advertised by this peer (see
+    /// [Config::public_address])
+    pub fn public_addr(&self) -> SocketAddr {
+        self.public_addr
+    }
+
+    /// Return the public key authenticating this peer in the Noise
+    /// handshakes, to be allowed by the other peers (see
+    /// [NoiseConfig::allowed_keys](config::NoiseConfig::allowed_keys)).
+    /// `None` if [Config::noise] is not enabled
+    pub fn noise_key(&self) -> Option<NoiseKey> {
+        self.noise_key
+    }
+
+    /// Return the [Header] of the messages sent by this peer
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    /// Send a custom [Message] to a set of targets
+    ///
+    /// The header of the message is replaced with the peer one, and broadcast
+    /// payloads are signed with the configured signing key (if any).
+    ///
+    /// Note:
+    /// The function returns just after the message is put on the internal queue
+    /// system. It **does not guarantee** the message will be sent
+    pub async fn send_message(
+        &self,
+        mut message: Message,
+        targets: Vec<SocketAddr>,
+    ) {
+        *message.header_mut() = self.header;
+        if let Message::Broadcast(_, payload) = &mut message {
+            payload.origin = self.broadcaster.sign(payload);
+        }
+        self.outbound_sender
+            .send((message, targets, None))
+            .await
+            .unwrap_or_else(|e| {
+                error!("Unable to send from send_message {}", e)
+            });
+    }
+
+    /// Send a [Message] to a set of targets as it is, without replacing its
+    /// header nor signing it, eg: to prototype new control messages with
+    /// [Message::Raw].
+    ///
+    /// The receivers discard the messages whose header is not valid for the
+    /// sender IP, see [KadcastEvent::MessageDropped]
+    #[cfg(feature = "unsafe_proto")]
+    pub async fn send_raw(&self, message: Message, targets: Vec<SocketAddr>) {
+        self.outbound_sender
+            .send((message, targets, None))
+            .await
+            .unwrap_or_else(|e| error!("Unable to send from send_raw {}", e));
+    }
+
+    /// Wait until the routing table holds at least `min_peers` alive peers,
+    /// eg: before the first broadcast.
+    ///
+    /// Returns `false` if the `timeout` expires first
+    pub async fn wait_until_ready(
+        &self,
+        min_peers: usize,
+        timeout: Duration,
+    ) -> bool {
+        // Subscribing first, the peers added while checking the table are
+        // not missed
+        let mut events = self.events.subscribe();
+        let ready = async {
+            loop {
+                if self.ktable.read().await.alive_nodes().count() >= min_peers {
+                    return;
+                }
+                loop {
+                    // The table is checked again if some events are missed.
+                    // The channel can't be closed while the peer is alive
+                    match events.recv().await {
+                        Ok(KadcastEvent::PeerAdded(_))
+                        | Ok(KadcastEvent::PeerRefreshed(_))
+                        | Err(_) => break,
+                        Ok(_) => continue,
+                    }
+                }
+            }
+        };
+        tokio::time::timeout(timeout, ready).await.is_ok()
+    }
+
+    /// Return a snapshot of the routing table
+    pub async fn route_table(&self) -> RouteTable {
+        self.ktable.read().await.snapshot()
+    }
+
+    /// Check if a peer, given by address or id, is known by the routing
+    /// table: its bucket, when it has been seen last and whether it's pending
+    /// eviction or queued for a free slot.
+    ///
+    /// Returns `None` if the peer is unknown
+    pub async fn knows(&self, peer: impl Into<PeerRef>) -> Option<PeerStatus> {
+        self.ktable.read().await.status(&peer.into())
+    }
+
+    /// Report the liveness of the peer: whether its sockets are bound, the
+    /// depth of the message queues, the amount of alive peers, the last
+    /// answered ping and the size of the decoder cache.
+    ///
+    /// Meant to back a readiness probe, see [Health::is_ready]
+    pub async fn health(&self) -> Health {
+        let running = |task: &JoinHandle<()>| !task.is_finished();
+        let table = self.ktable.read().await;
+        Health {
+            tasks_running: self.tasks.iter().all(running)
+                && self.sender_task.as_ref().is_some_and(running),
+            inbound_queue: self.inbound_sender.len(),
+            outbound_queue: self.outbound_sender.len(),
+            alive_peers: table.alive_nodes().count(),
+            last_round_trip: table.last_round_trip,
+            decoder_cache: self.probe.decoder_cache(),
+            channel_overflows: self.probe.overflows.snapshot(),
+        }
+    }
+
+    /// Estimate of the memory used by the routing table
+    pub async fn memory_usage(&self) -> MemoryUsage {
+        self.ktable.read().await.memory_usage()
+    }
+
+    /// Export the routing table as JSON, with the buckets, the peers and
+    /// their last seen time, score and latency. Timestamps are seconds since
+    /// UNIX epoch.
+    ///
+    /// Meant for support bundles and for replaying real topologies with
+    /// [Peer::import_table]
+    pub async fn export_table(&self) -> serde_json::Value {
+        let exported = self.ktable.read().await.export();
+        serde_json::to_value(exported).expect("Unable to serialize the table")
+    }
+
+    /// Insert the peers of a table exported with [Peer::export_table],
+    /// skipping the ones whose id is not valid. Meant for controlled testing.
+    ///
+    /// Returns the amount of peers inserted
+    pub async fn import_table(
+        &self,
+        table: serde_json::Value,
+    ) -> serde_json::Result<usize> {
+        let table: ExportedTable = serde_json::from_value(table)?;
+        let mut ktable = self.ktable.write().await;
+        let inserted = ktable.import(&table);
+        ktable.shed_memory();
+        for event in ktable.drain_events() {
+            event::emit(&self.events, event.into());
+        }
+        Ok(inserted)
+    }
+
+    /// Ban a peer (or every peer of a host) for the given duration.
+    ///
+    /// The banned peers are removed from the routing table, their messages
+    /// are discarded and they are never inserted again until the ban expires.
+    /// Bans are persisted along with the known peers
+    pub async fn ban(&self, target: PeerTarget, duration: Duration) {
+        self.remove_peer(target, Some(duration)).await;
+    }
+
+    /// Remove a peer (or every peer of a host) from the routing table
+    /// straight away, replacing it with the pending peers of its bucket.
+    ///
+    /// If `ban` is set the peer is also banned for the given duration,
+    /// otherwise it can be inserted again as soon as it sends a message.
+    /// Returns `false` if no peer has been removed
+    pub async fn remove_peer(
+        &self,
+        target: PeerTarget,
+        ban: Option<Duration>,
+    ) -> bool {
+        let mut table = self.ktable.write().await;
+        if let Some(duration) = ban {
+            table.bans().ban(target, duration);
+        }
+        let removed = table.remove_nodes(|node| {
+            target.matches(&node.value().address().ip(), node.id().as_binary())
+        });
+        for event in table.drain_events() {
+            event::emit(&self.events, event.into());
+        }
+        removed > 0
+    }
+
+    /// Pause or resume the relay of the received broadcast messages, eg:
+    /// under resource pressure or while the application can't validate them
+    /// fast enough. While paused the messages are still received and
+    /// notified to the listeners, but never relayed and
+    /// [NetworkListen::on_relay] isn't called.
+    ///
+    /// The relay of a peer in observer mode (see [Config::observer]) can't
+    /// be enabled
+    pub fn set_relay_enabled(&self, enabled: bool) {
+        self.relayer.set_enabled(enabled && !self.observer);
+    }
+
+    /// Whether the received broadcast messages are relayed, see
+    /// [Peer::set_relay_enabled]
+    pub fn is_relay_enabled(&self) -> bool {
+        self.relayer.is_enabled()
+    }
+
+    /// Relay a received broadcast message which has been held back, either
+    /// dropped by the listeners (eg: pending validation), not relayed
+    /// because of [Config::auto_propagate] or received while the relay was
+    /// paused. See [Config::max_held_messages].
+    ///
+    /// The message is relayed as received, to the buckets below
+    /// `from_height` (usually [MessageInfo::height]), which can't exceed
+    /// the height it has been received with. It's relayed once, and never
+    /// while the relay is paused.
+    ///
+    /// Returns `false` if the message is not held or has been received with
+    /// height 0
+    pub async fn repropagate(
+        &self,
+        message: impl Into<MessageRef>,
+        from_height: u8,
+    ) -> bool {
+        self.relayer.repropagate(&message.into(), from_height).await
+    }
+
+    /// Replace the allowlist, initially set with [Config::allowlist].
+    ///
+    /// The peers not allowed anymore are removed from the routing table.
+    /// `None` disables the allowlist-only mode
+    pub async fn set_allowlist(&self, targets: Option<Vec<PeerTarget>>) {
+        let mut table = self.ktable.write().await;
+        table.allowlist().set(targets);
+        let allowlist = table.allowlist().clone();
+        table.remove_nodes(|node| {
+            !allowlist.is_allowed_peer(
+                &node.value().address().ip(),
+                node.id().as_binary(),
+            )
+        });
+        for event in table.drain_events() {
+            event::emit(&self.events, event.into());
+        }
+    }
+
+    /// Replace the trusted peers, initially set with
+    /// [Config::trusted_peers]
+    pub async fn set_trusted_peers(&self, targets: Vec<PeerTarget>) {
+        self.ktable.write().await.set_trusted(targets);
+    }
+
+    /// Return the `k` known peers closest to an arbitrary key in the XOR
+    /// metric, the closest first.
+    ///
+    /// Keys of [ID_LEN] bytes are compared as they are, any other key is
+    /// hashed first
+    pub async fn closest_nodes(
+        &self,
+        key: &[u8],
+        k: usize,
+    ) -> Vec<PeerSnapshot> {
+        let key = kbucket::derive_key(key);
+        self.ktable
+            .read()
+            .await
+            .closest_nodes(&key, k)
+            .map(PeerSnapshot::from_node)
+            .collect()
+    }
+
+    /// Return the height of the bucket a key belongs to in the routing
+    /// table of this peer. `None` if the key is the id of this peer
+    pub fn bucket_height_for(&self, key: &BinaryKey) -> Option<BucketHeight> {
+        bucket_height(self.header.binary_id.as_binary(), key)
+    }
+
+    /// Return the peers of the bucket at the given height, which are the
+    /// candidate delegates of the broadcasts at that height. The least
+    /// recently seen peer comes first
+    pub async fn peers_at_height(
+        &self,
+        height: BucketHeight,
+    ) -> Vec<PeerSnapshot> {
+        self.ktable.read().await.peers_at_height(height)
+    }
+
+    /// Return how many peers occupy each non empty bucket of the routing
+    /// table, sorted by height
+    pub async fn bucket_occupancy(&self) -> Vec<BucketOccupancy> {
+        self.ktable.read().await.occupancy()
+    }
+
+    /// Attach an application defined metadata (eg: agent version or
+    /// services) to a peer of the routing table, replacing the previous one.
+    /// The metadata is included in the [RouteTable] snapshots.
+    ///
+    /// Returns `false` if the peer is not in the routing table or the
+    /// metadata is longer than [MAX_PEER_METADATA_LEN]
+    pub async fn annotate(&self, id: &BinaryKey, metadata: &[u8]) -> bool {
+        if metadata.len() > MAX_PEER_METADATA_LEN {
+            return false;
+        }
+        let mut table = self.ktable.write().await;
+        match table.node_mut(id) {
+            Some(node) => node.value_mut().set_metadata(metadata.to_vec()),
+            None => return false,
+        }
+        table.shed_memory();
+        for event in table.drain_events() {
+            event::emit(&self.events, event.into());
+        }
+        true
+    }
+
+    /// Run an iterative lookup of the peers closest to an arbitrary key in
+    /// the XOR metric, querying the closest known peers until no closer one
+    /// is found.
+    ///
+    /// Returns the addresses of the closest peers which replied, the closest
+    /// first. Keys of [ID_LEN] bytes are compared as they are, any other key
+    /// is hashed first
+    pub async fn lookup(&self, key: &[u8]) -> Vec<SocketAddr> {
+        lookup::lookup(
+            kbucket::derive_key(key),
+            self.lookup_size,
+            &self.ktable,
+            &self.outbound_sender,
+            &self.nodes_reply,
+        )
+        .await
+    }
+
+    /// Send a request to a peer and wait for its response, produced by the
+    /// [RequestHandler] of the receiver.
+    ///
+    /// The request is sent again every [Config::request_timeout], up to
+    /// [Config::request_retries] times. Requests and responses must fit a
+    /// single datagram ([MAX_REQUEST_LEN]) and, like the other control
+    /// messages, they are not encrypted with the
+    /// [Config::gossip_key]
+    pub async fn request(
+        &self,
+        target: SocketAddr,
+        request: &[u8],
+    ) -> Result<Vec<u8>, RequestError> {
+        rpc::request(
+            self.header,
+            target,
+            request,
+            self.request_timeout,
+            self.request_retries,
+            &self.requests,
+            &self.outbound_sender,
+        )
+        .await
+    }
+
+    /// Adjust the [Score] of the peers of the routing table included in the
+    /// target, in order to report their behaviour at application level.
+    ///
+    /// Peers with a negative score are the first to be evicted, peers with
+    /// the highest score are preferred when broadcasting
+    pub async fn adjust_score(&self, target: PeerTarget, delta: Score) {
+        self.ktable.write().await.adjust_score(&target, delta);
+    }
+
     /// Return the [SocketAddr] of a set of random active nodes.
     ///
     /// * `amount` - The max amount of nodes to return
@@ -136,8 +1008,28 @@ impl Peer {
             .choose_multiple(rng, amount)
     }
 
-    #[doc(hidden)]
-    pub async fn report(&self) {
+    /// Return up to `amount` random alive peers, drawn across all the
+    /// buckets.
+    ///
+    /// Meant to pick gossip partners or RPC targets outside of the broadcast
+    /// path
+    pub async fn sample_peers(&self, amount: usize) -> Vec<PeerSnapshot> {
+        let table_read = self.ktable.read().await;
+        let rng = &mut rand::thread_rng();
+        table_read
+            .alive_nodes()
+            .choose_multiple(rng, amount)
+            .into_iter()
+            .map(PeerSnapshot::from_node)
+            .collect()
+    }
+
+    /// Report the state of the network as seen by this peer: the buckets
+    /// of the routing table, their peers with their last seen time and
+    /// pending eviction, and the state of the transport. The buckets are
+    /// also logged, one line per bucket
+    pub async fn report(&self) -> NetworkReport {
+        let health = self.health().await;
         let table_read = self.ktable.read().await;
         /*
         The usage of `info!` macro can potentially raise a compilation error
@@ -156,6 +1048,11 @@ impl Peer {
             let nodes_joined = nodes.map(|p| p.value().address()).join(",");
             info!("H: {} - Nodes {}", h, nodes_joined);
         });
+        NetworkReport::new(
+            table_read.snapshot(),
+            table_read.occupancy(),
+            health,
+        )
     }
 
     /// Broadcast a message to the network
@@ -163,41 +1060,172 @@ impl Peer {
     /// # Arguments
     ///
     /// * `message` - Byte array containing the message to be broadcasted
-    /// * `height` - (Optional) Overrides default Kadcast broadcast height
+    /// * `height` - (Optional) Overrides the configured broadcast height. It
+    ///   never exceeds the configured max broadcast height
+    ///
+    /// Note:
+    /// The function returns just after the message is put on the internal queue
+    /// system. It **does not guarantee** the message will be broadcasted: the
+    /// returned [BroadcastHandle] tells how many buckets and delegates have
+    /// been targeted, and reports the send errors once the message is sent
+    pub async fn broadcast(
+        &self,
+        message: &[u8],
+        height: Option<usize>,
+    ) -> BroadcastHandle {
+        let options = BroadcastOptions {
+            height,
+            ..Default::default()
+        };
+        self.broadcast_with(message, options).await
+    }
+
+    /// Broadcast a message to the network with the given options, eg: on a
+    /// topic, with a priority and into some buckets only
+    ///
+    /// ```ignore
+    /// let options = BroadcastOptions::default()
+    ///     .with_topic(1)
+    ///     .with_priority(2)
+    ///     .with_heights(&[0, 1]);
+    /// peer.broadcast_with(message, options).await;
+    /// ```
     ///
     /// Note:
     /// The function returns just after the message is put on the internal queue
     /// system. It **does not guarantee** the message will be broadcasted
-    pub async fn broadcast(&self, message: &[u8], height: Option<usize>) {
-        if message.is_empty() {
-            error!("Message empty");
-            return;
+    pub async fn broadcast_with(
+        &self,
+        message: &[u8],
+        options: BroadcastOptions,
+    ) -> BroadcastHandle {
+        let mut payload = BroadcastPayload::new(0, message.to_vec())
+            .with_topic(options.topic)
+            .with_priority(options.priority);
+        payload.message_id = options.id;
+        match &options.heights {
+            Some(heights) => {
+                self.broadcaster
+                    .broadcast_into(payload, heights, options.beta)
+                    .await
+            }
+            None => {
+                self.broadcaster
+                    .broadcast(payload, options.height, options.beta)
+                    .await
+            }
         }
+    }
 
-        let tosend: Vec<(Message, Vec<SocketAddr>)> = self
-            .ktable
-            .read()
-            .await
-            .extract(height)
-            .map(|(h, nodes)| {
-                let msg = Message::Broadcast(
-                    self.header,
-                    BroadcastPayload {
-                        height: h.try_into().unwrap(),
-                        gossip_frame: message.to_vec(), //FIX_ME: avoid clone
-                    },
-                );
-                let targets: Vec<SocketAddr> =
-                    nodes.map(|node| *node.value().address()).collect();
-                (msg, targets)
-            })
-            .collect();
+    /// Broadcast many small messages at once, coalescing them into frames
+    /// of up to [Config::max_batch_len] bytes. It amortizes the overhead of
+    /// the headers and of the FEC encoding, which dominates when
+    /// broadcasting lots of tiny messages (eg: transactions).
+    ///
+    /// The receivers notify the messages one by one, as if they were
+    /// broadcasted separately. The frames are relayed as a whole: a
+    /// listener dropping one message (see [NetworkListen::on_relay]) stops
+    /// the relay of its whole frame
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The messages to be broadcasted, the empty ones are
+    ///   skipped
+    /// * `height` - (Optional) Overrides the configured broadcast height. It
+    ///   never exceeds the configured max broadcast height
+    ///
+    /// Returns the [BroadcastHandle] of each frame
+    ///
+    /// Note:
+    /// The function returns just after the frames are put on the internal
+    /// queue system. It **does not guarantee** the messages will be
+    /// broadcasted
+    pub async fn broadcast_batch<M: AsRef<[u8]>>(
+        &self,
+        messages: impl IntoIterator<Item = M>,
+        height: Option<usize>,
+    ) -> Vec<BroadcastHandle> {
+        let mut handles = vec![];
+        for frame in
+            BroadcastPayload::batch_frames(messages, self.max_batch_len)
+        {
+            let mut payload = BroadcastPayload::new(0, frame);
+            payload.batch = true;
+            handles
+                .push(self.broadcaster.broadcast(payload, height, None).await);
+        }
+        handles
+    }
 
-        for i in tosend {
-            self.outbound_sender.send(i).await.unwrap_or_else(|e| {
-                error!("Unable to send from broadcast {}", e)
-            });
+    /// Broadcast a message to the network at the given time, eg: to jitter
+    /// the gossip of the application without a timer of its own
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - Byte array containing the message to be broadcasted
+    /// * `at` - When the message is broadcasted, straight away if already
+    ///   past
+    /// * `height` - (Optional) Overrides the configured broadcast height. It
+    ///   never exceeds the configured max broadcast height
+    ///
+    /// The returned [ScheduledBroadcast] cancels the broadcast, or waits for
+    /// it to be sent. Pending broadcasts are discarded once the peer is shut
+    /// down
+    pub fn broadcast_at(
+        &self,
+        message: &[u8],
+        at: Instant,
+        height: Option<usize>,
+    ) -> ScheduledBroadcast {
+        let payload = BroadcastPayload::new(0, message.to_vec());
+        let (scheduled, handle) = Scheduled::new(at, payload, height);
+        if self.schedule.send(scheduled).is_err() {
+            error!("Unable to schedule the broadcast");
         }
+        handle
+    }
+
+    /// Broadcast a message to the network once `delay` has elapsed, see
+    /// [Peer::broadcast_at]
+    pub fn broadcast_after(
+        &self,
+        message: &[u8],
+        delay: Duration,
+        height: Option<usize>,
+    ) -> ScheduledBroadcast {
+        self.broadcast_at(message, Instant::now() + delay, height)
+    }
+
+    /// Broadcast a message to the given peers only, instead of the
+    /// delegates picked from the routing table (eg: to the members of a
+    /// committee). The message is encoded as any broadcast, but it's never
+    /// relayed by the receivers
+    ///
+    /// Note:
+    /// The function returns just after the message is put on the internal queue
+    /// system. It **does not guarantee** the message will be broadcasted
+    pub async fn broadcast_to(
+        &self,
+        message: &[u8],
+        targets: &[SocketAddr],
+    ) -> BroadcastHandle {
+        let (mut handle, tracker) = BroadcastHandle::new();
+        if message.is_empty() || targets.is_empty() {
+            error!("Message or targets empty");
+            return handle;
+        }
+        let mut payload = BroadcastPayload::new(0, message.to_vec());
+        let header = self.broadcaster.originate(&mut payload);
+        let msg =
+            Message::Broadcast(header.with_origin_height(Some(0)), payload);
+        handle.targeted(targets.len());
+        self.outbound_sender
+            .send((msg, targets.to_vec(), Some(tracker)))
+            .await
+            .unwrap_or_else(|e| {
+                error!("Unable to send from broadcast_to {}", e)
+            });
+        handle
     }
 
     /// Send a message to a peer in the network
@@ -216,19 +1244,39 @@ impl Peer {
         }
         // We use the Broadcast message type while setting height to 0
         // to prevent further propagation at the receiver
+        let mut payload = BroadcastPayload::new(0, message.to_vec()); //FIX_ME: avoid clone
+        payload.origin = self.broadcaster.sign(&payload);
         let msg = Message::Broadcast(
-            self.header,
-            BroadcastPayload {
-                height: 0,
-                gossip_frame: message.to_vec(), //FIX_ME: avoid clone
-            },
+            self.header.with_origin_height(Some(0)),
+            payload,
         );
         let targets = vec![target];
         self.outbound_sender
-            .send((msg, targets))
+            .send((msg, targets, None))
             .await
             .unwrap_or_else(|e| {
                 error!("Unable to send from send method {}", e)
             });
     }
 }
+
+impl Drop for Peer {
+    // Stop the tasks and persist the known peers. The table is skipped if
+    // it's locked, since dropping can't wait for it
+    fn drop(&mut self) {
+        for task in self.tasks.iter().chain(&self.sender_task) {
+            task.abort();
+        }
+        if self.peer_store.is_none() && self.bootstrap_cache.is_none() {
+            return;
+        }
+        match self.ktable.try_read() {
+            Some(table) => save_known_peers(
+                &table.stored(),
+                &self.peer_store,
+                &self.bootstrap_cache,
+            ),
+            None => warn!("Unable to save peers, table locked"),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::encoding::payload::Topic;
+use crate::{NetworkListen, Propagation};
+
+/// Identifier of a listener, returned by
+/// [Peer::add_listener](crate::Peer::add_listener)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListenerId(u64);
+
+/// Listener shared between the registry and the dispatch of a notification
+#[derive(Clone)]
+pub(crate) struct SharedListener(Arc<Mutex<Box<dyn NetworkListen>>>);
+
+impl SharedListener {
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Box<dyn NetworkListen>> {
+        self.0.lock().expect("Listener poisoned")
+    }
+
+    pub(crate) fn subscribed(&self, topic: Topic) -> bool {
+        self.lock()
+            .topics()
+            .is_none_or(|topics| topics.contains(&topic))
+    }
+}
+
+/// Listeners the received messages are dispatched to, in registration order
+#[derive(Clone, Default)]
+pub(crate) struct Listeners {
+    next_id: Arc<AtomicU64>,
+    listeners: Arc<Mutex<Vec<(ListenerId, SharedListener)>>>,
+}
+
+impl Listeners {
+    pub(crate) fn add(&self, listener: Box<dyn NetworkListen>) -> ListenerId {
+        let id = ListenerId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let listener = SharedListener(Arc::new(Mutex::new(listener)));
+        self.lock().push((id, listener));
+        id
+    }
+
+    pub(crate) fn remove(&self, id: ListenerId) -> bool {
+        let mut listeners = self.lock();
+        let len = listeners.len();
+        listeners.retain(|(registered, _)| registered != &id);
+        listeners.len() != len
+    }
+
+    pub(crate) fn ids(&self) -> Vec<ListenerId> {
+        self.lock().iter().map(|(id, _)| *id).collect()
+    }
+
+    /// Listeners subscribed to `topic`. A notification is dispatched to a
+    /// snapshot, so that listeners can be added and removed by the listeners
+    /// themselves
+    pub(crate) fn subscribed(&self, topic: Topic) -> Vec<SharedListener> {
+        let listeners: Vec<_> = self
+            .lock()
+            .iter()
+            .map(|(_, listener)| listener.clone())
+            .collect();
+        listeners
+            .into_iter()
+            .filter(|listener| listener.subscribed(topic))
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<(ListenerId, SharedListener)>> {
+        self.listeners.lock().expect("Listeners lock poisoned")
+    }
+}
+
+/// Combine the verdicts of several listeners on the propagation of a
+/// message: it's dropped if any listener drops it, and relayed with the
+/// lowest height requested. `None` if no listener has a verdict
+pub(crate) fn combine(
+    verdicts: impl Iterator<Item = Option<Propagation>>,
+) -> Option<Propagation> {
+    verdicts
+        .flatten()
+        .reduce(|combined, verdict| match (combined, verdict) {
+            (Propagation::Drop, _) | (_, Propagation::Drop) => {
+                Propagation::Drop
+            }
+            (Propagation::Propagate, other)
+            | (other, Propagation::Propagate) => other,
+            (
+                Propagation::PropagateWithHeight(a),
+                Propagation::PropagateWithHeight(b),
+            ) => Propagation::PropagateWithHeight(a.min(b)),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{combine, Listeners};
+    use crate::{MessageInfo, NetworkListen, Propagation, Topic};
+
+    struct TopicListener(Topic);
+
+    impl NetworkListen for TopicListener {
+        fn on_message(&self, _message: Vec<u8>, _metadata: MessageInfo) {}
+
+        fn topics(&self) -> Option<&[Topic]> {
+            Some(std::slice::from_ref(&self.0))
+        }
+    }
+
+    #[test]
+    fn test_listeners() {
+        let listeners = Listeners::default();
+        let first = listeners.add(Box::new(TopicListener(1)));
+        let second = listeners.add(Box::new(TopicListener(1)));
+        listeners.add(Box::new(TopicListener(2)));
+        assert_ne!(first, second);
+        assert_eq!(listeners.subscribed(1).len(), 2);
+        assert_eq!(listeners.ids()[..2], [first, second]);
+        assert!(listeners.remove(first));
+        assert!(!listeners.remove(first));
+        assert_eq!(listeners.subscribed(1).len(), 1);
+        assert_eq!(listeners.subscribed(3).len(), 0);
+    }
+
+    #[test]
+    fn test_combine() {
+        use Propagation::*;
+        assert_eq!(combine(vec![None, None].into_iter()), None);
+        assert_eq!(
+            combine(vec![None, Some(Propagate)].into_iter()),
+            Some(Propagate)
+        );
+        let with_height = vec![
+            Some(PropagateWithHeight(4)),
+            Some(Propagate),
+            Some(PropagateWithHeight(2)),
+        ];
+        assert_eq!(
+            combine(with_height.into_iter()),
+            Some(PropagateWithHeight(2))
+        );
+        assert_eq!(
+            combine(vec![Some(Propagate), Some(Drop)].into_iter()),
+            Some(Drop)
+        );
+    }
+}
@@ -0,0 +1,291 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, error, warn};
+
+use crate::channel::Sender;
+use crate::encoding::message::{Header, Message};
+use crate::encoding::payload::PeerEncodedInfo;
+use crate::kbucket::{xor_distance, BinaryKey, Tree};
+use crate::peer::PeerInfo;
+use crate::transport::MessageBeanOut;
+use crate::{RwLock, K_ALPHA};
+
+/// Time a queried peer is given to reply, before being considered failed
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// `Nodes` replies received by the handler, dispatched to the running
+/// lookups
+pub(crate) type NodesReplySender =
+    broadcast::Sender<(SocketAddr, Vec<PeerEncodedInfo>)>;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum CandidateState {
+    NotQueried,
+    Queried(Instant),
+    Responded,
+    Failed,
+}
+
+struct Candidate {
+    address: SocketAddr,
+    distance: BinaryKey,
+    state: CandidateState,
+}
+
+/// State of an iterative `FindNodes` lookup.
+///
+/// The closest peers known so far are queried, up to [K_ALPHA] at a time,
+/// and the peers they return are merged into the shortlist. The lookup ends
+/// when the `k` closest peers of the shortlist have all replied (or failed)
+struct NodeLookup {
+    target: BinaryKey,
+    own_id: BinaryKey,
+    k: usize,
+    shortlist: Vec<Candidate>,
+}
+
+impl NodeLookup {
+    fn new(target: BinaryKey, own_id: BinaryKey, k: usize) -> Self {
+        Self {
+            target,
+            own_id,
+            k,
+            shortlist: vec![],
+        }
+    }
+
+    fn add(&mut self, address: SocketAddr, id: BinaryKey) {
+        if id == self.own_id
+            || self.shortlist.iter().any(|c| c.address == address)
+        {
+            return;
+        }
+        let distance = xor_distance(&self.target, &id);
+        let idx = self
+            .shortlist
+            .partition_point(|candidate| candidate.distance <= distance);
+        self.shortlist.insert(
+            idx,
+            Candidate {
+                address,
+                distance,
+                state: CandidateState::NotQueried,
+            },
+        );
+    }
+
+    // The `k` closest candidates which didn't fail
+    fn closest(&self) -> impl Iterator<Item = &Candidate> {
+        self.shortlist
+            .iter()
+            .filter(|c| c.state != CandidateState::Failed)
+            .take(self.k)
+    }
+
+    /// Expire the timed out queries and return the peers to query next
+    fn next_queries(&mut self, now: Instant) -> Vec<SocketAddr> {
+        for candidate in &mut self.shortlist {
+            if let CandidateState::Queried(at) = candidate.state {
+                if now.duration_since(at) >= QUERY_TIMEOUT {
+                    candidate.state = CandidateState::Failed;
+                }
+            }
+        }
+        let in_flight = self
+            .closest()
+            .filter(|c| matches!(c.state, CandidateState::Queried(_)))
+            .count();
+        let to_query: Vec<_> = self
+            .closest()
+            .filter(|c| c.state == CandidateState::NotQueried)
+            .take(K_ALPHA.saturating_sub(in_flight))
+            .map(|c| c.address)
+            .collect();
+        for candidate in &mut self.shortlist {
+            if to_query.contains(&candidate.address) {
+                candidate.state = CandidateState::Queried(now);
+            }
+        }
+        to_query
+    }
+
+    fn on_reply(&mut self, from: SocketAddr, peers: &[PeerEncodedInfo]) {
+        match self.shortlist.iter_mut().find(|c| c.address == from) {
+            Some(candidate) if candidate.state != CandidateState::Failed => {
+                candidate.state = CandidateState::Responded
+            }
+            // Late replies and replies to other lookups are ignored
+            _ => return,
+        }
+        for peer in peers {
+            self.add(peer.to_socket_address(), peer.id);
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.closest()
+            .all(|candidate| candidate.state == CandidateState::Responded)
+    }
+
+    // Time left before the first in flight query expires
+    fn next_timeout(&self, now: Instant) -> Duration {
+        self.shortlist
+            .iter()
+            .filter_map(|candidate| match candidate.state {
+                CandidateState::Queried(at) => {
+                    Some((at + QUERY_TIMEOUT).saturating_duration_since(now))
+                }
+                _ => None,
+            })
+            .min()
+            .unwrap_or(QUERY_TIMEOUT)
+    }
+
+    /// The closest peers which replied
+    fn result(&self) -> Vec<SocketAddr> {
+        self.shortlist
+            .iter()
+            .filter(|c| c.state == CandidateState::Responded)
+            .take(self.k)
+            .map(|c| c.address)
+            .collect()
+    }
+}
+
+/// Run an iterative lookup of the `k` peers closest to the target, starting
+/// from the closest peers of the routing table.
+///
+/// Returns the addresses of the closest peers which replied, the closest
+/// first
+pub(crate) async fn lookup(
+    target: BinaryKey,
+    k: usize,
+    ktable: &RwLock<Tree<PeerInfo>>,
+    outbound_sender: &Sender<MessageBeanOut>,
+    nodes_reply: &NodesReplySender,
+) -> Vec<SocketAddr> {
+    // Subscribe before sending any query, so that no reply is missed
+    let mut replies = nodes_reply.subscribe();
+    let (header, filter, mut lookup) = {
+        let table = ktable.read().await;
+        let header = table.root().as_header();
+        let mut lookup =
+            NodeLookup::new(target, *header.binary_id.as_binary(), k);
+        for node in table.closest_nodes(&target, k) {
+            lookup.add(*node.value().address(), *node.id().as_binary());
+        }
+        (header, table.filter().clone(), lookup)
+    };
+    loop {
+        let targets = lookup.next_queries(Instant::now());
+        if lookup.is_finished() {
+            break;
+        }
+        if !targets.is_empty() {
+            send_queries(header, target, targets, outbound_sender).await;
+        }
+        let timeout = lookup.next_timeout(Instant::now());
+        match tokio::time::timeout(timeout, replies.recv()).await {
+            Ok(Ok((from, peers))) => {
+                let accepted: Vec<_> = peers
+                    .into_iter()
+                    .filter(|p| {
+                        filter.accepts(&p.to_socket_address().ip(), &p.id)
+                    })
+                    .collect();
+                lookup.on_reply(from, &accepted);
+            }
+            Ok(Err(RecvError::Lagged(skipped))) => {
+                warn!("Lookup missed {} replies", skipped)
+            }
+            Ok(Err(RecvError::Closed)) => break,
+            // A query expired
+            Err(_) => {}
+        }
+    }
+    let result = lookup.result();
+    debug!("Lookup completed with {} peers", result.len());
+    result
+}
+
+async fn send_queries(
+    header: Header,
+    target: BinaryKey,
+    targets: Vec<SocketAddr>,
+    outbound_sender: &Sender<MessageBeanOut>,
+) {
+    outbound_sender
+        .send((Message::FindNodes(header, target), targets, None))
+        .await
+        .unwrap_or_else(|e| error!("Unable to send lookup query {}", e));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Instant;
+
+    use super::{NodeLookup, QUERY_TIMEOUT};
+    use crate::encoding::payload::PeerEncodedInfo;
+    use crate::kbucket::xor_distance;
+    use crate::K_ALPHA;
+
+    fn peer(i: u8) -> PeerEncodedInfo {
+        PeerEncodedInfo::from_address(SocketAddr::new(
+            [10, 0, 0, i].into(),
+            666,
+        ))
+    }
+
+    #[test]
+    fn test_lookup_progress() {
+        let target = peer(0).id;
+        let own_id = peer(255).id;
+        let mut lookup = NodeLookup::new(target, own_id, 4);
+        for i in 1..5 {
+            let p = peer(i);
+            lookup.add(p.to_socket_address(), p.id);
+        }
+        let now = Instant::now();
+        let first = lookup.next_queries(now);
+        assert_eq!(first.len(), K_ALPHA);
+        assert!(!lookup.is_finished());
+
+        // Replies add new candidates (but never the local peer)
+        let found: Vec<_> = (5..20).map(peer).chain([peer(255)]).collect();
+        for from in &first {
+            lookup.on_reply(*from, &found);
+        }
+        assert_eq!(lookup.shortlist.len(), 19);
+
+        // Unresponsive peers fail, until the closest peers have all replied
+        let mut now = now;
+        while !lookup.is_finished() {
+            now += QUERY_TIMEOUT;
+            let queries = lookup.next_queries(now);
+            assert!(queries.len() <= K_ALPHA);
+            if let Some(from) = queries.first() {
+                lookup.on_reply(*from, &[]);
+            }
+        }
+        let result = lookup.result();
+        assert_eq!(result.len(), 4);
+        let distances: Vec<_> = result
+            .iter()
+            .map(|a| {
+                xor_distance(&target, &PeerEncodedInfo::from_address(*a).id)
+            })
+            .collect();
+        let mut sorted = distances.clone();
+        sorted.sort();
+        assert_eq!(distances, sorted);
+    }
+}
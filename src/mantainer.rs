@@ -5,56 +5,211 @@
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
 use std::net::{SocketAddr, ToSocketAddrs};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use tokio::sync::mpsc::Sender;
+use rand::seq::IteratorRandom;
+use tokio::task::JoinHandle;
 use tracing::*;
 
+use crate::channel::Sender;
+use crate::config::{BootstrapRetryConfig, Config};
 use crate::encoding::message::{Header, Message};
-use crate::kbucket::Tree;
-use crate::peer::PeerInfo;
+use crate::event::{self, BootstrapProgress, EventSender, KadcastEvent};
+use crate::gossip::RecentMessages;
+use crate::kbucket::{
+    BinaryKey, BootstrapCache, KeepAlivePolicy, PeerFilter, PeerStore,
+    StoredPeer, StoredTable, Tree,
+};
+use crate::lookup::{self, NodesReplySender};
+use crate::peer::{PeerInfo, PeerNode};
 use crate::transport::MessageBeanOut;
 use crate::RwLock;
 
+mod strategy;
+
+pub use strategy::{LightMaintenance, MaintenanceStrategy};
+
 pub(crate) struct TableMantainer {
     bootstrapping_nodes: Vec<String>,
+    bootstrap_retry: BootstrapRetryConfig,
     ktable: RwLock<Tree<PeerInfo>>,
     outbound_sender: Sender<MessageBeanOut>,
     my_ip: SocketAddr,
     header: Header,
+    stored_peers: Vec<StoredPeer>,
+    bootstrap_cache: Option<Arc<BootstrapCache>>,
+    events: EventSender,
+    nodes_reply: NodesReplySender,
+    lookup_size: usize,
+    strategy: Arc<dyn MaintenanceStrategy>,
+}
+
+/// Peers persisted across restarts
+pub(crate) struct KnownPeers {
+    pub(crate) store: Option<Arc<dyn PeerStore>>,
+    pub(crate) bootstrap_cache: Option<Arc<BootstrapCache>>,
+    /// Peers loaded from the `store`
+    pub(crate) stored: Vec<StoredPeer>,
 }
 
 impl TableMantainer {
     pub(crate) fn start(
-        bootstrapping_nodes: Vec<String>,
         ktable: RwLock<Tree<PeerInfo>>,
         outbound_sender: Sender<MessageBeanOut>,
-    ) {
-        tokio::spawn(async move {
+        events: EventSender,
+        nodes_reply: NodesReplySender,
+        known_peers: KnownPeers,
+        recent: Option<RecentMessages>,
+        config: &Config,
+    ) -> Vec<JoinHandle<()>> {
+        let bootstrapping_nodes = config.bootstrapping_nodes.clone();
+        let bootstrap_retry = config.bootstrap_retry;
+        let peer_store_interval = config.peer_store_interval;
+        let lookup_size = config.bucket.capacity;
+        let policy = config
+            .keep_alive_policy
+            .clone()
+            .unwrap_or_else(|| Arc::new(config.keep_alive));
+        let strategy = config
+            .maintenance_strategy
+            .clone()
+            .unwrap_or_else(|| Arc::new(config.maintenance));
+        let mut tasks = vec![config.spawn(TableMantainer::keep_alive(
+            ktable.clone(),
+            outbound_sender.clone(),
+            events.clone(),
+            policy,
+            config.keep_alive.interval,
+        ))];
+        if let Some(recent) = recent {
+            tasks.push(config.spawn(TableMantainer::pull_gossip(
+                ktable.clone(),
+                outbound_sender.clone(),
+                recent,
+            )));
+        }
+        let KnownPeers {
+            store,
+            bootstrap_cache,
+            stored: stored_peers,
+        } = known_peers;
+        if store.is_some() || bootstrap_cache.is_some() {
+            let ktable = ktable.clone();
+            let bootstrap_cache = bootstrap_cache.clone();
+            tasks.push(config.spawn(async move {
+                loop {
+                    tokio::time::sleep(peer_store_interval).await;
+                    let stored = ktable.read().await.stored();
+                    save_known_peers(&stored, &store, &bootstrap_cache);
+                }
+            }));
+        }
+        tasks.push(config.spawn(async move {
             let my_ip = *ktable.read().await.root().value().address();
             let header = ktable.read().await.root().as_header();
 
             let mantainer = Self {
                 bootstrapping_nodes,
+                bootstrap_retry,
                 ktable,
                 outbound_sender,
                 my_ip,
                 header,
+                stored_peers,
+                bootstrap_cache,
+                events,
+                nodes_reply,
+                lookup_size,
+                strategy,
             };
+            mantainer.contact_stored_peers().await;
+            mantainer.contact_bootstrappers().await;
+            let found = mantainer.self_lookup().await;
+            mantainer.bootstrap_progress(BootstrapProgress::Joined(found));
             mantainer.monitor_buckets().await;
-        });
+        }));
+        tasks
+    }
+
+    /// Ask the peers known before the last shutdown for their neighbours,
+    /// sparing the bootstrappers when they are still reachable
+    async fn contact_stored_peers(&self) {
+        let filter = self.ktable.read().await.filter().clone();
+        let targets: Vec<_> = self
+            .stored_peers
+            .iter()
+            .filter(|peer| self.is_valid_peer(&filter, peer.address, &peer.id))
+            .map(|peer| peer.address)
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+        info!("TableMantainer::contact_stored_peers {}", targets.len());
+        self.bootstrap_progress(BootstrapProgress::StoredPeers(targets.len()));
+        let binary_key = self.header.binary_id.as_binary();
+        let find_nodes = Message::FindNodes(self.header, *binary_key);
+        self.send((find_nodes, targets, None)).await;
+        tokio::time::sleep(self.strategy.reply_grace()).await;
+    }
+
+    /// Ask the best peers of the bootstrap cache for their neighbours,
+    /// recording the ones which don't reply
+    async fn contact_cached_peers(&self) {
+        let cache = match &self.bootstrap_cache {
+            Some(cache) => cache,
+            None => return,
+        };
+        let filter = self.ktable.read().await.filter().clone();
+        let sampled: Vec<_> = cache
+            .sample(self.lookup_size)
+            .into_iter()
+            .filter(|peer| self.is_valid_peer(&filter, peer.address, &peer.id))
+            .collect();
+        if sampled.is_empty() {
+            return;
+        }
+        info!("TableMantainer::contact_cached_peers {}", sampled.len());
+        self.bootstrap_progress(BootstrapProgress::CachedPeers(sampled.len()));
+        let binary_key = self.header.binary_id.as_binary();
+        let find_nodes = Message::FindNodes(self.header, *binary_key);
+        let targets = sampled.iter().map(|peer| peer.address).collect();
+        self.send((find_nodes, targets, None)).await;
+        tokio::time::sleep(self.strategy.reply_grace()).await;
+        let failed: Vec<_> = {
+            let table = self.ktable.read().await;
+            sampled
+                .iter()
+                .filter(|peer| table.has_peer(&peer.id).is_none())
+                .map(|peer| peer.address)
+                .collect()
+        };
+        cache.record_failures(&failed);
+    }
+
+    // Whether a persisted peer can be contacted
+    fn is_valid_peer(
+        &self,
+        filter: &PeerFilter,
+        address: SocketAddr,
+        id: &BinaryKey,
+    ) -> bool {
+        address != self.my_ip
+            && id == &PeerNode::compute_id(&address.ip(), address.port())
+            && filter.accepts(&address.ip(), id)
     }
 
     /// Check if the peer need to contact the bootstrappers in order to join the
     /// network
     async fn need_bootstrappers(&self) -> bool {
         let binary_key = self.header.binary_id.as_binary();
-        self.ktable
+        let close_peers = self
+            .ktable
             .read()
             .await
-            .closest_peers::<10>(binary_key)
-            .count()
-            < 3
+            .closest_peers(binary_key, self.lookup_size)
+            .count();
+        self.strategy.need_bootstrap(close_peers)
     }
 
     /// Return a vector containing the Socket Addresses bound to the provided
@@ -72,18 +227,57 @@ impl TableMantainer {
             .collect()
     }
 
-    /// Try to contact the bootstrappers node until no needed anymore
+    /// Try to contact the bootstrappers node until no needed anymore,
+    /// falling back to the bootstrap cache if they don't reply. The attempts
+    /// are spaced with an exponential backoff
     async fn contact_bootstrappers(&self) {
+        let mut attempt = 0;
         while self.need_bootstrappers().await {
-            info!("TableMantainer::contact_bootstrappers");
+            attempt += 1;
+            if attempt > 1 {
+                self.bootstrap_progress(BootstrapProgress::Retrying(attempt));
+            }
+            info!("TableMantainer::contact_bootstrappers #{}", attempt);
             let bootstrapping_nodes_addr = self.bootstrapping_nodes_addr();
             let binary_key = self.header.binary_id.as_binary();
             let find_nodes = Message::FindNodes(self.header, *binary_key);
-            self.send((find_nodes, bootstrapping_nodes_addr)).await;
-            tokio::time::sleep(Duration::from_secs(30)).await;
+            let started = Instant::now();
+            self.bootstrap_progress(BootstrapProgress::Bootstrappers(
+                bootstrapping_nodes_addr.len(),
+            ));
+            self.send((find_nodes, bootstrapping_nodes_addr, None))
+                .await;
+            if self.bootstrap_cache.is_some() {
+                tokio::time::sleep(self.strategy.reply_grace()).await;
+                if self.need_bootstrappers().await {
+                    self.contact_cached_peers().await;
+                }
+            }
+            let backoff = self.bootstrap_retry.backoff(attempt);
+            tokio::time::sleep_until((started + backoff).into()).await;
         }
     }
 
+    /// Look up the peers closest to the local one, in order to fill the
+    /// nearest buckets once joined the network, returning the amount of
+    /// peers found
+    async fn self_lookup(&self) -> usize {
+        let found = lookup::lookup(
+            *self.header.binary_id.as_binary(),
+            self.lookup_size,
+            &self.ktable,
+            &self.outbound_sender,
+            &self.nodes_reply,
+        )
+        .await;
+        info!("TableMantainer::self_lookup found {} peers", found.len());
+        found.len()
+    }
+
+    fn bootstrap_progress(&self, progress: BootstrapProgress) {
+        event::emit(&self.events, KadcastEvent::Bootstrap(progress));
+    }
+
     async fn send(&self, message: MessageBeanOut) {
         self.outbound_sender
             .send(message)
@@ -95,43 +289,166 @@ impl TableMantainer {
 
     /// This is the main function of this utility class. It's responsible to:
     /// 1. Contact bootstrappers (if needed)
-    /// 2. Ping idle buckets
-    /// 3. Remove idles nodes from buckets
+    /// 2. Refresh the idle buckets
+    /// 3. Look up the local id at each discovery round
     async fn monitor_buckets(&self) {
         info!("TableMantainer::monitor_buckets started");
         let idle_time: Duration =
             { self.ktable.read().await.config.bucket_ttl };
+        let mut next_discovery = self
+            .strategy
+            .discovery_interval()
+            .map(|interval| Instant::now() + interval);
         loop {
             self.contact_bootstrappers().await;
             info!("TableMantainer::monitor_buckets back to sleep");
 
-            tokio::time::sleep(idle_time).await;
+            let now = Instant::now();
+            let next_idle = self
+                .ktable
+                .read()
+                .await
+                .next_refresh()
+                .unwrap_or(now + idle_time);
+            let next_sweep = self.strategy.next_sweep(now, next_idle);
+            let wake_at = next_discovery
+                .map_or(next_sweep, |discovery| discovery.min(next_sweep));
+            tokio::time::sleep_until(wake_at.into()).await;
 
             info!("TableMantainer::monitor_buckets woke up");
-            self.ping_idle_buckets().await;
+            if matches!(next_discovery, Some(at) if at <= Instant::now()) {
+                self.self_lookup().await;
+                next_discovery = self
+                    .strategy
+                    .discovery_interval()
+                    .map(|interval| Instant::now() + interval);
+            }
+            self.refresh_buckets().await;
+        }
+    }
 
-            info!("TableMantainer::monitor_buckets removing idle nodes");
-            self.ktable.write().await.remove_idle_nodes();
+    /// Ping the idle nodes according to the keep alive policy, removing the
+    /// dead ones
+    async fn keep_alive(
+        ktable: RwLock<Tree<PeerInfo>>,
+        outbound_sender: Sender<MessageBeanOut>,
+        events: EventSender,
+        policy: Arc<dyn KeepAlivePolicy>,
+        interval: Duration,
+    ) {
+        let header = ktable.read().await.root().as_header();
+        loop {
+            tokio::time::sleep(interval).await;
+            let to_ping: Vec<_> = {
+                let mut table = ktable.write().await;
+                table.apply_score_reports();
+                table.apply_delivery_failures();
+                let to_ping = table.keep_alive(policy.as_ref());
+                for event in table.drain_events() {
+                    event::emit(&events, event.into());
+                }
+                to_ping.iter().map(|peer| *peer.address()).collect()
+            };
+            if !to_ping.is_empty() {
+                debug!("TableMantainer::keep_alive {}", to_ping.len());
+                outbound_sender
+                    .send((Message::Ping(header), to_ping, None))
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("Unable to send keep alive ping {:?}", e)
+                    });
+            }
         }
     }
 
-    /// Search for idle buckets (no message received) and try to contact some of
-    /// the belonging nodes
-    async fn ping_idle_buckets(&self) {
-        let table_lock_read = self.ktable.read().await;
+    /// Send the digest of the recent broadcast messages to some random
+    /// neighbours, which request the ones they missed
+    async fn pull_gossip(
+        ktable: RwLock<Tree<PeerInfo>>,
+        outbound_sender: Sender<MessageBeanOut>,
+        recent: RecentMessages,
+    ) {
+        let header = ktable.read().await.root().as_header();
+        let conf = *recent.conf();
+        loop {
+            tokio::time::sleep(conf.interval).await;
+            let digest = recent.digest();
+            if digest.is_empty() {
+                continue;
+            }
+            let targets: Vec<_> = ktable
+                .read()
+                .await
+                .all_sorted()
+                .flat_map(|(_, nodes)| nodes)
+                .map(|node| *node.value().address())
+                .choose_multiple(&mut rand::thread_rng(), conf.fanout);
+            if targets.is_empty() {
+                continue;
+            }
+            debug!("TableMantainer::pull_gossip {}", digest.len());
+            outbound_sender
+                .send((Message::IHave(header, digest), targets, None))
+                .await
+                .unwrap_or_else(|e| error!("Unable to send IHave {:?}", e));
+        }
+    }
 
-        let find_node_messages = table_lock_read
-            .idle_buckets()
-            .flat_map(|(_, idle_nodes)| idle_nodes)
-            .map(|target| {
-                (
-                    Message::FindNodes(self.header, *target.id().as_binary()),
-                    //TODO: Extract alpha nodes
-                    vec![*target.value().address()],
+    /// Refill each bucket which didn't see any traffic for the bucket TTL,
+    /// with a random walk: random keys in the range of the bucket are looked
+    /// up until it's full, up to `refresh_walks` times. The maintenance
+    /// strategy picks which idle buckets are refilled
+    async fn refresh_buckets(&self) {
+        let (idle, walks) = {
+            let mut table = self.ktable.write().await;
+            (table.buckets_to_refresh(), table.config.refresh_walks)
+        };
+        for &height in &idle {
+            event::emit(&self.events, KadcastEvent::BucketIdle(height));
+        }
+        for height in self.strategy.refresh(idle) {
+            for walk in 0..walks {
+                let key = self.header.binary_id.random_key_at(height);
+                let found = lookup::lookup(
+                    key,
+                    self.lookup_size,
+                    &self.ktable,
+                    &self.outbound_sender,
+                    &self.nodes_reply,
                 )
-            });
-        for find_node in find_node_messages {
-            self.send(find_node).await;
+                .await;
+                debug!(
+                    "TableMantainer::refresh_buckets {} walk {} found {} peers",
+                    height,
+                    walk,
+                    found.len()
+                );
+                if found.is_empty()
+                    || self.ktable.read().await.is_bucket_full(height)
+                {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Persist the peers of the routing table to the peer store, and record them
+/// in the bootstrap cache
+pub(crate) fn save_known_peers(
+    stored: &StoredTable,
+    store: &Option<Arc<dyn PeerStore>>,
+    bootstrap_cache: &Option<Arc<BootstrapCache>>,
+) {
+    if let Some(store) = store {
+        if let Err(e) = store.save(stored) {
+            error!("Unable to save peers - {}", e);
+        }
+    }
+    if let Some(cache) = bootstrap_cache {
+        cache.record_alive(&stored.peers);
+        if let Err(e) = cache.save() {
+            error!("Unable to save bootstrap cache - {}", e);
         }
     }
 }
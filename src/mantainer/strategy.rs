@@ -0,0 +1,117 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::time::{Duration, Instant};
+
+use crate::config::MaintenanceConfig;
+use crate::kbucket::BucketHeight;
+
+/// Strategy driving the maintenance of the routing table: when the
+/// bootstrapping nodes are contacted, how often the idle buckets are swept
+/// and refilled, and how often the peer looks up its own neighbourhood.
+///
+/// The idle nodes are pinged by the [KeepAlivePolicy](crate::KeepAlivePolicy)
+pub trait MaintenanceStrategy: Send + Sync {
+    /// Whether the bootstrapping nodes have to be contacted, knowing
+    /// `close_peers` peers close to the local one (up to the bucket
+    /// capacity)
+    fn need_bootstrap(&self, close_peers: usize) -> bool;
+
+    /// Time given to the contacted peers to reply, before falling back to
+    /// the next source of peers
+    fn reply_grace(&self) -> Duration;
+
+    /// When the idle buckets are swept next, the first bucket becoming idle
+    /// at `next_idle`
+    fn next_sweep(&self, now: Instant, next_idle: Instant) -> Instant;
+
+    /// The buckets refilled with random walks, out of the `idle` ones
+    fn refresh(&self, idle: Vec<BucketHeight>) -> Vec<BucketHeight> {
+        idle
+    }
+
+    /// Interval of the lookups of the local id, discovering the new peers
+    /// close to the local one. `None` looks it up only once joined
+    fn discovery_interval(&self) -> Option<Duration>;
+}
+
+impl MaintenanceStrategy for MaintenanceConfig {
+    fn need_bootstrap(&self, close_peers: usize) -> bool {
+        close_peers < self.min_peers
+    }
+
+    fn reply_grace(&self) -> Duration {
+        self.reply_grace
+    }
+
+    fn next_sweep(&self, now: Instant, next_idle: Instant) -> Instant {
+        match self.sweep_interval {
+            Some(interval) => next_idle.min(now + interval),
+            None => next_idle,
+        }
+    }
+
+    fn discovery_interval(&self) -> Option<Duration> {
+        self.discovery_interval
+    }
+}
+
+/// [MaintenanceStrategy] for light clients, which only contact the
+/// bootstrapping nodes until joining the network and never refill their
+/// idle buckets
+pub struct LightMaintenance(pub MaintenanceConfig);
+
+impl MaintenanceStrategy for LightMaintenance {
+    fn need_bootstrap(&self, close_peers: usize) -> bool {
+        self.0.need_bootstrap(close_peers)
+    }
+
+    fn reply_grace(&self) -> Duration {
+        self.0.reply_grace
+    }
+
+    fn next_sweep(&self, now: Instant, next_idle: Instant) -> Instant {
+        self.0.next_sweep(now, next_idle)
+    }
+
+    fn refresh(&self, _idle: Vec<BucketHeight>) -> Vec<BucketHeight> {
+        vec![]
+    }
+
+    fn discovery_interval(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{LightMaintenance, MaintenanceStrategy};
+    use crate::config::MaintenanceConfig;
+
+    #[test]
+    fn test_maintenance_config() {
+        let conf = MaintenanceConfig {
+            min_peers: 2,
+            sweep_interval: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        assert!(conf.need_bootstrap(1));
+        assert!(!conf.need_bootstrap(2));
+
+        let now = Instant::now();
+        let soon = now + Duration::from_secs(5);
+        let later = now + Duration::from_secs(60);
+        assert_eq!(conf.next_sweep(now, soon), soon);
+        assert_eq!(conf.next_sweep(now, later), now + Duration::from_secs(10));
+        assert_eq!(conf.refresh(vec![1, 2]), vec![1, 2]);
+
+        let light = LightMaintenance(conf);
+        assert!(light.refresh(vec![1, 2]).is_empty());
+        assert_eq!(light.discovery_interval(), None);
+    }
+}
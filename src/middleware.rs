@@ -0,0 +1,186 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::encoding::message::Message;
+
+/// Outcome of the interception of a message by a [MessageMiddleware]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interception {
+    /// Hand the message to the next middleware
+    Pass,
+    /// Discard the message, the next middlewares never see it
+    Drop,
+    /// Hand the message to the next middleware, and process it after the
+    /// given delay. The delays of the whole chain add up
+    Delay(Duration),
+}
+
+/// Interceptor of the messages received and sent by a peer, configured with
+/// [Config::middlewares](crate::config::Config::middlewares).
+///
+/// The middlewares are run in order on both paths, and can inspect, mutate,
+/// delay or drop the messages (eg: custom filtering, stamping or
+/// instrumentation). They are run by the network tasks, so they should
+/// never block
+pub trait MessageMiddleware: Send + Sync {
+    /// Intercept a message received from `from`, once decoded and
+    /// authenticated, before it's handled
+    fn inbound(
+        &self,
+        _message: &mut Message,
+        _from: SocketAddr,
+    ) -> Interception {
+        Interception::Pass
+    }
+
+    /// Intercept a message to be sent to `targets`, before it's encoded
+    fn outbound(
+        &self,
+        _message: &mut Message,
+        _targets: &[SocketAddr],
+    ) -> Interception {
+        Interception::Pass
+    }
+}
+
+impl fmt::Debug for dyn MessageMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MessageMiddleware")
+    }
+}
+
+/// Run a chain of middlewares, stopping at the first one dropping the
+/// message
+pub(crate) fn intercept<F>(
+    middlewares: &[Arc<dyn MessageMiddleware>],
+    mut run: F,
+) -> Interception
+where
+    F: FnMut(&dyn MessageMiddleware) -> Interception,
+{
+    let mut delay = Duration::ZERO;
+    for middleware in middlewares {
+        match run(middleware.as_ref()) {
+            Interception::Pass => {}
+            Interception::Drop => return Interception::Drop,
+            Interception::Delay(d) => delay += d,
+        }
+    }
+    match delay.is_zero() {
+        true => Interception::Pass,
+        false => Interception::Delay(delay),
+    }
+}
+
+/// Items held back until their deadline
+pub(crate) struct Delayed<T> {
+    items: Vec<(Instant, T)>,
+}
+
+impl<T> Default for Delayed<T> {
+    fn default() -> Self {
+        Self { items: vec![] }
+    }
+}
+
+impl<T> Delayed<T> {
+    pub(crate) fn push(&mut self, delay: Duration, item: T) {
+        self.items.push((Instant::now() + delay, item));
+    }
+
+    /// Items whose deadline is expired, the first pushed first
+    pub(crate) fn due(&mut self, now: Instant) -> Vec<T> {
+        let mut due = vec![];
+        let mut i = 0;
+        while i < self.items.len() {
+            match self.items[i].0 <= now {
+                true => due.push(self.items.remove(i).1),
+                false => i += 1,
+            }
+        }
+        due
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.items.iter().map(|(deadline, _)| *deadline).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use super::{intercept, Delayed, Interception, MessageMiddleware};
+    use crate::encoding::message::Message;
+    use crate::peer::PeerNode;
+
+    struct Fixed(Interception);
+
+    impl MessageMiddleware for Fixed {
+        fn inbound(
+            &self,
+            _message: &mut Message,
+            _from: std::net::SocketAddr,
+        ) -> Interception {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_intercept() {
+        let peer = PeerNode::generate("127.0.0.1:666");
+        let mut message = Message::Ping(peer.as_header());
+        let from = "127.0.0.1:666".parse().unwrap();
+        let mut chain = |verdicts: &[Interception]| {
+            let middlewares: Vec<Arc<dyn MessageMiddleware>> = verdicts
+                .iter()
+                .map(|v| Arc::new(Fixed(*v)) as Arc<dyn MessageMiddleware>)
+                .collect();
+            let mut run = 0;
+            let verdict = intercept(&middlewares, |m| {
+                run += 1;
+                m.inbound(&mut message, from)
+            });
+            (verdict, run)
+        };
+        let secs = Duration::from_secs;
+        assert_eq!(chain(&[]), (Interception::Pass, 0));
+        assert_eq!(
+            chain(&[
+                Interception::Delay(secs(1)),
+                Interception::Delay(secs(2))
+            ]),
+            (Interception::Delay(secs(3)), 2)
+        );
+        assert_eq!(
+            chain(&[
+                Interception::Pass,
+                Interception::Drop,
+                Interception::Delay(secs(1))
+            ]),
+            (Interception::Drop, 2)
+        );
+    }
+
+    #[test]
+    fn test_delayed() {
+        let mut delayed = Delayed::default();
+        assert_eq!(delayed.next_deadline(), None);
+        delayed.push(Duration::from_secs(60), 1);
+        delayed.push(Duration::ZERO, 2);
+        delayed.push(Duration::ZERO, 3);
+        assert!(delayed.next_deadline().unwrap() <= Instant::now());
+        assert_eq!(delayed.due(Instant::now()), vec![2, 3]);
+        assert!(delayed.due(Instant::now()).is_empty());
+        assert!(delayed.next_deadline().unwrap() > Instant::now());
+    }
+}
@@ -4,31 +4,93 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use crate::kbucket::{BinaryID, BinaryKey};
+use crate::kbucket::{derive_key, BinaryID, BinaryKey};
 use blake2::{Blake2s, Digest};
 use std::convert::TryInto;
 use std::net::{IpAddr, SocketAddr};
 pub type PeerNode = Node<PeerInfo>;
-use crate::encoding::message::Header;
-use crate::encoding::payload::{IpInfo, PeerEncodedInfo};
+use crate::config::Config;
+use crate::encoding::message::{DualAddress, Header, IdentityKey};
+use crate::encoding::payload::{is_valid_fallback, PeerEncodedInfo};
+use crate::identity::public_key;
 
 use crate::kbucket::Node;
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct PeerInfo {
     address: SocketAddr,
+    fallback: Option<SocketAddr>,
+    metadata: Vec<u8>,
+    observer: bool,
+    custom_id: bool,
+    identity: Option<IdentityKey>,
+    id_difficulty: u8,
 }
 
+/// Max length of the metadata attached to a peer
+pub const MAX_PEER_METADATA_LEN: usize = 256;
+
 impl PeerInfo {
     pub fn address(&self) -> &SocketAddr {
         &self.address
     }
+
+    /// Address of the other IP family advertised by a dual-homed peer, used
+    /// when the primary address is unreachable
+    pub fn fallback_address(&self) -> Option<&SocketAddr> {
+        self.fallback.as_ref()
+    }
+
+    /// Whether the peer is in observer mode, receiving the broadcasts
+    /// without relaying them
+    pub fn is_observer(&self) -> bool {
+        self.observer
+    }
+
+    /// Whether the id of the peer is supplied by its application instead
+    /// of being derived from its address
+    pub fn has_custom_id(&self) -> bool {
+        self.custom_id
+    }
+
+    /// Ed25519 public key the id of the peer is derived from, see
+    /// [Config::identity_key](crate::config::Config::identity_key)
+    pub fn identity(&self) -> Option<&IdentityKey> {
+        self.identity.as_ref()
+    }
+
+    /// Amount of zero bits required by the peer to the proof of work of
+    /// the node ids
+    pub fn id_difficulty(&self) -> usize {
+        self.id_difficulty as usize
+    }
+
+    /// Metadata attached by the application, empty if none
+    pub fn metadata(&self) -> &[u8] {
+        &self.metadata
+    }
+
+    pub(crate) fn set_metadata(&mut self, metadata: Vec<u8>) {
+        self.metadata = metadata;
+    }
+
+    pub(crate) fn set_custom_id(&mut self, custom_id: bool) {
+        self.custom_id = custom_id;
+    }
 }
 
 impl PeerNode {
     pub fn generate(address: &str) -> Self {
         let server: SocketAddr =
             address.parse().expect("Unable to parse address");
-        let info = PeerInfo { address: server };
+        let info = PeerInfo {
+            address: server,
+            fallback: None,
+            metadata: vec![],
+            observer: false,
+            custom_id: false,
+            identity: None,
+            id_difficulty: crate::K_DIFF_MIN_BIT as u8,
+        };
         let binary =
             PeerNode::compute_id(&info.address.ip(), info.address.port());
         let id = BinaryID::generate(binary);
@@ -36,13 +98,108 @@ impl PeerNode {
     }
 
     pub fn from_socket(address: SocketAddr, id: BinaryID) -> Self {
-        let info = PeerInfo { address };
+        let info = PeerInfo {
+            address,
+            fallback: None,
+            metadata: vec![],
+            observer: false,
+            custom_id: false,
+            identity: None,
+            id_difficulty: crate::K_DIFF_MIN_BIT as u8,
+        };
         Node::new(id, info)
     }
 
+    /// Create the local node, with the public addresses and the identity
+    /// of the [Config]
+    pub(crate) fn root(config: &Config) -> Self {
+        let address: SocketAddr = config
+            .public_address
+            .parse()
+            .expect("Unable to parse address");
+        let identity = config.identity_key.map(|secret| public_key(&secret));
+        let key = match (&identity, config.node_key) {
+            (Some(identity), _) => derive_key(identity),
+            (None, Some(key)) => key,
+            (None, None) => PeerNode::compute_id(&address.ip(), address.port()),
+        };
+        let difficulty = config.bucket.id_difficulty;
+        let id = BinaryID::generate_with_difficulty(key, difficulty);
+        let mut root = PeerNode::from_socket(address, id);
+        root.value_mut().custom_id =
+            identity.is_some() || config.node_key.is_some();
+        root.value_mut().identity = identity;
+        root.value_mut().id_difficulty = difficulty as u8;
+        root.value_mut().observer = config.observer;
+        match &config.public_fallback_address {
+            Some(fallback) => root.with_fallback(
+                fallback.parse().expect("Unable to parse fallback address"),
+            ),
+            None => root,
+        }
+    }
+
+    /// Create the node which sent `header` from `ip`, which is either its
+    /// primary ip or the fallback one
+    pub(crate) fn from_header(header: &Header, ip: IpAddr) -> Self {
+        let primary = match header.dual {
+            Some(dual) if dual.fallback.ip() == ip => dual.primary,
+            _ => ip,
+        };
+        let mut node = PeerNode::from_socket(
+            SocketAddr::new(primary, header.sender_port),
+            header.binary_id,
+        );
+        node.value_mut().fallback = header.dual.map(|dual| dual.fallback);
+        node.value_mut().observer = header.is_observer();
+        node.value_mut().set_custom_id(header.has_custom_id());
+        node.value_mut().identity = header.identity;
+        node.value_mut().id_difficulty = header.id_difficulty;
+        node
+    }
+
+    /// Advertise a fallback address of the other IP family.
+    ///
+    /// Panics if the address is not of the other family
+    pub fn with_fallback(mut self, fallback: SocketAddr) -> Self {
+        assert!(
+            is_valid_fallback(&self.value().address.ip(), &fallback),
+            "The fallback address must be of the other IP family"
+        );
+        self.value_mut().fallback = Some(fallback);
+        self
+    }
+
     pub(crate) fn verify_header(header: &Header, ip: &IpAddr) -> bool {
-        *header.binary_id.as_binary()
-            == PeerNode::compute_id(ip, header.sender_port)
+        let id = header.binary_id.as_binary();
+        // Key identities are bound to their public key, whose possession is
+        // challenged before inserting the node
+        if let Some(identity) = &header.identity {
+            return *id == derive_key(identity);
+        }
+        // Ids supplied by the application are not bound to any address
+        if header.has_custom_id() {
+            return true;
+        }
+        // Messages of dual-homed nodes may come from their fallback ip, the
+        // id is still bound to the primary one
+        *id == PeerNode::compute_id(ip, header.sender_port)
+            || header.dual.is_some_and(|dual| {
+                dual.fallback.ip() == *ip
+                    && *id
+                        == PeerNode::compute_id(
+                            &dual.primary,
+                            header.sender_port,
+                        )
+            })
+    }
+
+    /// The address to reply to a message with `header` received from `ip`
+    pub(crate) fn reply_address(header: &Header, ip: IpAddr) -> SocketAddr {
+        match header.dual {
+            Some(dual) if dual.fallback.ip() == ip => dual.fallback,
+            _ => SocketAddr::new(ip, header.sender_port),
+        }
     }
 
     pub(crate) fn compute_id(ip: &IpAddr, port: u16) -> BinaryKey {
@@ -63,29 +220,80 @@ impl PeerNode {
     }
 
     pub(crate) fn as_header(&self) -> Header {
-        Header {
+        let header = Header {
             binary_id: *self.id(),
             sender_port: self.value().address.port(),
             reserved: [0; 2],
+            timestamp: None,
+            dual: self.value().fallback.map(|fallback| DualAddress {
+                primary: self.value().address.ip(),
+                fallback,
+            }),
+            identity: self.value().identity,
+            id_difficulty: self.value().id_difficulty,
+            request_nonce: None,
+        };
+        let header = match self.value().observer {
+            true => header.with_observer(),
+            false => header,
+        };
+        match self.value().custom_id {
+            true => header.with_custom_id(),
+            false => header,
         }
     }
 
     pub(crate) fn as_peer_info(&self) -> PeerEncodedInfo {
         PeerEncodedInfo {
             id: *self.id().as_binary(),
-            ip: match self.value().address.ip() {
-                IpAddr::V4(ip) => IpInfo::IPv4(ip.octets()),
-                IpAddr::V6(ip) => IpInfo::IPv6(ip.octets()),
-            },
+            ip: self.value().address.ip().into(),
             port: self.value().address.port(),
+            fallback: self
+                .value()
+                .fallback
+                .map(|fallback| (fallback.ip().into(), fallback.port())),
+            custom_id: self.value().custom_id,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use crate::config::Config;
     use crate::peer::PeerNode;
 
+    #[test]
+    fn test_custom_id() {
+        let config = Config {
+            public_address: "192.168.1.1:666".to_string(),
+            node_key: Some([7; crate::K_ID_LEN_BYTES]),
+            ..Default::default()
+        };
+        let root = PeerNode::root(&config);
+        assert_eq!(root.id().as_binary(), &[7; crate::K_ID_LEN_BYTES]);
+        assert!(root.is_id_valid(crate::K_DIFF_MIN_BIT));
+        assert!(root.value().has_custom_id());
+
+        // The id is accepted from any address, and it's kept once moved
+        let header = root.as_header();
+        assert!(header.has_custom_id());
+        let moved: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(PeerNode::verify_header(&header, &moved));
+        let sender = PeerNode::from_header(&header, moved);
+        assert_eq!(sender.id(), root.id());
+        assert!(sender.value().has_custom_id());
+
+        let derived = PeerNode::root(&Config {
+            node_key: None,
+            ..config
+        });
+        assert!(!derived.value().has_custom_id());
+        assert!(!derived.as_header().has_custom_id());
+        assert_ne!(derived.id(), root.id());
+    }
+
     #[test]
     fn test_verify_header() {
         let wrong_header = PeerNode::generate("10.0.0.1:333").as_header();
@@ -104,4 +312,28 @@ mod tests {
             assert!(!PeerNode::verify_header(&wrong_header_sameport, ip));
         });
     }
+
+    #[test]
+    fn test_verify_dual_header() {
+        let fallback: SocketAddr = "[2001:db8::1]:777".parse().unwrap();
+        let node =
+            PeerNode::generate("192.168.1.1:666").with_fallback(fallback);
+        let header = node.as_header();
+        let primary = node.value().address.ip();
+        assert!(PeerNode::verify_header(&header, &primary));
+        assert!(PeerNode::verify_header(&header, &fallback.ip()));
+        let other: IpAddr = "2001:db8::2".parse().unwrap();
+        assert!(!PeerNode::verify_header(&header, &other));
+
+        // The reply goes to the address the message came from, the node is
+        // stored with its primary address
+        assert_eq!(PeerNode::reply_address(&header, fallback.ip()), fallback);
+        assert_eq!(
+            PeerNode::reply_address(&header, primary),
+            node.value().address
+        );
+        let sender = PeerNode::from_header(&header, fallback.ip());
+        assert_eq!(sender.value(), node.value());
+        assert_eq!(sender.id(), node.id());
+    }
 }
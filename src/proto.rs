@@ -0,0 +1,25 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+//! Messages of the Kadcast wire protocol.
+//!
+//! Advanced users can build their own messages and send them through
+//! [Peer::send_message](crate::Peer::send_message), in order to implement
+//! application level protocols on top of the peer socket.
+//!
+//! New control messages can be prototyped with [Message::Raw], received
+//! through a [MessageMiddleware](crate::MessageMiddleware). With the
+//! `unsafe_proto` feature, [Peer::send_raw](crate::Peer::send_raw) sends
+//! them as they are, without replacing their header.
+
+pub use crate::encoding::message::{
+    ChallengeToken, Header, IdentityKey, IdentitySignature, Message,
+    MessageUid, TransmissionInfo, MAX_RAW_MESSAGE_LEN, MIN_RAW_MESSAGE_TYPE,
+};
+pub use crate::encoding::payload::{
+    BroadcastPayload, IpInfo, NodePayload, PeerEncodedInfo,
+};
+pub use crate::kbucket::BinaryKey;
@@ -0,0 +1,139 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::encoding::payload::Priority;
+
+/// Rank of an outgoing message in a [PriorityQueue]: the control messages
+/// (eg: `Ping`, `Nodes`, acks) always precede the broadcast ones, which are
+/// ordered by their [Priority]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Rank {
+    Broadcast(Priority),
+    Control,
+}
+
+/// FIFO queue ordered by [Priority] (or by any other rank).
+///
+/// Items with higher priority are popped first, items with the same priority
+/// are popped in insertion order
+pub(crate) struct PriorityQueue<T, P = Priority> {
+    heap: BinaryHeap<Entry<T, P>>,
+    seq: u64,
+}
+
+pub(crate) struct Entry<T, P = Priority> {
+    priority: P,
+    seq: Reverse<u64>,
+    pub(crate) item: T,
+}
+
+impl<T, P: Ord> PriorityQueue<T, P> {
+    pub(crate) fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, priority: P, item: T) {
+        self.seq += 1;
+        self.heap.push(Entry {
+            priority,
+            seq: Reverse(self.seq),
+            item,
+        });
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<(P, T)> {
+        self.heap.pop().map(|p| (p.priority, p.item))
+    }
+
+    /// Remove the first entry. The entry can be put back in its original
+    /// position with [PriorityQueue::restore]
+    pub(crate) fn pop_entry(&mut self) -> Option<Entry<T, P>> {
+        self.heap.pop()
+    }
+
+    pub(crate) fn restore(&mut self, entry: Entry<T, P>) {
+        self.heap.push(entry)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.heap.len()
+    }
+}
+
+impl<T, P: Ord> PartialEq for Entry<T, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+impl<T, P: Ord> Eq for Entry<T, P> {}
+
+impl<T, P: Ord> PartialOrd for Entry<T, P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, P: Ord> Ord for Entry<T, P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.priority, self.seq).cmp(&(&other.priority, other.seq))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PriorityQueue, Rank};
+
+    #[test]
+    fn test_priority_order() {
+        let mut queue = PriorityQueue::new();
+        queue.push(0, "bulk_1");
+        queue.push(10, "consensus_1");
+        queue.push(0, "bulk_2");
+        queue.push(10, "consensus_2");
+        queue.push(5, "normal");
+        let order: Vec<_> =
+            std::iter::from_fn(|| queue.pop().map(|(_, i)| i)).collect();
+        assert_eq!(
+            order,
+            vec!["consensus_1", "consensus_2", "normal", "bulk_1", "bulk_2"]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_restore() {
+        let mut queue = PriorityQueue::new();
+        queue.push(0, "first");
+        queue.push(0, "second");
+        let entry = queue.pop_entry().unwrap();
+        assert_eq!(entry.item, "first");
+        queue.restore(entry);
+        assert_eq!(queue.pop(), Some((0, "first")));
+    }
+
+    #[test]
+    fn test_control_rank() {
+        let mut queue = PriorityQueue::new();
+        queue.push(Rank::Broadcast(u8::MAX), "broadcast");
+        queue.push(Rank::Control, "ping");
+        queue.push(Rank::Broadcast(0), "bulk");
+        assert_eq!(queue.len(), 3);
+        let order: Vec<_> =
+            std::iter::from_fn(|| queue.pop().map(|(_, i)| i)).collect();
+        assert_eq!(order, vec!["ping", "broadcast", "bulk"]);
+    }
+}
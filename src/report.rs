@@ -0,0 +1,197 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde_derive::Serialize;
+
+use crate::channel::ChannelOverflows;
+use crate::kbucket::{to_hex, BucketOccupancy, RouteTable, Score};
+use crate::Health;
+
+/// State of the network as seen by a [Peer](crate::Peer): the buckets of
+/// the routing table with their peers, and the state of the transport.
+///
+/// See [Peer::report](crate::Peer::report). Binary fields are hex encoded
+/// once serialized
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NetworkReport {
+    buckets: Vec<BucketReport>,
+    transport: TransportReport,
+}
+
+/// Bucket of a [NetworkReport]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BucketReport {
+    height: usize,
+    pending: usize,
+    peers: Vec<PeerReport>,
+}
+
+/// Peer of a [BucketReport]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PeerReport {
+    address: SocketAddr,
+    id: String,
+    last_seen_millis: u64,
+    pending_eviction: bool,
+    score: Score,
+    rtt_millis: Option<u64>,
+}
+
+/// Transport state of a [NetworkReport]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransportReport {
+    tasks_running: bool,
+    inbound_queue: usize,
+    outbound_queue: usize,
+    decoder_cache: usize,
+    channel_overflows: ChannelOverflows,
+}
+
+impl NetworkReport {
+    pub(crate) fn new(
+        table: RouteTable,
+        occupancy: Vec<BucketOccupancy>,
+        health: Health,
+    ) -> Self {
+        let pending = |height| {
+            occupancy
+                .iter()
+                .find(|bucket| bucket.height() == height)
+                .map_or(0, |bucket| bucket.pending())
+        };
+        let buckets = table
+            .buckets()
+            .iter()
+            .map(|bucket| BucketReport {
+                height: bucket.height(),
+                pending: pending(bucket.height()),
+                peers: bucket
+                    .peers()
+                    .iter()
+                    .map(|peer| PeerReport {
+                        address: *peer.address(),
+                        id: to_hex(peer.id()),
+                        last_seen_millis: millis(peer.last_seen().elapsed()),
+                        pending_eviction: peer.pending_eviction(),
+                        score: peer.score(),
+                        rtt_millis: peer.rtt().map(millis),
+                    })
+                    .collect(),
+            })
+            .collect();
+        let transport = TransportReport {
+            tasks_running: health.tasks_running,
+            inbound_queue: health.inbound_queue,
+            outbound_queue: health.outbound_queue,
+            decoder_cache: health.decoder_cache,
+            channel_overflows: health.channel_overflows,
+        };
+        NetworkReport { buckets, transport }
+    }
+
+    /// The non empty buckets, sorted by height
+    pub fn buckets(&self) -> &[BucketReport] {
+        &self.buckets
+    }
+
+    /// All the peers of the routing table
+    pub fn peers(&self) -> impl Iterator<Item = &PeerReport> {
+        self.buckets.iter().flat_map(|bucket| bucket.peers.iter())
+    }
+
+    pub fn transport(&self) -> &TransportReport {
+        &self.transport
+    }
+
+    /// Serialize the report as JSON, eg: for support bundles
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Unable to serialize the report")
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+impl BucketReport {
+    /// Height of the bucket, which is the XOR distance from the local peer
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Amount of peers waiting for a free slot of the bucket
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// The peers of the bucket, the least recently seen first
+    pub fn peers(&self) -> &[PeerReport] {
+        &self.peers
+    }
+}
+
+impl PeerReport {
+    pub fn address(&self) -> &SocketAddr {
+        &self.address
+    }
+
+    /// Hex encoded id of the peer
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Time elapsed since a message has been received from the peer, when
+    /// the report has been taken
+    pub fn last_seen(&self) -> Duration {
+        Duration::from_millis(self.last_seen_millis)
+    }
+
+    /// The peer has been pinged in order to be evicted in favour of a new
+    /// one, and it didn't reply yet
+    pub fn pending_eviction(&self) -> bool {
+        self.pending_eviction
+    }
+
+    /// Reputation of the peer
+    pub fn score(&self) -> Score {
+        self.score
+    }
+
+    /// Smoothed round trip time, measured when the peer answers a ping
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt_millis.map(Duration::from_millis)
+    }
+}
+
+impl TransportReport {
+    /// Every task of the peer is running, ie: none of them has failed
+    pub fn tasks_running(&self) -> bool {
+        self.tasks_running
+    }
+
+    /// Received messages waiting to be handled
+    pub fn inbound_queue(&self) -> usize {
+        self.inbound_queue
+    }
+
+    /// Messages waiting to be encoded and sent
+    pub fn outbound_queue(&self) -> usize {
+        self.outbound_queue
+    }
+
+    /// Broadcast messages being decoded, or recently decoded
+    pub fn decoder_cache(&self) -> usize {
+        self.decoder_cache
+    }
+
+    /// Messages dropped by the internal channels because they were full
+    pub fn channel_overflows(&self) -> ChannelOverflows {
+        self.channel_overflows
+    }
+}
@@ -0,0 +1,200 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tokio::time;
+use tracing::{debug, error};
+
+use crate::channel::Sender;
+use crate::encoding::message::{Header, Message};
+use crate::transport::MessageBeanOut;
+
+/// Identifier correlating a `Request` with its `Response`
+pub type RequestId = u64;
+
+/// Max length of the body of a request or a response, which must fit a
+/// single datagram
+pub const MAX_REQUEST_LEN: usize = 60_000;
+
+/// Max amount of requests handled at the same time. The requests received
+/// while every handler is busy are dropped
+pub(crate) const MAX_CONCURRENT_REQUESTS: usize = 64;
+
+/// Handler answering the requests sent with
+/// [Peer::request](crate::Peer::request)
+pub trait RequestHandler: Send + Sync {
+    /// The response to a request sent by the peer at `src`. `None` leaves
+    /// the request unanswered, the requester eventually times out.
+    ///
+    /// A request is delivered again when the requester retries, so handlers
+    /// should be idempotent. It's run on a blocking thread, and only for the
+    /// peers of the routing table
+    fn on_request(&self, request: Vec<u8>, src: SocketAddr) -> Option<Vec<u8>>;
+}
+
+impl fmt::Debug for dyn RequestHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RequestHandler")
+    }
+}
+
+/// Failure of a request sent with [Peer::request](crate::Peer::request)
+#[derive(Debug, PartialEq, Eq)]
+pub enum RequestError {
+    /// The request is longer than [MAX_REQUEST_LEN]
+    TooLong(usize),
+    /// No response received within the configured timeout and retries
+    Timeout,
+    /// The peer is shutting down
+    Closed,
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::TooLong(len) => {
+                write!(f, "Request too long: {}", len)
+            }
+            RequestError::Timeout => write!(f, "Request timed out"),
+            RequestError::Closed => write!(f, "Peer closed"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+// Target and response channel of the requests waiting for a response
+type Waiting = HashMap<RequestId, (SocketAddr, oneshot::Sender<Vec<u8>>)>;
+
+/// Requests waiting for a response, indexed by their id
+#[derive(Clone)]
+pub(crate) struct PendingRequests {
+    next_id: Arc<AtomicU64>,
+    pending: Arc<Mutex<Waiting>>,
+}
+
+impl Default for PendingRequests {
+    fn default() -> Self {
+        // A random starting id keeps the ids of a restarted peer from
+        // matching the responses to its previous requests
+        Self {
+            next_id: Arc::new(AtomicU64::new(rand::random())),
+            pending: Default::default(),
+        }
+    }
+}
+
+impl PendingRequests {
+    fn register(
+        &self,
+        target: SocketAddr,
+    ) -> (RequestId, oneshot::Receiver<Vec<u8>>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.lock().insert(id, (target, tx));
+        (id, rx)
+    }
+
+    fn cancel(&self, id: RequestId) {
+        self.lock().remove(&id);
+    }
+
+    /// Deliver a response received from one of the `sources` addresses.
+    ///
+    /// Returns `false` if no request with that id was sent to the sources
+    pub(crate) fn resolve(
+        &self,
+        id: RequestId,
+        sources: &[SocketAddr],
+        response: Vec<u8>,
+    ) -> bool {
+        let mut pending = self.lock();
+        match pending.get(&id) {
+            Some((target, _)) if sources.contains(target) => {
+                let (_, tx) = pending.remove(&id).expect("Request pending");
+                tx.send(response).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Waiting> {
+        self.pending.lock().expect("Requests lock poisoned")
+    }
+}
+
+// Forget a request once its future completes or is dropped
+struct Registration<'a>(&'a PendingRequests, RequestId);
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.0.cancel(self.1);
+    }
+}
+
+/// Send a request to `target`, sending it again every `timeout` up to
+/// `retries` times until a response is received
+pub(crate) async fn request(
+    header: Header,
+    target: SocketAddr,
+    body: &[u8],
+    timeout: Duration,
+    retries: usize,
+    requests: &PendingRequests,
+    outbound_sender: &Sender<MessageBeanOut>,
+) -> Result<Vec<u8>, RequestError> {
+    if body.len() > MAX_REQUEST_LEN {
+        return Err(RequestError::TooLong(body.len()));
+    }
+    let (id, mut rx) = requests.register(target);
+    let _registration = Registration(requests, id);
+    for attempt in 0..=retries {
+        debug!("Sending request {} to {} (#{})", id, target, attempt);
+        let message = Message::Request(header, id, body.to_vec());
+        if let Err(e) =
+            outbound_sender.send((message, vec![target], None)).await
+        {
+            error!("Unable to send request {}", e);
+            return Err(RequestError::Closed);
+        }
+        match time::timeout(timeout, &mut rx).await {
+            Ok(Ok(response)) => return Ok(response),
+            Ok(Err(_)) => return Err(RequestError::Closed),
+            Err(_) => continue,
+        }
+    }
+    Err(RequestError::Timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PendingRequests;
+
+    #[test]
+    fn test_pending_requests() {
+        let requests = PendingRequests::default();
+        let target = "10.0.0.1:666".parse().unwrap();
+        let other = "10.0.0.2:666".parse().unwrap();
+        let (id, mut rx) = requests.register(target);
+        let (cancelled, _) = requests.register(target);
+        assert_ne!(id, cancelled);
+        requests.cancel(cancelled);
+
+        // Responses are accepted from the target only, and once
+        assert!(!requests.resolve(id, &[other], vec![1]));
+        assert!(!requests.resolve(cancelled, &[target], vec![1]));
+        assert!(requests.resolve(id, &[other, target], vec![2]));
+        assert!(!requests.resolve(id, &[target], vec![3]));
+        assert_eq!(rx.try_recv().unwrap(), vec![2]);
+    }
+}
@@ -38,6 +38,11 @@ impl<T> DiagnosticRwLock<T> {
         }
     }
 
+    /// Acquire the read lock only if it's immediately available
+    pub(crate) fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.arc_lock.try_read().ok()
+    }
+
     pub(crate) async fn write(&self) -> RwLockWriteGuard<'_, T> {
         loop {
             match timeout(self.timeout, self.arc_lock.write()).await {
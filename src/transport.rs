@@ -5,81 +5,308 @@
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
+use bytes::{BufMut, Bytes, BytesMut};
 use socket2::SockRef;
 use tokio::{
     io,
     net::UdpSocket,
-    sync::mpsc::{self, Receiver, Sender},
+    sync::watch,
+    task::JoinHandle,
     time::{self},
 };
 use tracing::*;
 
-use crate::config::Config;
+use crate::channel::{self, Receiver, Sender};
+use crate::codec::BroadcastEncoder;
+use crate::config::{Config, ConfigError, FECConfig, RateLimitConfig};
+use crate::event::{self, DropReason, EventSender, Flood, KadcastEvent};
+use crate::health::HealthProbe;
+use crate::middleware::{self, Delayed, Interception, MessageMiddleware};
 use crate::{
-    encoding::{message::Message, Marshallable},
+    encoding::{
+        message::{BroadcastPayload, Header, Message},
+        DecodeError, Marshallable,
+    },
+    kbucket::{
+        PeerFilter, PeerTarget, INVALID_MESSAGE_PENALTY,
+        MALFORMED_MESSAGE_PENALTY, RATE_LIMIT_PENALTY,
+    },
     peer::PeerNode,
+    queue::{PriorityQueue, Rank},
     transport::{
+        ack::BroadcastAcks,
+        cipher::GossipCipher,
+        delivery::DeliveryTracker,
+        echo::ReplyNonces,
         encoding::{
-            Configurable, Decoder, Encoder, TransportDecoder, TransportEncoder,
+            Configurable, DecodeProgress, Decoder, Encoder, Reception,
+            TransportDecoder, TransportEncoder,
         },
+        flood::{FloodGuard, Guard},
+        mac::{ControlMac, MAC_LEN},
+        noise::{Inbound, NoiseSessions},
+        padding::TrafficPadding,
+        ratelimit::{Admission, InboundRateLimiter},
         sockets::MultipleOutSocket,
     },
 };
-pub(crate) type MessageBeanOut = (Message, Vec<SocketAddr>);
-pub(crate) type MessageBeanIn = (Message, SocketAddr);
+pub(crate) type MessageBeanOut =
+    (Message, Vec<SocketAddr>, Option<DeliveryTracker>);
+pub(crate) type MessageBeanIn = (Message, SocketAddr, Option<Reception>);
 type UDPChunk = (Vec<u8>, SocketAddr);
 
 const MAX_DATAGRAM_SIZE: usize = 65_507;
 pub(crate) struct WireNetwork {}
 
+// Answers awaited from the peers, recorded by the task sending the messages
+// and checked by the one decoding them
+#[derive(Clone)]
+struct Awaited {
+    acks: Option<Arc<BroadcastAcks>>,
+    nonces: Arc<ReplyNonces>,
+}
+
+/// Channels between the network tasks and the rest of the peer
+pub(crate) struct WireChannels {
+    /// Decoded incoming messages
+    pub(crate) inbound_tx: Sender<MessageBeanIn>,
+    /// Outgoing messages
+    pub(crate) outbound_rx: Receiver<MessageBeanOut>,
+    /// Outgoing messages sent by the network tasks themselves (eg: acks)
+    pub(crate) outbound_tx: Sender<MessageBeanOut>,
+    /// Progress of the broadcast messages being decoded
+    pub(crate) progress_tx: Sender<DecodeProgress>,
+    pub(crate) events: EventSender,
+    /// Rate limits changed with `Peer::reconfigure`
+    pub(crate) rate_limit: watch::Receiver<Option<RateLimitConfig>>,
+}
+
+// Encryption state shared by the task sending the messages and the one
+// decoding them
+#[derive(Clone)]
+struct Encryption {
+    noise: Option<Arc<Mutex<NoiseSessions>>>,
+    cipher: Option<Arc<GossipCipher>>,
+}
+
+// Channels and state shared by the decoding task with the other tasks
+struct DecodeContext {
+    inbound_channel_tx: Sender<MessageBeanIn>,
+    outbound_channel_tx: Sender<MessageBeanOut>,
+    progress_channel_tx: Sender<DecodeProgress>,
+    event_tx: EventSender,
+    filter: PeerFilter,
+    awaited: Awaited,
+    guard: Option<Arc<FloodGuard>>,
+    encryption: Encryption,
+    probe: Arc<HealthProbe>,
+    rate_limit: watch::Receiver<Option<RateLimitConfig>>,
+}
+
+// Encoded message waiting to be sent to its targets
+struct PendingSend {
+    chunks: Vec<Bytes>,
+    targets: Vec<SocketAddr>,
+    sent: usize,
+    tracker: Option<DeliveryTracker>,
+}
+
+impl PendingSend {
+    fn new(
+        chunks: Vec<Bytes>,
+        targets: Vec<SocketAddr>,
+        tracker: Option<DeliveryTracker>,
+    ) -> Self {
+        Self {
+            chunks,
+            targets,
+            sent: 0,
+            tracker,
+        }
+    }
+
+    // Return the next chunk to send, alongside its destination
+    fn next(&mut self) -> Option<(&[u8], SocketAddr)> {
+        let chunks = self.chunks.len();
+        if chunks == 0 {
+            return None;
+        }
+        let target = self.targets.get(self.sent / chunks)?;
+        let chunk = &self.chunks[self.sent % chunks];
+        self.sent += 1;
+        Some((chunk, *target))
+    }
+}
+
+pub(crate) mod ack;
+pub(crate) mod cipher;
+pub(crate) mod delivery;
+pub(crate) mod echo;
 pub(crate) mod encoding;
+pub(crate) mod flood;
+pub(crate) mod mac;
+pub(crate) mod noise;
+pub(crate) mod padding;
+pub(crate) mod ratelimit;
 pub(crate) mod sockets;
 
 impl WireNetwork {
     pub fn start(
-        inbound_channel_tx: Sender<MessageBeanIn>,
-        outbound_channel_rx: Receiver<MessageBeanOut>,
+        channels: WireChannels,
+        filter: PeerFilter,
+        sockets: Vec<std::net::UdpSocket>,
+        fec: watch::Receiver<FECConfig>,
+        probe: Arc<HealthProbe>,
+        my_header: Header,
         conf: Config,
-    ) {
+    ) -> (JoinHandle<()>, Vec<JoinHandle<()>>) {
+        let WireChannels {
+            inbound_tx: inbound_channel_tx,
+            outbound_rx: outbound_channel_rx,
+            outbound_tx: outbound_channel_tx,
+            progress_tx: progress_channel_tx,
+            events: event_tx,
+            rate_limit,
+        } = channels;
         let c = conf.clone();
-        let (dec_chan_tx, dec_chan_rx) = mpsc::channel(conf.channel_size);
-
-        tokio::spawn(async move {
-            WireNetwork::listen_out(outbound_channel_rx, &conf)
-                .await
-                .unwrap_or_else(|op| error!("Error in listen_out {:?}", op));
+        let (dec_chan_tx, dec_chan_rx) = channel::channel(
+            conf.channel_size,
+            conf.channels.datagrams,
+            probe.overflows.datagrams.clone(),
+        );
+        let acks = conf.broadcast_ack.map(|c| Arc::new(BroadcastAcks::new(c)));
+        let awaited = Awaited {
+            acks,
+            nonces: Arc::new(ReplyNonces::new(conf.require_nonce_echo)),
+        };
+        let guard = conf
+            .flood_guard
+            .map(|c| Arc::new(FloodGuard::new(c, filter.trusted.clone())));
+        // Handshake responses are sent to the advertised port
+        let noise = conf.noise.as_ref().map(|noise| {
+            let port = conf
+                .public_address
+                .parse::<SocketAddr>()
+                .map_or(0, |address| address.port());
+            Arc::new(Mutex::new(NoiseSessions::new(noise, port)))
         });
+        let encryption = Encryption {
+            noise,
+            cipher: conf
+                .gossip_key
+                .as_ref()
+                .map(|key| Arc::new(GossipCipher::new(key))),
+        };
 
+        let out_filter = filter.clone();
+        let out_encryption = encryption.clone();
+        let out_awaited = awaited.clone();
+        let out_fec = fec.clone();
+        let out_events = event_tx.clone();
         let c1 = c.clone();
-        tokio::spawn(async move {
-            WireNetwork::decode(inbound_channel_tx.clone(), dec_chan_rx, c)
+        let sender = c1.spawn(async move {
+            WireNetwork::listen_out(
+                outbound_channel_rx,
+                out_filter,
+                out_events,
+                out_awaited,
+                out_encryption,
+                out_fec,
+                &conf,
+            )
+            .await
+            .unwrap_or_else(|op| error!("Error in listen_out {:?}", op));
+        });
+
+        let in_guard = guard.clone();
+        let in_events = event_tx.clone();
+        let mut tasks = vec![c1.spawn(async move {
+            let context = DecodeContext {
+                inbound_channel_tx,
+                outbound_channel_tx,
+                progress_channel_tx,
+                event_tx,
+                filter,
+                awaited,
+                guard,
+                encryption,
+                probe,
+                rate_limit,
+            };
+            WireNetwork::decode(context, dec_chan_rx, fec, my_header, c)
                 .await
                 .unwrap_or_else(|op| error!("Error in decode {:?}", op));
-        });
+        })];
 
-        tokio::spawn(async move {
-            WireNetwork::listen_in(dec_chan_tx.clone(), c1)
+        for socket in sockets {
+            let dec_chan_tx = dec_chan_tx.clone();
+            let guard = in_guard.clone();
+            let event_tx = in_events.clone();
+            let conf = c1.clone();
+            tasks.push(c1.spawn(async move {
+                WireNetwork::listen_in(
+                    dec_chan_tx,
+                    socket,
+                    guard,
+                    event_tx,
+                    conf,
+                )
                 .await
                 .unwrap_or_else(|op| error!("Error in listen_in {:?}", op));
-        });
+            }));
+        }
+        (sender, tasks)
+    }
+
+    /// Bind the listening addresses, before starting the network.
+    ///
+    /// The public and fallback addresses bound to an ephemeral port (0) are
+    /// replaced with the actual ones, so that the peer advertises them
+    pub(crate) fn bind(
+        conf: &mut Config,
+    ) -> Result<Vec<std::net::UdpSocket>, ConfigError> {
+        let bind = |address: &str| {
+            let address: SocketAddr = address.parse().map_err(|_| {
+                ConfigError::InvalidAddress(address.to_string())
+            })?;
+            std::net::UdpSocket::bind(address)
+                .and_then(|socket| {
+                    socket.set_nonblocking(true)?;
+                    let port = socket.local_addr()?.port();
+                    Ok((SocketAddr::new(address.ip(), port), socket))
+                })
+                .map_err(|e| ConfigError::Bind(address, e))
+        };
+        // A custom listen address is expected to cover both IP families,
+        // and to be bound independently of the advertised ones
+        if let Some(listen) = &conf.listen_address {
+            return Ok(vec![bind(listen)?.1]);
+        }
+        let (bound, socket) = bind(&conf.public_address)?;
+        conf.public_address = bound.to_string();
+        let mut sockets = vec![socket];
+        // Dual-homed peers are reachable through the fallback address too
+        if let Some(fallback) = &conf.public_fallback_address {
+            let (bound, socket) = bind(fallback)?;
+            conf.public_fallback_address = Some(bound.to_string());
+            sockets.push(socket);
+        }
+        Ok(sockets)
     }
 
     async fn listen_in(
         dec_chan_tx: Sender<UDPChunk>,
+        socket: std::net::UdpSocket,
+        guard: Option<Arc<FloodGuard>>,
+        event_tx: EventSender,
         conf: Config,
     ) -> io::Result<()> {
         debug!("WireNetwork::listen_in started");
 
-        let socket = {
-            let listen_address = conf
-                .listen_address
-                .clone()
-                .unwrap_or_else(|| conf.public_address.clone());
-            UdpSocket::bind(listen_address)
-                .await
-                .expect("Unable to bind address")
-        };
+        let socket = UdpSocket::from_std(socket)?;
         info!("Listening on: {}", socket.local_addr()?);
 
         // Try to extend socket recv buffer size
@@ -94,6 +321,17 @@ impl WireNetwork {
                     error!("Error receiving from socket {}", e);
                     e
                 })?;
+            // Floods are discarded before paying for their decoding
+            if let Some(guard) = &guard {
+                match guard.datagram(remote_address.ip(), Instant::now()) {
+                    Guard::Admitted => {}
+                    Guard::Ignored => continue,
+                    Guard::Crossed(flood) => {
+                        WireNetwork::ignored(&event_tx, remote_address, flood);
+                        continue;
+                    }
+                }
+            }
 
             dec_chan_tx
                 .send((bytes[0..len].to_vec(), remote_address))
@@ -105,32 +343,467 @@ impl WireNetwork {
     }
 
     async fn decode(
-        inbound_channel_tx: Sender<MessageBeanIn>,
+        context: DecodeContext,
         mut dec_chan_rx: Receiver<UDPChunk>,
+        mut fec: watch::Receiver<FECConfig>,
+        my_header: Header,
         conf: Config,
     ) -> io::Result<()> {
         debug!("WireNetwork::decode started");
-        let mut decoder = TransportDecoder::configure(&conf.fec.decoder);
+        let DecodeContext {
+            inbound_channel_tx,
+            outbound_channel_tx,
+            progress_channel_tx,
+            event_tx,
+            filter,
+            awaited,
+            guard,
+            encryption,
+            probe,
+            mut rate_limit,
+        } = context;
+        let Awaited { acks, nonces } = awaited;
+        let Encryption { noise, cipher } = encryption;
+        let mut decoder =
+            TransportDecoder::configure(&fec.borrow_and_update().decoder);
+        let require_signed = conf.require_signed_broadcast;
+        let require_identity = conf.require_identity;
+        let replay_window = conf.replay_window;
+        let clock_skew = conf.clock_skew;
+        let mac = conf.network_secret.as_ref().map(ControlMac::new);
+        // Authentication tag following the control messages
+        let trailer = if mac.is_some() { MAC_LEN } else { 0 };
+        let padding = conf.padding.as_ref().map(TrafficPadding::new);
+        let mut limiter =
+            rate_limit.borrow_and_update().map(InboundRateLimiter::new);
+        let custom_decoder = conf.broadcast_decoder.clone();
+        // Handshake messages are answered straight from this task
+        let mut noise_sockets = noise
+            .as_ref()
+            .map(|_| MultipleOutSocket::configure(&conf.network));
 
         loop {
-            if let Some((message, remote_address)) = dec_chan_rx.recv().await {
-                match Message::unmarshal_binary(&mut &message[..]) {
+            if let Some((datagram, remote_address)) = dec_chan_rx.recv().await {
+                if fec.has_changed().unwrap_or(false) {
+                    decoder.reconfigure(&fec.borrow_and_update().decoder);
+                }
+                if rate_limit.has_changed().unwrap_or(false) {
+                    if let Some(conf) = *rate_limit.borrow_and_update() {
+                        match &mut limiter {
+                            Some(limiter) => limiter.reconfigure(conf),
+                            None => {
+                                limiter = Some(InboundRateLimiter::new(conf))
+                            }
+                        }
+                    }
+                }
+                if filter.bans.is_banned(&PeerTarget::Ip(remote_address.ip())) {
+                    trace!(
+                        "Discarding datagram from banned {}",
+                        remote_address
+                    );
+                    WireNetwork::dropped(
+                        &event_tx,
+                        remote_address,
+                        DropReason::Banned,
+                    );
+                    continue;
+                }
+                // Datagrams are decrypted before anything else, and the
+                // ones not belonging to a session are discarded
+                let datagram = match (&noise, &mut noise_sockets) {
+                    (Some(noise), Some(sockets)) => {
+                        let inbound = noise.lock().expect("Noise lock").open(
+                            &datagram,
+                            remote_address,
+                            Instant::now(),
+                        );
+                        match inbound {
+                            Inbound::Data(datagram) => datagram,
+                            Inbound::Handshake(replies) => {
+                                for (reply, target) in replies {
+                                    if let Err(e) =
+                                        sockets.send(&reply, &target).await
+                                    {
+                                        warn!(
+                                            "Unable to answer handshake of {} - {}",
+                                            target, e
+                                        );
+                                    }
+                                }
+                                continue;
+                            }
+                            Inbound::Invalid => {
+                                trace!(
+                                    "Discarding datagram without session from {}",
+                                    remote_address
+                                );
+                                WireNetwork::dropped(
+                                    &event_tx,
+                                    remote_address,
+                                    DropReason::NoSession,
+                                );
+                                continue;
+                            }
+                        }
+                    }
+                    _ => datagram,
+                };
+                let message = match &padding {
+                    Some(padding) => match padding.unpad(&datagram) {
+                        Some(message) => message,
+                        None => {
+                            warn!("Invalid padding from {}", remote_address);
+                            filter.reports.report(
+                                remote_address.ip(),
+                                MALFORMED_MESSAGE_PENALTY,
+                            );
+                            event::emit(
+                                &event_tx,
+                                KadcastEvent::DecodeFailed(remote_address),
+                            );
+                            WireNetwork::offence(
+                                &guard,
+                                &event_tx,
+                                remote_address,
+                                Flood::Malformed,
+                            );
+                            continue;
+                        }
+                    },
+                    None => &datagram[..],
+                };
+                let mut reader = message;
+                let decoded = Message::check_len(message, trailer)
+                    .and_then(|_| Message::unmarshal_binary(&mut reader));
+                match decoded {
                     Ok(deser) => {
                         debug!("> Received raw message {}", deser.type_byte());
-                        let to_process = decoder.decode(deser);
+                        let id = *deser.header().binary_id.as_binary();
+                        if filter.bans.is_banned(&PeerTarget::Id(id)) {
+                            trace!(
+                                "Discarding message from banned {}",
+                                remote_address
+                            );
+                            WireNetwork::dropped(
+                                &event_tx,
+                                remote_address,
+                                DropReason::Banned,
+                            );
+                            continue;
+                        }
+                        if !filter
+                            .allowlist
+                            .is_allowed_peer(&remote_address.ip(), &id)
+                        {
+                            trace!(
+                                "Discarding message from not allowed {}",
+                                remote_address
+                            );
+                            WireNetwork::dropped(
+                                &event_tx,
+                                remote_address,
+                                DropReason::NotAllowed,
+                            );
+                            continue;
+                        }
+                        let limiter = limiter.as_mut().filter(|_| {
+                            !filter
+                                .trusted
+                                .is_trusted_peer(&remote_address.ip(), &id)
+                        });
+                        if let Some(limiter) = limiter {
+                            let uid = match (&deser, &custom_decoder) {
+                                (Message::Broadcast(_, payload), Some(d)) => {
+                                    d.message_uid(&payload.gossip_frame)
+                                }
+                                (Message::Broadcast(_, payload), None) => {
+                                    TransportDecoder::chunk_uid(payload)
+                                }
+                                _ => None,
+                            };
+                            match limiter.admit(
+                                remote_address.ip(),
+                                uid,
+                                Instant::now(),
+                            ) {
+                                Admission::Admitted => {}
+                                admission => {
+                                    if admission == Admission::Exceeded {
+                                        warn!(
+                                            "Rate limit exceeded by {}",
+                                            remote_address
+                                        );
+                                        filter.reports.report(
+                                            remote_address.ip(),
+                                            RATE_LIMIT_PENALTY,
+                                        );
+                                    }
+                                    WireNetwork::dropped(
+                                        &event_tx,
+                                        remote_address,
+                                        DropReason::RateLimited,
+                                    );
+                                    continue;
+                                }
+                            }
+                        }
+                        if let Message::Unknown(_, message_type) = deser {
+                            debug!(
+                                "Skipping unknown message {} from {}",
+                                message_type, remote_address
+                            );
+                            event::emit(
+                                &event_tx,
+                                KadcastEvent::UnknownMessage(
+                                    message_type,
+                                    remote_address,
+                                ),
+                            );
+                            continue;
+                        }
+                        let is_broadcast =
+                            matches!(deser, Message::Broadcast(..));
+                        // The unread bytes of a control message are its
+                        // authentication trailer
+                        if let Some(mac) =
+                            mac.as_ref().filter(|_| !is_broadcast)
+                        {
+                            let len = message.len() - reader.len();
+                            if !mac.verify(&message[..len], reader) {
+                                warn!(
+                                    "Discarding unauthenticated message {} from {}",
+                                    deser.type_byte(),
+                                    remote_address
+                                );
+                                filter.reports.report(
+                                    remote_address.ip(),
+                                    INVALID_MESSAGE_PENALTY,
+                                );
+                                WireNetwork::dropped(
+                                    &event_tx,
+                                    remote_address,
+                                    DropReason::Unauthenticated,
+                                );
+                                WireNetwork::offence(
+                                    &guard,
+                                    &event_tx,
+                                    remote_address,
+                                    Flood::InvalidHeaders,
+                                );
+                                continue;
+                            }
+                        }
+                        // Broadcast messages are excluded because their chunks
+                        // are deduplicated by the decoder
+                        if !is_broadcast
+                            && replay_window.is_some_and(|window| {
+                                !deser.header().is_fresh(
+                                    Header::now(),
+                                    window,
+                                    clock_skew,
+                                )
+                            })
+                        {
+                            warn!(
+                                "Discarding stale message {} from {}",
+                                deser.type_byte(),
+                                remote_address
+                            );
+                            WireNetwork::dropped(
+                                &event_tx,
+                                remote_address,
+                                DropReason::Stale,
+                            );
+                            continue;
+                        }
+                        // Transmission info exchange is handled here, since
+                        // the decoder owns the related state
+                        let deser = match deser {
+                            Message::TransmissionInfoRequest(header, uid) => {
+                                if !PeerNode::verify_header(
+                                    &header,
+                                    &remote_address.ip(),
+                                ) {
+                                    continue;
+                                }
+                                if let Some(info) =
+                                    decoder.transmission_info(&uid)
+                                {
+                                    let response =
+                                        Message::TransmissionInfoResponse(
+                                            my_header, uid, info,
+                                        );
+                                    let target = PeerNode::reply_address(
+                                        &header,
+                                        remote_address.ip(),
+                                    );
+                                    WireNetwork::send_control(
+                                        &outbound_channel_tx,
+                                        response,
+                                        target,
+                                    );
+                                }
+                                continue;
+                            }
+                            Message::TransmissionInfoResponse(
+                                header,
+                                uid,
+                                info,
+                            ) => {
+                                if PeerNode::verify_header(
+                                    &header,
+                                    &remote_address.ip(),
+                                ) && !decoder
+                                    .provide_transmission_info(uid, info)
+                                {
+                                    debug!(
+                                        "Ignoring transmission info from {}",
+                                        remote_address
+                                    );
+                                }
+                                continue;
+                            }
+                            Message::Ack(header, uid) => {
+                                if !PeerNode::verify_header(
+                                    &header,
+                                    &remote_address.ip(),
+                                ) {
+                                    continue;
+                                }
+                                // Delegates are known by their reply address
+                                let from = [
+                                    remote_address,
+                                    PeerNode::reply_address(
+                                        &header,
+                                        remote_address.ip(),
+                                    ),
+                                ];
+                                if !acks
+                                    .as_ref()
+                                    .is_some_and(|acks| acks.ack(uid, &from))
+                                {
+                                    debug!(
+                                        "Ignoring unexpected ack from {}",
+                                        remote_address
+                                    );
+                                }
+                                continue;
+                            }
+                            deser => deser,
+                        };
+                        let sender = PeerNode::reply_address(
+                            deser.header(),
+                            remote_address.ip(),
+                        );
+                        let to_process = match (&custom_decoder, deser) {
+                            (
+                                Some(custom),
+                                Message::Broadcast(header, payload),
+                            ) => custom
+                                .decode(
+                                    &payload.gossip_frame,
+                                    remote_address.ip(),
+                                )
+                                .map(|gossip_frame| {
+                                    Message::Broadcast(
+                                        header,
+                                        BroadcastPayload {
+                                            gossip_frame,
+                                            ..payload
+                                        },
+                                    )
+                                }),
+                            (_, deser) => {
+                                decoder.decode(deser, remote_address.ip())
+                            }
+                        }
+                        .and_then(|message| match &cipher {
+                            Some(cipher) => cipher.decrypt(message),
+                            None => Some(message),
+                        });
+                        let reception = decoder.take_reception();
+                        probe.set_decoder_cache(decoder.cache_size());
+                        // Progress notifications are not critical, they are
+                        // dropped if the listener can't keep up
+                        for progress in decoder.drain_progress() {
+                            let _ = progress_channel_tx.try_send(progress);
+                        }
+                        for event in decoder.drain_events() {
+                            event::emit(&event_tx, event);
+                        }
+                        for uid in decoder.drain_info_requests() {
+                            let request = Message::TransmissionInfoRequest(
+                                my_header, uid,
+                            );
+                            WireNetwork::send_control(
+                                &outbound_channel_tx,
+                                request,
+                                sender,
+                            );
+                        }
+                        for uid in decoder.drain_acks() {
+                            WireNetwork::send_control(
+                                &outbound_channel_tx,
+                                Message::Ack(my_header, uid),
+                                sender,
+                            );
+                        }
                         if let Some(message) = to_process {
+                            if !WireNetwork::valid_origin(
+                                &message,
+                                require_signed,
+                                require_identity,
+                            ) {
+                                error!(
+                                    "Invalid broadcast signature from {}",
+                                    remote_address
+                                );
+                                filter.reports.report(
+                                    remote_address.ip(),
+                                    INVALID_MESSAGE_PENALTY,
+                                );
+                                WireNetwork::dropped(
+                                    &event_tx,
+                                    remote_address,
+                                    DropReason::InvalidSignature,
+                                );
+                                continue;
+                            }
                             let valid_header = PeerNode::verify_header(
                                 message.header(),
                                 &remote_address.ip(),
                             );
                             match valid_header {
+                                // Replies must answer a request sent by this
+                                // peer, not a recorded one
+                                true if !nonces.verify(
+                                    &message,
+                                    &PeerNode::reply_address(
+                                        message.header(),
+                                        remote_address.ip(),
+                                    ),
+                                    Instant::now(),
+                                ) =>
+                                {
+                                    debug!(
+                                        "Discarding unexpected reply {} from {}",
+                                        message.type_byte(),
+                                        remote_address
+                                    );
+                                    WireNetwork::dropped(
+                                        &event_tx,
+                                        remote_address,
+                                        DropReason::UnexpectedReply,
+                                    );
+                                }
                                 true => {
-                                    inbound_channel_tx
-                                        .send((message, remote_address))
-                                        .await
-                                        .unwrap_or_else(
-                                            |op| error!("Unable to send to inbound channel {:?}", op),
-                                        );
+                                    WireNetwork::deliver(
+                                        &inbound_channel_tx,
+                                        (message, remote_address, reception),
+                                        &conf,
+                                    )
+                                    .await
                                 }
                                 false => {
                                     error!(
@@ -138,49 +811,580 @@ impl WireNetwork {
                                         message.header(),
                                         &remote_address.ip()
                                     );
+                                    filter.reports.report(
+                                        remote_address.ip(),
+                                        INVALID_MESSAGE_PENALTY,
+                                    );
+                                    WireNetwork::dropped(
+                                        &event_tx,
+                                        remote_address,
+                                        DropReason::InvalidId,
+                                    );
+                                    WireNetwork::offence(
+                                        &guard,
+                                        &event_tx,
+                                        remote_address,
+                                        Flood::InvalidHeaders,
+                                    );
                                 }
                             }
                         }
                     }
-                    Err(e) => error!(
-                        "Error deser from {:?} - {} - {}",
-                        message, remote_address, e
-                    ),
+                    Err(e) => {
+                        error!(
+                            "Error deser from {:?} - {} - {}",
+                            message, remote_address, e
+                        );
+                        filter.reports.report(
+                            remote_address.ip(),
+                            MALFORMED_MESSAGE_PENALTY,
+                        );
+                        let oversized = matches!(
+                            e.get_ref().and_then(|e| e.downcast_ref()),
+                            Some(DecodeError::MessageTooLong(..))
+                        );
+                        if oversized {
+                            WireNetwork::dropped(
+                                &event_tx,
+                                remote_address,
+                                DropReason::Oversized,
+                            );
+                        } else {
+                            event::emit(
+                                &event_tx,
+                                KadcastEvent::DecodeFailed(remote_address),
+                            );
+                        }
+                        WireNetwork::offence(
+                            &guard,
+                            &event_tx,
+                            remote_address,
+                            Flood::Malformed,
+                        );
+                    }
                 }
             }
         }
     }
 
+    // Report a malformed datagram or an invalid header to the flood guard
+    fn offence(
+        guard: &Option<Arc<FloodGuard>>,
+        event_tx: &EventSender,
+        remote_address: SocketAddr,
+        flood: Flood,
+    ) {
+        if matches!(guard, Some(guard) if guard.offence(
+            remote_address.ip(),
+            flood,
+            Instant::now()
+        )) {
+            WireNetwork::ignored(event_tx, remote_address, flood);
+        }
+    }
+
+    fn ignored(
+        event_tx: &EventSender,
+        remote_address: SocketAddr,
+        flood: Flood,
+    ) {
+        warn!("Ignoring {} for a while - {:?}", remote_address.ip(), flood);
+        event::emit(
+            event_tx,
+            KadcastEvent::SourceIgnored(remote_address.ip(), flood),
+        );
+    }
+
+    fn dropped(
+        event_tx: &EventSender,
+        remote_address: SocketAddr,
+        reason: DropReason,
+    ) {
+        event::emit(
+            event_tx,
+            KadcastEvent::MessageDropped(remote_address, reason),
+        );
+    }
+
+    // Control messages generated by the transport are not critical, they are
+    // dropped if the outbound channel is full
+    fn send_control(
+        outbound_channel_tx: &Sender<MessageBeanOut>,
+        message: Message,
+        target: SocketAddr,
+    ) {
+        if let Err(e) =
+            outbound_channel_tx.try_send((message, vec![target], None))
+        {
+            warn!("Unable to send control message to {}: {}", target, e);
+        }
+    }
+
     async fn listen_out(
         mut outbound_channel_rx: Receiver<MessageBeanOut>,
+        filter: PeerFilter,
+        event_tx: EventSender,
+        awaited: Awaited,
+        encryption: Encryption,
+        mut fec: watch::Receiver<FECConfig>,
         conf: &Config,
     ) -> io::Result<()> {
         debug!("WireNetwork::listen_out started");
+        let Awaited { acks, nonces } = awaited;
+        let Encryption { noise, cipher } = encryption;
         let mut output_sockets = MultipleOutSocket::configure(&conf.network);
-        let encoder = TransportEncoder::configure(&conf.fec.encoder);
+        let mut encoder =
+            TransportEncoder::configure(&fec.borrow_and_update().encoder);
+        let custom_encoder = conf.broadcast_encoder.clone();
+        let mac = conf.network_secret.as_ref().map(ControlMac::new);
+        let padding = conf.padding.as_ref().map(TrafficPadding::new);
+        let replay_window = conf.replay_window;
+        let mut pending = PriorityQueue::new();
+        // Chunks are serialized in a shared buffer, whose memory is reused
+        // once they have been sent
+        let mut buffer = BytesMut::with_capacity(MAX_DATAGRAM_SIZE);
+        let prepare = |(mut message, to, tracker): MessageBeanOut,
+                       encoder: &TransportEncoder,
+                       buffer: &mut BytesMut| {
+            if tracker.as_ref().is_some_and(|t| t.is_cancelled()) {
+                return None;
+            }
+            debug!(
+                "< Message to send to ({:?}) - {:?} ",
+                to,
+                message.type_byte()
+            );
+            let priority = WireNetwork::priority(&message);
+            if replay_window.is_some() {
+                message.header_mut().timestamp = Some(Header::now());
+            }
+            nonces.stamp(&mut message, &to, Instant::now());
+            let message = match &cipher {
+                Some(cipher) => cipher.encrypt(message),
+                None => message,
+            };
+            let chunks = match &mac {
+                Some(mac) if !matches!(message, Message::Broadcast(..)) => {
+                    WireNetwork::authenticated(mac, &message, buffer)
+                }
+                _ => match (&custom_encoder, &message) {
+                    (Some(custom), Message::Broadcast(header, payload)) => {
+                        WireNetwork::custom_encoded(
+                            custom.as_ref(),
+                            header,
+                            payload,
+                            buffer,
+                        )
+                    }
+                    _ => encoder.encode_into(&message, buffer),
+                },
+            };
+            let chunks = WireNetwork::padded(padding.as_ref(), chunks);
+            match chunks {
+                Ok(chunks) => {
+                    let awaiting_ack = match (&acks, &message) {
+                        (Some(acks), Message::Broadcast(header, payload))
+                            if header.requests_ack() =>
+                        {
+                            Some((acks, encoder.repair_packets(payload)))
+                        }
+                        _ => None,
+                    };
+                    if let Some((acks, next_repair)) = awaiting_ack {
+                        let uid = encoder
+                            .message_uid(&message)
+                            .expect("Broadcast uid");
+                        if !acks.watch(
+                            uid,
+                            message,
+                            to.clone(),
+                            tracker.clone(),
+                            next_repair,
+                        ) {
+                            warn!("Too many broadcasts awaiting acks");
+                        }
+                    }
+                    Some((priority, PendingSend::new(chunks, to, tracker)))
+                }
+                Err(e) => {
+                    error!("Unable to encode msg {}", e);
+                    if let Some(tracker) = tracker {
+                        for target in to {
+                            tracker.failed(target, e.kind().into());
+                        }
+                    }
+                    None
+                }
+            }
+        };
+        // Messages held back by the middlewares
+        let mut delayed = Delayed::default();
         loop {
-            if let Some((message, to)) = outbound_channel_rx.recv().await {
-                debug!(
-                    "< Message to send to ({:?}) - {:?} ",
-                    to,
-                    message.type_byte()
-                );
-                let chunks: Vec<Vec<u8>> =
-                    encoder.encode(message).iter().map(|m| m.bytes()).collect();
-                for remote_addr in to.iter() {
-                    for chunk in &chunks {
-                        output_sockets
-                            .send(chunk, remote_addr)
+            // Wait for new messages only when there is nothing left to send,
+            // waking up when the next acknowledgement or delayed message is
+            // due
+            if pending.is_empty() {
+                let handshakes = noise.as_ref().and_then(|noise| {
+                    noise.lock().expect("Noise lock").next_deadline()
+                });
+                let deadline = acks
+                    .as_ref()
+                    .and_then(|a| a.next_deadline())
+                    .into_iter()
+                    .chain(delayed.next_deadline())
+                    .chain(handshakes)
+                    .min();
+                tokio::select! {
+                    bean = outbound_channel_rx.recv() => match bean {
+                        Some(bean) => {
+                            WireNetwork::reconfigure(&mut fec, &mut encoder);
+                            if let Some((priority, send)) =
+                                WireNetwork::intercept_out(
+                                    &conf.middlewares,
+                                    bean,
+                                    &mut delayed,
+                                )
+                                .and_then(|bean| {
+                                    prepare(bean, &encoder, &mut buffer)
+                                })
+                            {
+                                pending.push(priority, send);
+                            }
+                        }
+                        None => return Ok(()),
+                    },
+                    _ = WireNetwork::sleep_until(deadline) => {}
+                }
+            }
+            WireNetwork::reconfigure(&mut fec, &mut encoder);
+            // Once enough messages are pending the channel is left to fill
+            // up, applying its overflow policy to the senders
+            while pending.len() < outbound_channel_rx.capacity() {
+                let bean = match outbound_channel_rx.try_recv() {
+                    Ok(bean) => bean,
+                    Err(_) => break,
+                };
+                if let Some((priority, send)) = WireNetwork::intercept_out(
+                    &conf.middlewares,
+                    bean,
+                    &mut delayed,
+                )
+                .and_then(|bean| prepare(bean, &encoder, &mut buffer))
+                {
+                    pending.push(priority, send);
+                }
+            }
+            // The delayed messages have already been through the middlewares
+            for bean in delayed.due(Instant::now()) {
+                if let Some((priority, send)) =
+                    prepare(bean, &encoder, &mut buffer)
+                {
+                    pending.push(priority, send);
+                }
+            }
+            // Send further repair symbols to the delegates which didn't
+            // acknowledge in time
+            if let Some(acks) = &acks {
+                for entry in acks.due(Instant::now()) {
+                    if entry.tracker.as_ref().is_some_and(|t| t.is_cancelled())
+                    {
+                        continue;
+                    }
+                    debug!(
+                        "Retrying broadcast to {} unacknowledged delegates",
+                        entry.targets.len()
+                    );
+                    let chunks = encoder.repair_into(
+                        &entry.message,
+                        entry.next_repair,
+                        acks.repair_packets(),
+                        &mut buffer,
+                    );
+                    match WireNetwork::padded(padding.as_ref(), chunks) {
+                        Ok(chunks) => {
+                            pending.push(
+                                WireNetwork::priority(&entry.message),
+                                PendingSend::new(
+                                    chunks,
+                                    entry.targets.clone(),
+                                    entry.tracker.clone(),
+                                ),
+                            );
+                            acks.rewatch(entry);
+                        }
+                        Err(e) => error!("Unable to encode repair {}", e),
+                    }
+                }
+            }
+
+            // Send again the unanswered handshakes, giving up on the peers
+            // which never answer
+            if let Some(noise) = &noise {
+                let (resend, failed) =
+                    noise.lock().expect("Noise lock").expire(Instant::now());
+                for (datagram, target) in resend {
+                    if let Err(e) =
+                        output_sockets.send(&datagram, &target).await
+                    {
+                        warn!("Unable to send handshake to {} - {}", target, e);
+                    }
+                }
+                for target in failed {
+                    warn!("Noise handshake with {} timed out", target);
+                    filter.failures.report(target);
+                    event::emit(
+                        &event_tx,
+                        KadcastEvent::SendFailed(
+                            target,
+                            io::ErrorKind::TimedOut,
+                        ),
+                    );
+                }
+            }
+
+            // Send a single chunk before checking the channel again, this way
+            // an urgent message doesn't wait for a big broadcast to complete
+            if let Some(mut entry) = pending.pop_entry() {
+                if let Some(tracker) = &entry.item.tracker {
+                    if tracker.is_cancelled() {
+                        debug!("Dropping the chunks of a cancelled broadcast");
+                        continue;
+                    }
+                }
+                if let Some((chunk, remote_addr)) = entry.item.next() {
+                    let sent = match &noise {
+                        Some(noise) => {
+                            let sealed = noise
+                                .lock()
+                                .expect("Noise lock")
+                                .seal(chunk, remote_addr, Instant::now());
+                            let mut sent = Ok(());
+                            for datagram in sealed {
+                                sent = WireNetwork::send_to(
+                                    &mut output_sockets,
+                                    &filter,
+                                    &datagram,
+                                    remote_addr,
+                                )
+                                .await;
+                                if sent.is_err() {
+                                    break;
+                                }
+                            }
+                            sent
+                        }
+                        None => {
+                            WireNetwork::send_to(
+                                &mut output_sockets,
+                                &filter,
+                                chunk,
+                                remote_addr,
+                            )
                             .await
-                            .unwrap_or_else(|e| {
-                                error!("Unable to send msg {}", e)
-                            });
+                        }
+                    };
+                    let tracker = entry.item.tracker.as_ref();
+                    match sent {
+                        Ok(_) => tracker.iter().for_each(|t| t.sent()),
+                        Err(e) => {
+                            error!("Unable to send msg {}", e);
+                            filter.failures.report(remote_addr);
+                            event::emit(
+                                &event_tx,
+                                KadcastEvent::SendFailed(remote_addr, e.kind()),
+                            );
+                            if let Some(tracker) = tracker {
+                                tracker.failed(remote_addr, e);
+                            }
+                        }
+                    }
+                    pending.restore(entry);
+                    if let Some(padding) = &padding {
+                        time::sleep(padding.jitter()).await;
                     }
                 }
             }
         }
     }
 
+    // Send a datagram, failing over to the fallback address of dual-homed
+    // peers
+    async fn send_to(
+        output_sockets: &mut MultipleOutSocket,
+        filter: &PeerFilter,
+        datagram: &[u8],
+        remote_addr: SocketAddr,
+    ) -> io::Result<()> {
+        let sent = output_sockets.send(datagram, &remote_addr).await;
+        match (sent, filter.fallbacks.get(&remote_addr)) {
+            (Err(e), Some(fallback)) => {
+                warn!(
+                    "Unable to send msg to {} - {}, trying {}",
+                    remote_addr, e, fallback
+                );
+                output_sockets.send(datagram, &fallback).await
+            }
+            (sent, _) => sent,
+        }
+    }
+
+    // Run the outbound middlewares on a message to send, returning it only
+    // if it has to be sent straight away
+    fn intercept_out(
+        middlewares: &[Arc<dyn MessageMiddleware>],
+        mut bean: MessageBeanOut,
+        delayed: &mut Delayed<MessageBeanOut>,
+    ) -> Option<MessageBeanOut> {
+        let (message, to, _) = &mut bean;
+        match middleware::intercept(middlewares, |m| m.outbound(message, to)) {
+            Interception::Pass => Some(bean),
+            Interception::Drop => {
+                trace!("Message to {:?} dropped by a middleware", bean.1);
+                None
+            }
+            Interception::Delay(delay) => {
+                delayed.push(delay, bean);
+                None
+            }
+        }
+    }
+
+    // Run the inbound middlewares on a received message, before handing it
+    // to the message handler
+    async fn deliver(
+        inbound_channel_tx: &Sender<MessageBeanIn>,
+        mut bean: MessageBeanIn,
+        conf: &Config,
+    ) {
+        let (message, from, _) = &mut bean;
+        let from = *from;
+        let delay = match middleware::intercept(&conf.middlewares, |m| {
+            m.inbound(message, from)
+        }) {
+            Interception::Pass => None,
+            Interception::Drop => {
+                trace!("Message from {} dropped by a middleware", from);
+                return;
+            }
+            Interception::Delay(delay) => Some(delay),
+        };
+        let tx = inbound_channel_tx.clone();
+        let send = async move {
+            if let Some(delay) = delay {
+                time::sleep(delay).await;
+            }
+            tx.send(bean).await.unwrap_or_else(|op| {
+                error!("Unable to send to inbound channel {:?}", op)
+            });
+        };
+        // Delayed messages don't hold back the following ones
+        match delay {
+            Some(_) => {
+                conf.spawn(send);
+            }
+            None => send.await,
+        }
+    }
+
+    // Apply the FEC parameters changed with `Peer::reconfigure` to the
+    // messages encoded from now on
+    fn reconfigure(
+        fec: &mut watch::Receiver<FECConfig>,
+        encoder: &mut TransportEncoder,
+    ) {
+        if fec.has_changed().unwrap_or(false) {
+            *encoder =
+                TransportEncoder::configure(&fec.borrow_and_update().encoder);
+        }
+    }
+
+    // Serialize a control message followed by its authentication tag
+    fn authenticated(
+        mac: &ControlMac,
+        message: &Message,
+        buffer: &mut BytesMut,
+    ) -> io::Result<Vec<Bytes>> {
+        message.marshal_into(buffer)?;
+        let tag = mac.tag(buffer);
+        buffer.put_slice(&tag);
+        Ok(vec![buffer.split().freeze()])
+    }
+
+    // Encode a broadcast with a custom encoder, serializing each chunk with
+    // the header and the metadata of the message
+    fn custom_encoded(
+        encoder: &dyn BroadcastEncoder,
+        header: &Header,
+        payload: &BroadcastPayload,
+        buffer: &mut BytesMut,
+    ) -> io::Result<Vec<Bytes>> {
+        encoder
+            .encode(&payload.gossip_frame)
+            .iter()
+            .map(|frame| {
+                Message::marshal_broadcast(
+                    header,
+                    payload,
+                    &[frame],
+                    &mut (&mut *buffer).writer(),
+                )?;
+                Ok(buffer.split().freeze())
+            })
+            .collect()
+    }
+
+    // Padding is the last stage, applied to the serialized chunks
+    fn padded(
+        padding: Option<&TrafficPadding>,
+        chunks: io::Result<Vec<Bytes>>,
+    ) -> io::Result<Vec<Bytes>> {
+        match padding {
+            Some(padding) => chunks.map(|chunks| {
+                chunks.iter().map(|chunk| padding.pad(chunk)).collect()
+            }),
+            None => chunks,
+        }
+    }
+
+    // Wait for the `deadline`, forever if there is none
+    async fn sleep_until(deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => {
+                time::sleep_until(time::Instant::from_std(deadline)).await
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    // Control messages are sent ahead of any broadcast
+    fn priority(message: &Message) -> Rank {
+        match message {
+            Message::Broadcast(_, payload) => Rank::Broadcast(payload.priority),
+            _ => Rank::Control,
+        }
+    }
+
+    // Check the originator signature of a broadcast message. Unsigned messages
+    // are accepted only if signatures are not required, and signed ones
+    // whose key is not bound to the originator id only if key identities are
+    // not required
+    fn valid_origin(
+        message: &Message,
+        require_signed: bool,
+        require_identity: bool,
+    ) -> bool {
+        match message {
+            Message::Broadcast(_, payload) => match payload.origin {
+                Some(origin) => {
+                    payload.verify_origin()
+                        && (!require_identity || origin.binds_identity())
+                }
+                None => !require_signed,
+            },
+            _ => true,
+        }
+    }
+
     pub fn configure_socket(
         socket: &UdpSocket,
         conf: Config,
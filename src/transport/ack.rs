@@ -0,0 +1,195 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+use super::delivery::DeliveryTracker;
+use crate::config::BroadcastAckConfig;
+use crate::encoding::message::{Message, MessageUid};
+
+// Max amount of broadcast messages waiting for acknowledgements. Further
+// messages are sent without waiting for them
+const MAX_AWAITING_ACKS: usize = 1024;
+
+/// Broadcast message waiting for the acknowledgement of its delegates
+pub(crate) struct AwaitingAck {
+    uid: MessageUid,
+    pub(crate) message: Message,
+    /// Delegates which haven't acknowledged the message yet
+    pub(crate) targets: Vec<SocketAddr>,
+    pub(crate) tracker: Option<DeliveryTracker>,
+    /// First repair symbol not sent yet
+    pub(crate) next_repair: u32,
+    deadline: Instant,
+    retries: usize,
+}
+
+#[derive(Default)]
+struct AckState {
+    acked: HashMap<MessageUid, HashSet<SocketAddr>>,
+    awaiting: Vec<AwaitingAck>,
+}
+
+/// Broadcast messages sent in acknowledged mode. The acknowledgements are
+/// recorded by the task decoding the incoming messages, the delegates which
+/// don't acknowledge in time are retried by the task sending the outgoing
+/// ones
+pub(crate) struct BroadcastAcks {
+    conf: BroadcastAckConfig,
+    state: Mutex<AckState>,
+}
+
+impl BroadcastAcks {
+    pub(crate) fn new(conf: BroadcastAckConfig) -> Self {
+        Self {
+            conf,
+            state: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn repair_packets(&self) -> u32 {
+        self.conf.repair_packets
+    }
+
+    /// Wait for the acknowledgements of a message sent to `targets`, the
+    /// repair symbols up to `next_repair` excluded having been sent.
+    ///
+    /// Returns `false` if too many messages are already waiting
+    pub(crate) fn watch(
+        &self,
+        uid: MessageUid,
+        message: Message,
+        targets: Vec<SocketAddr>,
+        tracker: Option<DeliveryTracker>,
+        next_repair: u32,
+    ) -> bool {
+        let mut state = self.lock();
+        if state.awaiting.len() >= MAX_AWAITING_ACKS {
+            return false;
+        }
+        state.acked.entry(uid).or_default();
+        state.awaiting.push(AwaitingAck {
+            uid,
+            message,
+            targets,
+            tracker,
+            next_repair,
+            deadline: Instant::now() + self.conf.timeout,
+            retries: self.conf.retries,
+        });
+        true
+    }
+
+    /// Record the acknowledgement of a message by a peer, known by any of
+    /// the `from` addresses. Unexpected acknowledgements are ignored
+    pub(crate) fn ack(&self, uid: MessageUid, from: &[SocketAddr]) -> bool {
+        match self.lock().acked.get_mut(&uid) {
+            Some(acked) => {
+                acked.extend(from);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// When the next message waiting for acknowledgements is due
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.lock().awaiting.iter().map(|a| a.deadline).min()
+    }
+
+    /// Take the messages not acknowledged by some of their delegates in
+    /// time, which must be retried and then watched again with
+    /// [BroadcastAcks::rewatch].
+    ///
+    /// Messages acknowledged by every delegate, or out of retries, are
+    /// forgotten. The delegates which never acknowledged are reported to
+    /// the tracker of the message
+    pub(crate) fn due(&self, now: Instant) -> Vec<AwaitingAck> {
+        let mut state = self.lock();
+        let AckState { acked, awaiting } = &mut *state;
+        let mut due = vec![];
+        let mut i = 0;
+        while i < awaiting.len() {
+            if awaiting[i].deadline > now {
+                i += 1;
+                continue;
+            }
+            let mut entry = awaiting.swap_remove(i);
+            if let Some(acked) = acked.get(&entry.uid) {
+                entry.targets.retain(|target| !acked.contains(target));
+            }
+            if entry.targets.is_empty() || entry.retries == 0 {
+                acked.remove(&entry.uid);
+                if let Some(tracker) = &entry.tracker {
+                    tracker.unacknowledged(&entry.targets);
+                }
+                continue;
+            }
+            due.push(entry);
+        }
+        due
+    }
+
+    /// Wait again for the acknowledgements of a retried message
+    pub(crate) fn rewatch(&self, mut entry: AwaitingAck) {
+        entry.retries -= 1;
+        entry.next_repair += self.conf.repair_packets;
+        entry.deadline = Instant::now() + self.conf.timeout;
+        self.lock().awaiting.push(entry);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, AckState> {
+        self.state.lock().expect("Acks lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::BroadcastAcks;
+    use crate::config::BroadcastAckConfig;
+    use crate::encoding::message::Message;
+    use crate::peer::PeerNode;
+
+    #[test]
+    fn test_broadcast_acks() {
+        let acks = BroadcastAcks::new(BroadcastAckConfig {
+            timeout: Duration::ZERO,
+            retries: 1,
+            repair_packets: 4,
+        });
+        let header = PeerNode::generate("192.168.0.1:666").as_header();
+        let target = |i| format!("10.0.0.{}:666", i).parse().unwrap();
+        assert!(!acks.ack([1; 32], &[target(1)]));
+        assert!(acks.watch(
+            [1; 32],
+            Message::Ping(header),
+            vec![target(1), target(2)],
+            None,
+            5
+        ));
+        assert!(acks.next_deadline().is_some());
+        assert!(acks.ack([1; 32], &[target(1)]));
+
+        // Only the delegates which didn't acknowledge are retried
+        let due = acks.due(Instant::now());
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].targets, vec![target(2)]);
+        assert_eq!(due[0].next_repair, 5);
+        for entry in due {
+            acks.rewatch(entry);
+        }
+        // Out of retries
+        let due = acks.due(Instant::now());
+        assert!(due.is_empty());
+        assert!(acks.next_deadline().is_none());
+        assert!(!acks.ack([1; 32], &[target(2)]));
+    }
+}
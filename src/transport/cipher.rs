@@ -0,0 +1,239 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::sync::Mutex;
+
+use blake2::{Blake2s, Digest};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use tracing::warn;
+
+use crate::encoding::{
+    message::Message,
+    payload::{BroadcastPayload, Topic},
+};
+
+const NONCE_LEN: usize = 12;
+
+/// Max amount of nonces of the recent gossip frames, reused when the frames
+/// are relayed
+const MAX_RECENT_NONCES: usize = 4096;
+
+/// Length of the pre-shared key used to encrypt gossip frames
+pub const GOSSIP_KEY_LEN: usize = 32;
+
+pub type GossipKey = [u8; GOSSIP_KEY_LEN];
+
+type FrameDigest = [u8; 32];
+
+// Nonces of the recently encrypted or decrypted gossip frames
+#[derive(Default)]
+struct RecentNonces {
+    nonces: HashMap<FrameDigest, [u8; NONCE_LEN]>,
+    // Digests of the `nonces`, from the oldest
+    order: VecDeque<FrameDigest>,
+}
+
+impl RecentNonces {
+    fn record(&mut self, digest: FrameDigest, nonce: [u8; NONCE_LEN]) {
+        if self.nonces.insert(digest, nonce).is_some() {
+            return;
+        }
+        self.order.push_back(digest);
+        if self.order.len() > MAX_RECENT_NONCES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.nonces.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Authenticated encryption of the broadcast gossip frames.
+///
+/// Only the gossip frame is encrypted, the header and the broadcast height
+/// are kept in clear in order to allow routing and propagation. The topic
+/// is authenticated along with the frame.
+///
+/// The encryption key and the key identifying the frames are derived from
+/// the network key. The nonce of a new frame is random, while a relayed
+/// frame is encrypted again with the nonce it has been received with: this
+/// way the copies relayed by different peers are the same ciphertext,
+/// preserving the duplicate detection of the decoder. It's shared by the
+/// task sending the messages and the one decoding them
+pub(crate) struct GossipCipher {
+    cipher: ChaCha20Poly1305,
+    digest_key: [u8; 32],
+    recent: Mutex<RecentNonces>,
+}
+
+impl GossipCipher {
+    pub(crate) fn new(key: &GossipKey) -> Self {
+        let encryption_key = derive(key, b"kadcast-gossip-encryption");
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&encryption_key)),
+            digest_key: derive(key, b"kadcast-gossip-digest"),
+            recent: Mutex::default(),
+        }
+    }
+
+    // Identify a frame without revealing it
+    fn digest(&self, topic: Topic, plaintext: &[u8]) -> FrameDigest {
+        let mut hasher = Blake2s::with_params(&self.digest_key, &[], &[]);
+        hasher.update([topic]);
+        hasher.update(plaintext);
+        hasher.finalize().into()
+    }
+
+    // The nonce a frame has been received with, a random one for the new
+    // frames
+    fn nonce(&self, topic: Topic, plaintext: &[u8]) -> [u8; NONCE_LEN] {
+        let digest = self.digest(topic, plaintext);
+        let mut recent = self.recent.lock().expect("Cipher lock");
+        match recent.nonces.get(&digest) {
+            Some(nonce) => *nonce,
+            None => {
+                let nonce = rand::random();
+                recent.record(digest, nonce);
+                nonce
+            }
+        }
+    }
+
+    pub(crate) fn encrypt(&self, message: Message) -> Message {
+        match message {
+            Message::Broadcast(header, payload) => {
+                let nonce = self.nonce(payload.topic, &payload.gossip_frame);
+                let mut gossip_frame = nonce.to_vec();
+                let mut ciphertext = self
+                    .cipher
+                    .encrypt(
+                        Nonce::from_slice(&nonce),
+                        Payload {
+                            msg: &payload.gossip_frame,
+                            aad: &[payload.topic],
+                        },
+                    )
+                    .expect("Encryption failure");
+                gossip_frame.append(&mut ciphertext);
+                Message::Broadcast(
+                    header,
+                    BroadcastPayload {
+                        gossip_frame,
+                        ..payload
+                    },
+                )
+            }
+            _ => message,
+        }
+    }
+
+    /// Decrypt the gossip frame of a broadcast message.
+    ///
+    /// Returns `None` if the frame (and its topic) can't be authenticated
+    /// with the network key
+    pub(crate) fn decrypt(&self, message: Message) -> Option<Message> {
+        match message {
+            Message::Broadcast(header, payload) => {
+                if payload.gossip_frame.len() < NONCE_LEN {
+                    warn!("Encrypted gossip frame too short");
+                    return None;
+                }
+                let (nonce, ciphertext) =
+                    payload.gossip_frame.split_at(NONCE_LEN);
+                let decrypted = self.cipher.decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: ciphertext,
+                        aad: &[payload.topic],
+                    },
+                );
+                match decrypted {
+                    Ok(gossip_frame) => {
+                        let digest = self.digest(payload.topic, &gossip_frame);
+                        let nonce = nonce.try_into().expect("Wrong length");
+                        self.recent
+                            .lock()
+                            .expect("Cipher lock")
+                            .record(digest, nonce);
+                        Some(Message::Broadcast(
+                            header,
+                            BroadcastPayload {
+                                gossip_frame,
+                                ..payload
+                            },
+                        ))
+                    }
+                    Err(_) => {
+                        warn!("Unable to decrypt gossip frame");
+                        None
+                    }
+                }
+            }
+            _ => Some(message),
+        }
+    }
+}
+
+// Derive a subkey of the network key for the given purpose
+fn derive(key: &GossipKey, purpose: &[u8]) -> [u8; 32] {
+    let mut hasher = Blake2s::with_params(key, &[], &[]);
+    hasher.update(purpose);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GossipCipher;
+    use crate::encoding::{message::Message, payload::BroadcastPayload};
+    use crate::peer::PeerNode;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let header = PeerNode::generate("192.168.0.1:666").as_header();
+        let plain = || {
+            Message::Broadcast(
+                header,
+                BroadcastPayload {
+                    height: 3,
+                    topic: 0,
+                    priority: 0,
+                    origin: None,
+                    message_id: None,
+                    batch: false,
+                    gossip_frame: vec![1, 2, 3, 4, 5],
+                },
+            )
+        };
+        let cipher = GossipCipher::new(&[7; 32]);
+        let encrypted = cipher.encrypt(plain());
+        assert_ne!(encrypted, plain());
+        // The nonce of a new frame is random
+        let relayer = GossipCipher::new(&[7; 32]);
+        assert_ne!(encrypted, relayer.encrypt(plain()));
+        // A relayed frame keeps the nonce it has been received with
+        let relayer = GossipCipher::new(&[7; 32]);
+        assert_eq!(relayer.decrypt(cipher.encrypt(plain())), Some(plain()));
+        assert_eq!(relayer.encrypt(plain()), encrypted);
+
+        let other = GossipCipher::new(&[8; 32]);
+        assert_eq!(other.decrypt(cipher.encrypt(plain())), None);
+
+        // The topic is authenticated
+        let retopic = match encrypted {
+            Message::Broadcast(header, payload) => Message::Broadcast(
+                header,
+                BroadcastPayload {
+                    topic: 1,
+                    ..payload
+                },
+            ),
+            _ => unreachable!(),
+        };
+        assert_eq!(cipher.decrypt(retopic), None);
+    }
+}
@@ -0,0 +1,167 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+
+/// Outcome of the sending of a broadcast message, see
+/// [BroadcastHandle::delivered]
+#[derive(Debug, Default)]
+pub struct Delivery {
+    /// Chunks sent, over all the delegates
+    pub chunks: usize,
+    /// Delegates the message couldn't be sent to, with the first error
+    pub failures: Vec<(SocketAddr, io::Error)>,
+    /// Delegates which never acknowledged the message, when the
+    /// acknowledged mode is enabled (see
+    /// [Config::broadcast_ack](crate::config::Config::broadcast_ack))
+    pub unacknowledged: Vec<SocketAddr>,
+}
+
+/// Handle of a broadcast message, returned by
+/// [Peer::broadcast](crate::Peer::broadcast).
+///
+/// A broadcast targeting no bucket has reached nobody, eg: because the
+/// routing table is empty
+pub struct BroadcastHandle {
+    buckets: usize,
+    delegates: usize,
+    delivery: Arc<Mutex<Delivery>>,
+    cancelled: Arc<AtomicBool>,
+    pending: mpsc::Receiver<()>,
+}
+
+impl BroadcastHandle {
+    pub(crate) fn new() -> (Self, DeliveryTracker) {
+        let delivery = Arc::new(Mutex::new(Delivery::default()));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel(1);
+        let tracker = DeliveryTracker {
+            delivery: delivery.clone(),
+            cancelled: cancelled.clone(),
+            _pending: tx,
+        };
+        let handle = BroadcastHandle {
+            buckets: 0,
+            delegates: 0,
+            delivery,
+            cancelled,
+            pending: rx,
+        };
+        (handle, tracker)
+    }
+
+    pub(crate) fn targeted(&mut self, delegates: usize) {
+        self.buckets += 1;
+        self.delegates += delegates;
+    }
+
+    /// Amount of buckets the message has been sent to
+    pub fn buckets(&self) -> usize {
+        self.buckets
+    }
+
+    /// Amount of delegates the message has been sent to, over all the
+    /// buckets
+    pub fn delegates(&self) -> usize {
+        self.delegates
+    }
+
+    /// Stop sending the chunks of the message which are still queued (eg:
+    /// a block superseded while propagating). The chunks already sent are
+    /// not recalled.
+    ///
+    /// Relays are not notified: a message whose chunks stop short of the
+    /// amount needed to decode it is never relayed
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Wait until the message has been sent to every delegate, or dropped
+    /// because the broadcast has been cancelled or the peer shut down.
+    ///
+    /// In acknowledged mode the wait lasts until every delegate has
+    /// acknowledged the message, or has been retried for the last time
+    pub async fn delivered(mut self) -> Delivery {
+        // Completed once every tracker is dropped
+        let _ = self.pending.recv().await;
+        let mut delivery = self.delivery.lock().expect("Delivery poisoned");
+        std::mem::take(&mut delivery)
+    }
+}
+
+/// Delivery accounting of the outgoing messages of a broadcast, shared with
+/// its [BroadcastHandle]. The delivery is complete once every copy is
+/// dropped
+#[derive(Clone)]
+pub(crate) struct DeliveryTracker {
+    delivery: Arc<Mutex<Delivery>>,
+    cancelled: Arc<AtomicBool>,
+    _pending: mpsc::Sender<()>,
+}
+
+impl DeliveryTracker {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn sent(&self) {
+        self.delivery.lock().expect("Delivery poisoned").chunks += 1;
+    }
+
+    pub(crate) fn failed(&self, target: SocketAddr, error: io::Error) {
+        let mut delivery = self.delivery.lock().expect("Delivery poisoned");
+        if delivery
+            .failures
+            .iter()
+            .all(|(failed, _)| failed != &target)
+        {
+            delivery.failures.push((target, error));
+        }
+    }
+
+    pub(crate) fn unacknowledged(&self, targets: &[SocketAddr]) {
+        let mut delivery = self.delivery.lock().expect("Delivery poisoned");
+        delivery.unacknowledged.extend(targets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::BroadcastHandle;
+
+    #[tokio::test]
+    async fn test_delivery() {
+        let (mut handle, tracker) = BroadcastHandle::new();
+        handle.targeted(3);
+        handle.targeted(2);
+        assert_eq!((handle.buckets(), handle.delegates()), (2, 5));
+
+        let copy = tracker.clone();
+        drop(tracker);
+        assert!(!copy.is_cancelled());
+        handle.cancel();
+        assert!(copy.is_cancelled());
+        let target = "10.0.0.1:666".parse().unwrap();
+        copy.sent();
+        copy.sent();
+        copy.failed(target, io::ErrorKind::Other.into());
+        copy.failed(target, io::ErrorKind::Other.into());
+        copy.unacknowledged(&[target]);
+        drop(copy);
+        let delivery = handle.delivered().await;
+        assert_eq!(delivery.chunks, 2);
+        assert_eq!(delivery.failures.len(), 1);
+        assert_eq!(delivery.failures[0].0, target);
+        assert_eq!(delivery.unacknowledged, vec![target]);
+    }
+}
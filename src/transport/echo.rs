@@ -0,0 +1,226 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::encoding::message::Message;
+use crate::kbucket::BinaryKey;
+
+/// Time a reply echoing the nonce of a request is accepted for
+const NONCE_TTL: Duration = Duration::from_secs(30);
+
+/// Max amount of requests waiting for their replies. The oldest one is
+/// forgotten beyond it
+const MAX_PENDING_NONCES: usize = 4096;
+
+// Request waiting for its replies
+struct PendingNonce {
+    sent: Instant,
+    // Addresses the request was sent to, the only ones allowed to answer
+    targets: Vec<SocketAddr>,
+    // Peers which already answered a `Ping`
+    ponged: HashSet<BinaryKey>,
+}
+
+#[derive(Default)]
+struct Pending {
+    nonces: HashMap<u64, PendingNonce>,
+    // Nonces along with when they were sent, in send order
+    order: VecDeque<(u64, Instant)>,
+}
+
+/// Nonces of the `Ping` and `FindNodes` requests sent, which the `Pong` and
+/// `Nodes` replies have to echo. They are stamped by the task sending the
+/// outgoing messages and checked by the one decoding the incoming ones, so
+/// that recorded replies can't be replayed to fake the liveness of a peer.
+/// A nonce is bound to the addresses the request was sent to, so that a
+/// peer can't answer with its own nonce on behalf of another address
+pub(crate) struct ReplyNonces {
+    require_echo: bool,
+    pending: Mutex<Pending>,
+}
+
+impl ReplyNonces {
+    pub(crate) fn new(require_echo: bool) -> Self {
+        Self {
+            require_echo,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Stamp a new nonce on the header of a request sent to `targets`
+    pub(crate) fn stamp(
+        &self,
+        message: &mut Message,
+        targets: &[SocketAddr],
+        now: Instant,
+    ) {
+        if !matches!(message, Message::Ping(_) | Message::FindNodes(..)) {
+            return;
+        }
+        let nonce = rand::random();
+        let mut pending = self.pending.lock().expect("Nonces lock");
+        let Pending { nonces, order } = &mut *pending;
+        while let Some(&(oldest, sent)) = order.front() {
+            if now.duration_since(sent) < NONCE_TTL
+                && nonces.len() < MAX_PENDING_NONCES
+            {
+                break;
+            }
+            order.pop_front();
+            if nonces.get(&oldest).map(|p| p.sent) == Some(sent) {
+                nonces.remove(&oldest);
+            }
+        }
+        order.push_back((nonce, now));
+        nonces.insert(
+            nonce,
+            PendingNonce {
+                sent: now,
+                targets: targets.to_vec(),
+                ponged: HashSet::new(),
+            },
+        );
+        message.header_mut().request_nonce = Some(nonce);
+    }
+
+    /// Check if a reply from `from` (the reply address of the sender)
+    /// echoes the nonce of a request pending for it. A `Pong` is accepted
+    /// once per peer, the pages of a `Nodes` reply share the nonce. Replies
+    /// without a nonce are accepted unless the echo is required
+    pub(crate) fn verify(
+        &self,
+        message: &Message,
+        from: &SocketAddr,
+        now: Instant,
+    ) -> bool {
+        let is_pong = match message {
+            Message::Pong(_) => true,
+            Message::Nodes(..) => false,
+            _ => return true,
+        };
+        let header = message.header();
+        let nonce = match header.request_nonce {
+            Some(nonce) => nonce,
+            None => return !self.require_echo,
+        };
+        let mut pending = self.pending.lock().expect("Nonces lock");
+        match pending.nonces.get_mut(&nonce) {
+            Some(p)
+                if now.duration_since(p.sent) < NONCE_TTL
+                    && p.targets.contains(from) =>
+            {
+                !is_pong || p.ponged.insert(*header.binary_id.as_binary())
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::message::NodePayload;
+    use crate::peer::PeerNode;
+
+    #[test]
+    fn test_reply_nonces() {
+        let nonces = ReplyNonces::new(false);
+        let now = Instant::now();
+        let header = PeerNode::generate("192.168.0.1:666").as_header();
+        let other = PeerNode::generate("192.168.0.2:666").as_header();
+        let from = "192.168.0.1:666".parse().unwrap();
+        let other_from = "192.168.0.2:666".parse().unwrap();
+        let mut ping = Message::Ping(header);
+        nonces.stamp(&mut ping, &[from, other_from], now);
+        let nonce = ping.header().request_nonce;
+        assert!(nonce.is_some());
+
+        // Each peer answers once
+        let pong = |header: crate::Header| {
+            Message::Pong(crate::Header {
+                request_nonce: nonce,
+                ..header
+            })
+        };
+        assert!(nonces.verify(&pong(header), &from, now));
+        assert!(!nonces.verify(&pong(header), &from, now));
+        assert!(nonces.verify(&pong(other), &other_from, now));
+
+        // Unknown or expired nonces are rejected
+        let forged = Message::Pong(crate::Header {
+            request_nonce: nonce.map(|n| n.wrapping_add(1)),
+            ..other
+        });
+        assert!(!nonces.verify(&forged, &other_from, now));
+        let mut find_nodes = Message::FindNodes(header, [0; crate::ID_LEN]);
+        nonces.stamp(&mut find_nodes, &[other_from], now);
+        let nodes = Message::Nodes(
+            crate::Header {
+                request_nonce: find_nodes.header().request_nonce,
+                ..other
+            },
+            NodePayload { peers: vec![] },
+        );
+        assert!(nonces.verify(&nodes, &other_from, now));
+        assert!(nonces.verify(&nodes, &other_from, now));
+        assert!(!nonces.verify(&nodes, &other_from, now + NONCE_TTL));
+
+        // Nonces are bound to the addresses the request was sent to
+        let victim = "192.168.0.3:666".parse().unwrap();
+        assert!(!nonces.verify(&nodes, &victim, now));
+        let mut ping = Message::Ping(header);
+        nonces.stamp(&mut ping, &[other_from], now);
+        let spoofed = Message::Pong(crate::Header {
+            request_nonce: ping.header().request_nonce,
+            ..header
+        });
+        assert!(!nonces.verify(&spoofed, &victim, now));
+        assert!(nonces.verify(&spoofed, &other_from, now));
+
+        // Replies without a nonce are rejected only if required
+        let required = ReplyNonces::new(true);
+        assert!(nonces.verify(&Message::Pong(other), &other_from, now));
+        assert!(!required.verify(&Message::Pong(other), &other_from, now));
+        assert!(required.verify(&Message::Ping(other), &other_from, now));
+    }
+
+    #[test]
+    fn test_pending_nonces() {
+        let nonces = ReplyNonces::new(true);
+        let now = Instant::now();
+        let header = PeerNode::generate("192.168.0.1:666").as_header();
+        let from = "192.168.0.1:666".parse().unwrap();
+        let stamp = |at| {
+            let mut ping = Message::Ping(header);
+            nonces.stamp(&mut ping, &[from], at);
+            Message::Pong(crate::Header {
+                request_nonce: ping.header().request_nonce,
+                ..header
+            })
+        };
+        let first = stamp(now);
+        for _ in 1..MAX_PENDING_NONCES {
+            stamp(now);
+        }
+        // The oldest request is forgotten beyond the max
+        let last = stamp(now);
+        assert!(!nonces.verify(&first, &from, now));
+        assert!(nonces.verify(&last, &from, now));
+        assert_eq!(
+            nonces.pending.lock().unwrap().nonces.len(),
+            MAX_PENDING_NONCES
+        );
+
+        // And so are the expired ones
+        stamp(now + NONCE_TTL);
+        let pending = nonces.pending.lock().unwrap();
+        assert_eq!((pending.nonces.len(), pending.order.len()), (1, 1));
+    }
+}
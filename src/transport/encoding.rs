@@ -7,13 +7,21 @@
 mod plain_encoder;
 mod raptorq;
 
+pub use self::raptorq::DecodeProgress;
 pub(crate) use self::raptorq::RaptorQDecoder as TransportDecoder;
 pub(crate) use self::raptorq::RaptorQEncoder as TransportEncoder;
+pub use self::raptorq::RaptorQEncoderProfile as TransportEncoderProfile;
+pub(crate) use self::raptorq::Reception;
 
 pub type TransportEncoderConfig =
     <self::TransportEncoder as Configurable>::TConf;
 pub type TransportDecoderConfig =
     <self::TransportDecoder as Configurable>::TConf;
+use std::io;
+use std::net::IpAddr;
+
+use bytes::{Bytes, BytesMut};
+
 use crate::encoding::message::Message;
 
 pub trait Configurable {
@@ -24,8 +32,21 @@ pub trait Configurable {
 
 pub(crate) trait Encoder: Configurable {
     fn encode(&self, msg: Message) -> Vec<Message>;
+
+    /// Encode a message serializing the resulting chunks into `buf`.
+    ///
+    /// The returned chunks share the memory of `buf`, which can be reused
+    /// once they are dropped
+    fn encode_into(
+        &self,
+        msg: &Message,
+        buf: &mut BytesMut,
+    ) -> io::Result<Vec<Bytes>> {
+        msg.marshal_into(buf)?;
+        Ok(vec![buf.split().freeze()])
+    }
 }
 
 pub(crate) trait Decoder: Configurable {
-    fn decode(&mut self, chunk: Message) -> Option<Message>;
+    fn decode(&mut self, chunk: Message, source: IpAddr) -> Option<Message>;
 }
@@ -5,6 +5,7 @@
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
 use std::collections::HashMap;
+use std::net::IpAddr;
 
 use crate::encoding::message::Message;
 
@@ -29,7 +30,7 @@ impl Encoder for PlainEncoder {
 }
 
 impl Decoder for PlainEncoder {
-    fn decode(&mut self, chunk: Message) -> Option<Message> {
+    fn decode(&mut self, chunk: Message, _: IpAddr) -> Option<Message> {
         if let Message::Broadcast(header, payload) = chunk {
             Some(Message::Broadcast(header, payload))
         } else {
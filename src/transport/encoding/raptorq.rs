@@ -9,13 +9,24 @@ use std::convert::TryInto;
 use blake2::{Blake2s, Digest};
 use raptorq::ObjectTransmissionInformation;
 
-use crate::encoding::{payload::BroadcastPayload, Marshallable};
+use crate::encoding::{
+    message::TransmissionInfo, payload::BroadcastPayload, Marshallable,
+};
 
 mod decoder;
 mod encoder;
+mod limiter;
 
-pub(crate) use decoder::RaptorQDecoder;
+pub use decoder::DecodeProgress;
+pub(crate) use decoder::{RaptorQDecoder, Reception};
 pub(crate) use encoder::RaptorQEncoder;
+pub use encoder::RaptorQEncoderProfile;
+
+// Length of the encoded chunk header (uid + transmission info)
+const CHUNK_HEADER_LEN: usize = 32 + 12;
+
+// Length of the RaptorQ payload id prefixing every encoded packet
+const PAYLOAD_ID_LEN: usize = 4;
 
 struct ChunkedPayload<'a>(&'a BroadcastPayload);
 
@@ -25,6 +36,12 @@ impl BroadcastPayload {
         self.marshal_binary(&mut bytes).unwrap();
         bytes
     }
+    // Replace the transmission info of a chunk. It must be called only on
+    // valid chunks, see [ChunkedPayload::is_valid]
+    fn set_transmission_info(&mut self, info: &TransmissionInfo) {
+        self.gossip_frame[32..CHUNK_HEADER_LEN].copy_from_slice(info)
+    }
+
     fn generate_uid(&self) -> [u8; 32] {
         let mut hasher = Blake2s::new();
         hasher.update(&self.bytes()[1..]);
@@ -36,10 +53,22 @@ impl BroadcastPayload {
     }
 }
 impl<'a> ChunkedPayload<'a> {
+    // Check if the gossip frame is long enough to contain a chunk. This must be
+    // checked before accessing any other field
+    fn is_valid(&self) -> bool {
+        self.0.gossip_frame.len() >= CHUNK_HEADER_LEN + PAYLOAD_ID_LEN
+    }
+
     fn uid(&self) -> &[u8] {
         &self.0.gossip_frame[0..32]
     }
 
+    fn raw_transmission_info(&self) -> TransmissionInfo {
+        self.0.gossip_frame[32..CHUNK_HEADER_LEN]
+            .try_into()
+            .expect("slice with incorrect length")
+    }
+
     fn transmission_info(&self) -> ObjectTransmissionInformation {
         let slice = &self.0.gossip_frame[32..44];
         let transmission_info: &[u8; 12] =
@@ -48,7 +77,7 @@ impl<'a> ChunkedPayload<'a> {
     }
 
     fn encoded_chunk(&self) -> &[u8] {
-        &self.0.gossip_frame[44..]
+        &self.0.gossip_frame[CHUNK_HEADER_LEN..]
     }
 
     fn safe_uid(&self) -> [u8; 32] {
@@ -100,6 +129,11 @@ mod tests {
         let header = peer.as_header();
         let payload = BroadcastPayload {
             height: 255,
+            topic: 0,
+            priority: 0,
+            origin: None,
+            message_id: None,
+            batch: false,
             gossip_frame: data,
         };
         println!("orig payload len {}", payload.bytes().len());
@@ -124,7 +158,8 @@ mod tests {
             // println!("chunk {:?}", chunk);
             i = i + 1;
             sizetotal += chunk.bytes().len();
-            if let Some(d) = decoder.decode(chunk) {
+            if let Some(d) = decoder.decode(chunk, peer.value().address().ip())
+            {
                 decoded = Some(d);
                 println!("Decoder after {} messages ", i);
                 break;
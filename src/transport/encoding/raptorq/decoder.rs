@@ -5,33 +5,221 @@
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
 use crate::transport::{encoding::Configurable, Decoder};
-use raptorq::{Decoder as ExtDecoder, EncodingPacket};
+use raptorq::{
+    Decoder as ExtDecoder, EncodingPacket, ObjectTransmissionInformation,
+    PayloadId,
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
+    collections::{hash_map::Entry, HashMap, HashSet},
+    convert::TryInto,
+    net::IpAddr,
+    time::{Duration, Instant, SystemTime},
 };
 use tracing::{trace, warn};
 
-use crate::encoding::{message::Message, payload::BroadcastPayload};
+use crate::encoding::{
+    message::{Message, MessageUid, TransmissionInfo},
+    payload::{BroadcastPayload, Topic},
+};
 
+use super::limiter::{Contributions, SourceLimiter};
 use super::ChunkedPayload;
+use crate::KadcastEvent;
 
 const DEFAULT_CACHE_TTL_SECS: u64 = 60;
 const DEFAULT_CACHE_PRUNE_EVERY_SECS: u64 = 60 * 5;
+const DEFAULT_DEDUP_WINDOW_SECS: u64 = 60 * 10;
+const DEFAULT_MAX_BROADCAST_SIZE: u64 = 32 * 1024 * 1024;
+const DEFAULT_MAX_SYMBOL_SIZE: u16 = 8192;
+const DEFAULT_MAX_OBJECTS_PER_SOURCE: usize = 64;
+const DEFAULT_MAX_SYMBOLS_PER_SOURCE: usize = 32 * 1024;
+
+// Max number of source symbols per block allowed by RFC6330
+const MAX_SOURCE_SYMBOLS_PER_BLOCK: u64 = 56403;
+
+// Progress is notified for messages made of at least this amount of source
+// symbols, once every `1 / PROGRESS_STEPS` of them are received
+const PROGRESS_MIN_SYMBOLS: usize = 10;
+const PROGRESS_STEPS: usize = 10;
+
+// Max number of transmission info requests waiting for a response
+const MAX_PENDING_INFO_REQUESTS: usize = 1024;
+
+// Min time between two acknowledgements of the same message, which is
+// acknowledged again when its sender keeps sending chunks (eg: because the
+// previous acknowledgement got lost)
+const ACK_REPEAT_INTERVAL: Duration = Duration::from_millis(500);
 
 pub struct RaptorQDecoder {
     cache: HashMap<[u8; 32], CacheStatus>,
     last_pruned: Instant,
     conf: RaptorQDecoderConf,
+    progress: Vec<DecodeProgress>,
+    limiter: SourceLimiter,
+
+    // Transmission info of the decoded messages, served to the peers
+    // requesting it
+    known_info: HashMap<MessageUid, (TransmissionInfo, Instant)>,
+    // Messages whose transmission info has been requested
+    requested_info: HashMap<MessageUid, Instant>,
+    // Transmission info received from other peers. It overrides the one
+    // carried by the chunks
+    fetched_info: HashMap<MessageUid, (TransmissionInfo, Instant)>,
+    info_requests: Vec<MessageUid>,
+
+    // When the decoded messages have been delivered, kept for the dedup
+    // window regardless of the cache pruning
+    delivered: HashMap<MessageUid, Instant>,
+
+    // Last acknowledgement of the decoded messages whose sender requested it
+    acked: HashMap<MessageUid, Instant>,
+    acks: Vec<MessageUid>,
+
+    reception: Option<Reception>,
+}
+
+/// Reception progress of a broadcast message which is being decoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodeProgress {
+    uid: [u8; 32],
+    topic: Topic,
+    received: usize,
+    needed: usize,
+}
+
+impl DecodeProgress {
+    /// Identifier of the message being decoded
+    pub fn uid(&self) -> [u8; 32] {
+        self.uid
+    }
+
+    /// Topic of the message being decoded
+    pub fn topic(&self) -> Topic {
+        self.topic
+    }
+
+    /// Number of distinct symbols received so far
+    pub fn received_symbols(&self) -> usize {
+        self.received
+    }
+
+    /// Number of source symbols the message is made of. The message can be
+    /// decoded once (about) this amount of symbols is received
+    pub fn source_symbols(&self) -> usize {
+        self.needed
+    }
+
+    /// Percentage of the source symbols received so far, capped at 100
+    pub fn percent(&self) -> u8 {
+        (self.received * 100 / self.needed).min(100) as u8
+    }
+}
+
+// Distinct symbols received for a message. The same symbol can be received
+// from multiple peers
+struct ReceiveProgress {
+    symbols: HashSet<(u8, u32)>,
+    needed: usize,
+    notified_step: usize,
+}
+
+impl ReceiveProgress {
+    fn new(info: &ObjectTransmissionInformation) -> Option<Self> {
+        let symbol_size = info.symbol_size() as u64;
+        let needed = info.transfer_length().div_ceil(symbol_size);
+        let needed: usize = needed.try_into().ok()?;
+        match needed >= PROGRESS_MIN_SYMBOLS {
+            true => Some(Self {
+                symbols: HashSet::new(),
+                needed,
+                notified_step: 0,
+            }),
+            false => None,
+        }
+    }
+
+    // Register a received symbol, returning the amount of received symbols if
+    // a new progress step has been reached
+    fn receive(&mut self, id: &PayloadId) -> Option<usize> {
+        let new_symbol = self
+            .symbols
+            .insert((id.source_block_number(), id.encoding_symbol_id()));
+        let received = self.symbols.len();
+        let step =
+            (received * PROGRESS_STEPS / self.needed).min(PROGRESS_STEPS);
+        match new_symbol && step > self.notified_step {
+            true => {
+                self.notified_step = step;
+                Some(received)
+            }
+            false => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
 pub struct RaptorQDecoderConf {
     #[serde(with = "humantime_serde")]
     pub cache_ttl: Duration,
     #[serde(with = "humantime_serde")]
     pub cache_prune_every: Duration,
+
+    /// Time during which a decoded message is neither delivered nor
+    /// propagated again when it's received once more, even if it has been
+    /// pruned from the cache meanwhile (eg: with a different transmission
+    /// info)
+    #[serde(default = "default_dedup_window")]
+    #[serde(with = "humantime_serde")]
+    pub dedup_window: Duration,
+
+    /// Max size (in bytes) of a broadcast message which can be decoded.
+    /// Chunks declaring a bigger object are discarded
+    #[serde(default = "default_max_broadcast_size")]
+    pub max_broadcast_size: u64,
+
+    /// Max symbol size accepted. It must be greater or equal than the `mtu`
+    /// configured in the encoder of the network peers
+    #[serde(default = "default_max_symbol_size")]
+    pub max_symbol_size: u16,
+
+    /// Max number of messages being decoded which a single source IP can
+    /// start. Further messages from the same source are discarded
+    #[serde(default = "default_max_objects_per_source")]
+    pub max_objects_per_source: usize,
+
+    /// Max number of symbols, belonging to messages being decoded, which can
+    /// be buffered from a single source IP
+    #[serde(default = "default_max_symbols_per_source")]
+    pub max_symbols_per_source: usize,
+
+    /// Request the transmission info of a message to the sender of a chunk
+    /// whose transmission info is invalid (eg: corrupted).
+    ///
+    /// Default value `false`
+    #[serde(default)]
+    pub request_transmission_info: bool,
+}
+
+fn default_dedup_window() -> Duration {
+    Duration::from_secs(DEFAULT_DEDUP_WINDOW_SECS)
+}
+
+fn default_max_broadcast_size() -> u64 {
+    DEFAULT_MAX_BROADCAST_SIZE
+}
+
+fn default_max_symbol_size() -> u16 {
+    DEFAULT_MAX_SYMBOL_SIZE
+}
+
+fn default_max_objects_per_source() -> usize {
+    DEFAULT_MAX_OBJECTS_PER_SOURCE
+}
+
+fn default_max_symbols_per_source() -> usize {
+    DEFAULT_MAX_SYMBOLS_PER_SOURCE
 }
 
 impl Configurable for RaptorQDecoder {
@@ -42,6 +230,12 @@ impl Configurable for RaptorQDecoder {
                 DEFAULT_CACHE_PRUNE_EVERY_SECS,
             ),
             cache_ttl: Duration::from_secs(DEFAULT_CACHE_TTL_SECS),
+            dedup_window: default_dedup_window(),
+            max_broadcast_size: DEFAULT_MAX_BROADCAST_SIZE,
+            max_symbol_size: DEFAULT_MAX_SYMBOL_SIZE,
+            max_objects_per_source: DEFAULT_MAX_OBJECTS_PER_SOURCE,
+            max_symbols_per_source: DEFAULT_MAX_SYMBOLS_PER_SOURCE,
+            request_transmission_info: false,
         }
     }
     fn configure(conf: &Self::TConf) -> Self {
@@ -49,29 +243,266 @@ impl Configurable for RaptorQDecoder {
             conf: *conf,
             cache: HashMap::new(),
             last_pruned: Instant::now(),
+            progress: vec![],
+            limiter: SourceLimiter::new(
+                conf.max_objects_per_source,
+                conf.max_symbols_per_source,
+            ),
+            known_info: HashMap::new(),
+            requested_info: HashMap::new(),
+            fetched_info: HashMap::new(),
+            info_requests: vec![],
+            delivered: HashMap::new(),
+            acked: HashMap::new(),
+            acks: vec![],
+            reception: None,
         }
     }
 }
 
 enum CacheStatus {
-    Receiving(ExtDecoder, Instant, u8),
+    Receiving(Box<InFlight>),
     Processed(Instant),
 }
 
+// State of a message being decoded
+struct InFlight {
+    decoder: ExtDecoder,
+    expire_on: Instant,
+    max_height: u8,
+    progress: Option<ReceiveProgress>,
+    contributions: Contributions,
+    started: Instant,
+    chunks: usize,
+}
+
+/// Reception of the last decoded message
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Reception {
+    /// Chunks accepted until the message has been decoded
+    pub(crate) chunks: usize,
+    /// Time from the first accepted chunk to the decoding
+    pub(crate) duration: Duration,
+    /// When the message has been decoded
+    pub(crate) decoded_at: SystemTime,
+}
+
 impl CacheStatus {
     fn expired(&self) -> bool {
         let expire_on = match self {
-            CacheStatus::Receiving(_, expire_on, _) => expire_on,
+            CacheStatus::Receiving(in_flight) => &in_flight.expire_on,
             CacheStatus::Processed(expire_on) => expire_on,
         };
         expire_on < &Instant::now()
     }
 }
 
+impl RaptorQDecoderConf {
+    // Sanity check of the received transmission information, performed before
+    // allocating any decoder state.
+    fn validate(
+        &self,
+        info: &ObjectTransmissionInformation,
+    ) -> Result<(), &'static str> {
+        let symbol_size = info.symbol_size() as u64;
+        let transfer_length = info.transfer_length();
+        let source_blocks = info.source_blocks() as u64;
+        if symbol_size == 0 || symbol_size > self.max_symbol_size as u64 {
+            return Err("invalid symbol size");
+        }
+        if info.symbol_alignment() == 0
+            || !symbol_size.is_multiple_of(info.symbol_alignment() as u64)
+        {
+            return Err("invalid symbol alignment");
+        }
+        if transfer_length == 0 || transfer_length > self.max_broadcast_size {
+            return Err("invalid transfer length");
+        }
+        if source_blocks == 0 || info.sub_blocks() == 0 {
+            return Err("invalid block count");
+        }
+        let symbols = transfer_length.div_ceil(symbol_size);
+        if symbols < source_blocks {
+            return Err("more blocks than symbols");
+        }
+        let symbols_per_block = symbols.div_ceil(source_blocks);
+        if symbols_per_block > MAX_SOURCE_SYMBOLS_PER_BLOCK {
+            return Err("too many symbols per block");
+        }
+        Ok(())
+    }
+}
+
+impl RaptorQDecoder {
+    /// Progress of the messages being decoded, collected since the last call
+    pub(crate) fn drain_progress(
+        &mut self,
+    ) -> impl Iterator<Item = DecodeProgress> + '_ {
+        self.progress.drain(..)
+    }
+
+    /// Transmission info of a decoded message
+    pub(crate) fn transmission_info(
+        &self,
+        uid: &MessageUid,
+    ) -> Option<TransmissionInfo> {
+        self.known_info.get(uid).map(|(info, _)| *info)
+    }
+
+    /// Provide the transmission info of a message, received in response to
+    /// a request. Unrequested or invalid info is discarded
+    pub(crate) fn provide_transmission_info(
+        &mut self,
+        uid: MessageUid,
+        info: TransmissionInfo,
+    ) -> bool {
+        if self.requested_info.remove(&uid).is_none() {
+            return false;
+        }
+        let parsed = ObjectTransmissionInformation::deserialize(&info);
+        match self.conf.validate(&parsed) {
+            Ok(_) => {
+                let expire_on = Instant::now() + self.conf.cache_ttl;
+                self.fetched_info.insert(uid, (info, expire_on));
+                true
+            }
+            Err(e) => {
+                warn!("Discarding transmission info with {}", e);
+                false
+            }
+        }
+    }
+
+    /// Messages whose transmission info must be requested, collected since
+    /// the last call
+    pub(crate) fn drain_info_requests(
+        &mut self,
+    ) -> impl Iterator<Item = MessageUid> + '_ {
+        self.info_requests.drain(..)
+    }
+
+    /// Reception of the message decoded by the last chunk, if any
+    pub(crate) fn take_reception(&mut self) -> Option<Reception> {
+        self.reception.take()
+    }
+
+    /// Decoded messages to acknowledge to the sender of the last chunk,
+    /// collected since the last call
+    pub(crate) fn drain_acks(
+        &mut self,
+    ) -> impl Iterator<Item = MessageUid> + '_ {
+        self.acks.drain(..)
+    }
+
+    // Every X time, prune dupemap cache
+    fn prune(&mut self) {
+        if self.last_pruned.elapsed() > self.conf.cache_prune_every {
+            let limiter = &mut self.limiter;
+            self.cache.retain(|_, status| {
+                if !status.expired() {
+                    return true;
+                }
+                if let CacheStatus::Receiving(in_flight) = status {
+                    limiter.release(&in_flight.contributions);
+                }
+                false
+            });
+            let now = Instant::now();
+            self.known_info.retain(|_, (_, expire_on)| *expire_on > now);
+            self.fetched_info
+                .retain(|_, (_, expire_on)| *expire_on > now);
+            self.requested_info.retain(|_, expire_on| *expire_on > now);
+            let ttl = self.conf.cache_ttl;
+            self.acked
+                .retain(|_, acked| now.duration_since(*acked) < ttl);
+            let window = self.conf.dedup_window;
+            self.delivered
+                .retain(|_, delivered| now.duration_since(*delivered) < window);
+            self.last_pruned = Instant::now();
+        }
+    }
+
+    // Check if a message has been delivered within the dedup window
+    fn is_delivered(&self, uid: &MessageUid) -> bool {
+        self.delivered.get(uid).is_some_and(|delivered| {
+            delivered.elapsed() < self.conf.dedup_window
+        })
+    }
+
+    // Acknowledge a decoded message, unless it has just been acknowledged
+    fn ack(&mut self, uid: MessageUid) {
+        let now = Instant::now();
+        match self.acked.get(&uid) {
+            Some(acked) if now.duration_since(*acked) < ACK_REPEAT_INTERVAL => {
+            }
+            _ => {
+                self.acked.insert(uid, now);
+                self.acks.push(uid);
+            }
+        }
+    }
+
+    /// Apply a new configuration, keeping the messages being decoded
+    pub(crate) fn reconfigure(&mut self, conf: &RaptorQDecoderConf) {
+        self.conf = *conf;
+        self.limiter.set_limits(
+            conf.max_objects_per_source,
+            conf.max_symbols_per_source,
+        );
+    }
+
+    /// Uid of the message a broadcast chunk belongs to, if the chunk is
+    /// valid
+    pub(crate) fn chunk_uid(payload: &BroadcastPayload) -> Option<MessageUid> {
+        let chunked = ChunkedPayload(payload);
+        match chunked.is_valid() {
+            true => chunked.uid().try_into().ok(),
+            false => None,
+        }
+    }
+
+    /// Amount of messages being decoded or recently decoded
+    pub(crate) fn cache_size(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Events raised since the last call
+    pub(crate) fn drain_events(
+        &mut self,
+    ) -> impl Iterator<Item = KadcastEvent> + '_ {
+        self.limiter.drain_events()
+    }
+}
+
 impl Decoder for RaptorQDecoder {
-    fn decode(&mut self, message: Message) -> Option<Message> {
-        if let Message::Broadcast(header, payload) = message {
+    fn decode(&mut self, message: Message, source: IpAddr) -> Option<Message> {
+        if let Message::Broadcast(header, mut payload) = message {
             trace!("> Decoding broadcast chunk");
+            self.prune();
+            if !ChunkedPayload(&payload).is_valid() {
+                warn!("Discarding chunk too short");
+                return None;
+            }
+            let message_uid: MessageUid = ChunkedPayload(&payload)
+                .uid()
+                .try_into()
+                .expect("Wrong length");
+            // Avoid to deliver and repropagate a message twice within the
+            // dedup window
+            // Messages with an application defined id are delivered once,
+            // whatever the content of the other messages with the same id
+            let message_id = payload.message_id;
+            if self.is_delivered(&message_uid)
+                || matches!(&message_id, Some(id) if self.is_delivered(id))
+            {
+                if header.requests_ack() {
+                    self.ack(message_uid);
+                }
+                return None;
+            }
+            if let Some((info, _)) = self.fetched_info.get(&message_uid) {
+                payload.set_transmission_info(info);
+            }
             let chunked = ChunkedPayload(&payload);
             let uid = chunked.safe_uid();
 
@@ -84,18 +515,85 @@ impl Decoder for RaptorQDecoder {
                 // CacheStatus::Receiving status and binds a new Decoder with
                 // the received transmission information
                 std::collections::hash_map::Entry::Vacant(v) => {
-                    v.insert(CacheStatus::Receiving(
-                        ExtDecoder::new(chunked.transmission_info()),
-                        Instant::now() + self.conf.cache_ttl,
-                        payload.height,
-                    ))
+                    let info = chunked.transmission_info();
+                    if let Err(e) = self.conf.validate(&info) {
+                        warn!("Discarding chunk with {} - {:?}", e, info);
+                        let pending = self.requested_info.len();
+                        if self.conf.request_transmission_info
+                            && pending < MAX_PENDING_INFO_REQUESTS
+                        {
+                            if let Entry::Vacant(requested) =
+                                self.requested_info.entry(message_uid)
+                            {
+                                requested.insert(
+                                    Instant::now() + self.conf.cache_ttl,
+                                );
+                                self.info_requests.push(message_uid);
+                            }
+                        }
+                        return None;
+                    }
+                    let contributions = self.limiter.admit_object(source)?;
+                    v.insert(CacheStatus::Receiving(Box::new(InFlight {
+                        decoder: ExtDecoder::new(info),
+                        expire_on: Instant::now() + self.conf.cache_ttl,
+                        max_height: payload.height,
+                        progress: ReceiveProgress::new(&info),
+                        contributions,
+                        started: Instant::now(),
+                        chunks: 0,
+                    })))
                 }
             };
 
             let decoded = match status {
                 // Avoid to repropagate already processed messages
-                CacheStatus::Processed(_) => None,
-                CacheStatus::Receiving(decoder, _, max_height) => {
+                CacheStatus::Processed(_) => {
+                    if header.requests_ack() {
+                        self.ack(message_uid);
+                    }
+                    None
+                }
+                CacheStatus::Receiving(in_flight) => {
+                    let InFlight {
+                        decoder,
+                        max_height,
+                        progress,
+                        contributions,
+                        started,
+                        chunks,
+                        ..
+                    } = in_flight.as_mut();
+                    let packet =
+                        EncodingPacket::deserialize(chunked.encoded_chunk());
+                    let block = packet.payload_id().source_block_number();
+                    if block >= chunked.transmission_info().source_blocks() {
+                        warn!("Discarding chunk with invalid block {}", block);
+                        return None;
+                    }
+                    if !self.limiter.admit_symbol(source, contributions) {
+                        return None;
+                    }
+                    *chunks += 1;
+                    let reception = Reception {
+                        chunks: *chunks,
+                        duration: started.elapsed(),
+                        decoded_at: SystemTime::now(),
+                    };
+
+                    if let Some(progress) = progress {
+                        if let Some(received) =
+                            progress.receive(packet.payload_id())
+                        {
+                            self.progress.push(DecodeProgress {
+                                uid: message_uid,
+                                topic: payload.topic,
+                                received,
+                                needed: progress.needed,
+                            });
+                        }
+                    }
+
                     // Depending on Beta replication, we can receive chunks of
                     // the same message from multiple peers.
                     // Those peers can send with different broadcast height.
@@ -106,14 +604,17 @@ impl Decoder for RaptorQDecoder {
                     }
 
                     decoder
-                        .decode(EncodingPacket::deserialize(
-                            chunked.encoded_chunk(),
-                        ))
+                        .decode(packet)
                         // If decoded successfully, create the new
                         // BroadcastMessage
                         .and_then(|decoded| {
                             let payload = BroadcastPayload {
                                 height: *max_height,
+                                topic: payload.topic,
+                                priority: payload.priority,
+                                origin: payload.origin,
+                                message_id: payload.message_id,
+                                batch: payload.batch,
                                 gossip_frame: decoded,
                             };
                             // Perform sanity check
@@ -132,23 +633,45 @@ impl Decoder for RaptorQDecoder {
                         // will drop useless Decoder and avoid
                         // to propagate already processed messages
                         .map(|decoded| {
-                            self.cache.insert(
+                            let receiving = self.cache.insert(
                                 uid,
                                 CacheStatus::Processed(
                                     Instant::now() + self.conf.cache_ttl,
                                 ),
                             );
+                            if let Some(CacheStatus::Receiving(in_flight)) =
+                                receiving
+                            {
+                                self.limiter.release(&in_flight.contributions);
+                            }
+                            self.known_info.insert(
+                                message_uid,
+                                (
+                                    chunked.raw_transmission_info(),
+                                    Instant::now() + self.conf.cache_ttl,
+                                ),
+                            );
+                            self.delivered.insert(message_uid, Instant::now());
                             trace!("> Broadcast message decoded!");
+                            self.reception = Some(reception);
+                            if header.requests_ack() {
+                                self.acks.push(message_uid);
+                                self.acked.insert(message_uid, Instant::now());
+                            }
                             decoded
                         })
                 }
             };
-            // Every X time, prune dupemap cache
-            if self.last_pruned.elapsed() > self.conf.cache_prune_every {
-                self.cache.retain(|_, status| !status.expired());
-                self.last_pruned = Instant::now();
+            match (decoded, message_id) {
+                // Another message with the same id may have been decoded
+                // while this one was still being received
+                (Some(_), Some(id)) if self.is_delivered(&id) => None,
+                (Some(decoded), Some(id)) => {
+                    self.delivered.insert(id, Instant::now());
+                    Some(decoded)
+                }
+                (decoded, _) => decoded,
             }
-            decoded
         } else {
             Some(message)
         }
@@ -159,19 +682,20 @@ impl Decoder for RaptorQDecoder {
 mod tests {
     use std::{thread, time::Duration};
 
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use bytes::{Bytes, BytesMut};
+
     use super::RaptorQDecoder;
     use crate::transport::encoding::raptorq::RaptorQEncoder;
     use crate::{
-        encoding::{message::Message, payload::BroadcastPayload},
+        encoding::{message::Message, payload::BroadcastPayload, Marshallable},
         peer::PeerNode,
         transport::encoding::{Configurable, Decoder, Encoder},
+        KadcastEvent,
     };
 
-    impl RaptorQDecoder {
-        fn cache_size(&self) -> usize {
-            self.cache.len()
-        }
-    }
+    const SOURCE: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
 
     #[test]
     fn test_expiring_cache() {
@@ -181,6 +705,7 @@ mod tests {
         let mut conf = RaptorQDecoder::default_configuration();
         conf.cache_prune_every = Duration::from_millis(500);
         conf.cache_ttl = Duration::from_secs(1);
+        conf.dedup_window = Duration::ZERO;
         let mut dec = RaptorQDecoder::configure(&conf);
         assert_eq!(dec.cache_size(), 0);
 
@@ -189,10 +714,15 @@ mod tests {
             root.as_header(),
             BroadcastPayload {
                 height: 0,
+                topic: 0,
+                priority: 0,
+                origin: None,
+                message_id: None,
+                batch: false,
                 gossip_frame: vec![0],
             },
         )) {
-            dec.decode(n);
+            dec.decode(n, SOURCE);
         }
         assert_eq!(dec.cache_size(), 1);
 
@@ -209,10 +739,15 @@ mod tests {
                 root.as_header(),
                 BroadcastPayload {
                     height: 0,
+                    topic: 0,
+                    priority: 0,
+                    origin: None,
+                    message_id: None,
+                    batch: false,
                     gossip_frame: vec![i],
                 },
             )) {
-                dec.decode(n);
+                dec.decode(n, SOURCE);
             }
         }
         assert_eq!(dec.cache_size(), 3);
@@ -225,11 +760,442 @@ mod tests {
             root.as_header(),
             BroadcastPayload {
                 height: 0,
+                topic: 0,
+                priority: 0,
+                origin: None,
+                message_id: None,
+                batch: false,
                 gossip_frame: vec![0],
             },
         )) {
-            dec.decode(n);
+            dec.decode(n, SOURCE);
         }
         assert_eq!(dec.cache_size(), 1);
     }
+
+    #[test]
+    fn test_dedup_window() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let mut conf = RaptorQDecoder::default_configuration();
+        conf.cache_prune_every = Duration::from_millis(100);
+        conf.cache_ttl = Duration::from_millis(100);
+        let message = || {
+            Message::Broadcast(
+                root.as_header(),
+                BroadcastPayload::new(0, vec![0; 1000]),
+            )
+        };
+        let decoded = |dec: &mut RaptorQDecoder| {
+            enc.encode(message())
+                .into_iter()
+                .filter_map(|chunk| dec.decode(chunk, SOURCE))
+                .count()
+        };
+
+        let mut dec = RaptorQDecoder::configure(&conf);
+        assert_eq!(decoded(&mut dec), 1);
+        // Received again once pruned from the cache
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(decoded(&mut dec), 0);
+        assert_eq!(dec.cache_size(), 0);
+
+        conf.dedup_window = Duration::from_millis(100);
+        let mut dec = RaptorQDecoder::configure(&conf);
+        assert_eq!(decoded(&mut dec), 1);
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(decoded(&mut dec), 1);
+    }
+
+    #[test]
+    fn test_message_id_dedup() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let mut dec =
+            RaptorQDecoder::configure(&RaptorQDecoder::default_configuration());
+        let decoded = |dec: &mut RaptorQDecoder, payload: BroadcastPayload| {
+            enc.encode(Message::Broadcast(root.as_header(), payload))
+                .into_iter()
+                .filter_map(|chunk| dec.decode(chunk, SOURCE))
+                .count()
+        };
+        let message = |frame: u8| BroadcastPayload::new(0, vec![frame; 1000]);
+
+        // Different contents, same id
+        assert_eq!(decoded(&mut dec, message(1).with_message_id([1; 32])), 1);
+        assert_eq!(decoded(&mut dec, message(2).with_message_id([1; 32])), 0);
+        assert_eq!(decoded(&mut dec, message(2).with_message_id([2; 32])), 1);
+        // Without an id, messages are deduplicated by their content
+        assert_eq!(decoded(&mut dec, message(1)), 1);
+        assert_eq!(decoded(&mut dec, message(1)), 0);
+
+        // Chunks of two messages with the same id received at once
+        let first = enc.encode(Message::Broadcast(
+            root.as_header(),
+            message(3).with_message_id([3; 32]),
+        ));
+        let second = enc.encode(Message::Broadcast(
+            root.as_header(),
+            message(4).with_message_id([3; 32]),
+        ));
+        let delivered = first
+            .into_iter()
+            .zip(second)
+            .flat_map(|(a, b)| [a, b])
+            .filter_map(|chunk| dec.decode(chunk, SOURCE))
+            .count();
+        assert_eq!(delivered, 1);
+    }
+
+    #[test]
+    fn test_transmission_info_exchange() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let message = || {
+            Message::Broadcast(
+                root.as_header(),
+                BroadcastPayload {
+                    height: 0,
+                    topic: 0,
+                    priority: 0,
+                    origin: None,
+                    message_id: None,
+                    batch: false,
+                    gossip_frame: vec![7; 5000],
+                },
+            )
+        };
+
+        // A peer which received the message correctly
+        let mut provider =
+            RaptorQDecoder::configure(&RaptorQDecoder::default_configuration());
+        let mut decoded = None;
+        for chunk in enc.encode(message()) {
+            decoded = decoded.or_else(|| provider.decode(chunk, SOURCE));
+        }
+        assert_eq!(decoded, Some(message()));
+
+        // A peer receiving chunks with corrupted transmission info
+        let mut conf = RaptorQDecoder::default_configuration();
+        conf.request_transmission_info = true;
+        let mut dec = RaptorQDecoder::configure(&conf);
+        let corrupted = || {
+            enc.encode(message()).into_iter().map(|chunk| match chunk {
+                Message::Broadcast(header, mut payload) => {
+                    payload.set_transmission_info(&[0; 12]);
+                    Message::Broadcast(header, payload)
+                }
+                _ => unreachable!(),
+            })
+        };
+        for chunk in corrupted() {
+            assert_eq!(dec.decode(chunk, SOURCE), None);
+        }
+        let requests: Vec<_> = dec.drain_info_requests().collect();
+        assert_eq!(requests.len(), 1);
+        let uid = requests[0];
+        assert_eq!(dec.transmission_info(&uid), None);
+
+        // Unrequested and invalid info are discarded
+        let info = provider.transmission_info(&uid).expect("Known info");
+        assert!(!dec.provide_transmission_info([1; 32], info));
+        assert!(!dec.provide_transmission_info(uid, [0; 12]));
+        assert!(!dec.provide_transmission_info(uid, info));
+
+        // Request again and decode with the fetched info
+        for chunk in corrupted() {
+            dec.decode(chunk, SOURCE);
+        }
+        assert_eq!(dec.drain_info_requests().count(), 1);
+        assert!(dec.provide_transmission_info(uid, info));
+        let mut decoded = None;
+        for chunk in corrupted() {
+            decoded = decoded.or_else(|| dec.decode(chunk, SOURCE));
+        }
+        assert_eq!(decoded, Some(message()));
+        assert_eq!(dec.transmission_info(&uid), Some(info));
+    }
+
+    #[test]
+    fn test_acks() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let message = |header| {
+            Message::Broadcast(
+                header,
+                BroadcastPayload {
+                    height: 0,
+                    topic: 0,
+                    priority: 0,
+                    origin: None,
+                    message_id: None,
+                    batch: false,
+                    gossip_frame: vec![3; 5000],
+                },
+            )
+        };
+        let acked = message(root.as_header().with_ack_request());
+        let uid = enc.message_uid(&acked).expect("Broadcast uid");
+        let chunk = |bytes: Bytes| {
+            Message::unmarshal_binary(&mut &bytes[..]).expect("Valid chunk")
+        };
+
+        // Decoded with the further repair symbols only
+        let mut dec =
+            RaptorQDecoder::configure(&RaptorQDecoder::default_configuration());
+        let first = match &acked {
+            Message::Broadcast(_, payload) => enc.repair_packets(payload),
+            _ => unreachable!(),
+        };
+        let mut buf = BytesMut::new();
+        let mut decoded = false;
+        for repair in enc.repair_into(&acked, first, 5, &mut buf).unwrap() {
+            decoded |= dec.decode(chunk(repair), SOURCE).is_some();
+        }
+        assert!(decoded);
+        assert_eq!(dec.drain_acks().collect::<Vec<_>>(), vec![uid]);
+        // Late chunks are acknowledged again, but not right away
+        for late in enc.encode(message(root.as_header().with_ack_request())) {
+            dec.decode(late, SOURCE);
+        }
+        assert_eq!(dec.drain_acks().count(), 0);
+
+        // Acknowledgements must be requested
+        let mut dec =
+            RaptorQDecoder::configure(&RaptorQDecoder::default_configuration());
+        for chunk in enc.encode(message(root.as_header())) {
+            dec.decode(chunk, SOURCE);
+        }
+        assert_eq!(dec.drain_acks().count(), 0);
+    }
+
+    #[test]
+    fn test_reception() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let mut dec =
+            RaptorQDecoder::configure(&RaptorQDecoder::default_configuration());
+        let chunks = enc.encode(Message::Broadcast(
+            root.as_header(),
+            BroadcastPayload {
+                height: 0,
+                topic: 0,
+                priority: 0,
+                origin: None,
+                message_id: None,
+                batch: false,
+                gossip_frame: vec![1; 10_000],
+            },
+        ));
+        let mut sent = 0;
+        let mut reception = None;
+        for chunk in chunks {
+            sent += 1;
+            if dec.decode(chunk, SOURCE).is_some() {
+                reception = dec.take_reception();
+                break;
+            }
+            assert!(dec.take_reception().is_none());
+        }
+        let reception = reception.expect("Message decoded");
+        assert_eq!(reception.chunks, sent);
+        assert!(dec.take_reception().is_none());
+    }
+
+    #[test]
+    fn test_progress() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let mut dec =
+            RaptorQDecoder::configure(&RaptorQDecoder::default_configuration());
+        let chunks = enc.encode(Message::Broadcast(
+            root.as_header(),
+            BroadcastPayload {
+                height: 0,
+                topic: 4,
+                priority: 0,
+                origin: None,
+                message_id: None,
+                batch: false,
+                gossip_frame: vec![0; 130_000],
+            },
+        ));
+        let mut progress = vec![];
+        let mut decoded = false;
+        for chunk in chunks {
+            // Duplicated chunks don't count
+            let duplicated = Message::unmarshal_binary(&mut &chunk.bytes()[..])
+                .expect("Valid chunk");
+            let already_decoded = decoded;
+            decoded |= dec.decode(chunk, SOURCE).is_some();
+            dec.decode(duplicated, SOURCE);
+            let new_progress: Vec<_> = dec.drain_progress().collect();
+            if already_decoded {
+                assert!(new_progress.is_empty());
+            }
+            progress.extend(new_progress);
+        }
+        assert!(decoded);
+        assert!(progress.len() >= 9 && progress.len() <= 10);
+        let mut last = 0;
+        for p in &progress {
+            assert_eq!(p.topic(), 4);
+            assert_eq!(p.uid(), progress[0].uid());
+            assert!(p.percent() >= last + 10);
+            last = p.percent();
+        }
+
+        // Small messages don't report any progress
+        for chunk in enc.encode(Message::Broadcast(
+            root.as_header(),
+            BroadcastPayload {
+                height: 0,
+                topic: 0,
+                priority: 0,
+                origin: None,
+                message_id: None,
+                batch: false,
+                gossip_frame: vec![1; 1000],
+            },
+        )) {
+            dec.decode(chunk, SOURCE);
+        }
+        assert_eq!(dec.drain_progress().count(), 0);
+    }
+
+    #[test]
+    fn test_invalid_transmission_info() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let mut dec =
+            RaptorQDecoder::configure(&RaptorQDecoder::default_configuration());
+        // Serialized transmission info, see
+        // `ObjectTransmissionInformation::serialize`
+        let info = |len: u64, symbol_size: u16, blocks: u8| {
+            let mut bytes = [0u8; 12];
+            bytes[0..5].copy_from_slice(&len.to_be_bytes()[3..]);
+            bytes[6..8].copy_from_slice(&symbol_size.to_be_bytes());
+            bytes[8] = blocks;
+            bytes[9..11].copy_from_slice(&1u16.to_be_bytes());
+            bytes[11] = 4;
+            bytes
+        };
+        let invalid_infos = [
+            // Object bigger than max_broadcast_size
+            info(1 << 38, 1300, 1),
+            // Symbol bigger than max_symbol_size
+            info(1000, 65000, 1),
+            // Too many symbols per block
+            info(10_000_000, 4, 1),
+            // No source blocks
+            info(1000, 1300, 0),
+        ];
+        for info in invalid_infos {
+            for mut chunk in enc.encode(Message::Broadcast(
+                root.as_header(),
+                BroadcastPayload {
+                    height: 0,
+                    topic: 0,
+                    priority: 0,
+                    origin: None,
+                    message_id: None,
+                    batch: false,
+                    gossip_frame: vec![0; 1000],
+                },
+            )) {
+                if let Message::Broadcast(_, payload) = &mut chunk {
+                    payload.gossip_frame[32..44].copy_from_slice(&info);
+                }
+                assert_eq!(dec.decode(chunk, SOURCE), None);
+            }
+        }
+
+        // Chunk too short to contain the transmission info
+        dec.decode(
+            Message::Broadcast(
+                root.as_header(),
+                BroadcastPayload {
+                    height: 0,
+                    topic: 0,
+                    priority: 0,
+                    origin: None,
+                    message_id: None,
+                    batch: false,
+                    gossip_frame: vec![0; 40],
+                },
+            ),
+            SOURCE,
+        );
+        assert_eq!(dec.cache_size(), 0);
+    }
+
+    #[test]
+    fn test_source_limits() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let mut conf = RaptorQDecoder::default_configuration();
+        conf.max_objects_per_source = 2;
+        conf.max_symbols_per_source = 5;
+        let mut dec = RaptorQDecoder::configure(&conf);
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let chunks = |i: u8, len: usize| {
+            enc.encode(Message::Broadcast(
+                root.as_header(),
+                BroadcastPayload {
+                    height: 0,
+                    topic: 0,
+                    priority: 0,
+                    origin: None,
+                    message_id: None,
+                    batch: false,
+                    gossip_frame: vec![i; len],
+                },
+            ))
+        };
+
+        // Start 2 messages, the third is discarded
+        for i in 0..3 {
+            let chunk = chunks(i, 10_000).remove(0);
+            assert_eq!(dec.decode(chunk, SOURCE), None);
+        }
+        assert_eq!(dec.cache_size(), 2);
+        let events: Vec<_> = dec.drain_events().collect();
+        assert_eq!(events, vec![KadcastEvent::SourceThrottled(SOURCE)]);
+
+        // Other sources are not affected, decoding a message releases the
+        // state of its sources
+        let chunk = chunks(2, 1000).remove(0);
+        assert!(dec.decode(chunk, other).is_some());
+        assert_eq!(dec.cache_size(), 3);
+        assert_eq!(dec.limiter.usage(&other), (0, 0));
+
+        // Buffered symbols are limited too: 2 symbols already buffered
+        let mut first = chunks(0, 10_000).into_iter().skip(1);
+        for chunk in first.by_ref().take(3) {
+            dec.decode(chunk, SOURCE);
+        }
+        assert_eq!(dec.drain_events().count(), 0);
+        for chunk in first.by_ref().take(3) {
+            assert_eq!(dec.decode(chunk, SOURCE), None);
+        }
+        // The source is notified only once while throttled
+        assert_eq!(dec.drain_events().count(), 0);
+        assert_eq!(dec.limiter.usage(&SOURCE), (2, 5));
+
+        // Raising the limits keeps the messages being decoded
+        conf.max_objects_per_source = 3;
+        conf.max_symbols_per_source = 10;
+        dec.reconfigure(&conf);
+        let chunk = chunks(3, 10_000).remove(0);
+        assert_eq!(dec.decode(chunk, SOURCE), None);
+        assert_eq!(dec.limiter.usage(&SOURCE), (3, 6));
+    }
 }
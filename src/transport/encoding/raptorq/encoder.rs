@@ -6,24 +6,60 @@
 
 use crate::transport::{encoding::Configurable, Encoder};
 
-use crate::encoding::{message::Message, payload::BroadcastPayload};
+use crate::encoding::{
+    message::{Message, MessageUid},
+    payload::BroadcastPayload,
+};
 
 const DEFAULT_MIN_REPAIR_PACKETS_PER_BLOCK: u32 = 5;
 const DEFAULT_MTU: u16 = 1300;
 const DEFAULT_FEQ_REDUNDANCY: f32 = 0.15;
 
-use raptorq::Encoder as ExtEncoder;
+// Lower bound of the symbol size left once the optional fields of a payload
+// are taken from the `mtu`
+const MIN_SYMBOL_SIZE: u16 = 64;
+
+use std::io;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use raptorq::{Encoder as ExtEncoder, EncodingPacket};
 use serde_derive::{Deserialize, Serialize};
 
 pub struct RaptorQEncoder {
     conf: RaptorQEncoderConf,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct RaptorQEncoderConf {
     min_repair_packets_per_block: u32,
     mtu: u16,
     fec_redundancy: f32,
+
+    /// FEC profiles applied according to the size of the broadcasted
+    /// message.
+    ///
+    /// A message is encoded with the profile having the smallest `max_size`
+    /// greater or equal than its size. Messages bigger than any profile are
+    /// encoded with the global parameters above
+    #[serde(default)]
+    pub profiles: Vec<RaptorQEncoderProfile>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RaptorQEncoderProfile {
+    /// Max size (in bytes) of the messages the profile applies to
+    pub max_size: usize,
+    pub min_repair_packets_per_block: u32,
+    /// Symbol size. It must not exceed the `max_symbol_size` of the decoder
+    /// of the network peers.
+    ///
+    /// The symbols of the signed messages, and of the ones with an id, are
+    /// made smaller by the length of the signature and the id, which every
+    /// chunk carries
+    pub mtu: u16,
+    pub fec_redundancy: f32,
 }
 
 impl Default for RaptorQEncoderConf {
@@ -32,10 +68,36 @@ impl Default for RaptorQEncoderConf {
             fec_redundancy: DEFAULT_FEQ_REDUNDANCY,
             min_repair_packets_per_block: DEFAULT_MIN_REPAIR_PACKETS_PER_BLOCK,
             mtu: DEFAULT_MTU,
+            profiles: vec![],
         }
     }
 }
 
+impl RaptorQEncoderConf {
+    // Select the parameters to encode a message of `size` bytes
+    fn profile(&self, size: usize) -> RaptorQEncoderProfile {
+        self.profiles
+            .iter()
+            .find(|profile| size <= profile.max_size)
+            .copied()
+            .unwrap_or(RaptorQEncoderProfile {
+                max_size: usize::MAX,
+                min_repair_packets_per_block: self.min_repair_packets_per_block,
+                mtu: self.mtu,
+                fec_redundancy: self.fec_redundancy,
+            })
+    }
+}
+
+impl RaptorQEncoderProfile {
+    // Symbol size of the chunks of `payload`, which fit the same datagrams
+    // whatever the optional fields
+    fn symbol_size(&self, payload: &BroadcastPayload) -> u16 {
+        let metadata_len = payload.chunk_metadata_len() as u16;
+        self.mtu.saturating_sub(metadata_len).max(MIN_SYMBOL_SIZE)
+    }
+}
+
 impl Configurable for RaptorQEncoder {
     type TConf = RaptorQEncoderConf;
 
@@ -43,30 +105,99 @@ impl Configurable for RaptorQEncoder {
         RaptorQEncoderConf::default()
     }
     fn configure(conf: &Self::TConf) -> Self {
-        Self { conf: *conf }
+        let mut conf = conf.clone();
+        conf.profiles.sort_by_key(|profile| profile.max_size);
+        Self { conf }
+    }
+}
+
+impl RaptorQEncoder {
+    // Return the packets encoding the gossip frame of `payload`, alongside
+    // the serialized transmission info
+    fn encoded_packets(
+        &self,
+        payload: &BroadcastPayload,
+    ) -> (Vec<EncodingPacket>, [u8; 12]) {
+        let profile = self.conf.profile(payload.gossip_frame.len());
+        let encoder = ExtEncoder::with_defaults(
+            &payload.gossip_frame,
+            profile.symbol_size(payload),
+        );
+        let transmission_info = encoder.get_config().serialize();
+        (
+            encoder.get_encoded_packets(self.repair_packets(payload)),
+            transmission_info,
+        )
+    }
+
+    /// Amount of repair packets per source block of the encoded `payload`
+    pub(crate) fn repair_packets(&self, payload: &BroadcastPayload) -> u32 {
+        let profile = self.conf.profile(payload.gossip_frame.len());
+        let repair_packets =
+            (payload.gossip_frame.len() as f32 * profile.fec_redundancy
+                / profile.symbol_size(payload) as f32) as u32;
+        repair_packets.max(profile.min_repair_packets_per_block)
+    }
+
+    /// Identifier of the chunks of a broadcast message
+    pub(crate) fn message_uid(&self, msg: &Message) -> Option<MessageUid> {
+        match msg {
+            Message::Broadcast(_, payload) => Some(payload.generate_uid()),
+            _ => None,
+        }
+    }
+
+    /// Serialize `count` further repair chunks per source block of a
+    /// broadcast message, starting from the repair symbol `first`
+    pub(crate) fn repair_into(
+        &self,
+        msg: &Message,
+        first: u32,
+        count: u32,
+        buf: &mut BytesMut,
+    ) -> io::Result<Vec<Bytes>> {
+        let (header, payload) = match msg {
+            Message::Broadcast(header, payload) => (header, payload),
+            _ => return Ok(vec![]),
+        };
+        let profile = self.conf.profile(payload.gossip_frame.len());
+        let encoder = ExtEncoder::with_defaults(
+            &payload.gossip_frame,
+            profile.symbol_size(payload),
+        );
+        let transmission_info = encoder.get_config().serialize();
+        let uid = payload.generate_uid();
+        let mut chunks = vec![];
+        for block in encoder.get_block_encoders() {
+            for packet in block.repair_packets(first, count) {
+                let frame = [
+                    &uid[..],
+                    &transmission_info[..],
+                    &packet.payload_id().serialize()[..],
+                    packet.data(),
+                ];
+                Message::marshal_broadcast(
+                    header,
+                    payload,
+                    &frame,
+                    &mut (&mut *buf).writer(),
+                )?;
+                chunks.push(buf.split().freeze());
+            }
+        }
+        Ok(chunks)
     }
 }
 
 impl Encoder for RaptorQEncoder {
     fn encode<'msg>(&self, msg: Message) -> Vec<Message> {
         if let Message::Broadcast(header, payload) = msg {
-            let encoder =
-                ExtEncoder::with_defaults(&payload.gossip_frame, self.conf.mtu);
-            let mut transmission_info =
-                encoder.get_config().serialize().to_vec();
+            let (packets, transmission_info) = self.encoded_packets(&payload);
 
             let mut base_packet = payload.generate_uid().to_vec();
-            base_packet.append(&mut transmission_info);
-
-            let mut repair_packets =
-                (payload.gossip_frame.len() as f32 * self.conf.fec_redundancy
-                    / self.conf.mtu as f32) as u32;
-            if repair_packets < self.conf.min_repair_packets_per_block {
-                repair_packets = self.conf.min_repair_packets_per_block
-            }
+            base_packet.extend_from_slice(&transmission_info);
 
-            encoder
-                .get_encoded_packets(repair_packets)
+            packets
                 .iter()
                 .map(|encoded_packet| {
                     let mut packet_with_uid = base_packet.clone();
@@ -75,6 +206,11 @@ impl Encoder for RaptorQEncoder {
                         header,
                         BroadcastPayload {
                             height: payload.height,
+                            topic: payload.topic,
+                            priority: payload.priority,
+                            origin: payload.origin,
+                            message_id: payload.message_id,
+                            batch: payload.batch,
                             gossip_frame: packet_with_uid,
                         },
                     )
@@ -84,4 +220,193 @@ impl Encoder for RaptorQEncoder {
             vec![msg]
         }
     }
+
+    fn encode_into(
+        &self,
+        msg: &Message,
+        buf: &mut BytesMut,
+    ) -> io::Result<Vec<Bytes>> {
+        if let Message::Broadcast(header, payload) = msg {
+            let (packets, transmission_info) = self.encoded_packets(payload);
+            let uid = payload.generate_uid();
+            let mut chunks = Vec::with_capacity(packets.len());
+            for packet in &packets {
+                let frame = [
+                    &uid[..],
+                    &transmission_info[..],
+                    &packet.payload_id().serialize()[..],
+                    packet.data(),
+                ];
+                Message::marshal_broadcast(
+                    header,
+                    payload,
+                    &frame,
+                    &mut (&mut *buf).writer(),
+                )?;
+                chunks.push(buf.split().freeze());
+            }
+            Ok(chunks)
+        } else {
+            msg.marshal_into(buf)?;
+            Ok(vec![buf.split().freeze()])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+
+    use super::{RaptorQEncoder, RaptorQEncoderProfile};
+    use crate::{
+        encoding::{
+            message::Message,
+            payload::{BroadcastPayload, OriginSignature},
+        },
+        peer::PeerNode,
+        transport::encoding::{Configurable, Encoder},
+        K_ID_LEN_BYTES,
+    };
+
+    #[test]
+    fn test_size_profiles() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let mut conf = RaptorQEncoder::default_configuration();
+        conf.profiles = vec![
+            RaptorQEncoderProfile {
+                max_size: 10_000,
+                min_repair_packets_per_block: 1,
+                mtu: 1024,
+                fec_redundancy: 0.0,
+            },
+            RaptorQEncoderProfile {
+                max_size: 128,
+                min_repair_packets_per_block: 2,
+                mtu: 128,
+                fec_redundancy: 0.0,
+            },
+        ];
+        let default_enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let enc = RaptorQEncoder::configure(&conf);
+        let chunks = |enc: &RaptorQEncoder, size: usize| {
+            enc.encode(Message::Broadcast(
+                root.as_header(),
+                BroadcastPayload {
+                    height: 0,
+                    topic: 0,
+                    priority: 0,
+                    origin: None,
+                    message_id: None,
+                    batch: false,
+                    gossip_frame: vec![0; size],
+                },
+            ))
+            .len()
+        };
+        // Source symbols plus min repair packets of the matching profile
+        assert_eq!(chunks(&enc, 128), 1 + 2);
+        assert_eq!(chunks(&enc, 5120), 5 + 1);
+        // Bigger messages are encoded with the global parameters
+        assert_eq!(chunks(&enc, 20_000), chunks(&default_enc, 20_000));
+    }
+
+    #[test]
+    fn test_repair_into() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let message = Message::Broadcast(
+            root.as_header(),
+            BroadcastPayload {
+                height: 0,
+                topic: 0,
+                priority: 0,
+                origin: None,
+                message_id: None,
+                batch: false,
+                gossip_frame: (0..5000).map(|i| i as u8).collect(),
+            },
+        );
+        let mut buf = BytesMut::new();
+        let sent = enc.encode_into(&message, &mut buf).unwrap();
+        let first = match &message {
+            Message::Broadcast(_, payload) => enc.repair_packets(payload),
+            _ => unreachable!(),
+        };
+        let repair = enc.repair_into(&message, first, 3, &mut buf).unwrap();
+        assert_eq!(repair.len(), 3);
+        // Repair chunks carry new symbols of the same message
+        assert!(repair.iter().all(|chunk| !sent.contains(chunk)));
+        assert!(repair.iter().all(|chunk| chunk.len() == sent[0].len()));
+        assert!(enc
+            .repair_into(&Message::Ping(root.as_header()), 0, 3, &mut buf)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_chunk_metadata() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let payload = BroadcastPayload {
+            height: 0,
+            topic: 0,
+            priority: 0,
+            origin: None,
+            message_id: None,
+            batch: false,
+            gossip_frame: (0..5000).map(|i| i as u8).collect(),
+        };
+        let signed = BroadcastPayload {
+            origin: Some(OriginSignature {
+                public_key: [1; 32],
+                signature: [2; 64],
+                origin_id: [3; K_ID_LEN_BYTES],
+            }),
+            message_id: Some([4; 32]),
+            ..payload.clone()
+        };
+        let mut buf = BytesMut::new();
+        let mut chunks = |payload| {
+            let message = Message::Broadcast(root.as_header(), payload);
+            enc.encode_into(&message, &mut buf).unwrap()
+        };
+        let plain = chunks(payload);
+        let signed = chunks(signed);
+        // The signature and the id don't make the chunks any bigger
+        let max_len = |chunks: &[Bytes]| chunks.iter().map(Bytes::len).max();
+        assert!(max_len(&signed) <= max_len(&plain));
+        assert!(signed.len() > plain.len());
+    }
+
+    #[test]
+    fn test_encode_into() {
+        let root = PeerNode::generate("192.168.0.1:666");
+        let enc =
+            RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
+        let messages = vec![
+            Message::Ping(root.as_header()),
+            Message::Broadcast(
+                root.as_header(),
+                BroadcastPayload {
+                    height: 3,
+                    topic: 1,
+                    priority: 2,
+                    origin: None,
+                    message_id: None,
+                    batch: false,
+                    gossip_frame: (0..5000).map(|i| i as u8).collect(),
+                },
+            ),
+        ];
+        let mut buf = BytesMut::new();
+        for message in messages {
+            let chunks = enc.encode_into(&message, &mut buf).unwrap();
+            let expected: Vec<_> =
+                enc.encode(message).iter().map(|m| m.bytes()).collect();
+            assert_eq!(chunks, expected);
+        }
+    }
 }
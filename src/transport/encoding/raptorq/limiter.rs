@@ -0,0 +1,132 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use tracing::warn;
+
+use crate::KadcastEvent;
+
+#[derive(Default)]
+struct SourceUsage {
+    objects: usize,
+    symbols: usize,
+    throttled: bool,
+}
+
+/// Decoder state attributed to the sources of an in-flight message
+pub(super) struct Contributions {
+    creator: IpAddr,
+    symbols: HashMap<IpAddr, usize>,
+}
+
+/// Limits the decoder state every single source can allocate
+pub(super) struct SourceLimiter {
+    usage: HashMap<IpAddr, SourceUsage>,
+    max_objects: usize,
+    max_symbols: usize,
+    events: Vec<KadcastEvent>,
+}
+
+impl SourceLimiter {
+    pub(super) fn new(max_objects: usize, max_symbols: usize) -> Self {
+        Self {
+            usage: HashMap::new(),
+            max_objects,
+            max_symbols,
+            events: vec![],
+        }
+    }
+
+    /// Change the limits, applied to the next objects and symbols
+    pub(super) fn set_limits(
+        &mut self,
+        max_objects: usize,
+        max_symbols: usize,
+    ) {
+        self.max_objects = max_objects;
+        self.max_symbols = max_symbols;
+    }
+
+    // Throttle a source, notifying it only the first time
+    fn throttle(
+        usage: &mut SourceUsage,
+        source: IpAddr,
+        events: &mut Vec<KadcastEvent>,
+    ) {
+        if !usage.throttled {
+            warn!("Throttling chunks from {}", source);
+            usage.throttled = true;
+            events.push(KadcastEvent::SourceThrottled(source));
+        }
+    }
+
+    /// Register a new in-flight message received from `source`.
+    ///
+    /// Returns `None` if the source exceeded its limit
+    pub(super) fn admit_object(
+        &mut self,
+        source: IpAddr,
+    ) -> Option<Contributions> {
+        let usage = self.usage.entry(source).or_default();
+        if usage.objects >= self.max_objects {
+            Self::throttle(usage, source, &mut self.events);
+            return None;
+        }
+        usage.objects += 1;
+        Some(Contributions {
+            creator: source,
+            symbols: HashMap::new(),
+        })
+    }
+
+    /// Register a symbol received from `source`.
+    ///
+    /// Returns `false` if the source exceeded its limit
+    pub(super) fn admit_symbol(
+        &mut self,
+        source: IpAddr,
+        contributions: &mut Contributions,
+    ) -> bool {
+        let usage = self.usage.entry(source).or_default();
+        if usage.symbols >= self.max_symbols {
+            Self::throttle(usage, source, &mut self.events);
+            return false;
+        }
+        usage.symbols += 1;
+        *contributions.symbols.entry(source).or_default() += 1;
+        true
+    }
+
+    /// Release the state of a message which has been decoded or expired
+    pub(super) fn release(&mut self, contributions: &Contributions) {
+        if let Some(usage) = self.usage.get_mut(&contributions.creator) {
+            usage.objects = usage.objects.saturating_sub(1);
+        }
+        for (source, symbols) in &contributions.symbols {
+            if let Some(usage) = self.usage.get_mut(source) {
+                usage.symbols = usage.symbols.saturating_sub(*symbols);
+            }
+        }
+        // Sources without pending state are forgotten (and unthrottled)
+        self.usage
+            .retain(|_, usage| usage.objects > 0 || usage.symbols > 0);
+    }
+
+    #[cfg(test)]
+    pub(super) fn usage(&self, source: &IpAddr) -> (usize, usize) {
+        self.usage
+            .get(source)
+            .map_or((0, 0), |usage| (usage.objects, usage.symbols))
+    }
+
+    pub(super) fn drain_events(
+        &mut self,
+    ) -> impl Iterator<Item = KadcastEvent> + '_ {
+        self.events.drain(..)
+    }
+}
@@ -0,0 +1,281 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::config::FloodGuardConfig;
+use crate::event::Flood;
+use crate::kbucket::TrustedPeers;
+
+// Sources tracked, and sources ignored, at the same time. Once reached, the
+// source tracked (or ignored) first is forgotten
+const MAX_TRACKED_SOURCES: usize = 4096;
+
+/// Outcome of the flood guard on a received datagram
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Guard {
+    Admitted,
+    /// The source is ignored
+    Ignored,
+    /// The datagram crosses a threshold, the source is ignored from now on
+    Crossed(Flood),
+}
+
+struct SourceCounts {
+    window_start: Instant,
+    datagrams: u32,
+    malformed: u32,
+    invalid_headers: u32,
+}
+
+impl SourceCounts {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            datagrams: 0,
+            malformed: 0,
+            invalid_headers: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct GuardState {
+    sources: HashMap<IpAddr, SourceCounts>,
+    // Tracked sources along with the start of their window, oldest first
+    sources_order: VecDeque<(IpAddr, Instant)>,
+    // Sources ignored until the given instant
+    ignored: HashMap<IpAddr, Instant>,
+    // Ignored sources along with the end of their penalty, oldest first
+    ignored_order: VecDeque<(IpAddr, Instant)>,
+}
+
+impl GuardState {
+    // Forget the sources whose window is over, and the source tracked first
+    // if there is no room for `ip`
+    fn expire_sources(&mut self, ip: &IpAddr, window: Duration, now: Instant) {
+        while let Some(&(oldest, window_start)) = self.sources_order.front() {
+            let expired = now.duration_since(window_start) >= window;
+            let full = self.sources.len() >= MAX_TRACKED_SOURCES
+                && !self.sources.contains_key(ip);
+            if !expired && !full {
+                break;
+            }
+            self.sources_order.pop_front();
+            // The source may have crossed a threshold and started over
+            if self.sources.get(&oldest).map(|c| c.window_start)
+                == Some(window_start)
+            {
+                self.sources.remove(&oldest);
+            }
+        }
+    }
+
+    // Forget the sources whose penalty is over, and the sources ignored first
+    // beyond the max
+    fn expire_ignored(&mut self, now: Instant) {
+        while let Some(&(oldest, until)) = self.ignored_order.front() {
+            if until > now && self.ignored.len() <= MAX_TRACKED_SOURCES {
+                break;
+            }
+            self.ignored_order.pop_front();
+            if self.ignored.get(&oldest) == Some(&until) {
+                self.ignored.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Per source IP counters of the datagrams, of the malformed ones and of the
+/// header verification failures. The datagrams are counted by the tasks
+/// reading the sockets, which discard the ones of the ignored sources; the
+/// failures are reported by the task decoding them. The trusted hosts are
+/// never counted
+pub(crate) struct FloodGuard {
+    conf: FloodGuardConfig,
+    trusted: Arc<TrustedPeers>,
+    state: Mutex<GuardState>,
+}
+
+impl FloodGuard {
+    pub(crate) fn new(
+        conf: FloodGuardConfig,
+        trusted: Arc<TrustedPeers>,
+    ) -> Self {
+        Self {
+            conf,
+            trusted,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Account a datagram received from `ip`
+    pub(crate) fn datagram(&self, ip: IpAddr, now: Instant) -> Guard {
+        if self.trusted.is_trusted_host(&ip) {
+            return Guard::Admitted;
+        }
+        let mut state = self.lock();
+        state.expire_ignored(now);
+        if state.ignored.contains_key(&ip) {
+            return Guard::Ignored;
+        }
+        self.count(&mut state, ip, Flood::Datagrams, now)
+    }
+
+    /// Account a malformed datagram or an invalid header received from
+    /// `ip`. Returns `true` if the source crossed the threshold
+    pub(crate) fn offence(
+        &self,
+        ip: IpAddr,
+        flood: Flood,
+        now: Instant,
+    ) -> bool {
+        if self.trusted.is_trusted_host(&ip) {
+            return false;
+        }
+        let mut state = self.lock();
+        state.expire_ignored(now);
+        if state.ignored.contains_key(&ip) {
+            return false;
+        }
+        self.count(&mut state, ip, flood, now) == Guard::Crossed(flood)
+    }
+
+    fn count(
+        &self,
+        state: &mut GuardState,
+        ip: IpAddr,
+        flood: Flood,
+        now: Instant,
+    ) -> Guard {
+        state.expire_sources(&ip, self.conf.window, now);
+        let order = &mut state.sources_order;
+        let counts = state.sources.entry(ip).or_insert_with(|| {
+            order.push_back((ip, now));
+            SourceCounts::new(now)
+        });
+        let (count, max) = match flood {
+            Flood::Datagrams => {
+                (&mut counts.datagrams, self.conf.max_datagrams)
+            }
+            Flood::Malformed => {
+                (&mut counts.malformed, self.conf.max_malformed)
+            }
+            Flood::InvalidHeaders => {
+                (&mut counts.invalid_headers, self.conf.max_invalid_headers)
+            }
+        };
+        *count += 1;
+        if *count <= max {
+            return Guard::Admitted;
+        }
+        state.sources.remove(&ip);
+        let until = now + self.conf.ignore_duration;
+        state.ignored.insert(ip, until);
+        state.ignored_order.push_back((ip, until));
+        state.expire_ignored(now);
+        Guard::Crossed(flood)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, GuardState> {
+        self.state.lock().expect("Flood guard poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flood_guard() {
+        let conf = FloodGuardConfig {
+            window: Duration::from_secs(1),
+            max_datagrams: 3,
+            max_malformed: 1,
+            max_invalid_headers: 1,
+            ignore_duration: Duration::from_secs(10),
+        };
+        let trusted = Arc::new(TrustedPeers::default());
+        let guard = FloodGuard::new(conf, trusted.clone());
+        let source = "192.168.0.1".parse().unwrap();
+        let other = "192.168.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(guard.datagram(source, now), Guard::Admitted);
+        }
+        // The counters are reset by each window
+        let later = now + Duration::from_secs(1);
+        assert_eq!(guard.datagram(source, later), Guard::Admitted);
+        assert_eq!(guard.datagram(source, later), Guard::Admitted);
+        assert_eq!(guard.datagram(source, later), Guard::Admitted);
+        assert_eq!(
+            guard.datagram(source, later),
+            Guard::Crossed(Flood::Datagrams)
+        );
+        assert_eq!(guard.datagram(source, later), Guard::Ignored);
+        assert_eq!(guard.datagram(other, later), Guard::Admitted);
+
+        // Offences cross lower thresholds
+        assert!(!guard.offence(other, Flood::Malformed, later));
+        assert!(guard.offence(other, Flood::Malformed, later));
+        assert_eq!(guard.datagram(other, later), Guard::Ignored);
+
+        // Sources are ignored for a while
+        let expired = later + Duration::from_secs(10);
+        assert_eq!(guard.datagram(source, expired), Guard::Admitted);
+        assert!(!guard.offence(source, Flood::InvalidHeaders, expired));
+        assert!(guard.offence(source, Flood::InvalidHeaders, expired));
+
+        // The trusted hosts are never ignored
+        trusted.set(vec![crate::PeerTarget::Ip(source)]);
+        assert_eq!(guard.datagram(source, expired), Guard::Admitted);
+        assert!(!guard.offence(source, Flood::InvalidHeaders, expired));
+    }
+
+    #[test]
+    fn test_tracked_sources() {
+        let conf = FloodGuardConfig {
+            window: Duration::from_secs(1),
+            max_datagrams: 1,
+            max_malformed: 0,
+            max_invalid_headers: 1,
+            ignore_duration: Duration::from_secs(10),
+        };
+        let guard = FloodGuard::new(conf, Arc::default());
+        let source = |i: usize| IpAddr::from((i as u32).to_be_bytes());
+        let now = Instant::now();
+        for i in 0..=MAX_TRACKED_SOURCES {
+            assert_eq!(guard.datagram(source(i), now), Guard::Admitted);
+        }
+        // The source tracked first has been forgotten to make room
+        assert_eq!(guard.lock().sources.len(), MAX_TRACKED_SOURCES);
+        assert_eq!(guard.datagram(source(0), now), Guard::Admitted);
+        assert_eq!(
+            guard.datagram(source(2), now),
+            Guard::Crossed(Flood::Datagrams)
+        );
+
+        // So is the source ignored first
+        for i in 0..=MAX_TRACKED_SOURCES {
+            guard.offence(source(i), Flood::Malformed, now);
+        }
+        assert_eq!(guard.lock().ignored.len(), MAX_TRACKED_SOURCES);
+        assert_eq!(guard.datagram(source(2), now), Guard::Admitted);
+        assert_eq!(guard.datagram(source(3), now), Guard::Ignored);
+
+        // The sources whose window is over are forgotten
+        let later = now + Duration::from_secs(10);
+        assert_eq!(guard.datagram(source(0), later), Guard::Admitted);
+        let state = guard.lock();
+        assert_eq!((state.sources.len(), state.ignored.len()), (1, 0));
+        assert_eq!(state.sources_order.len(), 1);
+        assert!(state.ignored_order.is_empty());
+    }
+}
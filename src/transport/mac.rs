@@ -0,0 +1,65 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use blake2::{Blake2s, Digest};
+
+/// Length of the authentication tag appended to the control messages
+pub(crate) const MAC_LEN: usize = 32;
+
+/// Length of the shared secret used to authenticate control messages
+pub const NETWORK_SECRET_LEN: usize = 32;
+
+pub type NetworkSecret = [u8; NETWORK_SECRET_LEN];
+
+/// Authentication of the control messages (`Ping`, `Pong`, `FindNodes` and
+/// `Nodes`) with a secret shared by the whole network.
+///
+/// The tag is a keyed BLAKE2s of the serialized message, appended to the
+/// message itself.
+pub(crate) struct ControlMac {
+    secret: NetworkSecret,
+}
+
+impl ControlMac {
+    pub(crate) fn new(secret: &NetworkSecret) -> Self {
+        Self { secret: *secret }
+    }
+
+    pub(crate) fn tag(&self, message: &[u8]) -> [u8; MAC_LEN] {
+        let mut hasher = Blake2s::with_params(&self.secret, &[], &[]);
+        hasher.update(message);
+        hasher.finalize().into()
+    }
+
+    /// Check the `trailer` following a serialized `message`
+    pub(crate) fn verify(&self, message: &[u8], trailer: &[u8]) -> bool {
+        // Compare without short-circuit, to not leak the matching prefix
+        trailer.len() == MAC_LEN
+            && self
+                .tag(message)
+                .iter()
+                .zip(trailer)
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ControlMac;
+
+    #[test]
+    fn test_verify() {
+        let mac = ControlMac::new(&[1; 32]);
+        let message = [5, 4, 3, 2, 1];
+        let tag = mac.tag(&message);
+        assert!(mac.verify(&message, &tag));
+        assert!(!mac.verify(&message[1..], &tag));
+        assert!(!mac.verify(&message, &tag[1..]));
+        assert!(!mac.verify(&message, &[]));
+        assert!(!ControlMac::new(&[2; 32]).verify(&message, &tag));
+    }
+}
@@ -0,0 +1,1194 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use blake2::{Blake2s, Digest};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use curve25519_dalek::constants::X25519_BASEPOINT;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use tracing::{debug, warn};
+
+use crate::config::NoiseConfig;
+
+/// Length of the X25519 keys of the Noise handshake
+pub const NOISE_KEY_LEN: usize = 32;
+
+pub type NoiseKey = [u8; NOISE_KEY_LEN];
+
+const PROTOCOL_NAME: &[u8] = b"Noise_XX_25519_ChaChaPoly_BLAKE2s";
+const PROLOGUE: &[u8] = b"kadcast";
+
+const HASH_LEN: usize = 32;
+const BLOCK_LEN: usize = 64;
+const TAG_LEN: usize = 16;
+
+const HANDSHAKE_INIT: u8 = 1;
+const HANDSHAKE_RESPONSE: u8 = 2;
+const HANDSHAKE_FINAL: u8 = 3;
+const TRANSPORT_DATA: u8 = 4;
+const COOKIE_REPLY: u8 = 5;
+
+// Payload of the first handshake message, the port to answer to
+const INIT_PAYLOAD_LEN: usize = 2;
+
+// Receiver index and Noise message of a handshake init, which is followed by
+// the cookie once the responder sent one
+const INIT_LEN: usize = 4 + NOISE_KEY_LEN + INIT_PAYLOAD_LEN;
+
+const COOKIE_LEN: usize = 16;
+
+// How long the secret the cookies are derived from is used. Cookies derived
+// from the previous secret are still accepted
+const COOKIE_SECRET_LIFETIME: Duration = Duration::from_secs(120);
+
+// Type, receiver index and nonce of a transport datagram
+const DATA_HEADER_LEN: usize = 1 + 4 + 8;
+
+// Amount of nonces below the highest received one which are still accepted,
+// since datagrams can be reordered
+const REPLAY_WINDOW: u64 = 128;
+
+// Max amount of handshakes started by this peer and in progress
+const MAX_INITIATED_HANDSHAKES: usize = 1024;
+
+// Max amount of handshakes started by remote peers and in progress, the
+// oldest being forgotten past it
+const MAX_ANSWERED_HANDSHAKES: usize = 1024;
+
+// Max amount of datagrams waiting for a handshake to complete
+const MAX_QUEUED_DATAGRAMS: usize = 1024;
+
+type Hash = [u8; HASH_LEN];
+
+fn hash(data: &[&[u8]]) -> Hash {
+    let mut hasher = Blake2s::new();
+    for data in data {
+        hasher.update(data);
+    }
+    hasher.finalize().into()
+}
+
+fn hmac(key: &Hash, data: &[&[u8]]) -> Hash {
+    let mut inner_pad = [0x36; BLOCK_LEN];
+    let mut outer_pad = [0x5c; BLOCK_LEN];
+    for (i, byte) in key.iter().enumerate() {
+        inner_pad[i] ^= byte;
+        outer_pad[i] ^= byte;
+    }
+    let mut inner = vec![&inner_pad[..]];
+    inner.extend_from_slice(data);
+    hash(&[&outer_pad, &hash(&inner)])
+}
+
+// HKDF of the Noise specification, deriving two outputs
+fn hkdf(chaining_key: &Hash, input: &[u8]) -> (Hash, Hash) {
+    let temp = hmac(chaining_key, &[input]);
+    let first = hmac(&temp, &[&[1]]);
+    let second = hmac(&temp, &[&first, &[2]]);
+    (first, second)
+}
+
+fn scalar(secret: &NoiseKey) -> Scalar {
+    let mut bits = *secret;
+    bits[0] &= 248;
+    bits[31] &= 127;
+    bits[31] |= 64;
+    Scalar::from_bits(bits)
+}
+
+/// Public X25519 key of a secret one
+pub(crate) fn public_key(secret: &NoiseKey) -> NoiseKey {
+    (X25519_BASEPOINT * scalar(secret)).to_bytes()
+}
+
+// X25519 shared secret. `None` for the low order public keys, which would
+// give an all zero secret
+fn dh(secret: &NoiseKey, public: &NoiseKey) -> Option<NoiseKey> {
+    let shared = (MontgomeryPoint(*public) * scalar(secret)).to_bytes();
+    match shared == [0; NOISE_KEY_LEN] {
+        true => None,
+        false => Some(shared),
+    }
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+fn read_u32(bytes: &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?))
+}
+
+struct StaticKeypair {
+    secret: NoiseKey,
+    public: NoiseKey,
+}
+
+// Cipher state of the handshake, whose nonce is implicit
+#[derive(Clone, Default)]
+struct CipherState {
+    key: Option<Hash>,
+    nonce: u64,
+}
+
+impl CipherState {
+    fn encrypt(&mut self, ad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        match &self.key {
+            Some(key) => {
+                let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+                let payload = Payload {
+                    msg: plaintext,
+                    aad: ad,
+                };
+                let ciphertext = cipher
+                    .encrypt(&nonce(self.nonce), payload)
+                    .expect("Encryption failure");
+                self.nonce += 1;
+                ciphertext
+            }
+            None => plaintext.to_vec(),
+        }
+    }
+
+    fn decrypt(&mut self, ad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        match &self.key {
+            Some(key) => {
+                let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+                let payload = Payload {
+                    msg: ciphertext,
+                    aad: ad,
+                };
+                let plaintext =
+                    cipher.decrypt(&nonce(self.nonce), payload).ok()?;
+                self.nonce += 1;
+                Some(plaintext)
+            }
+            None => Some(ciphertext.to_vec()),
+        }
+    }
+}
+
+/// State of a Noise_XX handshake:
+///
+/// ```text
+/// -> e
+/// <- e, ee, s, es
+/// -> s, se
+/// ```
+#[derive(Clone)]
+struct Handshake {
+    chaining_key: Hash,
+    hash: Hash,
+    cipher: CipherState,
+    ephemeral: NoiseKey,
+    remote_ephemeral: NoiseKey,
+    remote_static: NoiseKey,
+}
+
+impl Handshake {
+    fn new(ephemeral: NoiseKey) -> Self {
+        let name = hash(&[PROTOCOL_NAME]);
+        let mut handshake = Handshake {
+            chaining_key: name,
+            hash: name,
+            cipher: CipherState::default(),
+            ephemeral,
+            remote_ephemeral: [0; NOISE_KEY_LEN],
+            remote_static: [0; NOISE_KEY_LEN],
+        };
+        handshake.mix_hash(PROLOGUE);
+        handshake
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        self.hash = hash(&[&self.hash, data]);
+    }
+
+    fn mix_dh(&mut self, secret: &NoiseKey, public: &NoiseKey) -> Option<()> {
+        let (chaining_key, key) =
+            hkdf(&self.chaining_key, &dh(secret, public)?);
+        self.chaining_key = chaining_key;
+        self.cipher = CipherState {
+            key: Some(key),
+            nonce: 0,
+        };
+        Some(())
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let ciphertext = self.cipher.encrypt(&self.hash, plaintext);
+        self.mix_hash(&ciphertext);
+        ciphertext
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let plaintext = self.cipher.decrypt(&self.hash, ciphertext)?;
+        self.mix_hash(ciphertext);
+        Some(plaintext)
+    }
+
+    fn read_key(&mut self, ciphertext: &[u8]) -> Option<NoiseKey> {
+        self.decrypt_and_hash(ciphertext)?.try_into().ok()
+    }
+
+    // -> e
+    fn write_init(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut message = public_key(&self.ephemeral).to_vec();
+        self.mix_hash(&message);
+        message.extend(self.encrypt_and_hash(payload));
+        message
+    }
+
+    fn read_init(&mut self, message: &[u8]) -> Option<Vec<u8>> {
+        self.remote_ephemeral =
+            message.get(..NOISE_KEY_LEN)?.try_into().ok()?;
+        self.mix_hash(&message[..NOISE_KEY_LEN]);
+        self.decrypt_and_hash(&message[NOISE_KEY_LEN..])
+    }
+
+    // <- e, ee, s, es
+    fn write_response(
+        &mut self,
+        keys: &StaticKeypair,
+        payload: &[u8],
+    ) -> Option<Vec<u8>> {
+        let mut message = public_key(&self.ephemeral).to_vec();
+        self.mix_hash(&message);
+        let (ephemeral, remote_ephemeral) =
+            (self.ephemeral, self.remote_ephemeral);
+        self.mix_dh(&ephemeral, &remote_ephemeral)?;
+        message.extend(self.encrypt_and_hash(&keys.public));
+        self.mix_dh(&keys.secret, &remote_ephemeral)?;
+        message.extend(self.encrypt_and_hash(payload));
+        Some(message)
+    }
+
+    fn read_response(&mut self, message: &[u8]) -> Option<Vec<u8>> {
+        let static_end = NOISE_KEY_LEN * 2 + TAG_LEN;
+        self.remote_ephemeral =
+            message.get(..NOISE_KEY_LEN)?.try_into().ok()?;
+        self.mix_hash(&message[..NOISE_KEY_LEN]);
+        let (ephemeral, remote_ephemeral) =
+            (self.ephemeral, self.remote_ephemeral);
+        self.mix_dh(&ephemeral, &remote_ephemeral)?;
+        self.remote_static =
+            self.read_key(message.get(NOISE_KEY_LEN..static_end)?)?;
+        let remote_static = self.remote_static;
+        self.mix_dh(&ephemeral, &remote_static)?;
+        self.decrypt_and_hash(&message[static_end..])
+    }
+
+    // -> s, se
+    fn write_final(
+        &mut self,
+        keys: &StaticKeypair,
+        payload: &[u8],
+    ) -> Option<Vec<u8>> {
+        let mut message = self.encrypt_and_hash(&keys.public);
+        let remote_ephemeral = self.remote_ephemeral;
+        self.mix_dh(&keys.secret, &remote_ephemeral)?;
+        message.extend(self.encrypt_and_hash(payload));
+        Some(message)
+    }
+
+    fn read_final(&mut self, message: &[u8]) -> Option<Vec<u8>> {
+        let static_end = NOISE_KEY_LEN + TAG_LEN;
+        self.remote_static = self.read_key(message.get(..static_end)?)?;
+        let (ephemeral, remote_static) = (self.ephemeral, self.remote_static);
+        self.mix_dh(&ephemeral, &remote_static)?;
+        self.decrypt_and_hash(&message[static_end..])
+    }
+
+    // The transport keys of the initiator, then the ones of the responder
+    fn split(&self) -> (Hash, Hash) {
+        hkdf(&self.chaining_key, &[])
+    }
+}
+
+// Nonces already received, in order to discard the replayed datagrams
+#[derive(Default)]
+struct ReplayWindow {
+    // The highest nonce received, plus one
+    next: u64,
+    // Bit `i` is set if the nonce `next - 1 - i` has been received
+    received: u128,
+}
+
+impl ReplayWindow {
+    fn is_new(&self, nonce: u64) -> bool {
+        if nonce >= self.next {
+            return true;
+        }
+        let age = self.next - 1 - nonce;
+        age < REPLAY_WINDOW && self.received & (1 << age) == 0
+    }
+
+    fn insert(&mut self, nonce: u64) {
+        if nonce >= self.next {
+            let shift = nonce + 1 - self.next;
+            self.received = match shift < REPLAY_WINDOW {
+                true => self.received << shift,
+                false => 0,
+            };
+            self.received |= 1;
+            self.next = nonce + 1;
+        } else {
+            self.received |= 1 << (self.next - 1 - nonce);
+        }
+    }
+}
+
+// Session established with a peer, whose nonces are sent along with the
+// datagrams since they can be lost or reordered
+struct Session {
+    remote_index: u32,
+    send: ChaCha20Poly1305,
+    sent: u64,
+    receive: ChaCha20Poly1305,
+    received: ReplayWindow,
+    established: Instant,
+    // Final handshake message, sent again until the responder proves it
+    // received it by sending a datagram
+    unconfirmed: Option<(Vec<u8>, Instant)>,
+}
+
+impl Session {
+    fn new(
+        handshake: &Handshake,
+        initiator: bool,
+        remote_index: u32,
+        now: Instant,
+    ) -> Self {
+        let (initiator_key, responder_key) = handshake.split();
+        let (send, receive) = match initiator {
+            true => (initiator_key, responder_key),
+            false => (responder_key, initiator_key),
+        };
+        Session {
+            remote_index,
+            send: ChaCha20Poly1305::new(Key::from_slice(&send)),
+            sent: 0,
+            receive: ChaCha20Poly1305::new(Key::from_slice(&receive)),
+            received: ReplayWindow::default(),
+            established: now,
+            unconfirmed: None,
+        }
+    }
+
+    fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let mut datagram =
+            Vec::with_capacity(DATA_HEADER_LEN + plaintext.len() + TAG_LEN);
+        datagram.push(TRANSPORT_DATA);
+        datagram.extend_from_slice(&self.remote_index.to_le_bytes());
+        datagram.extend_from_slice(&self.sent.to_le_bytes());
+        let payload = Payload {
+            msg: plaintext,
+            aad: &datagram,
+        };
+        let ciphertext = self
+            .send
+            .encrypt(&nonce(self.sent), payload)
+            .expect("Encryption failure");
+        self.sent += 1;
+        datagram.extend(ciphertext);
+        datagram
+    }
+
+    fn open(&mut self, datagram: &[u8]) -> Option<Vec<u8>> {
+        let counter = datagram.get(5..DATA_HEADER_LEN)?;
+        let counter = u64::from_le_bytes(counter.try_into().ok()?);
+        if !self.received.is_new(counter) {
+            return None;
+        }
+        let payload = Payload {
+            msg: &datagram[DATA_HEADER_LEN..],
+            aad: &datagram[..DATA_HEADER_LEN],
+        };
+        let plaintext = self.receive.decrypt(&nonce(counter), payload).ok()?;
+        self.received.insert(counter);
+        self.unconfirmed = None;
+        Some(plaintext)
+    }
+}
+
+// Handshake started by this peer, waiting for the response
+struct Initiated {
+    address: SocketAddr,
+    handshake: Handshake,
+    message: Vec<u8>,
+    attempts: usize,
+    sent_at: Instant,
+    queued: Vec<Vec<u8>>,
+}
+
+// Handshake started by a remote peer, waiting for the final message
+struct Answered {
+    address: SocketAddr,
+    handshake: Handshake,
+    remote_index: u32,
+    received_at: Instant,
+}
+
+// Secrets the cookies are derived from, renewed periodically
+struct CookieSecrets {
+    current: Hash,
+    previous: Hash,
+    renewed: Instant,
+}
+
+impl CookieSecrets {
+    fn new(now: Instant) -> Self {
+        CookieSecrets {
+            current: rand::random(),
+            previous: rand::random(),
+            renewed: now,
+        }
+    }
+
+    fn renew(&mut self, now: Instant) {
+        if now.duration_since(self.renewed) >= COOKIE_SECRET_LIFETIME {
+            self.previous = self.current;
+            self.current = rand::random();
+            self.renewed = now;
+        }
+    }
+
+    fn cookie(secret: &Hash, address: &SocketAddr) -> [u8; COOKIE_LEN] {
+        let ip = match address.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+            IpAddr::V6(ip) => ip.octets(),
+        };
+        let mac = hmac(secret, &[&ip, &address.port().to_le_bytes()]);
+        mac[..COOKIE_LEN].try_into().expect("Cookie length")
+    }
+
+    fn is_valid(&self, cookie: &[u8], address: &SocketAddr) -> bool {
+        cookie == Self::cookie(&self.current, address)
+            || cookie == Self::cookie(&self.previous, address)
+    }
+}
+
+/// Outcome of a datagram received with [NoiseSessions::open]
+pub(crate) enum Inbound {
+    /// A datagram of an established session, decrypted
+    Data(Vec<u8>),
+    /// A handshake message, along with the datagrams to send in response
+    Handshake(Vec<(Vec<u8>, SocketAddr)>),
+    /// A datagram not belonging to any session, or not authentic
+    Invalid,
+}
+
+/// Noise sessions with the other peers, see
+/// [Config::noise](crate::config::Config::noise).
+///
+/// Every datagram is sent encrypted with the keys of a session established
+/// through a Noise_XX handshake (X25519, ChaCha20-Poly1305 and BLAKE2s).
+/// Sessions are identified by an index chosen by the receiver, so that they
+/// don't depend on the source port of the datagrams, and are renewed with a
+/// new handshake once older than the rekey interval.
+///
+/// As in WireGuard, a handshake is only answered once the initiator proved
+/// it receives the datagrams sent to its address, by sending back a cookie
+/// derived from it. The cookie is sent in a datagram smaller than the
+/// handshake init, and without keeping any state.
+pub(crate) struct NoiseSessions {
+    keys: StaticKeypair,
+    allowed_keys: Option<Vec<NoiseKey>>,
+    rekey_interval: Duration,
+    handshake_timeout: Duration,
+    handshake_retries: usize,
+    // Announced in the handshakes, the responses are sent to this port
+    listen_port: u16,
+    last_index: u32,
+    // Established sessions, by local index
+    sessions: HashMap<u32, Session>,
+    // Index of the session used to send to each address
+    current: HashMap<SocketAddr, u32>,
+    initiated: HashMap<u32, Initiated>,
+    initiating: HashMap<SocketAddr, u32>,
+    answered: HashMap<u32, Answered>,
+    // Indexes of the answered handshakes, oldest first
+    answered_order: VecDeque<u32>,
+    cookie_secrets: CookieSecrets,
+}
+
+impl NoiseSessions {
+    pub(crate) fn new(config: &NoiseConfig, listen_port: u16) -> Self {
+        let secret = config.static_key.unwrap_or_else(rand::random);
+        Self {
+            keys: StaticKeypair {
+                public: public_key(&secret),
+                secret,
+            },
+            allowed_keys: config.allowed_keys.clone(),
+            rekey_interval: config.rekey_interval,
+            handshake_timeout: config.handshake_timeout,
+            handshake_retries: config.handshake_retries,
+            listen_port,
+            last_index: rand::random(),
+            sessions: HashMap::new(),
+            current: HashMap::new(),
+            initiated: HashMap::new(),
+            initiating: HashMap::new(),
+            answered: HashMap::new(),
+            answered_order: VecDeque::new(),
+            cookie_secrets: CookieSecrets::new(Instant::now()),
+        }
+    }
+
+    fn next_index(&mut self) -> u32 {
+        loop {
+            self.last_index = self.last_index.wrapping_add(1);
+            let index = self.last_index;
+            if !self.sessions.contains_key(&index)
+                && !self.initiated.contains_key(&index)
+                && !self.answered.contains_key(&index)
+            {
+                return index;
+            }
+        }
+    }
+
+    fn is_allowed(&self, key: &NoiseKey) -> bool {
+        match &self.allowed_keys {
+            Some(allowed) => allowed.contains(key),
+            None => true,
+        }
+    }
+
+    /// Encrypt a datagram to send to `to`, starting a handshake if there is
+    /// no session with it or the session is due to be renewed.
+    ///
+    /// Returns the datagrams to send, in order. The datagram is queued until
+    /// the handshake completes if there is no session yet
+    pub(crate) fn seal(
+        &mut self,
+        datagram: &[u8],
+        to: SocketAddr,
+        now: Instant,
+    ) -> Vec<Vec<u8>> {
+        let mut datagrams = vec![];
+        let session = self.current.get(&to).and_then(|i| self.sessions.get(i));
+        let renew = match session {
+            Some(session) => {
+                now.duration_since(session.established) >= self.rekey_interval
+            }
+            None => true,
+        };
+        if renew && !self.initiating.contains_key(&to) {
+            datagrams.extend(self.initiate(to, now));
+        }
+        let timeout = self.handshake_timeout;
+        let sessions = &mut self.sessions;
+        match self.current.get(&to).and_then(|i| sessions.get_mut(i)) {
+            Some(session) => {
+                if let Some((message, sent_at)) = &mut session.unconfirmed {
+                    if now.duration_since(*sent_at) >= timeout {
+                        datagrams.push(message.clone());
+                        *sent_at = now;
+                    }
+                }
+                datagrams.push(session.seal(datagram));
+            }
+            None => {
+                let initiated = &mut self.initiated;
+                let initiated =
+                    self.initiating.get(&to).and_then(|i| initiated.get_mut(i));
+                match initiated {
+                    Some(initiated)
+                        if initiated.queued.len() < MAX_QUEUED_DATAGRAMS =>
+                    {
+                        initiated.queued.push(datagram.to_vec())
+                    }
+                    _ => warn!("Dropping datagram waiting for {}", to),
+                }
+            }
+        }
+        datagrams
+    }
+
+    fn initiate(&mut self, to: SocketAddr, now: Instant) -> Option<Vec<u8>> {
+        if self.initiated.len() >= MAX_INITIATED_HANDSHAKES {
+            warn!("Too many Noise handshakes in progress");
+            return None;
+        }
+        let index = self.next_index();
+        let mut handshake = Handshake::new(rand::random());
+        let mut message = vec![HANDSHAKE_INIT];
+        message.extend_from_slice(&index.to_le_bytes());
+        message.extend(handshake.write_init(&self.listen_port.to_le_bytes()));
+        debug!("Starting Noise handshake with {}", to);
+        self.initiated.insert(
+            index,
+            Initiated {
+                address: to,
+                handshake,
+                message: message.clone(),
+                attempts: 1,
+                sent_at: now,
+                queued: vec![],
+            },
+        );
+        self.initiating.insert(to, index);
+        Some(message)
+    }
+
+    /// Handle a datagram received from `from`
+    pub(crate) fn open(
+        &mut self,
+        datagram: &[u8],
+        from: SocketAddr,
+        now: Instant,
+    ) -> Inbound {
+        let message = &datagram[datagram.len().min(1)..];
+        let handled = match datagram.first() {
+            Some(&TRANSPORT_DATA) => {
+                return match self.open_data(datagram) {
+                    Some(plaintext) => Inbound::Data(plaintext),
+                    None => Inbound::Invalid,
+                }
+            }
+            Some(&HANDSHAKE_INIT) => self.answer(message, from, now),
+            Some(&HANDSHAKE_RESPONSE) => self.complete(message, now),
+            Some(&HANDSHAKE_FINAL) => self.confirm(message, now),
+            Some(&COOKIE_REPLY) => self.retry(message, now),
+            _ => None,
+        };
+        match handled {
+            Some(replies) => Inbound::Handshake(replies),
+            None => Inbound::Invalid,
+        }
+    }
+
+    fn open_data(&mut self, datagram: &[u8]) -> Option<Vec<u8>> {
+        if datagram.len() < DATA_HEADER_LEN + TAG_LEN {
+            return None;
+        }
+        let index = read_u32(&datagram[1..])?;
+        self.sessions.get_mut(&index)?.open(datagram)
+    }
+
+    // Answer a handshake started by a remote peer, or send it the cookie of
+    // its address if it didn't send it along
+    fn answer(
+        &mut self,
+        message: &[u8],
+        from: SocketAddr,
+        now: Instant,
+    ) -> Option<Vec<(Vec<u8>, SocketAddr)>> {
+        let remote_index = read_u32(message)?;
+        let mut handshake = Handshake::new(rand::random());
+        let port = handshake.read_init(message.get(4..INIT_LEN)?)?;
+        let port = u16::from_le_bytes(port.try_into().ok()?);
+        let address = SocketAddr::new(from.ip(), port);
+        self.cookie_secrets.renew(now);
+        if !self.cookie_secrets.is_valid(&message[INIT_LEN..], &address) {
+            let cookie =
+                CookieSecrets::cookie(&self.cookie_secrets.current, &address);
+            let mut reply = vec![COOKIE_REPLY];
+            reply.extend_from_slice(&remote_index.to_le_bytes());
+            reply.extend_from_slice(&cookie);
+            return Some(vec![(reply, address)]);
+        }
+        self.evict_answered();
+        let index = self.next_index();
+        let mut response = vec![HANDSHAKE_RESPONSE];
+        response.extend_from_slice(&remote_index.to_le_bytes());
+        response.extend_from_slice(&index.to_le_bytes());
+        response.extend(handshake.write_response(&self.keys, &[])?);
+        self.answered.insert(
+            index,
+            Answered {
+                address,
+                handshake,
+                remote_index,
+                received_at: now,
+            },
+        );
+        self.answered_order.push_back(index);
+        Some(vec![(response, address)])
+    }
+
+    // Forget the oldest answered handshakes until there is room for another
+    fn evict_answered(&mut self) {
+        while self.answered.len() >= MAX_ANSWERED_HANDSHAKES {
+            match self.answered_order.pop_front() {
+                Some(index) => {
+                    if let Some(answered) = self.answered.remove(&index) {
+                        debug!("Evicting handshake with {}", answered.address);
+                    }
+                }
+                None => break,
+            }
+        }
+        // The handshakes completed or expired are left behind
+        if self.answered_order.len() > MAX_ANSWERED_HANDSHAKES * 2 {
+            let answered = &self.answered;
+            self.answered_order
+                .retain(|index| answered.contains_key(index));
+        }
+    }
+
+    // Send a handshake init again along with the cookie of the responder
+    fn retry(
+        &mut self,
+        message: &[u8],
+        now: Instant,
+    ) -> Option<Vec<(Vec<u8>, SocketAddr)>> {
+        let index = read_u32(message)?;
+        let cookie = message.get(4..4 + COOKIE_LEN)?;
+        let initiated = self.initiated.get_mut(&index)?;
+        // A replayed reply doesn't trigger another init
+        if initiated.message.get(1 + INIT_LEN..) == Some(cookie) {
+            return None;
+        }
+        initiated.message.truncate(1 + INIT_LEN);
+        initiated.message.extend_from_slice(cookie);
+        initiated.sent_at = now;
+        Some(vec![(initiated.message.clone(), initiated.address)])
+    }
+
+    // Complete a handshake started by this peer, sending the final message
+    // and the datagrams waiting for it
+    fn complete(
+        &mut self,
+        message: &[u8],
+        now: Instant,
+    ) -> Option<Vec<(Vec<u8>, SocketAddr)>> {
+        let index = read_u32(message)?;
+        let remote_index = read_u32(message.get(4..)?)?;
+        // The handshake is updated only once the response is authentic
+        let mut handshake = self.initiated.get(&index)?.handshake.clone();
+        handshake.read_response(&message[8..])?;
+        if !self.is_allowed(&handshake.remote_static) {
+            warn!("Refusing Noise session with a not allowed key");
+            return None;
+        }
+        let mut final_message = vec![HANDSHAKE_FINAL];
+        final_message.extend_from_slice(&remote_index.to_le_bytes());
+        final_message.extend(handshake.write_final(&self.keys, &[])?);
+
+        let initiated = self.initiated.remove(&index)?;
+        let address = initiated.address;
+        if self.initiating.get(&address) == Some(&index) {
+            self.initiating.remove(&address);
+        }
+        let mut session = Session::new(&handshake, true, remote_index, now);
+        session.unconfirmed = Some((final_message.clone(), now));
+        let mut datagrams = vec![(final_message, address)];
+        for datagram in initiated.queued {
+            datagrams.push((session.seal(&datagram), address));
+        }
+        debug!("Noise session established with {}", address);
+        self.sessions.insert(index, session);
+        self.current.insert(address, index);
+        Some(datagrams)
+    }
+
+    // Establish the session of a handshake started by a remote peer
+    fn confirm(
+        &mut self,
+        message: &[u8],
+        now: Instant,
+    ) -> Option<Vec<(Vec<u8>, SocketAddr)>> {
+        let index = read_u32(message)?;
+        let mut handshake = self.answered.get(&index)?.handshake.clone();
+        handshake.read_final(&message[4..])?;
+        if !self.is_allowed(&handshake.remote_static) {
+            warn!("Refusing Noise session with a not allowed key");
+            self.answered.remove(&index);
+            return None;
+        }
+        let answered = self.answered.remove(&index)?;
+        let address = answered.address;
+        let mut session =
+            Session::new(&handshake, false, answered.remote_index, now);
+        // A handshake started towards the same peer in the meantime is
+        // superseded by this one
+        let mut datagrams = vec![];
+        if let Some(initiated) = self
+            .initiating
+            .remove(&address)
+            .and_then(|i| self.initiated.remove(&i))
+        {
+            for datagram in initiated.queued {
+                datagrams.push((session.seal(&datagram), address));
+            }
+        }
+        debug!("Noise session established with {}", address);
+        self.sessions.insert(index, session);
+        self.current.insert(address, index);
+        Some(datagrams)
+    }
+
+    /// Send again the handshakes not answered in time and forget the
+    /// expired sessions.
+    ///
+    /// Returns the datagrams to send, and the addresses whose handshake
+    /// failed after every retry
+    pub(crate) fn expire(
+        &mut self,
+        now: Instant,
+    ) -> (Vec<(Vec<u8>, SocketAddr)>, Vec<SocketAddr>) {
+        let mut resend = vec![];
+        let mut failed = vec![];
+        let timeout = self.handshake_timeout;
+        let retries = self.handshake_retries;
+        self.initiated.retain(|_, initiated| {
+            if now.duration_since(initiated.sent_at) < timeout {
+                return true;
+            }
+            if initiated.attempts > retries {
+                failed.push(initiated.address);
+                return false;
+            }
+            initiated.attempts += 1;
+            initiated.sent_at = now;
+            resend.push((initiated.message.clone(), initiated.address));
+            true
+        });
+        for address in &failed {
+            self.initiating.remove(address);
+        }
+        let answer_timeout = timeout * (retries as u32 + 1);
+        self.answered.retain(|_, answered| {
+            now.duration_since(answered.received_at) < answer_timeout
+        });
+        // Sessions are kept for a further rekey interval after being
+        // renewed, for the datagrams still in flight
+        let lifetime = self.rekey_interval * 2;
+        self.sessions.retain(|_, session| {
+            now.duration_since(session.established) < lifetime
+        });
+        let sessions = &self.sessions;
+        self.current.retain(|_, index| sessions.contains_key(index));
+        (resend, failed)
+    }
+
+    /// When the next unanswered handshake has to be sent again
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.initiated
+            .values()
+            .map(|initiated| initiated.sent_at + self.handshake_timeout)
+            .min()
+    }
+
+    #[cfg(test)]
+    fn session_with(&self, address: &SocketAddr) -> Option<&Session> {
+        self.current.get(address).and_then(|i| self.sessions.get(i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(key: &str) -> NoiseKey {
+        (0..key.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&key[i..i + 2], 16).unwrap())
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap()
+    }
+
+    fn sessions(key: u8, port: u16) -> NoiseSessions {
+        let config = NoiseConfig {
+            static_key: Some([key; NOISE_KEY_LEN]),
+            ..Default::default()
+        };
+        NoiseSessions::new(&config, port)
+    }
+
+    fn replies(inbound: Inbound) -> Vec<(Vec<u8>, SocketAddr)> {
+        match inbound {
+            Inbound::Handshake(replies) => replies,
+            _ => panic!("Expected a handshake"),
+        }
+    }
+
+    // Send an init, and then again along with the cookie sent in response
+    fn init_with_cookie(
+        a: &mut NoiseSessions,
+        b: &mut NoiseSessions,
+        init: &[u8],
+        a_source: SocketAddr,
+        now: Instant,
+    ) -> Vec<u8> {
+        let cookie = replies(b.open(init, a_source, now));
+        let b_addr = "10.0.0.2:9000".parse().unwrap();
+        replies(a.open(&cookie[0].0, b_addr, now)).remove(0).0
+    }
+
+    fn data(inbound: Inbound) -> Option<Vec<u8>> {
+        match inbound {
+            Inbound::Data(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_x25519() {
+        // RFC 7748, section 6.1
+        let alice = hex(
+            "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a",
+        );
+        let bob = hex(
+            "5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb",
+        );
+        assert_eq!(
+            public_key(&alice),
+            hex("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+        );
+        assert_eq!(
+            public_key(&bob),
+            hex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+        );
+        let shared = hex(
+            "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742",
+        );
+        assert_eq!(dh(&alice, &public_key(&bob)), Some(shared));
+        assert_eq!(dh(&bob, &public_key(&alice)), Some(shared));
+        assert_eq!(dh(&alice, &[0; NOISE_KEY_LEN]), None);
+    }
+
+    #[test]
+    fn test_handshake() {
+        let now = Instant::now();
+        let a_addr: SocketAddr = "10.0.0.1:9000".parse().unwrap();
+        let b_addr: SocketAddr = "10.0.0.2:9000".parse().unwrap();
+        // The datagrams are sent from an ephemeral port
+        let a_source: SocketAddr = "10.0.0.1:5555".parse().unwrap();
+        let mut a = sessions(1, 9000);
+        let mut b = sessions(2, 9000);
+
+        let init = a.seal(b"first", b_addr, now);
+        assert_eq!(init.len(), 1);
+        // The init is answered once it carries the cookie of the address of
+        // the initiator, sent there without keeping any state
+        let cookie = replies(b.open(&init[0], a_source, now));
+        assert_eq!(cookie.len(), 1);
+        assert_eq!(cookie[0].1, a_addr);
+        assert!(cookie[0].0.len() < init[0].len());
+        assert!(b.answered.is_empty());
+        let init = replies(a.open(&cookie[0].0, b_addr, now));
+        assert_eq!(init.len(), 1);
+        assert_eq!(init[0].1, b_addr);
+        let response = replies(b.open(&init[0].0, a_source, now));
+        assert_eq!(response.len(), 1);
+        assert_eq!(response[0].1, a_addr);
+        let completed = replies(a.open(&response[0].0, b_addr, now));
+        // The final message, then the queued datagram
+        assert_eq!(completed.len(), 2);
+        assert!(replies(b.open(&completed[0].0, a_source, now)).is_empty());
+        let first = data(b.open(&completed[1].0, a_source, now));
+        assert_eq!(first, Some(b"first".to_vec()));
+        // Replayed datagrams are discarded
+        assert!(data(b.open(&completed[1].0, a_source, now)).is_none());
+
+        let reply = b.seal(b"reply", a_addr, now);
+        assert_eq!(reply.len(), 1);
+        assert_eq!(data(a.open(&reply[0], b_addr, now)), Some(b"reply".into()));
+        let second = a.seal(b"second", b_addr, now);
+        assert_eq!(second.len(), 1);
+        let mut tampered = second[0].clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(data(b.open(&tampered, a_source, now)).is_none());
+        assert!(data(b.open(&second[0], a_source, now)).is_some());
+        assert!(a.session_with(&b_addr).unwrap().unconfirmed.is_none());
+
+        // Sessions are renewed once older than the rekey interval
+        let later = now + NoiseConfig::default().rekey_interval;
+        let renewed = a.seal(b"third", b_addr, later);
+        assert_eq!(renewed.len(), 2);
+        assert!(data(b.open(&renewed[1], a_source, later)).is_some());
+    }
+
+    #[test]
+    fn test_allowed_keys() {
+        let now = Instant::now();
+        let b_addr: SocketAddr = "10.0.0.2:9000".parse().unwrap();
+        let a_source: SocketAddr = "10.0.0.1:5555".parse().unwrap();
+        let mut a = sessions(1, 9000);
+        a.allowed_keys = Some(vec![public_key(&[3; NOISE_KEY_LEN])]);
+        let mut b = sessions(2, 9000);
+
+        let init = a.seal(b"first", b_addr, now);
+        let init = init_with_cookie(&mut a, &mut b, &init[0], a_source, now);
+        let response = replies(b.open(&init, a_source, now));
+        assert!(matches!(
+            a.open(&response[0].0, b_addr, now),
+            Inbound::Invalid
+        ));
+        assert!(a.session_with(&b_addr).is_none());
+    }
+
+    #[test]
+    fn test_cookie() {
+        let b_addr: SocketAddr = "10.0.0.2:9000".parse().unwrap();
+        let a_source: SocketAddr = "10.0.0.1:5555".parse().unwrap();
+        let mut a = sessions(1, 9000);
+        let mut b = sessions(2, 9000);
+        // The cookie secrets are renewed from the creation of the sessions
+        let now = Instant::now();
+
+        let init = a.seal(b"first", b_addr, now);
+        let cookie = replies(b.open(&init[0], a_source, now));
+        let init = replies(a.open(&cookie[0].0, b_addr, now));
+        // A replayed cookie doesn't trigger another init
+        assert!(matches!(
+            a.open(&cookie[0].0, b_addr, now),
+            Inbound::Invalid
+        ));
+        // The cookie is bound to the address of the initiator
+        let spoofed: SocketAddr = "10.0.0.3:5555".parse().unwrap();
+        let reply = replies(b.open(&init[0].0, spoofed, now));
+        assert_eq!(reply[0].0[0], COOKIE_REPLY);
+        assert!(b.answered.is_empty());
+
+        // Cookies of the previous secret are still accepted
+        let later = now + COOKIE_SECRET_LIFETIME;
+        let response = replies(b.open(&init[0].0, a_source, later));
+        assert_eq!(response[0].0[0], HANDSHAKE_RESPONSE);
+        let expired = later + COOKIE_SECRET_LIFETIME;
+        let reply = replies(b.open(&init[0].0, a_source, expired));
+        assert_eq!(reply[0].0[0], COOKIE_REPLY);
+    }
+
+    #[test]
+    fn test_evict_answered() {
+        let now = Instant::now();
+        let b_addr: SocketAddr = "10.0.0.2:9000".parse().unwrap();
+        let a_source: SocketAddr = "10.0.0.1:5555".parse().unwrap();
+        let mut a = sessions(1, 9000);
+        let mut b = sessions(2, 9000);
+
+        let init = a.seal(b"first", b_addr, now);
+        let init = init_with_cookie(&mut a, &mut b, &init[0], a_source, now);
+        let response = replies(b.open(&init, a_source, now));
+        for _ in 0..MAX_ANSWERED_HANDSHAKES {
+            let response = replies(b.open(&init, a_source, now));
+            assert_eq!(response[0].0[0], HANDSHAKE_RESPONSE);
+        }
+        assert_eq!(b.answered.len(), MAX_ANSWERED_HANDSHAKES);
+        // The oldest handshake has been forgotten
+        let completed = replies(a.open(&response[0].0, b_addr, now));
+        assert!(matches!(
+            b.open(&completed[0].0, a_source, now),
+            Inbound::Invalid
+        ));
+    }
+
+    #[test]
+    fn test_handshake_vector() {
+        // Static and ephemeral keys of the cacophony test vectors, the
+        // messages computed with an independent implementation of the Noise
+        // specification since the prologue differs
+        let keypair = |secret| StaticKeypair {
+            secret,
+            public: public_key(&secret),
+        };
+        let initiator = keypair(hex(
+            "e61ef9919cde45dd5f82166404bd08e38bceb5dfdfded0a34c8df7ed542214d1",
+        ));
+        let responder = keypair(hex(
+            "4a3acbfdb163dec651dfa3194dece676d437029c62a408b4c5ea9114246e4893",
+        ));
+        let mut i = Handshake::new(hex(
+            "893e28b9dc6ca8d611ab664754b8ceb7bac5117349a4439a6b0569da977c464a",
+        ));
+        let mut r = Handshake::new(hex(
+            "bbdb4cdbd309f1a1f2e1456967fe288cadd6f712d65dc7b7793d5e63da6b375b",
+        ));
+        let payloads: [&[u8]; 3] =
+            [b"Ludwig von Mises", b"Murray Rothbard", b"F. A. Hayek"];
+        let expected = [
+            "ca35def5ae56cec33dc2036731ab14896bc4c75dbb07a61f879f8e3afa4c7944\
+             4c756477696720766f6e204d69736573",
+            "95ebc60d2b1fa672c1f46a8aa265ef51bfe38e7ccb39ec5be34069f144808843\
+             7c365eb362a1c991b0557fe8a7fb187d99346765d93ec63db6c1b01504ebeec5\
+             73177b8088267d95dd63e4434d745b42a196a6cead1e11b2bb13e336fa1361aa\
+             81811a1556be6b579675760e2d38af",
+            "46c3307de83b014258717d97781c1f50936d8b7d50c0722a1739654d10392d41\
+             7f115bc28e3ae0be30574d3a9d21415b802efa4220cff733e7b566deb39abc19\
+             f3dbc35908918480f540d0",
+        ];
+        let bytes = |hex: &str| {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let message = i.write_init(payloads[0]);
+        assert_eq!(message, bytes(expected[0]));
+        assert_eq!(r.read_init(&message), Some(payloads[0].to_vec()));
+        let message = r.write_response(&responder, payloads[1]).unwrap();
+        assert_eq!(message, bytes(expected[1]));
+        assert_eq!(i.read_response(&message), Some(payloads[1].to_vec()));
+        assert_eq!(i.remote_static, responder.public);
+        let message = i.write_final(&initiator, payloads[2]).unwrap();
+        assert_eq!(message, bytes(expected[2]));
+        assert_eq!(r.read_final(&message), Some(payloads[2].to_vec()));
+        assert_eq!(r.remote_static, initiator.public);
+
+        let handshake_hash = bytes(
+            "5746c416ed1ddd7d4d18431c3fabcc8a5c11c5afc480d6147fd3cf92a83f8e14",
+        );
+        assert_eq!(i.hash.to_vec(), handshake_hash);
+        assert_eq!(r.hash.to_vec(), handshake_hash);
+        let keys = (
+            hex("d4ca4072b97ceacdf7eced1f6f2251524d42aa1bc76fa0ad534a3c54f9feb940"),
+            hex("17b77c0d11150ecba7d01c033999b5052bc68824f7629995843bede11235eca1"),
+        );
+        assert_eq!(i.split(), keys);
+        assert_eq!(r.split(), keys);
+    }
+
+    #[test]
+    fn test_expire() {
+        let now = Instant::now();
+        let b_addr: SocketAddr = "10.0.0.2:9000".parse().unwrap();
+        let mut a = sessions(1, 9000);
+        let config = NoiseConfig::default();
+        let init = a.seal(b"first", b_addr, now);
+        assert_eq!(a.next_deadline(), Some(now + config.handshake_timeout));
+
+        let mut at = now;
+        for _ in 0..config.handshake_retries {
+            at += config.handshake_timeout;
+            let (resend, failed) = a.expire(at);
+            assert_eq!(resend, vec![(init[0].clone(), b_addr)]);
+            assert!(failed.is_empty());
+        }
+        at += config.handshake_timeout;
+        let (resend, failed) = a.expire(at);
+        assert!(resend.is_empty());
+        assert_eq!(failed, vec![b_addr]);
+        assert_eq!(a.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let mut window = ReplayWindow::default();
+        for nonce in [0, 2, 1, 200, 150] {
+            assert!(window.is_new(nonce));
+            window.insert(nonce);
+            assert!(!window.is_new(nonce));
+        }
+        assert!(window.is_new(199));
+        // Too old to tell
+        assert!(!window.is_new(2));
+    }
+}
@@ -0,0 +1,117 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use rand::{Rng, RngCore};
+
+use super::MAX_DATAGRAM_SIZE;
+use crate::config::PaddingConfig;
+
+// Length of the trailer carrying the size of the padding
+const TRAILER_LEN: usize = 2;
+
+/// Padding of the outgoing datagrams to a fixed set of sizes, with a random
+/// delay between them.
+///
+/// The padding is made of random bytes followed by a trailer with its
+/// length (trailer included), which allows the receiver to strip it.
+pub(crate) struct TrafficPadding {
+    buckets: Vec<usize>,
+    max_jitter: Duration,
+}
+
+impl TrafficPadding {
+    pub(crate) fn new(conf: &PaddingConfig) -> Self {
+        let mut buckets: Vec<_> = conf
+            .buckets
+            .iter()
+            .map(|&size| size.clamp(TRAILER_LEN, MAX_DATAGRAM_SIZE))
+            .collect();
+        buckets.sort_unstable();
+        buckets.dedup();
+        Self {
+            buckets,
+            max_jitter: conf.max_jitter,
+        }
+    }
+
+    // Size of the padded datagram. Datagrams bigger than every bucket are
+    // padded to a multiple of the biggest one
+    fn padded_size(&self, len: usize) -> usize {
+        let len = len + TRAILER_LEN;
+        let size = match self.buckets.iter().find(|&&size| size >= len) {
+            Some(size) => *size,
+            None => match self.buckets.last() {
+                Some(&max) => len.div_ceil(max) * max,
+                None => len,
+            },
+        };
+        size.min(MAX_DATAGRAM_SIZE).max(len)
+    }
+
+    pub(crate) fn pad(&self, datagram: &[u8]) -> Bytes {
+        let size = self.padded_size(datagram.len());
+        let padding = size - datagram.len();
+        let mut padded = BytesMut::with_capacity(size);
+        padded.put_slice(datagram);
+        padded.resize(size - TRAILER_LEN, 0);
+        rand::thread_rng().fill_bytes(&mut padded[datagram.len()..]);
+        padded.put_u16_le(padding as u16);
+        padded.freeze()
+    }
+
+    /// Strip the padding from a received datagram.
+    ///
+    /// Returns `None` if the datagram has not been padded correctly
+    pub(crate) fn unpad<'a>(&self, datagram: &'a [u8]) -> Option<&'a [u8]> {
+        let len = datagram.len();
+        if len < TRAILER_LEN {
+            return None;
+        }
+        let trailer = [datagram[len - 2], datagram[len - 1]];
+        let padding = u16::from_le_bytes(trailer) as usize;
+        if padding < TRAILER_LEN || padding > len {
+            return None;
+        }
+        Some(&datagram[..len - padding])
+    }
+
+    /// Random delay to wait after sending a datagram
+    pub(crate) fn jitter(&self) -> Duration {
+        if self.max_jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        rand::thread_rng().gen_range(Duration::ZERO..=self.max_jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::TrafficPadding;
+    use crate::config::PaddingConfig;
+
+    #[test]
+    fn test_pad_unpad() {
+        let padding = TrafficPadding::new(&PaddingConfig {
+            buckets: vec![1024, 256],
+            max_jitter: Duration::from_millis(10),
+        });
+        for (len, padded) in [(0, 256), (254, 256), (255, 1024), (2000, 2048)] {
+            let datagram = vec![7; len];
+            let bytes = padding.pad(&datagram);
+            assert_eq!(bytes.len(), padded);
+            assert_eq!(padding.unpad(&bytes), Some(&datagram[..]));
+        }
+        assert_eq!(padding.unpad(&[1]), None);
+        assert_eq!(padding.unpad(&[1, 0]), None);
+        assert_eq!(padding.unpad(&[1, 4, 0]), None);
+        assert!(padding.jitter() <= Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,217 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+use crate::encoding::message::MessageUid;
+
+// Length of the window the limits apply to
+const WINDOW: Duration = Duration::from_secs(1);
+
+// Sources tracked at the same time. Once reached, the source whose window
+// started first is forgotten
+const MAX_TRACKED_SOURCES: usize = 4096;
+
+/// Outcome of the rate limiting of a received message
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Admission {
+    Admitted,
+    /// The message exceeds the limit, which the source already exceeded in
+    /// the current window
+    Dropped,
+    /// The message is the first exceeding the limit in the current window
+    Exceeded,
+}
+
+struct SourceRate {
+    window_start: Instant,
+    control: u32,
+    uids: HashSet<MessageUid>,
+    exceeded: bool,
+}
+
+impl SourceRate {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            control: 0,
+            uids: HashSet::new(),
+            exceeded: false,
+        }
+    }
+}
+
+/// Per-source limits of the control messages and of the distinct broadcast
+/// messages received, over a fixed window of one second.
+///
+/// Sources are identified by their IP, whatever the port they send from
+pub(crate) struct InboundRateLimiter {
+    conf: RateLimitConfig,
+    sources: HashMap<IpAddr, SourceRate>,
+    // Tracked sources, in the order their window started
+    order: VecDeque<IpAddr>,
+}
+
+impl InboundRateLimiter {
+    pub(crate) fn new(conf: RateLimitConfig) -> Self {
+        Self {
+            conf,
+            sources: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Apply the limits changed with `Peer::reconfigure`, from the current
+    /// window on
+    pub(crate) fn reconfigure(&mut self, conf: RateLimitConfig) {
+        self.conf = conf;
+    }
+
+    /// Account a message received from `source`. Broadcast chunks are
+    /// identified by the `uid` of their message, control messages have none
+    pub(crate) fn admit(
+        &mut self,
+        source: IpAddr,
+        uid: Option<MessageUid>,
+        now: Instant,
+    ) -> Admission {
+        // Every tracked source has an entry in `order`, so the sources whose
+        // window is over are found first
+        while let Some(oldest) = self.order.front() {
+            let expired = self.sources.get(oldest).is_none_or(|rate| {
+                now.duration_since(rate.window_start) >= WINDOW
+            });
+            let full = self.sources.len() >= MAX_TRACKED_SOURCES
+                && !self.sources.contains_key(&source);
+            if !expired && !full {
+                break;
+            }
+            self.sources.remove(oldest);
+            self.order.pop_front();
+        }
+        let order = &mut self.order;
+        let rate = self.sources.entry(source).or_insert_with(|| {
+            order.push_back(source);
+            SourceRate::new(now)
+        });
+        let admitted = match uid {
+            None => {
+                let admitted = rate.control < self.conf.max_control_messages;
+                if admitted {
+                    rate.control += 1;
+                }
+                admitted
+            }
+            Some(uid) if rate.uids.contains(&uid) => true,
+            Some(uid) => {
+                let admitted =
+                    rate.uids.len() < self.conf.max_broadcast_uids as usize;
+                if admitted {
+                    rate.uids.insert(uid);
+                }
+                admitted
+            }
+        };
+        match (admitted, rate.exceeded) {
+            (true, _) => Admission::Admitted,
+            (false, true) => Admission::Dropped,
+            (false, false) => {
+                rate.exceeded = true;
+                Admission::Exceeded
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    use super::{Admission, InboundRateLimiter, MAX_TRACKED_SOURCES};
+    use crate::config::RateLimitConfig;
+
+    #[test]
+    fn test_rate_limit() {
+        let mut limiter = InboundRateLimiter::new(RateLimitConfig {
+            max_control_messages: 2,
+            max_broadcast_uids: 1,
+        });
+        let source = "192.168.0.1".parse().unwrap();
+        let other = "192.168.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(limiter.admit(source, None, now), Admission::Admitted);
+        assert_eq!(limiter.admit(source, None, now), Admission::Admitted);
+        assert_eq!(limiter.admit(source, None, now), Admission::Exceeded);
+        assert_eq!(limiter.admit(source, None, now), Admission::Dropped);
+        assert_eq!(limiter.admit(other, None, now), Admission::Admitted);
+
+        // The chunks of an introduced message are not limited
+        let uid = Some([1; 32]);
+        assert_eq!(limiter.admit(source, uid, now), Admission::Admitted);
+        assert_eq!(limiter.admit(source, uid, now), Admission::Admitted);
+        let uid = Some([2; 32]);
+        assert_eq!(limiter.admit(source, uid, now), Admission::Dropped);
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.admit(source, uid, later), Admission::Admitted);
+        assert_eq!(limiter.admit(source, None, later), Admission::Admitted);
+    }
+
+    #[test]
+    fn test_tracked_sources() {
+        let mut limiter = InboundRateLimiter::new(RateLimitConfig {
+            max_control_messages: 1,
+            max_broadcast_uids: 1,
+        });
+        let now = Instant::now();
+        let source = |i: usize| IpAddr::from((i as u32).to_be_bytes());
+        for i in 0..MAX_TRACKED_SOURCES {
+            assert_eq!(
+                limiter.admit(source(i), None, now),
+                Admission::Admitted
+            );
+        }
+        // The source tracked first is forgotten to make room
+        let new = source(MAX_TRACKED_SOURCES);
+        assert_eq!(limiter.admit(new, None, now), Admission::Admitted);
+        assert_eq!(limiter.sources.len(), MAX_TRACKED_SOURCES);
+        assert_eq!(limiter.admit(source(0), None, now), Admission::Admitted);
+        assert_eq!(limiter.admit(source(2), None, now), Admission::Exceeded);
+
+        // The sources whose window is over are forgotten
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.admit(new, None, later), Admission::Admitted);
+        assert_eq!(limiter.sources.len(), 1);
+        assert_eq!(limiter.order.len(), 1);
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        let mut limiter = InboundRateLimiter::new(RateLimitConfig {
+            max_control_messages: 1,
+            max_broadcast_uids: 1,
+        });
+        let source = "192.168.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(limiter.admit(source, None, now), Admission::Admitted);
+        assert_eq!(limiter.admit(source, None, now), Admission::Exceeded);
+
+        // The messages already accounted in the window still count
+        limiter.reconfigure(RateLimitConfig {
+            max_control_messages: 3,
+            max_broadcast_uids: 1,
+        });
+        assert_eq!(limiter.admit(source, None, now), Admission::Admitted);
+        assert_eq!(limiter.admit(source, None, now), Admission::Admitted);
+        assert_eq!(limiter.admit(source, None, now), Admission::Dropped);
+    }
+}
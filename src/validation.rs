@@ -0,0 +1,147 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fmt;
+use std::sync::Arc;
+
+use tracing::{debug, warn};
+
+use crate::event::{self, DropReason, EventSender, KadcastEvent};
+use crate::kbucket::{ScoreReports, INVALID_MESSAGE_PENALTY};
+use crate::MessageInfo;
+
+/// Outcome of the validation of a received broadcast message by a
+/// [MessageValidator]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    /// Relay the message and notify it to the listeners
+    Accept,
+    /// Discard the message, which is invalid. It counts against the
+    /// reputation of the sender
+    Reject,
+    /// Discard the message without blaming the sender (eg: a message which
+    /// is valid but no longer useful)
+    Ignore,
+}
+
+/// Validator of the broadcast messages received, configured with
+/// [Config::validator](crate::config::Config::validator).
+///
+/// It's run once a message is decoded, before it's relayed or notified to
+/// the listeners, so that invalid data is never propagated. The messages
+/// coalesced in a batch are validated one by one, a batch is discarded if
+/// any of its messages is. It's run by the task notifying the listeners, so
+/// it should never block
+pub trait MessageValidator: Send + Sync {
+    fn validate(&self, message: &[u8], metadata: &MessageInfo) -> Validation;
+}
+
+impl fmt::Debug for dyn MessageValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MessageValidator")
+    }
+}
+
+/// Combine the validations of the messages of a batch: it's rejected if any
+/// message is, otherwise ignored if any message is
+fn combine(validations: impl Iterator<Item = Validation>) -> Validation {
+    validations.fold(Validation::Accept, |combined, validation| {
+        match (combined, validation) {
+            (Validation::Reject, _) | (_, Validation::Reject) => {
+                Validation::Reject
+            }
+            (Validation::Ignore, _) | (_, Validation::Ignore) => {
+                Validation::Ignore
+            }
+            _ => Validation::Accept,
+        }
+    })
+}
+
+/// Gate of the received broadcast messages, penalizing the senders of the
+/// rejected ones
+pub(crate) struct Gate {
+    validator: Option<Arc<dyn MessageValidator>>,
+    reports: Arc<ScoreReports>,
+    events: EventSender,
+}
+
+impl Gate {
+    pub(crate) fn new(
+        validator: Option<Arc<dyn MessageValidator>>,
+        reports: Arc<ScoreReports>,
+        events: EventSender,
+    ) -> Self {
+        Self {
+            validator,
+            reports,
+            events,
+        }
+    }
+
+    /// Check if the messages of a payload can be relayed and notified
+    pub(crate) fn admits(
+        &self,
+        messages: &[&[u8]],
+        metadata: &MessageInfo,
+    ) -> bool {
+        let validator = match &self.validator {
+            Some(validator) => validator,
+            None => return true,
+        };
+        let validations = messages
+            .iter()
+            .map(|message| validator.validate(message, metadata));
+        match combine(validations) {
+            Validation::Accept => true,
+            Validation::Ignore => {
+                debug!("Ignoring message from {}", metadata.src);
+                false
+            }
+            Validation::Reject => {
+                warn!("Rejecting invalid message from {}", metadata.src);
+                self.reports
+                    .report(metadata.src.ip(), INVALID_MESSAGE_PENALTY);
+                event::emit(
+                    &self.events,
+                    KadcastEvent::MessageDropped(
+                        metadata.src,
+                        DropReason::Rejected,
+                    ),
+                );
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{combine, Validation};
+
+    #[test]
+    fn test_combine_validations() {
+        let combined =
+            |validations: &[Validation]| combine(validations.iter().copied());
+        assert_eq!(combined(&[]), Validation::Accept);
+        assert_eq!(
+            combined(&[Validation::Accept, Validation::Accept]),
+            Validation::Accept
+        );
+        assert_eq!(
+            combined(&[Validation::Accept, Validation::Ignore]),
+            Validation::Ignore
+        );
+        assert_eq!(
+            combined(&[
+                Validation::Ignore,
+                Validation::Reject,
+                Validation::Accept
+            ]),
+            Validation::Reject
+        );
+    }
+}
@@ -8,12 +8,29 @@
 mod tests {
 
     use std::{
-        collections::HashMap,
-        net::{SocketAddr, ToSocketAddrs},
+        collections::{hash_map::DefaultHasher, HashMap, HashSet},
+        convert::TryInto,
+        hash::{Hash, Hasher},
+        net::{IpAddr, SocketAddr, ToSocketAddrs},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
         time::Duration,
     };
 
-    use kadcast::{config::Config, MessageInfo, NetworkListen, Peer};
+    use kadcast::proto::MIN_RAW_MESSAGE_TYPE;
+    use kadcast::{
+        config::{
+            AmplificationGuardConfig, Config, ConfigError, FloodGuardConfig,
+            MaintenanceConfig, NoiseConfig, PartialConfig, RateLimitConfig,
+        },
+        BootstrapProgress, BroadcastDecoder, BroadcastEncoder,
+        BroadcastOptions, DropReason, Flood, Interception, KadcastEvent,
+        Message, MessageInfo, MessageMiddleware, MessageValidator,
+        NetworkListen, Peer, PeerTarget, RequestError, RequestHandler,
+        SecurityKind, Validation, MAX_REQUEST_LEN,
+    };
     use tokio::{sync::mpsc, time::timeout};
     use tracing::info;
     use tracing::warn;
@@ -60,7 +77,15 @@ mod tests {
             tokio::time::sleep(Duration::from_millis(500)).await;
             peers.insert(i, create_peer(i, bootstraps.clone(), tx.clone()));
         }
-        tokio::time::sleep(Duration::from_millis(2000)).await;
+        for peer in peers.values() {
+            let ready = peer
+                .wait_until_ready(
+                    BOOTSTRAP_COUNT as usize,
+                    Duration::from_secs(5),
+                )
+                .await;
+            assert!(ready, "The peer never joined the network");
+        }
         let mut data: Vec<u8> = vec![0; MESSAGE_SIZE];
         for i in 0..data.len() {
             data[i] = rand::Rng::gen(&mut rand::thread_rng());
@@ -68,7 +93,14 @@ mod tests {
         for i in 1..NODES {
             // for (i, p) in peers.iter() {
             info!("ROUTING TABLE PEER #{}", i);
-            peers.get(&i).unwrap().report().await;
+            let report = peers.get(&i).unwrap().report().await;
+            assert!(report.transport().tasks_running());
+            assert!(report.peers().count() >= BOOTSTRAP_COUNT as usize);
+            let json = report.to_json();
+            assert_eq!(
+                json["buckets"].as_array().unwrap().len(),
+                report.buckets().len()
+            );
             info!("----------------------");
             info!("FIRST 20 ALIVE ADDRESSES FOR #{}", i);
             for s in peers.get(&i).unwrap().alive_nodes(20).await {
@@ -77,11 +109,15 @@ mod tests {
             info!("----------------------");
         }
 
-        peers
+        let handle = peers
             .get(&(NODES - 1))
             .unwrap()
             .broadcast(&data, None)
             .await;
+        assert!(handle.buckets() > 0);
+        let delivery = handle.delivered().await;
+        assert!(delivery.chunks > 0);
+        assert!(delivery.failures.is_empty());
         let res =
             timeout(Duration::from_secs(WAIT_SEC), receive(rx, NODES - 1))
                 .await;
@@ -91,6 +127,1158 @@ mod tests {
         );
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn shutdown_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let bootstrap = create_peer(100, vec![], tx.clone());
+        let bootstrap_addr = format!("127.0.0.1:{}", BASE_PORT + 100);
+        let peer = create_peer(101, vec![bootstrap_addr.clone()], tx);
+        let peer_addr: SocketAddr =
+            format!("127.0.0.1:{}", BASE_PORT + 101).parse().unwrap();
+        let bootstrap_addr: SocketAddr = bootstrap_addr.parse().unwrap();
+
+        // The goodbye is sent to the peers of the routing table. The reply of
+        // the bootstrap may get lost if the peer is not listening yet
+        let known = timeout(Duration::from_secs(5), async {
+            while bootstrap.knows(peer_addr).await.is_none()
+                || peer.knows(bootstrap_addr).await.is_none()
+            {
+                bootstrap.send(b"hello", peer_addr).await;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        });
+        assert!(known.await.is_ok(), "The peer never joined the bootstrap");
+
+        peer.shutdown().await;
+        // The goodbye removes the peer from the table of the bootstrap
+        let removed = timeout(Duration::from_secs(5), async {
+            while bootstrap.knows(peer_addr).await.is_some() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        });
+        assert!(removed.await.is_ok(), "The peer is still in the table");
+        // The socket is released
+        assert!(tokio::net::UdpSocket::bind(peer_addr).await.is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn request_test() {
+        struct Reverse;
+        impl RequestHandler for Reverse {
+            fn on_request(
+                &self,
+                mut request: Vec<u8>,
+                _: SocketAddr,
+            ) -> Option<Vec<u8>> {
+                request.reverse();
+                Some(request)
+            }
+        }
+        let (tx, _rx) = mpsc::channel(100);
+        let listener = |port| KadcastListener {
+            grpc_sender: tx.clone(),
+            receiver_port: port as usize,
+        };
+        let address = |i| format!("127.0.0.1:{}", BASE_PORT + i);
+
+        let conf = Config {
+            public_address: address(102),
+            request_handler: Some(Arc::new(Reverse)),
+            ..Default::default()
+        };
+        let _server = Peer::new(conf, listener(BASE_PORT + 102));
+        let conf = Config {
+            public_address: address(103),
+            request_timeout: Duration::from_millis(200),
+            request_retries: 1,
+            ..Default::default()
+        };
+        let client = Peer::new(conf, listener(BASE_PORT + 103));
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let server_addr = address(102).parse().unwrap();
+        let response = client.request(server_addr, b"hello").await;
+        assert_eq!(response, Ok(b"olleh".to_vec()));
+        // Nobody is listening on the address
+        let missing = address(104).parse().unwrap();
+        let response = client.request(missing, b"hello").await;
+        assert_eq!(response, Err(RequestError::Timeout));
+        let too_long = vec![0; MAX_REQUEST_LEN + 1];
+        let response = client.request(server_addr, &too_long).await;
+        assert_eq!(response, Err(RequestError::TooLong(too_long.len())));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn reconfigure_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let conf = Config {
+            public_address: format!("127.0.0.1:{}", BASE_PORT + 106),
+            ..Default::default()
+        };
+        let peer = Peer::new(
+            conf,
+            KadcastListener {
+                grpc_sender: tx,
+                receiver_port: (BASE_PORT + 106) as usize,
+            },
+        );
+        let mut events = peer.events();
+        let invalid = PartialConfig {
+            max_objects_per_source: Some(0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            peer.reconfigure(invalid).await,
+            Err(ConfigError::OutOfRange(_))
+        ));
+        let invalid = PartialConfig {
+            rate_limit: Some(RateLimitConfig {
+                max_control_messages: 0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            peer.reconfigure(invalid).await,
+            Err(ConfigError::OutOfRange("rate_limit.max_control_messages"))
+        ));
+        let partial = PartialConfig {
+            node_ttl: Some(Duration::from_secs(5)),
+            max_objects_per_source: Some(10),
+            ..Default::default()
+        };
+        peer.reconfigure(partial)
+            .await
+            .expect("Valid partial config");
+        // The bootstrap attempts may be notified first
+        let event = timeout(Duration::from_secs(1), async {
+            loop {
+                match events.recv().await {
+                    Ok(KadcastEvent::Bootstrap(_)) => continue,
+                    event => return event,
+                }
+            }
+        })
+        .await;
+        assert!(matches!(event, Ok(Ok(KadcastEvent::Reconfigured))));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn decode_failed_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let address = format!("127.0.0.1:{}", BASE_PORT + 107);
+        let conf = Config {
+            public_address: address.clone(),
+            ..Default::default()
+        };
+        let peer = Peer::new(
+            conf,
+            KadcastListener {
+                grpc_sender: tx,
+                receiver_port: (BASE_PORT + 107) as usize,
+            },
+        );
+        let mut events = peer.events();
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.send_to(&[0xff; 8], &address).unwrap();
+        let from = socket.local_addr().unwrap();
+        let failed = timeout(Duration::from_secs(1), async {
+            loop {
+                match events.recv().await {
+                    Ok(KadcastEvent::DecodeFailed(addr)) => break addr,
+                    Ok(_) => continue,
+                    Err(e) => panic!("No event received: {}", e),
+                }
+            }
+        })
+        .await;
+        assert_eq!(failed.ok(), Some(from));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn rate_limit_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let conf = Config {
+            public_address: format!("127.0.0.1:{}", BASE_PORT + 116),
+            rate_limit: Some(RateLimitConfig {
+                max_broadcast_uids: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let receiver = Peer::new(
+            conf,
+            KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + 116) as usize,
+            },
+        );
+        let mut events = receiver.events();
+        let sender = create_peer(117, vec![], tx);
+        let target = receiver.public_addr();
+
+        sender.broadcast_to(&[1; MESSAGE_SIZE], &[target]).await;
+        sender.broadcast_to(&[2; MESSAGE_SIZE], &[target]).await;
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (_, (message, _, _)) = received.unwrap().unwrap();
+        assert_eq!(message, vec![1; MESSAGE_SIZE]);
+        let dropped = timeout(Duration::from_secs(1), async {
+            loop {
+                match events.recv().await {
+                    Ok(KadcastEvent::MessageDropped(_, reason)) => {
+                        break reason
+                    }
+                    Ok(_) => continue,
+                    Err(e) => panic!("No event received: {}", e),
+                }
+            }
+        })
+        .await;
+        assert_eq!(dropped.ok(), Some(DropReason::RateLimited));
+        let limited = timeout(Duration::from_secs(1), rx.recv()).await;
+        assert!(limited.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn rate_limit_reconfigure_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let receiver = create_peer(153, vec![], tx.clone());
+        let sender = create_peer(154, vec![], tx);
+        let target = receiver.public_addr();
+
+        // The limits are enabled at runtime
+        let partial = PartialConfig {
+            rate_limit: Some(RateLimitConfig {
+                max_broadcast_uids: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        receiver
+            .reconfigure(partial)
+            .await
+            .expect("Valid partial config");
+        sender.broadcast_to(&[1; MESSAGE_SIZE], &[target]).await;
+        sender.broadcast_to(&[2; MESSAGE_SIZE], &[target]).await;
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (_, (message, _, _)) = received.unwrap().unwrap();
+        assert_eq!(message, vec![1; MESSAGE_SIZE]);
+        let limited = timeout(Duration::from_secs(1), rx.recv()).await;
+        assert!(limited.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn bootstrap_retry_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let bootstrap_addr = format!("127.0.0.1:{}", BASE_PORT + 108);
+        // The peer starts before its bootstrap
+        let peer = create_peer(109, vec![bootstrap_addr], tx.clone());
+        let mut events = peer.events();
+        let retrying = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(KadcastEvent::Bootstrap(
+                    BootstrapProgress::Retrying(_),
+                )) = events.recv().await
+                {
+                    return;
+                }
+            }
+        });
+        assert!(retrying.await.is_ok(), "The bootstrap is not retried");
+        let _bootstrap = create_peer(108, vec![], tx);
+        assert!(
+            peer.wait_until_ready(1, Duration::from_secs(10)).await,
+            "The peer never joined the bootstrap"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn ephemeral_port_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let listener = || KadcastListener {
+            grpc_sender: tx.clone(),
+            receiver_port: 0,
+        };
+        let conf = Config {
+            public_address: "127.0.0.1:0".to_string(),
+            ..Default::default()
+        };
+        let peer = Peer::new(conf, listener());
+        let port = peer.listen_addr().port();
+        assert_ne!(port, 0);
+        // The bound port is advertised
+        assert_eq!(peer.header().sender_port(), port);
+        assert_eq!(peer.public_addr(), peer.listen_addr());
+        assert_eq!(peer.id(), peer.header().binary_id().as_binary());
+        assert_eq!(peer.bucket_height_for(peer.id()), None);
+
+        // The advertised port is independent of the listening one
+        let public_port = (BASE_PORT + 110) as u16;
+        let conf = Config {
+            public_address: format!("127.0.0.1:{}", public_port),
+            listen_address: Some("127.0.0.1:0".to_string()),
+            ..Default::default()
+        };
+        let peer = Peer::new(conf, listener());
+        assert_ne!(peer.listen_addr().port(), 0);
+        assert_eq!(peer.header().sender_port(), public_port);
+        assert_eq!(peer.public_addr().port(), public_port);
+    }
+
+    #[test]
+    fn runtime_test() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("kadcast-peer")
+            .enable_all()
+            .build()
+            .unwrap();
+        let (tx, _rx) = mpsc::channel(100);
+        // No ambient runtime: the tasks are spawned on the given one
+        let conf = Config {
+            public_address: "127.0.0.1:0".to_string(),
+            runtime: Some(runtime.handle().clone()),
+            ..Default::default()
+        };
+        let peer = Peer::new(
+            conf,
+            KadcastListener {
+                grpc_sender: tx,
+                receiver_port: 0,
+            },
+        );
+        let mut events = peer.events();
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.send_to(&[0xff; 8], peer.listen_addr()).unwrap();
+        let failed = runtime.block_on(async {
+            let decode_failed = async {
+                while !matches!(
+                    events.recv().await,
+                    Ok(KadcastEvent::DecodeFailed(_))
+                ) {}
+            };
+            timeout(Duration::from_secs(1), decode_failed).await
+        });
+        assert!(failed.is_ok(), "The datagram is not received");
+        drop(peer);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn broadcast_to_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let sender = create_peer(111, vec![], tx.clone());
+        let _receiver = create_peer(112, vec![], tx.clone());
+        let _other = create_peer(113, vec![], tx);
+        let target: SocketAddr =
+            format!("127.0.0.1:{}", BASE_PORT + 112).parse().unwrap();
+
+        // The receiver is not in the routing table of the sender
+        let data = vec![7; MESSAGE_SIZE];
+        let handle = sender.broadcast_to(&data, &[target]).await;
+        assert_eq!(handle.delegates(), 1);
+        let delivery = handle.delivered().await;
+        assert!(delivery.failures.is_empty());
+
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (port, (message, _, height)) = received.unwrap().unwrap();
+        assert_eq!(port, (BASE_PORT + 112) as usize);
+        assert_eq!(message, data);
+        // Never relayed
+        assert_eq!(height, 0);
+        let relayed = timeout(Duration::from_secs(1), rx.recv()).await;
+        assert!(relayed.is_err());
+    }
+
+    // Stamp the outgoing broadcasts, and drop the incoming ones starting
+    // with a 0
+    struct Stamper;
+
+    impl MessageMiddleware for Stamper {
+        fn inbound(
+            &self,
+            message: &mut Message,
+            _from: SocketAddr,
+        ) -> Interception {
+            match message {
+                Message::Broadcast(_, payload)
+                    if payload.gossip_frame().first() == Some(&0) =>
+                {
+                    Interception::Drop
+                }
+                _ => Interception::Pass,
+            }
+        }
+
+        fn outbound(
+            &self,
+            message: &mut Message,
+            _targets: &[SocketAddr],
+        ) -> Interception {
+            match message {
+                Message::Broadcast(_, payload) => {
+                    payload.gossip_frame_mut().push(0xff);
+                    Interception::Delay(Duration::from_millis(100))
+                }
+                _ => Interception::Pass,
+            }
+        }
+    }
+
+    const CODEC_CHUNK_LEN: usize = 1000;
+
+    // Split the frames in chunks prefixed by the hash of the frame, their
+    // index and their count. The decoded messages are recorded, so that
+    // peers sharing the codec decode a message once
+    #[derive(Default)]
+    struct SplitCodec {
+        encoded: AtomicUsize,
+        chunks: Mutex<HashMap<u64, Vec<Option<Vec<u8>>>>>,
+        decoded: Mutex<HashSet<u64>>,
+    }
+
+    impl BroadcastEncoder for SplitCodec {
+        fn encode(&self, gossip_frame: &[u8]) -> Vec<Vec<u8>> {
+            let mut hasher = DefaultHasher::new();
+            gossip_frame.hash(&mut hasher);
+            let hash = hasher.finish().to_le_bytes();
+            let parts: Vec<_> = gossip_frame.chunks(CODEC_CHUNK_LEN).collect();
+            self.encoded.fetch_add(parts.len(), Ordering::Relaxed);
+            parts
+                .iter()
+                .enumerate()
+                .map(|(i, part)| {
+                    let index = (i as u16).to_le_bytes();
+                    let count = (parts.len() as u16).to_le_bytes();
+                    [&hash[..], &index, &count, part].concat()
+                })
+                .collect()
+        }
+    }
+
+    impl BroadcastDecoder for SplitCodec {
+        fn decode(&self, chunk: &[u8], _source: IpAddr) -> Option<Vec<u8>> {
+            if chunk.len() < 12 {
+                return None;
+            }
+            let hash = u64::from_le_bytes(chunk[..8].try_into().unwrap());
+            let index = u16::from_le_bytes([chunk[8], chunk[9]]) as usize;
+            let count = u16::from_le_bytes([chunk[10], chunk[11]]) as usize;
+            if index >= count || self.decoded.lock().unwrap().contains(&hash) {
+                return None;
+            }
+            let mut chunks = self.chunks.lock().unwrap();
+            let parts = chunks.entry(hash).or_insert_with(|| vec![None; count]);
+            parts[index] = Some(chunk[12..].to_vec());
+            if parts.iter().any(Option::is_none) {
+                return None;
+            }
+            let parts = chunks.remove(&hash).unwrap();
+            self.decoded.lock().unwrap().insert(hash);
+            Some(parts.into_iter().flatten().flatten().collect())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn custom_codec_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let codec = Arc::new(SplitCodec::default());
+        let conf = |port| Config {
+            public_address: format!("127.0.0.1:{}", BASE_PORT + port),
+            broadcast_encoder: Some(codec.clone()),
+            broadcast_decoder: Some(codec.clone()),
+            ..Default::default()
+        };
+        let invalid = Config {
+            broadcast_ack: Some(Default::default()),
+            ..conf(155)
+        };
+        assert!(matches!(
+            invalid.validate(),
+            Err(ConfigError::OutOfRange("broadcast_ack"))
+        ));
+
+        let peer = |port| {
+            let listener = KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + port) as usize,
+            };
+            Peer::new(conf(port), listener)
+        };
+        let sender = peer(155);
+        let receiver = peer(156);
+        let other = peer(157);
+
+        // The peers share the cache of the decoded messages, which are
+        // delivered once
+        let data = vec![7; MESSAGE_SIZE];
+        let targets = [receiver.public_addr(), other.public_addr()];
+        sender.broadcast_to(&data, &targets).await;
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (_, (message, _, _)) = received.unwrap().unwrap();
+        assert_eq!(message, data);
+        let again = timeout(Duration::from_secs(1), rx.recv()).await;
+        assert!(again.is_err());
+        assert_eq!(
+            codec.encoded.load(Ordering::Relaxed),
+            MESSAGE_SIZE / CODEC_CHUNK_LEN
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn middleware_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let peer = |port| {
+            let conf = Config {
+                public_address: format!("127.0.0.1:{}", BASE_PORT + port),
+                middlewares: vec![Arc::new(Stamper)],
+                ..Default::default()
+            };
+            let listener = KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + port) as usize,
+            };
+            Peer::new(conf, listener)
+        };
+        let sender = peer(114);
+        let _receiver = peer(115);
+        let target: SocketAddr =
+            format!("127.0.0.1:{}", BASE_PORT + 115).parse().unwrap();
+
+        sender.broadcast_to(&[0, 1, 2], &[target]).await;
+        sender.broadcast_to(&[1, 2, 3], &[target]).await;
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (port, (message, _, _)) = received.unwrap().unwrap();
+        assert_eq!(port, (BASE_PORT + 115) as usize);
+        assert_eq!(message, vec![1, 2, 3, 0xff]);
+        let dropped = timeout(Duration::from_secs(1), rx.recv()).await;
+        assert!(dropped.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn health_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let bootstrap = create_peer(118, vec![], tx.clone());
+        let health = bootstrap.health().await;
+        assert!(health.tasks_running());
+        assert_eq!(health.alive_peers(), 0);
+        assert_eq!(health.decoder_cache(), 0);
+        assert!(!health.is_ready(1));
+
+        let bootstrap_addr = format!("127.0.0.1:{}", BASE_PORT + 118);
+        let peer = create_peer(119, vec![bootstrap_addr], tx);
+        assert!(peer.wait_until_ready(1, Duration::from_secs(5)).await);
+        peer.broadcast_to(&[1; MESSAGE_SIZE], &[bootstrap.public_addr()])
+            .await;
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        assert!(received.unwrap().is_some());
+
+        let health = bootstrap.health().await;
+        assert!(health.is_ready(1));
+        assert_eq!(health.decoder_cache(), 1);
+        assert_eq!(health.outbound_queue(), 0);
+    }
+
+    // Collect the raw messages received
+    struct RawReceiver(mpsc::Sender<(u8, Vec<u8>)>);
+
+    impl MessageMiddleware for RawReceiver {
+        fn inbound(
+            &self,
+            message: &mut Message,
+            _from: SocketAddr,
+        ) -> Interception {
+            match message {
+                Message::Raw(_, message_type, body) => {
+                    let _ = self.0.try_send((*message_type, body.clone()));
+                    Interception::Drop
+                }
+                _ => Interception::Pass,
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn raw_message_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let (raw_tx, mut raw_rx) = mpsc::channel(100);
+        let conf = Config {
+            public_address: format!("127.0.0.1:{}", BASE_PORT + 120),
+            middlewares: vec![Arc::new(RawReceiver(raw_tx))],
+            ..Default::default()
+        };
+        let receiver = Peer::new(
+            conf,
+            KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + 120) as usize,
+            },
+        );
+        let sender = create_peer(121, vec![], tx);
+        let target = vec![receiver.public_addr()];
+
+        let raw = |body: Vec<u8>| {
+            Message::Raw(sender.header(), MIN_RAW_MESSAGE_TYPE + 1, body)
+        };
+        sender
+            .send_message(raw(vec![1, 2, 3]), target.clone())
+            .await;
+        let received = timeout(Duration::from_secs(5), raw_rx.recv()).await;
+        assert_eq!(
+            received.unwrap(),
+            Some((MIN_RAW_MESSAGE_TYPE + 1, vec![1, 2, 3]))
+        );
+
+        #[cfg(feature = "unsafe_proto")]
+        {
+            sender.send_raw(raw(vec![4]), target).await;
+            let received = timeout(Duration::from_secs(5), raw_rx.recv()).await;
+            assert_eq!(
+                received.unwrap(),
+                Some((MIN_RAW_MESSAGE_TYPE + 1, vec![4]))
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn observer_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let bootstrap = create_peer(122, vec![], tx.clone());
+        let bootstrap_addr = format!("127.0.0.1:{}", BASE_PORT + 122);
+        let conf = Config {
+            public_address: format!("127.0.0.1:{}", BASE_PORT + 123),
+            bootstrapping_nodes: vec![bootstrap_addr.clone()],
+            observer: true,
+            // The observer never contacts the peers joining later
+            recursive_discovery: false,
+            maintenance: MaintenanceConfig {
+                min_peers: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let observer = Peer::new(
+            conf,
+            KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + 123) as usize,
+            },
+        );
+        assert!(observer.wait_until_ready(1, Duration::from_secs(5)).await);
+        assert!(!observer.is_relay_enabled());
+        observer.set_relay_enabled(true);
+        assert!(!observer.is_relay_enabled());
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // The bootstrapping node never hands out the observer
+        let peer = create_peer(124, vec![bootstrap_addr], tx);
+        assert!(peer.wait_until_ready(1, Duration::from_secs(5)).await);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(bootstrap.knows(observer.public_addr()).await.is_some());
+        assert!(peer.knows(observer.public_addr()).await.is_none());
+
+        bootstrap
+            .broadcast_to(&[1; MESSAGE_SIZE], &[observer.public_addr()])
+            .await;
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (port, _) = received.unwrap().unwrap();
+        assert_eq!(port, (BASE_PORT + 123) as usize);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn batch_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let bootstrap_addr = format!("127.0.0.1:{}", BASE_PORT + 125);
+        let _bootstrap = create_peer(125, vec![], tx.clone());
+        let peer = create_peer(126, vec![bootstrap_addr], tx);
+        assert!(peer.wait_until_ready(1, Duration::from_secs(5)).await);
+
+        let messages: Vec<_> = (1..=5u8).map(|i| vec![i; 10]).collect();
+        let handles = peer.broadcast_batch(&messages, None).await;
+        assert_eq!(handles.len(), 1);
+        assert_eq!(handles[0].buckets(), 1);
+        // The messages are notified one by one
+        for expected in &messages {
+            let received = timeout(Duration::from_secs(5), rx.recv()).await;
+            let (port, (message, _, _)) = received.unwrap().unwrap();
+            assert_eq!(port, (BASE_PORT + 125) as usize);
+            assert_eq!(&message, expected);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn scheduled_broadcast_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let bootstrap_addr = format!("127.0.0.1:{}", BASE_PORT + 127);
+        let _bootstrap = create_peer(127, vec![], tx.clone());
+        let peer = create_peer(128, vec![bootstrap_addr], tx);
+        assert!(peer.wait_until_ready(1, Duration::from_secs(5)).await);
+
+        let delay = Duration::from_millis(500);
+        let cancelled = peer.broadcast_after(&[1; 10], delay, None);
+        let scheduled = peer.broadcast_after(&[2; 10], delay, None);
+        cancelled.cancel();
+        assert!(cancelled.is_cancelled());
+        let early = timeout(delay / 2, rx.recv()).await;
+        assert!(early.is_err(), "Broadcasted before the scheduled time");
+
+        let handle = scheduled.sent().await.expect("Broadcasted");
+        assert_eq!(handle.buckets(), 1);
+        assert!(cancelled.sent().await.is_none());
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (_, (message, _, _)) = received.unwrap().unwrap();
+        assert_eq!(message, vec![2; 10]);
+        assert!(timeout(delay, rx.recv()).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn heights_broadcast_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let bootstrap_addr = format!("127.0.0.1:{}", BASE_PORT + 129);
+        let _bootstrap = create_peer(129, vec![], tx.clone());
+        let peer = create_peer(130, vec![bootstrap_addr], tx);
+        assert!(peer.wait_until_ready(1, Duration::from_secs(5)).await);
+        let height = peer.bucket_occupancy().await[0].height();
+
+        let other = (height + 1) % 128;
+        let options = |heights: &[_]| {
+            BroadcastOptions::default()
+                .with_heights(heights)
+                .with_topic(1)
+        };
+        let handle = peer.broadcast_with(&[1; 10], options(&[other])).await;
+        assert_eq!(handle.buckets(), 0);
+        let handle = peer.broadcast_with(&[2; 10], options(&[height])).await;
+        assert_eq!(handle.buckets(), 1);
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (port, (message, _, _)) = received.unwrap().unwrap();
+        assert_eq!(port, (BASE_PORT + 129) as usize);
+        assert_eq!(message, vec![2; 10]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn noise_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let peer = |i, noise: Option<NoiseConfig>| {
+            let conf = Config {
+                public_address: format!("127.0.0.1:{}", BASE_PORT + i),
+                bootstrapping_nodes: vec![format!(
+                    "127.0.0.1:{}",
+                    BASE_PORT + 131
+                )],
+                noise,
+                ..Default::default()
+            };
+            let listener = KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + i) as usize,
+            };
+            Peer::new(conf, listener)
+        };
+        let bootstrap = peer(131, Some(NoiseConfig::default()));
+        let bootstrap_key = bootstrap.noise_key().expect("Noise enabled");
+        let allowed = NoiseConfig {
+            allowed_keys: Some(vec![bootstrap_key]),
+            ..Default::default()
+        };
+        let joined = peer(132, Some(allowed));
+        assert!(joined.wait_until_ready(1, Duration::from_secs(5)).await);
+
+        let handle = joined.broadcast(&[1; MESSAGE_SIZE], None).await;
+        assert_eq!(handle.buckets(), 1);
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (port, (message, _, _)) = received.unwrap().unwrap();
+        assert_eq!(port, (BASE_PORT + 131) as usize);
+        assert_eq!(message, vec![1; MESSAGE_SIZE]);
+
+        // Peers without a session, or not allowed, never join
+        let plain = peer(133, None);
+        let not_allowed = NoiseConfig {
+            allowed_keys: Some(vec![[1; 32]]),
+            ..Default::default()
+        };
+        let refused = peer(134, Some(not_allowed));
+        assert!(!plain.wait_until_ready(1, Duration::from_secs(2)).await);
+        assert!(!refused.wait_until_ready(1, Duration::from_secs(1)).await);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn identity_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let peer = |i, identity_key: Option<[u8; 32]>| {
+            let conf = Config {
+                public_address: format!("127.0.0.1:{}", BASE_PORT + i),
+                bootstrapping_nodes: vec![format!(
+                    "127.0.0.1:{}",
+                    BASE_PORT + 135
+                )],
+                identity_key,
+                require_identity: true,
+                ..Default::default()
+            };
+            let listener = KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + i) as usize,
+            };
+            Peer::new(conf, listener)
+        };
+        let bootstrap = peer(135, Some([1; 32]));
+        let joined = peer(136, Some([2; 32]));
+        assert!(joined.wait_until_ready(1, Duration::from_secs(5)).await);
+
+        // Peers without a key identity can join, but they are never
+        // inserted by the ones requiring it
+        let anonymous = peer(137, None);
+        assert!(anonymous.wait_until_ready(1, Duration::from_secs(5)).await);
+        let report = bootstrap.report().await;
+        let addresses: Vec<_> = report.peers().map(|p| *p.address()).collect();
+        assert_eq!(
+            addresses,
+            vec![format!("127.0.0.1:{}", BASE_PORT + 136).parse().unwrap()]
+        );
+
+        // Broadcasts are signed with the key identity, which binds them to
+        // the originator id
+        let target = format!("127.0.0.1:{}", BASE_PORT + 135).parse().unwrap();
+        joined.send(b"bound", target).await;
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (port, (message, _, _)) = received.unwrap().unwrap();
+        assert_eq!(port, (BASE_PORT + 135) as usize);
+        assert_eq!(message, b"bound");
+
+        // A signing key is not bound to the id of the originator
+        let signer = Peer::new(
+            Config {
+                public_address: format!("127.0.0.1:{}", BASE_PORT + 152),
+                signing_key: Some([3; 32]),
+                ..Default::default()
+            },
+            KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + 152) as usize,
+            },
+        );
+        signer.send(b"unbound", target).await;
+        let dropped = timeout(Duration::from_secs(1), rx.recv()).await;
+        assert!(dropped.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn id_difficulty_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let peer = |i, id_difficulty| {
+            let mut conf = Config {
+                public_address: format!("127.0.0.1:{}", BASE_PORT + i),
+                bootstrapping_nodes: vec![format!(
+                    "127.0.0.1:{}",
+                    BASE_PORT + 138
+                )],
+                ..Default::default()
+            };
+            conf.bucket.id_difficulty = id_difficulty;
+            let listener = KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + i) as usize,
+            };
+            Peer::new(conf, listener)
+        };
+        let bootstrap = peer(138, 12);
+        assert_eq!(bootstrap.header().id_difficulty(), 12);
+        assert!(bootstrap.header().binary_id().has_difficulty(12));
+        let joined = peer(139, 12);
+        assert!(joined.wait_until_ready(1, Duration::from_secs(5)).await);
+
+        // Peers of another difficulty never join
+        let other = peer(140, 8);
+        assert!(!other.wait_until_ready(1, Duration::from_secs(2)).await);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn nonce_echo_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let peer = |i| {
+            let conf = Config {
+                public_address: format!("127.0.0.1:{}", BASE_PORT + i),
+                bootstrapping_nodes: vec![format!(
+                    "127.0.0.1:{}",
+                    BASE_PORT + 141
+                )],
+                require_nonce_echo: true,
+                ..Default::default()
+            };
+            let listener = KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + i) as usize,
+            };
+            Peer::new(conf, listener)
+        };
+        let _bootstrap = peer(141);
+        let joined = peer(142);
+        let mut events = joined.events();
+        assert!(joined.wait_until_ready(1, Duration::from_secs(5)).await);
+        // Every reply echoed the nonce of its request
+        while let Ok(event) = events.try_recv() {
+            assert!(!matches!(
+                event,
+                KadcastEvent::MessageDropped(_, DropReason::UnexpectedReply)
+            ));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn flood_guard_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let conf = Config {
+            public_address: format!("127.0.0.1:{}", BASE_PORT + 143),
+            flood_guard: Some(FloodGuardConfig {
+                max_malformed: 3,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let listener = KadcastListener {
+            grpc_sender: tx,
+            receiver_port: (BASE_PORT + 143) as usize,
+        };
+        let peer = Peer::new(conf, listener);
+        let mut events = peer.events();
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        for _ in 0..10 {
+            socket.send_to(&[0xff; 8], peer.listen_addr()).unwrap();
+        }
+        let ignored = timeout(Duration::from_secs(1), async {
+            loop {
+                if let Ok(KadcastEvent::SourceIgnored(ip, flood)) =
+                    events.recv().await
+                {
+                    break (ip, flood);
+                }
+            }
+        })
+        .await;
+        let localhost = "127.0.0.1".parse().unwrap();
+        assert_eq!(ignored.ok(), Some((localhost, Flood::Malformed)));
+        // Wait for the datagrams already received to be decoded
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        while events.try_recv().is_ok() {}
+        // The following ones are discarded undecoded
+        socket.send_to(&[0xff; 8], peer.listen_addr()).unwrap();
+        let decoded = timeout(Duration::from_millis(500), async {
+            while !matches!(
+                events.recv().await,
+                Ok(KadcastEvent::DecodeFailed(_))
+            ) {}
+        })
+        .await;
+        assert!(decoded.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn validator_test() {
+        struct FirstByte;
+        impl MessageValidator for FirstByte {
+            fn validate(&self, message: &[u8], _: &MessageInfo) -> Validation {
+                match message[0] {
+                    0 => Validation::Reject,
+                    1 => Validation::Ignore,
+                    _ => Validation::Accept,
+                }
+            }
+        }
+        let (tx, mut rx) = mpsc::channel(100);
+        let conf = Config {
+            public_address: format!("127.0.0.1:{}", BASE_PORT + 144),
+            validator: Some(Arc::new(FirstByte)),
+            ..Default::default()
+        };
+        let receiver = Peer::new(
+            conf,
+            KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + 144) as usize,
+            },
+        );
+        let mut events = receiver.events();
+        let sender = create_peer(145, vec![], tx);
+        let target = receiver.public_addr();
+
+        sender.broadcast_to(&[0; MESSAGE_SIZE], &[target]).await;
+        let rejected = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(KadcastEvent::MessageDropped(_, reason)) =
+                    events.recv().await
+                {
+                    break reason;
+                }
+            }
+        })
+        .await;
+        assert_eq!(rejected.ok(), Some(DropReason::Rejected));
+        sender.broadcast_to(&[1; MESSAGE_SIZE], &[target]).await;
+        sender.broadcast_to(&[2; MESSAGE_SIZE], &[target]).await;
+        // Only the accepted message is notified
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (_, (message, _, _)) = received.unwrap().unwrap();
+        assert_eq!(message, vec![2; MESSAGE_SIZE]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn trusted_peers_test() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let conf = Config {
+            public_address: format!("127.0.0.1:{}", BASE_PORT + 146),
+            rate_limit: Some(RateLimitConfig {
+                max_broadcast_uids: 1,
+                ..Default::default()
+            }),
+            trusted_peers: vec![PeerTarget::Ip("127.0.0.1".parse().unwrap())],
+            ..Default::default()
+        };
+        let receiver = Peer::new(
+            conf,
+            KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + 146) as usize,
+            },
+        );
+        let sender = create_peer(147, vec![], tx);
+        let target = receiver.public_addr();
+
+        // Trusted peers are not rate limited
+        sender.broadcast_to(&[1; MESSAGE_SIZE], &[target]).await;
+        sender.broadcast_to(&[2; MESSAGE_SIZE], &[target]).await;
+        for expected in 1..=2 {
+            let received = timeout(Duration::from_secs(5), rx.recv()).await;
+            let (_, (message, _, _)) = received.unwrap().unwrap();
+            assert_eq!(message, vec![expected; MESSAGE_SIZE]);
+        }
+
+        receiver.set_trusted_peers(vec![]).await;
+        sender.broadcast_to(&[3; MESSAGE_SIZE], &[target]).await;
+        sender.broadcast_to(&[4; MESSAGE_SIZE], &[target]).await;
+        let received = timeout(Duration::from_secs(5), rx.recv()).await;
+        let (_, (message, _, _)) = received.unwrap().unwrap();
+        assert_eq!(message, vec![3; MESSAGE_SIZE]);
+        let limited = timeout(Duration::from_secs(1), rx.recv()).await;
+        assert!(limited.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn audit_log_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let path = std::env::temp_dir().join("kadcast-audit-test.log");
+        let _ = std::fs::remove_file(&path);
+        let conf = Config {
+            public_address: format!("127.0.0.1:{}", BASE_PORT + 148),
+            audit_log: Some(path.clone()),
+            ..Default::default()
+        };
+        let receiver = Peer::new(
+            conf,
+            KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + 148) as usize,
+            },
+        );
+        let sender = create_peer(149, vec![], tx);
+        let host = "127.0.0.1".parse().unwrap();
+        receiver
+            .ban(PeerTarget::Ip(host), Duration::from_secs(60))
+            .await;
+        let mut events = receiver.events();
+        sender
+            .broadcast_to(&[1; MESSAGE_SIZE], &[receiver.public_addr()])
+            .await;
+        let security = timeout(Duration::from_secs(5), async {
+            loop {
+                match events.recv().await {
+                    Ok(KadcastEvent::Security(event)) => break event,
+                    Ok(_) => continue,
+                    Err(e) => panic!("No event received: {}", e),
+                }
+            }
+        })
+        .await
+        .expect("A security event");
+        assert_eq!(security.source, host);
+        assert_eq!(security.kind, SecurityKind::BannedSource);
+
+        // The event is appended to the audit log
+        let logged = timeout(Duration::from_secs(1), async {
+            loop {
+                let log = std::fs::read_to_string(&path).unwrap_or_default();
+                if log.contains("BannedSource") {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(logged.is_ok(), "The event is not logged");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn amplification_guard_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let conf = Config {
+            public_address: format!("127.0.0.1:{}", BASE_PORT + 150),
+            amplification_guard: Some(AmplificationGuardConfig::default()),
+            ..Default::default()
+        };
+        let receiver = Peer::new(
+            conf,
+            KadcastListener {
+                grpc_sender: tx.clone(),
+                receiver_port: (BASE_PORT + 150) as usize,
+            },
+        );
+        let mut events = receiver.events();
+        // The bootstrapper never contacted the new peer
+        let bootstrap = vec![receiver.public_addr().to_string()];
+        let _sender = create_peer(151, bootstrap, tx);
+        let dropped = timeout(Duration::from_secs(5), async {
+            loop {
+                match events.recv().await {
+                    Ok(KadcastEvent::MessageDropped(
+                        _,
+                        DropReason::NoContact,
+                    )) => break,
+                    Ok(_) => continue,
+                    Err(e) => panic!("No event received: {}", e),
+                }
+            }
+        })
+        .await;
+        assert!(dropped.is_ok(), "FindNodes answered without contact");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn try_new_test() {
+        let (tx, _rx) = mpsc::channel(100);
+        let listener = |port| KadcastListener {
+            grpc_sender: tx.clone(),
+            receiver_port: port as usize,
+        };
+        let address = format!("127.0.0.1:{}", BASE_PORT + 105);
+        let conf = Config {
+            public_address: address.clone(),
+            ..Default::default()
+        };
+        let _peer = Peer::try_new(conf.clone(), listener(BASE_PORT + 105))
+            .expect("Valid config");
+        // The address is already bound
+        let bound = Peer::try_new(conf, listener(BASE_PORT + 105));
+        assert!(matches!(bound, Err(ConfigError::Bind(..))));
+
+        let conf = Config {
+            public_address: address,
+            bootstrapping_nodes: vec!["no port".to_string()],
+            ..Default::default()
+        };
+        let invalid = Peer::try_new(conf, listener(BASE_PORT + 105));
+        assert!(matches!(invalid, Err(ConfigError::InvalidNode(_))));
+    }
+
     async fn receive(
         mut rx: mpsc::Receiver<(usize, (Vec<u8>, SocketAddr, u8))>,
         expected: i32,